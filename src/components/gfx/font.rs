@@ -25,6 +25,7 @@ use render_context::RenderContext;
 use text::glyph::{GlyphStore, GlyphIndex};
 use text::shaping::ShaperMethods;
 use text::{Shaper, TextRun};
+use web_font::WebFontData;
 
 // FontHandle encapsulates access to the platform's font API,
 // e.g. quartz, FreeType. It provides access to metrics and tables
@@ -132,6 +133,7 @@ impl FontDescriptor {
 #[deriving(Clone, Eq)]
 pub enum FontSelector {
     SelectorPlatformIdentifier(~str),
+    SelectorWebFont(WebFontData),
 }
 
 // This struct is the result of mapping a specified FontStyle into the
@@ -208,6 +210,7 @@ pub struct Font {
     priv handle: FontHandle,
     priv azure_font: Option<ScaledFont>,
     priv shaper: Option<Shaper>,
+    priv selector: FontSelector,
     style: UsedFontStyle,
     metrics: FontMetrics,
     backend: BackendType,
@@ -229,11 +232,13 @@ impl<'a> Font {
         };
 
         let metrics = handle.get_metrics();
+        let selector = SelectorPlatformIdentifier(handle.face_identifier());
 
         return Ok(Rc::from_mut(RefCell::new(Font {
             handle: handle,
             azure_font: None,
             shaper: None,
+            selector: selector,
             style: (*style).clone(),
             metrics: metrics,
             backend: backend,
@@ -246,11 +251,13 @@ impl<'a> Font {
                                style: &SpecifiedFontStyle, backend: BackendType)
                                -> Font {
         let metrics = handle.get_metrics();
+        let selector = SelectorPlatformIdentifier(handle.face_identifier());
 
         Font {
             handle: handle,
             azure_font: None,
             shaper: None,
+            selector: selector,
             style: (*style).clone(),
             metrics: metrics,
             backend: backend,
@@ -259,6 +266,22 @@ impl<'a> Font {
         }
     }
 
+    /// Creates a font from a downloaded web font. Its descriptor keeps the font data, so that
+    /// other tasks can create the same font.
+    pub fn new_from_web_font(fctx: &FontContext, data: &WebFontData,
+                             style: &SpecifiedFontStyle, backend: BackendType)
+                             -> Result<Font, ()> {
+        let handle: FontHandle = match FontHandleMethods::new_from_buffer(&fctx.handle,
+                                                                          data.data.get().clone(),
+                                                                          style) {
+            Ok(handle) => handle,
+            Err(()) => return Err(()),
+        };
+        let mut font = Font::new_from_adopted_handle(fctx, handle, style, backend);
+        font.selector = SelectorWebFont(data.clone());
+        Ok(font)
+    }
+
     pub fn new_from_existing_handle(fctx: &FontContext, handle: &FontHandle,
                                 style: &SpecifiedFontStyle, backend: BackendType)
                                 -> Result<Rc<RefCell<Font>>,()> {
@@ -436,7 +459,7 @@ impl Font {
     }
 
     pub fn get_descriptor(&self) -> FontDescriptor {
-        FontDescriptor::new(self.style.clone(), SelectorPlatformIdentifier(self.handle.face_identifier()))
+        FontDescriptor::new(self.style.clone(), self.selector.clone())
     }
 
     pub fn glyph_index(&self, codepoint: char) -> Option<GlyphIndex> {
@@ -3,10 +3,11 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use font::{Font, FontDescriptor, FontGroup, FontHandleMethods, SelectorPlatformIdentifier};
-use font::{SpecifiedFontStyle, UsedFontStyle};
+use font::{SelectorWebFont, SpecifiedFontStyle, UsedFontStyle};
 use font_list::FontList;
 use platform::font::FontHandle;
 use platform::font_context::FontContextHandle;
+use web_font::{WebFontList, LocalFont, PendingFont, DownloadedFont};
 
 use azure::azure_hl::BackendType;
 use servo_util::cache::{Cache, LRUCache};
@@ -27,6 +28,9 @@ pub struct FontContextInfo {
 
     /// A channel up to the profiler.
     profiler_chan: ProfilerChan,
+
+    /// The fonts of the document's `@font-face` rules.
+    web_fonts: WebFontList,
 }
 
 pub trait FontContextHandleMethods {
@@ -41,6 +45,9 @@ pub struct FontContext {
     backend: BackendType,
     generic_fonts: HashMap<~str,~str>,
     profiler_chan: ProfilerChan,
+    web_fonts: WebFontList,
+    /// The generation of `web_fonts` that the font groups in `group_cache` were resolved with.
+    web_font_generation: uint,
 }
 
 impl FontContext {
@@ -68,11 +75,20 @@ impl FontContext {
             backend: info.backend,
             generic_fonts: generic_fonts,
             profiler_chan: info.profiler_chan.clone(),
+            web_font_generation: info.web_fonts.generation(),
+            web_fonts: info.web_fonts,
         }
     }
 
     pub fn get_resolved_font_for_style(&mut self, style: &SpecifiedFontStyle)
                                        -> Rc<RefCell<FontGroup>> {
+        // Font groups resolved before a web font was added or loaded might not use it.
+        let web_font_generation = self.web_fonts.generation();
+        if web_font_generation != self.web_font_generation {
+            self.group_cache.evict_all();
+            self.web_font_generation = web_font_generation;
+        }
+
         match self.group_cache.find(style) {
             Some(fg) => {
                 debug!("font group cache hit");
@@ -122,6 +138,16 @@ impl FontContext {
 
         // TODO(Issue #193): make iteration over 'font-family' more robust.
         for family in style.families.iter() {
+            match self.find_web_font(family.as_slice(), style) {
+                Some(ref font_desc) => {
+                    debug!("(create font group) found web font for `{:s}`", family.as_slice());
+                    let instance = self.get_font_by_descriptor(font_desc);
+                    instance.map(|font| fonts.push(font.clone()));
+                    continue
+                }
+                None => {}
+            }
+
             let transformed_family_name = self.transform_family(family);
             debug!("(create font group) transformed family is `{:s}`", transformed_family_name);
             let mut found = false;
@@ -205,6 +231,41 @@ impl FontContext {
         }
     }
 
+    /// Looks for `family` among the document's web fonts, and returns the first of the face's
+    /// sources that is available: a `local()` font installed on the system, or a downloaded
+    /// font once it has loaded.
+    fn find_web_font(&mut self, family: &str, style: &SpecifiedFontStyle)
+                     -> Option<FontDescriptor> {
+        let sources = match self.web_fonts.find(family, style) {
+            Some(sources) => sources,
+            None => return None,
+        };
+        for source in sources.move_iter() {
+            match source {
+                LocalFont(name) => {
+                    let font_entry = match self.font_list {
+                        Some(ref mut font_list) => font_list.find_font_in_family(&name, style),
+                        None => None,
+                    };
+                    match font_entry {
+                        Some(font_entry) => {
+                            let font_id =
+                                SelectorPlatformIdentifier(font_entry.handle.face_identifier());
+                            return Some(FontDescriptor::new((*style).clone(), font_id))
+                        }
+                        None => {}
+                    }
+                }
+                DownloadedFont(data) => {
+                    return Some(FontDescriptor::new((*style).clone(), SelectorWebFont(data)))
+                }
+                // Faces are tried again once the download finishes.
+                PendingFont(..) => {}
+            }
+        }
+        None
+    }
+
     fn create_font_instance(&self, desc: &FontDescriptor) -> Result<Rc<RefCell<Font>>, ()> {
         return match &desc.selector {
             // TODO(Issue #174): implement by-platform-name font selectors.
@@ -221,6 +282,11 @@ impl FontContext {
                                                               self.backend))))
                 })
             }
+            &SelectorWebFont(ref data) => {
+                Font::new_from_web_font(self, data, &desc.style, self.backend).map(|font| {
+                    Rc::from_mut(RefCell::new(font))
+                })
+            }
         };
     }
 }
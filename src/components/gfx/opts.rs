@@ -8,7 +8,9 @@
 use azure::azure_hl::{BackendType, CairoBackend, CoreGraphicsBackend};
 use azure::azure_hl::{CoreGraphicsAcceleratedBackend, Direct2DBackend, SkiaBackend};
 use extra::getopts::groups;
+use servo_net::network_archive::{NetworkArchive, Record, Replay};
 use std::num;
+use std::os;
 use std::rt;
 
 /// Global flags for Servo, currently set on the command line.
@@ -46,6 +48,24 @@ pub struct Opts {
     output_file: Option<~str>,
     headless: bool,
     hard_fail: bool,
+
+    /// A directory in which to keep the HTTP cache across sessions (`--http-cache`). If this is
+    /// `None`, responses are only cached in memory.
+    http_cache_dir: Option<Path>,
+
+    /// A file to write a HAR log of every network request to on exit (`--har`).
+    har_path: Option<Path>,
+
+    /// A directory to record every response into (`--record-network`) or to serve every load
+    /// from (`--replay-network`).
+    network_archive: Option<NetworkArchive>,
+
+    /// How many megabytes of images to keep once no page uses them (`--image-cache-size`).
+    image_cache_size: uint,
+
+    /// A time in seconds to run all transitions and animations at instead of the time of day
+    /// (`--animation-time`), so that the output is the same on every run.
+    animation_time: Option<f64>,
 }
 
 fn print_usage(app: &str, opts: &[groups::OptGroup]) {
@@ -53,7 +73,17 @@ fn print_usage(app: &str, opts: &[groups::OptGroup]) {
     println(groups::usage(message, opts));
 }
 
-pub fn from_cmdline_args(args: &[~str]) -> Opts {
+/// Reports a command line that can't be run, and makes servo exit with an error status.
+fn usage_error(app: &str, opts: &[groups::OptGroup], message: &str) -> Option<Opts> {
+    println(message);
+    print_usage(app, opts);
+    os::set_exit_status(1);
+    None
+}
+
+/// Returns `None` if servo shouldn't run at all, either because the command line is invalid or
+/// because it asked for help.
+pub fn from_cmdline_args(args: &[~str]) -> Option<Opts> {
     let app_name = args[0].to_str();
     let args = args.tail();
 
@@ -68,24 +98,27 @@ pub fn from_cmdline_args(args: &[~str]) -> Opts {
         groups::optopt("y", "layout-threads", "Number of threads to use for layout", "1"),
         groups::optflag("z", "headless", "Headless mode"),
         groups::optflag("f", "hard-fail", "Exit on task failure instead of displaying about:failure"),
+        groups::optopt("", "http-cache", "Directory for the on-disk HTTP cache", "DIR"),
+        groups::optopt("", "har", "Write a HAR log of all network requests on exit", "FILE"),
+        groups::optopt("", "record-network", "Record all responses into a directory", "DIR"),
+        groups::optopt("", "replay-network", "Serve all loads from a recorded directory", "DIR"),
+        groups::optopt("", "image-cache-size", "Megabytes of unused images to keep", "64"),
+        groups::optopt("", "animation-time", "Run animations at a fixed time", "SECONDS"),
         groups::optflag("h", "help", "Print this message")
     ];
 
     let opt_match = match groups::getopts(args, opts) {
         Ok(m) => m,
-        Err(f) => fail!(f.to_err_msg()),
+        Err(f) => return usage_error(app_name, opts, f.to_err_msg()),
     };
 
     if opt_match.opt_present("h") || opt_match.opt_present("help") {
         print_usage(app_name, opts);
-        // TODO: how to return a null struct and let the caller know that
-        // it should abort?
-        fail!("")
+        return None;
     };
 
     let urls = if opt_match.free.is_empty() {
-        print_usage(app_name, opts);
-        fail!(~"servo asks that you provide 1 or more URLs")
+        return usage_error(app_name, opts, "servo asks that you provide 1 or more URLs");
     } else {
         opt_match.free.clone()
     };
@@ -126,12 +159,28 @@ pub fn from_cmdline_args(args: &[~str]) -> Opts {
 
     let cpu_painting = opt_match.opt_present("c");
 
+    let network_archive = match (opt_match.opt_str("record-network"),
+                                 opt_match.opt_str("replay-network")) {
+        (Some(..), Some(..)) => {
+            return usage_error(app_name, opts,
+                               "--record-network and --replay-network can't be used together");
+        }
+        (Some(dir), None) => Some(NetworkArchive::new(Record, Path::new(dir))),
+        (None, Some(dir)) => Some(NetworkArchive::new(Replay, Path::new(dir))),
+        (None, None) => None,
+    };
+
+    let image_cache_size: uint = match opt_match.opt_str("image-cache-size") {
+        Some(image_cache_size_str) => from_str(image_cache_size_str).unwrap(),
+        None => 64,
+    };
+
     let layout_threads: uint = match opt_match.opt_str("y") {
         Some(layout_threads_str) => from_str(layout_threads_str).unwrap(),
         None => num::max(rt::default_sched_threads() * 3 / 4, 1),
     };
 
-    Opts {
+    Some(Opts {
         urls: urls,
         render_backend: render_backend,
         n_render_threads: n_render_threads,
@@ -143,5 +192,10 @@ pub fn from_cmdline_args(args: &[~str]) -> Opts {
         output_file: opt_match.opt_str("o"),
         headless: opt_match.opt_present("z"),
         hard_fail: opt_match.opt_present("f"),
-    }
+        http_cache_dir: opt_match.opt_str("http-cache").map(|dir| Path::new(dir)),
+        har_path: opt_match.opt_str("har").map(|file| Path::new(file)),
+        network_archive: network_archive,
+        image_cache_size: image_cache_size,
+        animation_time: opt_match.opt_str("animation-time").map(|time| from_str(time).unwrap()),
+    })
 }
@@ -29,6 +29,7 @@ use display_list::DisplayListCollection;
 use font_context::{FontContext, FontContextInfo};
 use opts::Opts;
 use render_context::RenderContext;
+use web_font::WebFontList;
 
 pub struct RenderLayer<T> {
     display_list_collection: Arc<DisplayListCollection<T>>,
@@ -164,6 +165,9 @@ impl<C: RenderListener + Send,T:Send+Freeze> RenderTask<C,T> {
                         backend: opts.render_backend.clone(),
                         needs_font_list: false,
                         profiler_chan: profiler_chan.clone(),
+                        // Fonts are only resolved by family in layout; web fonts get here
+                        // with their data in the font descriptors.
+                        web_fonts: WebFontList::new(),
                     }),
                     opts: opts,
                     profiler_chan: profiler_chan,
@@ -0,0 +1,356 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Fonts from `@font-face` rules. Each document has its own list, which the font contexts of its
+//! layout task look in before the fonts installed on the system.
+
+use font::{FontHandleMethods, FontStyle, SpecifiedFontStyle};
+use platform::font::FontHandle;
+use platform::font_context::FontContextHandle;
+
+use extra::arc::{Arc, RWArc};
+use extra::url::Url;
+use servo_net::resource_task::{ResourceTask, load_whole_resource};
+use servo_util::task::spawn_named;
+use std::ascii::StrAsciiExt;
+use std::from_str::from_str;
+use style::{FontFaceRule, UrlSource, LocalSource};
+use style::computed_values::{font_style, font_weight};
+#[cfg(test)]
+use style::Source;
+
+/// The formats that `FontHandleMethods::new_from_buffer` can read.
+static SUPPORTED_FORMATS: [&'static str, ..2] = ["truetype", "opentype"];
+
+/// A font face, with where to get it from.
+#[deriving(Clone)]
+pub struct WebFont {
+    family: ~str,
+    weight: font_weight::T,
+    style: font_style::T,
+    /// The sources of the rule that were usable when it was added, which tells a face that is
+    /// added again apart from a new one.
+    rule_sources: ~[WebFontSource],
+    /// The sources that can still be used, in the order they are tried.
+    sources: ~[WebFontSource],
+}
+
+#[deriving(Clone, Eq)]
+pub enum WebFontSource {
+    /// The family name of a font installed on the system.
+    LocalFont(~str),
+    /// The URL of a font that is being downloaded, or waits for the ones before it to fail.
+    PendingFont(~str),
+    DownloadedFont(WebFontData),
+}
+
+/// The data of a downloaded font. It is compared by the URL it came from, so that it can be part
+/// of font descriptors, which lets the render task create the font too.
+#[deriving(Clone)]
+pub struct WebFontData {
+    url: ~str,
+    data: Arc<~[u8]>,
+}
+
+impl Eq for WebFontData {
+    fn eq(&self, other: &WebFontData) -> bool {
+        self.url == other.url
+    }
+}
+
+/// The sources of a rule in the order they are tried, leaving out repeated sources and fonts
+/// whose format hints only name formats we can't read.
+fn usable_sources(rule: &FontFaceRule) -> ~[WebFontSource] {
+    let mut sources = ~[];
+    for source in rule.sources.iter() {
+        let source = match *source {
+            LocalSource(ref name) => LocalFont(name.clone()),
+            UrlSource(ref url, ref formats) => {
+                let supported = formats.is_empty() || formats.iter().any(|format| {
+                    SUPPORTED_FORMATS.iter().any(|supported| *supported == format.as_slice())
+                });
+                if !supported {
+                    continue
+                }
+                PendingFont(url.to_str())
+            }
+        };
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+    sources
+}
+
+struct WebFonts {
+    fonts: ~[WebFont],
+    /// Bumped whenever a font is added or finishes loading, so that font contexts know to forget
+    /// the font groups they resolved without it.
+    generation: uint,
+}
+
+/// The web fonts of a document, shared between its layout task, the font contexts of its layout
+/// workers and the tasks downloading the fonts.
+#[deriving(Clone)]
+pub struct WebFontList {
+    priv fonts: RWArc<WebFonts>,
+}
+
+impl WebFontList {
+    pub fn new() -> WebFontList {
+        WebFontList {
+            fonts: RWArc::new(WebFonts {
+                fonts: ~[],
+                generation: 0,
+            }),
+        }
+    }
+
+    pub fn generation(&self) -> uint {
+        self.fonts.read(|fonts| fonts.generation)
+    }
+
+    /// Finds the face of `family` that best matches the weight and slant of `style`, and returns
+    /// where to get it from. Family names are matched case-insensitively.
+    ///
+    /// FIXME(Issue #177): `unicode-range` is not taken into account, as font groups don't fall
+    /// back to the next font for characters the first one doesn't cover.
+    pub fn find(&self, family: &str, style: &SpecifiedFontStyle) -> Option<~[WebFontSource]> {
+        self.fonts.read(|fonts| {
+            let mut best: Option<(&WebFont, (bool, int))> = None;
+            for font in fonts.fonts.iter() {
+                if !font.family.eq_ignore_ascii_case(family) {
+                    continue
+                }
+                // The slant is the first thing to match, then the nearest weight.
+                let distance = (font.style != style.style,
+                                (font.weight as int - style.weight as int).abs());
+                let better = match best {
+                    None => true,
+                    Some((_, best_distance)) => distance < best_distance,
+                };
+                if better {
+                    best = Some((font, distance));
+                }
+            }
+            best.map(|(font, _)| font.sources.clone())
+        })
+    }
+
+    /// Adds the face of an `@font-face` rule, unless it was added before. Its `local()` fonts
+    /// can be used right away; its downloaded fonts are tried in order in a new task, until one
+    /// of them can be read. Once one has been added, `on_load` is called so that the document
+    /// can be laid out with it.
+    pub fn add(&self, rule: &FontFaceRule, resource_task: ResourceTask, on_load: proc()) {
+        let sources = usable_sources(rule);
+        let urls: ~[~str] = sources.iter().filter_map(|source| {
+            match *source {
+                PendingFont(ref url) => Some(url.clone()),
+                LocalFont(..) | DownloadedFont(..) => None,
+            }
+        }).collect();
+        let index = self.fonts.write(|fonts| {
+            let known = fonts.fonts.iter().any(|font| {
+                font.family == rule.family && font.weight == rule.weight &&
+                    font.style == rule.style && font.rule_sources == sources
+            });
+            if known {
+                return None
+            }
+            fonts.fonts.push(WebFont {
+                family: rule.family.clone(),
+                weight: rule.weight,
+                style: rule.style,
+                rule_sources: sources.clone(),
+                sources: sources.clone(),
+            });
+            fonts.generation += 1;
+            Some(fonts.fonts.len() - 1)
+        });
+        let index = match index {
+            Some(index) if !urls.is_empty() => index,
+            _ => return,
+        };
+
+        let style = FontStyle {
+            pt_size: 16.,
+            weight: rule.weight,
+            style: rule.style,
+            families: ~[rule.family.clone()],
+        };
+        let list = self.clone();
+        spawn_named("WebFontLoader", proc() {
+            let handle = FontContextHandle::new();
+            let mut loaded = false;
+            for url in urls.iter() {
+                let data = from_str(url.as_slice()).and_then(|url| {
+                    load_font(&handle, &resource_task, url, &style)
+                });
+                loaded = data.is_some();
+                list.finish_download(index, url.as_slice(), data);
+                if loaded {
+                    break
+                }
+            }
+            if loaded {
+                on_load()
+            } else {
+                debug!("web font: no usable source for {:s}", style.families[0])
+            }
+        });
+    }
+
+    /// Replaces the pending source for `url` of the face at `index` with the font downloaded
+    /// from it, or drops it if it couldn't be used. Once a font has been downloaded, the URLs
+    /// after it are never tried.
+    fn finish_download(&self, index: uint, url: &str, data: Option<WebFontData>) {
+        self.fonts.write(|fonts| {
+            let loaded = {
+                let sources = &mut fonts.fonts[index].sources;
+                let position = sources.iter().position(|source| {
+                    match *source {
+                        PendingFont(ref pending) => pending.as_slice() == url,
+                        LocalFont(..) | DownloadedFont(..) => false,
+                    }
+                });
+                match (position, data) {
+                    (Some(position), Some(data)) => {
+                        sources[position] = DownloadedFont(data);
+                        sources.retain(|source| {
+                            match *source {
+                                PendingFont(..) => false,
+                                LocalFont(..) | DownloadedFont(..) => true,
+                            }
+                        });
+                        true
+                    }
+                    (Some(position), None) => {
+                        sources.remove(position);
+                        false
+                    }
+                    (None, _) => false,
+                }
+            };
+            // Font groups were resolved without the pending sources, so dropping one doesn't
+            // change them.
+            if loaded {
+                fonts.generation += 1;
+            }
+        })
+    }
+}
+
+/// Downloads a font, and checks that it can be read: sources without format hints could be in
+/// any format.
+fn load_font(handle: &FontContextHandle, resource_task: &ResourceTask, url: Url,
+             style: &SpecifiedFontStyle) -> Option<WebFontData> {
+    let url_str = url.to_str();
+    let data = match load_whole_resource(resource_task, url) {
+        Ok((_, data)) => data,
+        Err(_) => {
+            debug!("web font: failed to load {:s}", url_str);
+            return None
+        }
+    };
+    let font: Result<FontHandle, ()> = FontHandleMethods::new_from_buffer(handle, data.clone(),
+                                                                         style);
+    if font.is_err() {
+        debug!("web font: failed to read {:s}", url_str);
+        return None
+    }
+    Some(WebFontData {
+        url: url_str,
+        data: Arc::new(data),
+    })
+}
+
+#[cfg(test)]
+fn font_face_rule(family: &str, sources: ~[Source]) -> FontFaceRule {
+    FontFaceRule {
+        family: family.to_owned(),
+        sources: sources,
+        weight: font_weight::Weight400,
+        style: font_style::normal,
+        unicode_range: ~[(0, 0x10FFFF)],
+    }
+}
+
+#[cfg(test)]
+fn resource_task() -> ResourceTask {
+    // Nothing is loaded in these tests, so nothing needs to answer.
+    let (_control_port, resource_task) = SharedChan::new();
+    resource_task
+}
+
+#[cfg(test)]
+fn url_source(url: &str, formats: ~[~str]) -> Source {
+    UrlSource(from_str(url).unwrap(), formats)
+}
+
+#[test]
+fn test_sources_in_order_without_unreadable_formats() {
+    let rule = font_face_rule("Foo", ~[LocalSource(~"Foo Regular"),
+                                       url_source("http://example.com/a.woff2", ~[~"woff2"]),
+                                       url_source("http://example.com/b.ttf",
+                                                  ~[~"woff", ~"truetype"]),
+                                       LocalSource(~"Foo"),
+                                       url_source("http://example.com/c.otf", ~[])]);
+    assert_eq!(usable_sources(&rule), ~[LocalFont(~"Foo Regular"),
+                                        PendingFont(~"http://example.com/b.ttf"),
+                                        LocalFont(~"Foo"),
+                                        PendingFont(~"http://example.com/c.otf")]);
+}
+
+#[test]
+fn test_download_fallback() {
+    let list = WebFontList::new();
+    // Without any URL to load, `add` doesn't start downloading.
+    let rule = font_face_rule("Foo", ~[LocalSource(~"Foo")]);
+    list.add(&rule, resource_task(), proc() {});
+    list.fonts.write(|fonts| {
+        fonts.fonts[0].sources = ~[PendingFont(~"http://example.com/a.ttf"), LocalFont(~"Foo"),
+                                   PendingFont(~"http://example.com/b.ttf"),
+                                   PendingFont(~"http://example.com/c.ttf")];
+    });
+    let generation = list.generation();
+    let style = FontStyle {
+        pt_size: 16.,
+        weight: font_weight::Weight400,
+        style: font_style::normal,
+        families: ~[~"foo"],
+    };
+
+    // A font that can't be used is dropped, and the next one is tried.
+    list.finish_download(0, "http://example.com/a.ttf", None);
+    assert_eq!(list.find("foo", &style), Some(~[LocalFont(~"Foo"),
+                                                PendingFont(~"http://example.com/b.ttf"),
+                                                PendingFont(~"http://example.com/c.ttf")]));
+    assert_eq!(list.generation(), generation);
+
+    // Once one has loaded, the ones after it are never tried.
+    let data = WebFontData {
+        url: ~"http://example.com/b.ttf",
+        data: Arc::new(~[]),
+    };
+    list.finish_download(0, "http://example.com/b.ttf", Some(data.clone()));
+    assert_eq!(list.find("foo", &style), Some(~[LocalFont(~"Foo"), DownloadedFont(data)]));
+    assert!(list.generation() > generation);
+}
+
+#[test]
+fn test_faces_added_once() {
+    let list = WebFontList::new();
+    let rule = font_face_rule("Foo", ~[LocalSource(~"Foo"), LocalSource(~"Foo")]);
+    list.add(&rule, resource_task(), proc() {});
+    let generation = list.generation();
+    list.add(&rule, resource_task(), proc() {});
+    assert_eq!(list.generation(), generation);
+    assert_eq!(list.fonts.read(|fonts| fonts.fonts.len()), 1);
+    assert_eq!(list.fonts.read(|fonts| fonts.fonts[0].sources.clone()), ~[LocalFont(~"Foo")]);
+
+    let mut bold = font_face_rule("Foo", ~[LocalSource(~"Foo")]);
+    bold.weight = font_weight::Weight700;
+    list.add(&bold, resource_task(), proc() {});
+    assert_eq!(list.fonts.read(|fonts| fonts.fonts.len()), 2);
+}
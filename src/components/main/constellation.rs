@@ -20,7 +20,7 @@ use servo_net::image_cache_task::{ImageCacheTask, ImageCacheTaskClient};
 use servo_net::resource_task::ResourceTask;
 use servo_net::resource_task;
 use servo_util::time::ProfilerChan;
-use servo_util::url::make_url;
+use servo_util::url::failure_url;
 use servo_util::task::spawn_named;
 use std::hashmap::{HashMap, HashSet};
 use std::util::replace;
@@ -390,9 +390,10 @@ impl Constellation {
                                              self.profiler_chan.clone(),
                                              self.window_size,
                                              self.opts.clone());
-        let failure = ~"about:failure";
-        let url = make_url(failure, None);
-        pipeline.load(url);
+        let failed_url = self.pipelines.find(&pipeline_id)
+            .and_then(|old| old.url.clone())
+            .map_default(~"", |url| url.to_str());
+        pipeline.load(failure_url(failed_url, "The page's task failed."));
 
         let frames = self.find_all(pipeline_id);
         for frame_tree in frames.iter() {
@@ -534,6 +535,7 @@ impl Constellation {
                                   self.chan.clone(),
                                   self.compositor_chan.clone(),
                                   self.image_cache_task.clone(),
+                                  self.resource_task.clone(),
                                   self.profiler_chan.clone(),
                                   self.opts.clone(),
                                   source_pipeline)
@@ -7,25 +7,39 @@
 use css::node_style::StyledNode;
 use layout::extra::LayoutAuxMethods;
 use layout::incremental;
-use layout::util::LayoutDataAccess;
-use layout::wrapper::LayoutNode;
+use layout::util::{LayoutDataAccess, PrivateLayoutData};
+use layout::wrapper::{AnimatingNodeSet, LayoutNode};
 
 use extra::arc::Arc;
 use script::layout_interface::LayoutChan;
 use servo_util::smallvec::SmallVec;
-use style::{TNode, Stylist, cascade};
+use style::{TNode, Stylist, ComputedValues, cascade, update_animations, update_transitions};
 use style::{Before, After};
 
 pub trait MatchMethods {
     fn match_node(&self, stylist: &Stylist);
 
-    /// Performs aux initialization, selector matching, and cascading sequentially.
+    /// Performs aux initialization, selector matching, and cascading sequentially. The nodes
+    /// left with transitions or animations that still change are added to `animating_nodes`.
     fn match_and_cascade_subtree(&self,
                                  stylist: &Stylist,
                                  layout_chan: &LayoutChan,
-                                 parent: Option<LayoutNode>);
-
-    unsafe fn cascade_node(&self, parent: Option<LayoutNode>);
+                                 parent: Option<LayoutNode>,
+                                 now: f64,
+                                 animating_nodes: &AnimatingNodeSet);
+
+    /// Cascades the styles of this subtree again without matching selectors, as when only its
+    /// transitions and animations have moved along.
+    fn cascade_subtree(&self,
+                       stylist: &Stylist,
+                       parent: Option<LayoutNode>,
+                       now: f64,
+                       animating_nodes: &AnimatingNodeSet);
+
+    /// Cascades the style of this node, running its transitions and animations up to `now`, in
+    /// seconds. Returns whether any of them still changes with time.
+    unsafe fn cascade_node(&self, parent: Option<LayoutNode>, stylist: &Stylist, now: f64)
+                           -> bool;
 }
 
 impl<'ln> MatchMethods for LayoutNode<'ln> {
@@ -40,10 +54,11 @@ impl<'ln> MatchMethods for LayoutNode<'ln> {
         let mut layout_data_ref = self.mutate_layout_data();
         match *layout_data_ref.get() {
             Some(ref mut layout_data) => {
-                stylist.get_applicable_declarations(self,
-                                                    style_attribute,
-                                                    None,
-                                                    &mut layout_data.data.applicable_declarations);
+                layout_data.data.normal_declaration_count = stylist.get_applicable_declarations(
+                    self,
+                    style_attribute,
+                    None,
+                    &mut layout_data.data.applicable_declarations);
                 stylist.get_applicable_declarations(self,
                                                     None,
                                                     Some(Before),
@@ -64,7 +79,9 @@ impl<'ln> MatchMethods for LayoutNode<'ln> {
     fn match_and_cascade_subtree(&self,
                                  stylist: &Stylist,
                                  layout_chan: &LayoutChan,
-                                 parent: Option<LayoutNode>) {
+                                 parent: Option<LayoutNode>,
+                                 now: f64,
+                                 animating_nodes: &AnimatingNodeSet) {
         self.initialize_layout_data((*layout_chan).clone());
 
         if self.is_element() {
@@ -72,17 +89,36 @@ impl<'ln> MatchMethods for LayoutNode<'ln> {
         }
 
         unsafe {
-            self.cascade_node(parent)
+            if self.cascade_node(parent, stylist, now) {
+                animating_nodes.insert(self)
+            }
+        }
+
+        for kid in self.children() {
+            kid.match_and_cascade_subtree(stylist, layout_chan, Some(*self), now, animating_nodes)
+        }
+    }
+
+    fn cascade_subtree(&self,
+                       stylist: &Stylist,
+                       parent: Option<LayoutNode>,
+                       now: f64,
+                       animating_nodes: &AnimatingNodeSet) {
+        unsafe {
+            if self.cascade_node(parent, stylist, now) {
+                animating_nodes.insert(self)
+            }
         }
 
         for kid in self.children() {
-            kid.match_and_cascade_subtree(stylist, layout_chan, Some(*self))
+            kid.cascade_subtree(stylist, Some(*self), now, animating_nodes)
         }
     }
 
-    unsafe fn cascade_node(&self, parent: Option<LayoutNode>) {
+    unsafe fn cascade_node(&self, parent: Option<LayoutNode>, stylist: &Stylist, now: f64)
+                           -> bool {
         macro_rules! cascade_node(
-            ($applicable_declarations: ident, $style: ident) => {{
+            ($applicable_declarations: ident, $style: ident, $animate: expr) => {{
                 // Get our parent's style. This must be unsafe so that we don't touch the parent's
                 // borrow flags.
                 //
@@ -107,23 +143,27 @@ impl<'ln> MatchMethods for LayoutNode<'ln> {
                 let computed_values = {
                     let layout_data_ref = self.borrow_layout_data();
                     let layout_data = layout_data_ref.get().as_ref().unwrap();
-                    Arc::new(cascade(layout_data.data.$applicable_declarations.as_slice(),
-                                     parent_style))
+                    cascade(layout_data.data.$applicable_declarations.as_slice(), parent_style)
                 };
 
                 let mut layout_data_ref = self.mutate_layout_data();
                 match *layout_data_ref.get() {
                     None => fail!("no layout data"),
                     Some(ref mut layout_data) => {
-                        let style = &mut layout_data.data.$style;
-                        match *style {
+                        let data = &mut *layout_data.data;
+                        let computed_values = if $animate {
+                            animate_style(data, computed_values, parent_style, stylist, now)
+                        } else {
+                            Arc::new(computed_values)
+                        };
+                        match data.$style {
                             None => (),
                             Some(ref previous_style) => {
-                                layout_data.data.restyle_damage = Some(incremental::compute_damage(
+                                data.restyle_damage = Some(incremental::compute_damage(
                                     previous_style.get(), computed_values.get()).to_int())
                             }
                         }
-                        *style = Some(computed_values)
+                        data.$style = Some(computed_values)
                     }
                 }
             }}
@@ -135,19 +175,58 @@ impl<'ln> MatchMethods for LayoutNode<'ln> {
                 layout_data_ref.get().as_ref().unwrap().data.before_applicable_declarations.len()
             };
             if before_len > 0 {
-                cascade_node!(before_applicable_declarations, before_style);
+                // FIXME: Pseudo-elements don't have transitions or animations of their own yet.
+                cascade_node!(before_applicable_declarations, before_style, false);
             }
         }
-        cascade_node!(applicable_declarations, style);
+        cascade_node!(applicable_declarations, style, true);
         {
             let after_len = {
                 let layout_data_ref = self.borrow_layout_data();
                 layout_data_ref.get().as_ref().unwrap().data.after_applicable_declarations.len()
             };
             if after_len > 0 {
-                cascade_node!(after_applicable_declarations, after_style);
+                cascade_node!(after_applicable_declarations, after_style, false);
             }
         }
+
+        let layout_data_ref = self.borrow_layout_data();
+        let data = &layout_data_ref.get().as_ref().unwrap().data;
+        !data.transitions.is_empty() || data.animating
     }
 }
 
+/// Runs the transitions and animations of a node on its newly cascaded style.
+fn animate_style(data: &mut PrivateLayoutData,
+                 mut style: ComputedValues,
+                 parent_style: Option<&ComputedValues>,
+                 stylist: &Stylist,
+                 now: f64)
+                 -> Arc<ComputedValues> {
+    match data.unanimated_style.as_ref().or(data.style.as_ref()) {
+        None => (),
+        Some(previous_style) => {
+            update_transitions(&mut data.transitions, previous_style.get(), &mut style, now)
+        }
+    }
+
+    if data.animations.is_empty() && style.Animation.animation_name.is_empty() {
+        data.unanimated_style = None;
+        data.animating = false;
+        return Arc::new(style)
+    }
+    data.unanimated_style = Some(Arc::new(style.clone()));
+    // Keyframes override the normal declarations, but not the `!important` ones.
+    let applicable_declarations = data.applicable_declarations.as_slice();
+    let normal = applicable_declarations.slice_to(data.normal_declaration_count);
+    let important = applicable_declarations.slice_from(data.normal_declaration_count);
+    data.animating = update_animations(&mut data.animations, &mut style, now, |name| {
+        stylist.get_keyframes(name)
+    }, |keyframe_declarations| {
+        let mut declarations = normal.to_owned();
+        declarations.push(keyframe_declarations.clone());
+        declarations.push_all(important);
+        cascade(declarations.as_slice(), parent_style)
+    });
+    Arc::new(style)
+}
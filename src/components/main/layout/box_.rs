@@ -1119,7 +1119,12 @@ impl Box {
                                     - self.noncontent_inline_right();
                 bounds.size.height = bounds.size.height - self.noncontent_height();
 
-                match image_ref.get().get_image() {
+                // Images shown smaller than they are get scaled down once, instead of on every
+                // paint.
+                // FIXME: This doesn't account for zooming, so zoomed in images are blurry.
+                let size = Size2D(bounds.size.width.to_nearest_px(),
+                                  bounds.size.height.to_nearest_px());
+                match image_ref.get().get_image_at_size(size) {
                     Some(image) => {
                         debug!("(building display list) building image box");
 
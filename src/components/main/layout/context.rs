@@ -8,7 +8,7 @@ use extra::arc::{Arc, MutexArc};
 use green::task::GreenTask;
 use layout::flow::FlowLeafSet;
 use layout::util::OpaqueNode;
-use layout::wrapper::DomLeafSet;
+use layout::wrapper::{AnimatingNodeSet, DomLeafSet};
 use std::cast;
 use std::ptr;
 use std::rt::Runtime;
@@ -57,6 +57,13 @@ pub struct LayoutContext {
 
     /// The root node at which we're starting the layout.
     reflow_root: OpaqueNode,
+
+    /// The time transitions are run up to when styles are cascaded, in seconds.
+    animation_time: f64,
+
+    /// The nodes found to have transitions or animations that still change with time while
+    /// styles are cascaded.
+    animating_nodes: Arc<AnimatingNodeSet>,
 }
 
 impl LayoutContext {
@@ -19,7 +19,8 @@ use layout::parallel::{AssignHeightsAndStoreOverflowTraversalKind, BubbleWidthsT
 use layout::parallel::{UnsafeFlow};
 use layout::parallel;
 use layout::util::{LayoutDataAccess, OpaqueNode, LayoutDataWrapper};
-use layout::wrapper::{DomLeafSet, LayoutNode, TLayoutNode, ThreadSafeLayoutNode};
+use layout::wrapper::{AnimatingNodeSet, DomLeafSet, LayoutNode, TLayoutNode};
+use layout::wrapper::ThreadSafeLayoutNode;
 
 use extra::arc::{Arc, MutexArc};
 use geom::rect::Rect;
@@ -29,20 +30,22 @@ use gfx::display_list::{DisplayList, DisplayListCollection};
 use gfx::font_context::FontContextInfo;
 use gfx::opts::Opts;
 use gfx::render_task::{RenderMsg, RenderChan, RenderLayer};
+use gfx::web_font::WebFontList;
 use gfx::{render_task, color};
-use script::dom::event::ReflowEvent;
+use script::dom::event::{AnimationTickEvent, ReflowEvent};
 use script::dom::node::{ElementNodeTypeId, LayoutDataRef};
 use script::dom::element::{HTMLBodyElementTypeId, HTMLHtmlElementTypeId};
-use script::layout_interface::{AddStylesheetMsg, ContentBoxQuery};
+use script::layout_interface::{AddStylesheetMsg, AnimationDocumentDamage, ContentBoxQuery};
 use script::layout_interface::{ContentBoxesQuery, ContentBoxesResponse, ExitNowMsg, LayoutQuery};
 use script::layout_interface::{HitTestQuery, ContentBoxResponse, HitTestResponse};
 use script::layout_interface::{ContentChangedDocumentDamage, LayoutChan, Msg, PrepareToExitMsg};
 use script::layout_interface::{QueryMsg, ReapLayoutDataMsg, Reflow, ReflowDocumentDamage};
-use script::layout_interface::{ReflowForDisplay, ReflowMsg};
+use script::layout_interface::{ReflowForDisplay, ReflowMsg, StepAnimationClockMsg};
 use script::script_task::{ReflowCompleteMsg, ScriptChan, SendEventMsg};
 use servo_msg::constellation_msg::{ConstellationChan, PipelineId};
-use servo_net::image_cache_task::{ImageCacheTask, ImageResponseMsg};
+use servo_net::image_cache_task::{ImageCacheTask, ImageResponseMsg, PurgeOwner};
 use servo_net::local_image_cache::{ImageResponder, LocalImageCache};
+use servo_net::resource_task::ResourceTask;
 use servo_util::geometry::Au;
 use servo_util::time::{ProfilerChan, profile};
 use servo_util::time;
@@ -51,10 +54,12 @@ use servo_util::workqueue::WorkQueue;
 use std::cast::transmute;
 use std::cast;
 use std::cell::RefCell;
-use std::comm::Port;
+use std::comm::{Chan, Port};
+use std::io::timer;
 use std::ptr;
 use std::util;
-use style::{AuthorOrigin, Stylesheet, Stylist};
+use style::{AnimationClock, AuthorOrigin, Device, Screen, Stylesheet, Stylist, SystemClock};
+use style::{ManualClock, TNode};
 
 /// Information needed by the layout task.
 pub struct LayoutTask {
@@ -82,6 +87,12 @@ pub struct LayoutTask {
     /// The local image cache.
     local_image_cache: MutexArc<LocalImageCache>,
 
+    /// The channel on which web fonts are downloaded.
+    resource_task: ResourceTask,
+
+    /// The fonts of the document's `@font-face` rules.
+    web_fonts: WebFontList,
+
     /// The set of leaves in the DOM tree.
     dom_leaf_set: Arc<DomLeafSet>,
 
@@ -102,6 +113,16 @@ pub struct LayoutTask {
     /// The channel on which messages can be sent to the profiler.
     profiler_chan: ProfilerChan,
 
+    /// The clock transitions and animations are run with.
+    clock: AnimationClock,
+
+    /// The nodes with transitions or animations that still change with time.
+    animating_nodes: Arc<AnimatingNodeSet>,
+
+    /// Signalled once the reflow asked for to move transitions and animations along has been
+    /// sent, if one has been asked for.
+    animation_tick: Option<Port<()>>,
+
     opts: Opts
 }
 
@@ -218,6 +239,21 @@ impl<'a> PostorderFlowTraversal for AssignHeightsAndStoreOverflowTraversal<'a> {
     }
 }
 
+/// The device that media queries are evaluated against.
+fn screen_device(screen_size: Size2D<Au>) -> Device {
+    // FIXME: The compositor knows the hidpi factor, but doesn't tell us yet.
+    Device {
+        media_type: Screen,
+        viewport_width: screen_size.width,
+        viewport_height: screen_size.height,
+        device_pixel_ratio: 1.,
+    }
+}
+
+/// How long to wait between the reflows that move transitions and animations along, in
+/// milliseconds.
+static ANIMATION_TICK_MS: u64 = 16;
+
 struct LayoutImageResponder {
     id: PipelineId,
     script_chan: ScriptChan,
@@ -243,6 +279,7 @@ impl LayoutTask {
                   script_chan: ScriptChan,
                   render_chan: RenderChan<OpaqueNode>,
                   img_cache_task: ImageCacheTask,
+                  resource_task: ResourceTask,
                   opts: Opts,
                   profiler_chan: ProfilerChan,
                   shutdown_chan: Chan<()>) {
@@ -255,6 +292,7 @@ impl LayoutTask {
                                                  script_chan,
                                                  render_chan,
                                                  img_cache_task,
+                                                 resource_task,
                                                  &opts,
                                                  profiler_chan);
                 layout.start();
@@ -271,10 +309,12 @@ impl LayoutTask {
            script_chan: ScriptChan,
            render_chan: RenderChan<OpaqueNode>, 
            image_cache_task: ImageCacheTask,
+           resource_task: ResourceTask,
            opts: &Opts,
            profiler_chan: ProfilerChan)
            -> LayoutTask {
-        let local_image_cache = MutexArc::new(LocalImageCache(image_cache_task.clone()));
+        let PipelineId(owner) = id;
+        let local_image_cache = MutexArc::new(LocalImageCache(image_cache_task.clone(), owner));
         let screen_size = Size2D(Au(0), Au(0));
         let parallel_traversal = if opts.layout_threads != 1 {
             Some(WorkQueue::new(opts.layout_threads, ptr::mut_null()))
@@ -291,6 +331,8 @@ impl LayoutTask {
             render_chan: render_chan,
             image_cache_task: image_cache_task.clone(),
             local_image_cache: local_image_cache,
+            resource_task: resource_task,
+            web_fonts: WebFontList::new(),
             screen_size: screen_size,
             dom_leaf_set: Arc::new(DomLeafSet::new()),
             flow_leaf_set: Arc::new(FlowLeafSet::new()),
@@ -299,6 +341,12 @@ impl LayoutTask {
             stylist: ~new_stylist(),
             parallel_traversal: parallel_traversal,
             profiler_chan: profiler_chan,
+            clock: match opts.animation_time {
+                Some(time) => ManualClock(time),
+                None => SystemClock,
+            },
+            animating_nodes: Arc::new(AnimatingNodeSet::new()),
+            animation_tick: None,
             opts: opts.clone()
         }
     }
@@ -316,6 +364,7 @@ impl LayoutTask {
             backend: self.opts.render_backend,
             needs_font_list: true,
             profiler_chan: self.profiler_chan.clone(),
+            web_fonts: self.web_fonts.clone(),
         };
 
         LayoutContext {
@@ -328,6 +377,8 @@ impl LayoutTask {
             font_context_info: font_context_info,
             stylist: &*self.stylist,
             reflow_root: OpaqueNode::from_layout_node(reflow_root),
+            animation_time: self.clock.now(),
+            animating_nodes: self.animating_nodes.clone(),
         }
     }
 
@@ -346,6 +397,7 @@ impl LayoutTask {
                     self.handle_query(query.take_unwrap());
                 });
             }
+            StepAnimationClockMsg(seconds) => self.step_animation_clock(seconds),
             ReapLayoutDataMsg(dead_layout_data) => {
                 unsafe {
                     self.handle_reap_layout_data(dead_layout_data)
@@ -400,12 +452,34 @@ impl LayoutTask {
             Some(ref mut traversal) => traversal.shutdown(),
         }
 
+        // The images this pipeline displayed may be evicted now.
+        let PipelineId(owner) = self.id;
+        self.image_cache_task.send(PurgeOwner(owner));
+
         self.render_chan.send(render_task::ExitMsg(response_chan));
         response_port.recv()
     }
 
     fn handle_add_stylesheet(&mut self, sheet: Stylesheet) {
-        self.stylist.add_stylesheet(sheet, AuthorOrigin)
+        self.stylist.add_stylesheet(sheet, AuthorOrigin);
+        self.add_web_fonts()
+    }
+
+    /// Adds the faces of the `@font-face` rules that apply on the current device to the
+    /// document's web fonts. Faces that were added before are skipped, so this runs again
+    /// whenever a new device changes which `@media` rules apply.
+    fn add_web_fonts(&self) {
+        self.stylist.iter_font_face_rules(|rule| {
+            let id = self.id.clone();
+            let script_chan = self.script_chan.clone();
+            self.web_fonts.add(rule, self.resource_task.clone(), proc() {
+                script_chan.send(SendEventMsg(id, ReflowEvent))
+            })
+        })
+    }
+
+    fn step_animation_clock(&mut self, seconds: f64) {
+        self.clock.step(seconds)
     }
 
     /// Builds the flow tree.
@@ -538,27 +612,58 @@ impl LayoutTask {
 
         let current_screen_size = Size2D(Au::from_px(data.window_size.width as int),
                                          Au::from_px(data.window_size.height as int));
+        let mut media_queries_changed = false;
         if self.screen_size != current_screen_size {
-            all_style_damage = true
+            all_style_damage = true;
+            media_queries_changed = self.stylist.set_device(screen_device(current_screen_size));
+            if media_queries_changed {
+                self.add_web_fonts()
+            }
         }
         self.screen_size = current_screen_size;
 
         // Create a layout context for use throughout the following passes.
         let mut layout_ctx = self.build_layout_context(node);
 
+        let mut restyled = false;
         let mut layout_root = profile(time::LayoutStyleRecalcCategory,
                                       self.profiler_chan.clone(),
                                       || {
-            // Perform CSS selector matching if necessary.
+            // Perform CSS selector matching if necessary. Resizing only needs it when it changes
+            // which media queries match.
             match data.damage.level {
-                ReflowDocumentDamage => {}
+                ReflowDocumentDamage if !media_queries_changed &&
+                        self.animating_nodes.get().is_empty() => {}
+                AnimationDocumentDamage if !media_queries_changed => {
+                    restyled = true;
+                    profile(time::LayoutSelectorMatchCategory, self.profiler_chan.clone(), || {
+                        self.restyle_animating_nodes(node, layout_ctx.animation_time)
+                    })
+                }
+                // The animating nodes were found before script last ran, and may have been
+                // removed from the document since, so they are found again by cascading the
+                // whole tree.
+                ReflowDocumentDamage if !media_queries_changed => {
+                    restyled = true;
+                    self.animating_nodes.get().clear();
+                    profile(time::LayoutSelectorMatchCategory, self.profiler_chan.clone(), || {
+                        node.cascade_subtree(self.stylist,
+                                             None,
+                                             layout_ctx.animation_time,
+                                             self.animating_nodes.get())
+                    })
+                }
                 _ => {
+                    restyled = true;
+                    self.animating_nodes.get().clear();
                     profile(time::LayoutSelectorMatchCategory, self.profiler_chan.clone(), || {
                         match self.parallel_traversal {
                             None => {
                                 node.match_and_cascade_subtree(self.stylist,
                                                                &layout_ctx.layout_chan,
-                                                               None)
+                                                               None,
+                                                               layout_ctx.animation_time,
+                                                               self.animating_nodes.get())
                             }
                             Some(ref mut traversal) => {
                                 parallel::match_and_cascade_subtree(node,
@@ -576,6 +681,12 @@ impl LayoutTask {
                     || self.construct_flow_tree(&mut layout_ctx, *node))
         });
 
+        // Transitions and animations only move along when styles are cascaded again, so ask for
+        // that until they are all done. A manual clock only moves with `StepAnimationClockMsg`.
+        if restyled && !self.clock.is_manual() && !self.animating_nodes.get().is_empty() {
+            self.schedule_animation_tick();
+        }
+
         // Verification of the flow tree, which ensures that all nodes were either marked as leaves
         // or as non-leaves. This becomes a no-op in release builds. (It is inconsequential to
         // memory safety but is a useful debugging tool.)
@@ -669,6 +780,44 @@ impl LayoutTask {
         data.script_chan.send(ReflowCompleteMsg(self.id, data.id));
     }
 
+    /// Cascades the styles of the nodes with running transitions and animations again, along with
+    /// their descendants, which may inherit from them.
+    fn restyle_animating_nodes(&self, root: &LayoutNode, now: f64) {
+        let root = OpaqueNode::from_layout_node(root);
+        for unsafe_node in self.animating_nodes.get().take_by_depth().move_iter() {
+            let node: LayoutNode = unsafe {
+                cast::transmute(unsafe_node)
+            };
+            if self.animating_nodes.get().contains(&node) {
+                continue
+            }
+            let parent = if OpaqueNode::from_layout_node(&node) == root {
+                None
+            } else {
+                node.parent_node()
+            };
+            node.cascade_subtree(self.stylist, parent, now, self.animating_nodes.get())
+        }
+    }
+
+    /// Asks script to move transitions and animations along a frame from now, unless a previous
+    /// request hasn't been sent yet.
+    fn schedule_animation_tick(&mut self) {
+        match self.animation_tick {
+            Some(ref port) if port.try_recv().is_none() => return,
+            _ => {}
+        }
+        let (port, chan) = Chan::new();
+        self.animation_tick = Some(port);
+        let id = self.id.clone();
+        let script_chan = self.script_chan.clone();
+        spawn_named("LayoutAnimationTick", proc() {
+            timer::sleep(ANIMATION_TICK_MS);
+            script_chan.send(SendEventMsg(id, AnimationTickEvent));
+            chan.send(());
+        });
+    }
+
     /// Handles a query from the script task. This is the main routine that DOM functions like
     /// `getClientRects()` or `getBoundingClientRect()` ultimately invoke.
     fn handle_query(&self, query: LayoutQuery) {
@@ -137,9 +137,9 @@ fn match_and_cascade_node(unsafe_layout_node: UnsafeLayoutNode,
         // parser.
         node.initialize_layout_data(layout_context.layout_chan.clone());
 
+        let stylist: &Stylist = cast::transmute(layout_context.stylist);
         if node.is_element() {
             // Perform the CSS selector matching.
-            let stylist: &Stylist = cast::transmute(layout_context.stylist);
             node.match_node(stylist);
         }
 
@@ -149,7 +149,9 @@ fn match_and_cascade_node(unsafe_layout_node: UnsafeLayoutNode,
         } else {
             node.parent_node()
         };
-        node.cascade_node(parent_opt);
+        if node.cascade_node(parent_opt, stylist, layout_context.animation_time) {
+            layout_context.animating_nodes.get().insert(&node)
+        }
 
         // Enqueue kids.
         let mut child_count = 0;
@@ -18,7 +18,7 @@ use std::cell::{Ref, RefMut};
 use std::iter::Enumerate;
 use std::libc::uintptr_t;
 use std::vec::VecIterator;
-use style::{ComputedValues, PropertyDeclaration};
+use style::{ComputedValues, PropertyDeclaration, PropertyTransition, RunningAnimation};
 
 /// A range of nodes.
 pub struct NodeRange {
@@ -133,6 +133,9 @@ pub struct PrivateLayoutData {
     /// The results of CSS matching for this node.
     applicable_declarations: SmallVec16<Arc<~[PropertyDeclaration]>>,
 
+    /// How many of `applicable_declarations` come before the `!important` ones.
+    normal_declaration_count: uint,
+
     before_applicable_declarations: SmallVec0<Arc<~[PropertyDeclaration]>>,
 
     after_applicable_declarations: SmallVec0<Arc<~[PropertyDeclaration]>>,
@@ -147,6 +150,19 @@ pub struct PrivateLayoutData {
     /// Description of how to account for recent style changes.
     restyle_damage: Option<int>,
 
+    /// The transitions running on this node, which `style` has been updated with.
+    transitions: ~[PropertyTransition],
+
+    /// The animations of this node, which `style` has been updated with after its transitions.
+    animations: ~[RunningAnimation],
+
+    /// Whether any of `animations` still changes with time.
+    animating: bool,
+
+    /// If this node has animations, its style with only its transitions applied. Transitions
+    /// start from this rather than from `style`, so that animations don't start any.
+    unanimated_style: Option<Arc<ComputedValues>>,
+
     /// The current results of flow construction for this node. This is either a flow or a
     /// `ConstructionItem`. See comments in `construct.rs` for more details.
     flow_construction_result: ConstructionResult,
@@ -160,12 +176,17 @@ impl PrivateLayoutData {
     pub fn new() -> PrivateLayoutData {
         PrivateLayoutData {
             applicable_declarations: SmallVec16::new(),
+            normal_declaration_count: 0,
             before_applicable_declarations: SmallVec0::new(),
             after_applicable_declarations: SmallVec0::new(),
             before_style: None,
             style: None,
             after_style: None,
             restyle_damage: None,
+            transitions: ~[],
+            animations: ~[],
+            animating: false,
+            unanimated_style: None,
             flow_construction_result: NoConstructionResult,
             parallel: DomParallelInfo::new(),
         }
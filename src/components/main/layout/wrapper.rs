@@ -535,3 +535,57 @@ impl DomLeafSet {
     }
 }
 
+/// Keeps track of the DOM nodes whose transitions or animations still change with time, so that
+/// moving them along only restyles those nodes and their descendants.
+pub struct AnimatingNodeSet {
+    priv set: ConcurrentHashMap<UnsafeLayoutNode,()>,
+}
+
+impl AnimatingNodeSet {
+    /// Creates a new, empty set.
+    pub fn new() -> AnimatingNodeSet {
+        AnimatingNodeSet {
+            set: ConcurrentHashMap::new(),
+        }
+    }
+
+    /// Inserts a DOM node into the set.
+    pub fn insert(&self, node: &LayoutNode) {
+        self.set.insert(layout_node_to_unsafe_layout_node(node), ());
+    }
+
+    /// Removes all DOM nodes from the set.
+    pub fn clear(&self) {
+        self.set.clear()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.size() == 0
+    }
+
+    /// Whether a DOM node is in the set.
+    pub fn contains(&self, node: &LayoutNode) -> bool {
+        self.set.contains_key(&layout_node_to_unsafe_layout_node(node))
+    }
+
+    /// Removes all DOM nodes from the set, and returns them sorted by their depth in the tree.
+    /// Restyling them in that order, skipping those that restyling an ancestor has put back into
+    /// the set, restyles each of them without restyling every level of a nested one over again.
+    pub fn take_by_depth(&self) -> ~[UnsafeLayoutNode] {
+        let mut nodes: ~[(uint, UnsafeLayoutNode)] = self.set.iter().map(|(&unsafe_node, _)| {
+            let node: LayoutNode = unsafe {
+                cast::transmute(unsafe_node)
+            };
+            let mut depth = 0;
+            let mut ancestor = node.parent_node();
+            while ancestor.is_some() {
+                depth += 1;
+                ancestor = ancestor.unwrap().parent_node();
+            }
+            (depth, unsafe_node)
+        }).collect();
+        self.clear();
+        nodes.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+        nodes.move_iter().map(|(_, unsafe_node)| unsafe_node).collect()
+    }
+}
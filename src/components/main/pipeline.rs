@@ -49,6 +49,7 @@ impl Pipeline {
                        constellation_chan: ConstellationChan,
                        compositor_chan: CompositorChan,
                        image_cache_task: ImageCacheTask,
+                       resource_task: ResourceTask,
                        profiler_chan: ProfilerChan,
                        opts: Opts,
                        script_pipeline: &Pipeline)
@@ -73,6 +74,7 @@ impl Pipeline {
                            script_pipeline.script_chan.clone(),
                            render_chan.clone(),
                            image_cache_task.clone(),
+                           resource_task,
                            opts.clone(),
                            profiler_chan,
                            layout_shutdown_chan);
@@ -125,7 +127,7 @@ impl Pipeline {
                            script_port,
                            script_chan.clone(),
                            constellation_chan.clone(),
-                           resource_task,
+                           resource_task.clone(),
                            image_cache_task.clone(),
                            window_size);
 
@@ -144,6 +146,7 @@ impl Pipeline {
                            script_chan.clone(),
                            render_chan.clone(),
                            image_cache_task,
+                           resource_task,
                            opts.clone(),
                            profiler_chan,
                            layout_shutdown_chan);
@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Serves the browser's built-in `about:` pages.
+
+use resource_task::{Done, Payload, Metadata, LoadData, LoadResponse, LoaderTask, ResourceTask};
+use resource_task::{LoaderFactory, LoaderTaskFactory, LoadError, InvalidUrl, start_sending};
+use util::escape_html;
+
+use extra::url::Url;
+use servo_util::time::{ProfilerChan, ReportMsg};
+
+#[cfg(test)]
+use resource_task::{Exit, load_whole_resource};
+#[cfg(test)]
+use servo_util::time::{Profiler, ExitMsg};
+#[cfg(test)]
+use std::from_str::FromStr;
+#[cfg(test)]
+use std::str;
+
+/// Makes the loaders for `about:` pages. They ask the profiler for its report directly: the
+/// resource task runs them inline, so it can't answer them itself.
+struct AboutLoaderFactory {
+    profiler_chan: ProfilerChan,
+}
+
+impl LoaderFactory for AboutLoaderFactory {
+    fn create(&self, _resource_task: ResourceTask) -> LoaderTask {
+        let profiler_chan = self.profiler_chan.clone();
+        proc(load_data, start_chan) {
+            // NB: we don't spawn a new task; these pages are tiny and built from data we
+            // already have, apart from a round trip to the profiler.
+            load(load_data, start_chan, &profiler_chan)
+        }
+    }
+}
+
+pub fn factory(profiler_chan: ProfilerChan) -> LoaderTaskFactory {
+    ~AboutLoaderFactory {
+        profiler_chan: profiler_chan,
+    } as LoaderTaskFactory
+}
+
+fn load(load_data: LoadData, start_chan: Chan<LoadResponse>, profiler_chan: &ProfilerChan) {
+    let url = load_data.url;
+    assert!("about" == url.scheme);
+
+    let page = match url.path.trim_left_chars(&'/') {
+        "blank" => ~"",
+        "failure" => failure_page(&url),
+        "memory" | "profile" => profile_page(profiler_chan),
+        _ => {
+            debug!("about_loader: unknown page {:s}", url.to_str());
+            let error = InvalidUrl(format!("{:s} is not a known about: page", url.to_str()));
+            start_sending(start_chan, Metadata::default(url)).send(Done(Err(error)));
+            return;
+        }
+    };
+
+    let mut metadata = Metadata::default(url);
+    metadata.content_type = Some((~"text", ~"html"));
+    metadata.charset = Some(~"utf-8");
+    let progress_chan = start_sending(start_chan, metadata);
+    if !page.is_empty() {
+        progress_chan.send(Payload(page.into_bytes()));
+    }
+    progress_chan.send(Done(Ok(())));
+}
+
+/// The page shown in place of a pipeline that failed. The URL that failed to load and the
+/// reason are passed in the `url` and `reason` query parameters.
+fn failure_page(url: &Url) -> ~str {
+    let param = |name: &str| {
+        url.query.iter().find(|&&(ref key, _)| name == key.as_slice())
+                        .map_default(~"", |&(_, ref value)| escape_html(value.as_slice()))
+    };
+    format!("<html>\n<head><title>about:failure</title></head>\n<body>\n\
+             <h1>Failed to load page</h1>\n\
+             <p>URL: <code>{:s}</code></p>\n\
+             <p>Reason: {:s}</p>\n\
+             </body>\n</html>\n",
+            param("url"), param("reason"))
+}
+
+/// A snapshot of the time profiler's buckets.
+fn profile_page(profiler_chan: &ProfilerChan) -> ~str {
+    let (report_port, report_chan) = Chan::new();
+    profiler_chan.send(ReportMsg(report_chan));
+    let report = match report_port.recv_opt() {
+        Some(Some(report)) => format!("<pre>{:s}</pre>", escape_html(report.as_slice())),
+        Some(None) | None => {
+            ~"<p>The profiler is not running. Start servo with <code>-p</code> to enable it.</p>"
+        }
+    };
+    format!("<html>\n<head><title>about:profile</title></head>\n<body>\n\
+             <h1>Profile</h1>\n{:s}\n</body>\n</html>\n",
+            report)
+}
+
+#[cfg(test)]
+fn load_page(url: &str) -> (Metadata, ~str, Result<(), LoadError>) {
+    let (start_port, start_chan) = Chan::new();
+    let url: Url = FromStr::from_str(url).unwrap();
+    load(LoadData::new(url), start_chan, &Profiler::create(None));
+
+    let response = start_port.recv();
+    let mut body = ~[];
+    loop {
+        match response.progress_port.recv() {
+            Payload(data) => body.push_all(data),
+            Done(result) => return (response.metadata, str::from_utf8_owned(body), result),
+        }
+    }
+}
+
+#[test]
+fn test_blank() {
+    let (metadata, body, result) = load_page("about:blank");
+    assert!(result.is_ok());
+    assert_eq!(metadata.content_type, Some((~"text", ~"html")));
+    assert!(body.is_empty());
+}
+
+#[test]
+fn test_failure() {
+    let (_, body, result) = load_page(
+        "about:failure?url=http%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2&reason=%3Cboom%3E");
+    assert!(result.is_ok());
+    assert!(body.contains("http://example.com/?a=1&amp;b=2"));
+    assert!(body.contains("&lt;boom&gt;"));
+}
+
+#[test]
+fn test_profile_without_profiler() {
+    let (_, body, result) = load_page("about:profile");
+    assert!(result.is_ok());
+    assert!(body.contains("not running"));
+}
+
+#[test]
+fn test_unknown_page() {
+    let (_, _, result) = load_page("about:nonsense");
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_profile_through_resource_task() {
+    let profiler_chan = Profiler::create(Some(0.01));
+    let resource_task = ResourceTask(profiler_chan.clone(), None, None, None);
+    for page in ["about:profile", "about:memory"].iter() {
+        let url = FromStr::from_str(*page).unwrap();
+        match load_whole_resource(&resource_task, url) {
+            Ok((_, body)) => assert!(str::from_utf8_owned(body).contains("<pre>")),
+            Err(..) => fail!("{:s} failed to load", *page),
+        }
+    }
+    resource_task.send(Exit);
+    profiler_chan.send(ExitMsg);
+}
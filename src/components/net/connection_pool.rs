@@ -0,0 +1,283 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Keep-alive reuse of HTTP connections.
+//!
+//! The resource task owns a `ConnectionPool` of idle connections. `http_loader` makes its
+//! requests over `PooledStream`s: when rust-http asks one to connect it takes an idle connection
+//! to the same address from the pool if there is one, and when it is dropped it goes back into
+//! the pool if the loader has said that the exchange on it finished cleanly.
+
+use resource_task::{ResourceTask, CheckoutConnection, ReturnConnection};
+
+use http::connecter::Connecter;
+use servo_util::io::result;
+use std::hashmap::HashMap;
+use std::io::{IoError, Reader, Writer};
+use std::io::net::ip::SocketAddr;
+use std::io::net::tcp::TcpStream;
+use std::local_data;
+
+/// How long an idle connection is kept. Servers typically close keep-alive connections after
+/// somewhere between five seconds and a minute, so this errs on the short side.
+static IDLE_TIMEOUT_NS: u64 = 15 * 1000000000;
+
+/// The most idle connections kept for one address.
+static MAX_IDLE_PER_ADDRESS: uint = 6;
+
+/// Idle connections, keyed by the address they are connected to.
+pub struct ConnectionPool {
+    priv idle: HashMap<~str, ~[(TcpStream, u64)]>,
+}
+
+impl ConnectionPool {
+    pub fn new() -> ConnectionPool {
+        ConnectionPool {
+            idle: HashMap::new(),
+        }
+    }
+
+    /// Takes the most recently used connection to `address`, if one hasn't timed out.
+    pub fn checkout(&mut self, address: &~str, now: u64) -> Option<TcpStream> {
+        self.expire(now);
+        match self.idle.find_mut(address) {
+            Some(streams) => streams.pop_opt().map(|(stream, _)| stream),
+            None => None,
+        }
+    }
+
+    /// Keeps an idle connection to `address` for later requests.
+    pub fn checkin(&mut self, address: ~str, stream: TcpStream, now: u64) {
+        self.expire(now);
+        let streams = self.idle.find_or_insert(address, ~[]);
+        if streams.len() < MAX_IDLE_PER_ADDRESS {
+            streams.push((stream, now));
+        }
+    }
+
+    /// The number of idle connections to `address`.
+    pub fn idle_count(&self, address: &~str) -> uint {
+        self.idle.find(address).map_default(0, |streams| streams.len())
+    }
+
+    /// Closes connections that have been idle for too long.
+    fn expire(&mut self, now: u64) {
+        for (_, streams) in self.idle.mut_iter() {
+            streams.retain(|&(_, idle_since)| now - idle_since < IDLE_TIMEOUT_NS);
+        }
+    }
+}
+
+/// What the `PooledStream` of the current exchange needs to know. rust-http creates the stream
+/// itself, so this is passed through task-local data.
+struct ExchangeState {
+    resource_task: ResourceTask,
+    /// Whether the stream came from the pool rather than a new connection.
+    reused: bool,
+    /// Whether the stream can go back into the pool when it is dropped.
+    reusable: bool,
+    /// Whether a read or write on the stream failed.
+    broken: bool,
+}
+
+local_data_key!(exchange_state: ExchangeState)
+
+/// Starts a request/response exchange on this task. The next `PooledStream` to connect uses
+/// `resource_task`'s pool.
+pub fn start_exchange(resource_task: ResourceTask) {
+    local_data::set(exchange_state, ExchangeState {
+        resource_task: resource_task,
+        reused: false,
+        reusable: false,
+        broken: false,
+    });
+}
+
+/// Whether the current exchange is running over a connection taken from the pool. Such a
+/// connection may have been closed by the server while it sat idle.
+pub fn connection_was_reused() -> bool {
+    local_data::get(exchange_state, |state| state.map_default(false, |state| state.reused))
+}
+
+/// Whether a read or write failed during the current exchange. Streams report such failures as
+/// the end of the data, so this is how a body that was cut off by one is told from a whole one.
+pub fn connection_broke() -> bool {
+    local_data::get(exchange_state, |state| state.map_default(false, |state| state.broken))
+}
+
+/// Records whether the response was read to its end and the server will keep the connection
+/// open. This must be called before the response is dropped.
+pub fn finish_exchange(reusable: bool) {
+    local_data::get_mut(exchange_state, |state| {
+        match state {
+            Some(state) => state.reusable = reusable,
+            None => (),
+        }
+    });
+}
+
+/// A connection that returns to the pool when it is dropped, if it can be reused.
+pub struct PooledStream {
+    priv stream: Option<TcpStream>,
+    priv address: ~str,
+    /// Whether a read or write failed. Errors end the data instead of failing the task, and
+    /// the stream never goes back into the pool after one.
+    priv broken: bool,
+}
+
+impl PooledStream {
+    fn record_error(&mut self, error: IoError) {
+        debug!("connection_pool: connection to {:s} broke: {:s}", self.address,
+               error.to_str());
+        self.broken = true;
+        local_data::get_mut(exchange_state, |state| {
+            match state {
+                Some(state) => state.broken = true,
+                None => (),
+            }
+        });
+    }
+}
+
+impl Connecter for PooledStream {
+    fn connect(addr: SocketAddr) -> Option<PooledStream> {
+        let address = addr.to_str();
+        let resource_task = local_data::get(exchange_state, |state| {
+            state.map(|state| state.resource_task.clone())
+        });
+        let pooled = resource_task.and_then(|resource_task| {
+            let (stream_port, stream_chan) = Chan::new();
+            resource_task.send(CheckoutConnection(address.clone(), stream_chan));
+            stream_port.recv()
+        });
+
+        match pooled {
+            Some(stream) => {
+                debug!("connection_pool: reusing connection to {:s}", address);
+                local_data::get_mut(exchange_state, |state| {
+                    match state {
+                        Some(state) => state.reused = true,
+                        None => (),
+                    }
+                });
+                Some(PooledStream { stream: Some(stream), address: address, broken: false })
+            }
+            None => {
+                TcpStream::connect(addr).map(|stream| {
+                    PooledStream { stream: Some(stream), address: address, broken: false }
+                })
+            }
+        }
+    }
+}
+
+impl Reader for PooledStream {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        let read = {
+            let stream = self.stream.get_mut_ref();
+            result(|| stream.read(buf))
+        };
+        match read {
+            Ok(read) => read,
+            Err(error) => {
+                self.record_error(error);
+                None
+            }
+        }
+    }
+}
+
+impl Writer for PooledStream {
+    fn write(&mut self, buf: &[u8]) {
+        let written = {
+            let stream = self.stream.get_mut_ref();
+            result(|| stream.write(buf))
+        };
+        match written {
+            Ok(()) => (),
+            Err(error) => self.record_error(error),
+        }
+    }
+
+    fn flush(&mut self) {
+        let flushed = {
+            let stream = self.stream.get_mut_ref();
+            result(|| stream.flush())
+        };
+        match flushed {
+            Ok(()) => (),
+            Err(error) => self.record_error(error),
+        }
+    }
+}
+
+impl Drop for PooledStream {
+    fn drop(&mut self) {
+        if self.broken {
+            return
+        }
+        let resource_task = local_data::get(exchange_state, |state| {
+            state.and_then(|state| {
+                if state.reusable {
+                    Some(state.resource_task.clone())
+                } else {
+                    None
+                }
+            })
+        });
+        match resource_task {
+            Some(resource_task) => {
+                debug!("connection_pool: keeping connection to {:s}", self.address);
+                resource_task.try_send(ReturnConnection(self.address.clone(),
+                                                        self.stream.take_unwrap()));
+            }
+            None => (),
+        }
+    }
+}
+
+#[cfg(test)]
+fn connected_pair() -> TcpStream {
+    use std::io::net::tcp::TcpListener;
+    use std::io::{Acceptor, Listener};
+
+    let mut listener = TcpListener::bind(from_str("127.0.0.1:0").unwrap()).unwrap();
+    let addr = listener.socket_name().unwrap();
+    let mut acceptor = listener.listen().unwrap();
+    let stream = TcpStream::connect(addr).unwrap();
+    acceptor.accept().unwrap();
+    stream
+}
+
+#[test]
+fn test_checkout_takes_idle_connection() {
+    let mut pool = ConnectionPool::new();
+    let address = ~"127.0.0.1:80";
+    assert!(pool.checkout(&address, 0).is_none());
+
+    pool.checkin(address.clone(), connected_pair(), 0);
+    assert_eq!(pool.idle_count(&address), 1);
+    assert!(pool.checkout(&~"127.0.0.1:8080", 1).is_none());
+    assert!(pool.checkout(&address, 1).is_some());
+    assert_eq!(pool.idle_count(&address), 0);
+}
+
+#[test]
+fn test_idle_timeout() {
+    let mut pool = ConnectionPool::new();
+    let address = ~"127.0.0.1:80";
+    pool.checkin(address.clone(), connected_pair(), 0);
+    assert!(pool.checkout(&address, IDLE_TIMEOUT_NS).is_none());
+    assert_eq!(pool.idle_count(&address), 0);
+}
+
+#[test]
+fn test_idle_limit() {
+    let mut pool = ConnectionPool::new();
+    let address = ~"127.0.0.1:80";
+    for _ in range(0, MAX_IDLE_PER_ADDRESS + 1) {
+        pool.checkin(address.clone(), connected_pair(), 0);
+    }
+    assert_eq!(pool.idle_count(&address), MAX_IDLE_PER_ADDRESS);
+}
@@ -0,0 +1,266 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Streaming decompression of `Content-Encoding: gzip` and `deflate` response bodies, using
+//! zlib.
+
+use std::ascii::StrAsciiExt;
+use std::c_str::ToCStr;
+use std::libc::{c_char, c_int, c_uint, c_ulong, c_void};
+use std::mem;
+use std::ptr;
+
+static CHUNK_SIZE: uint = 16384;
+
+static Z_OK: c_int = 0;
+static Z_STREAM_END: c_int = 1;
+static Z_BUF_ERROR: c_int = -5;
+static Z_NO_FLUSH: c_int = 0;
+
+/// `windowBits` values for `inflateInit2`.
+static GZIP_WINDOW_BITS: c_int = 15 + 16;
+static ZLIB_WINDOW_BITS: c_int = 15;
+static RAW_DEFLATE_WINDOW_BITS: c_int = -15;
+
+/// Mirrors zlib's `z_stream`.
+struct ZStream {
+    next_in: *u8,
+    avail_in: c_uint,
+    total_in: c_ulong,
+    next_out: *mut u8,
+    avail_out: c_uint,
+    total_out: c_ulong,
+    msg: *c_char,
+    state: *c_void,
+    zalloc: *c_void,
+    zfree: *c_void,
+    opaque: *c_void,
+    data_type: c_int,
+    adler: c_ulong,
+    reserved: c_ulong,
+}
+
+#[link(name = "z")]
+extern {
+    fn inflateInit2_(strm: *mut ZStream, window_bits: c_int, version: *c_char,
+                     stream_size: c_int) -> c_int;
+    fn inflate(strm: *mut ZStream, flush: c_int) -> c_int;
+    fn inflateEnd(strm: *mut ZStream) -> c_int;
+}
+
+/// The content codings we can decode, and advertise in `Accept-Encoding`.
+#[deriving(Eq, Clone)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Parses a `Content-Encoding` header value, ignoring case. `Some(None)` means no decoding
+    /// is needed; `None` means the coding is not supported.
+    pub fn parse(value: &str) -> Option<Option<ContentEncoding>> {
+        let value = value.trim();
+        if value.is_empty() || value.eq_ignore_ascii_case("identity") {
+            Some(None)
+        } else if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+            Some(Some(Gzip))
+        } else if value.eq_ignore_ascii_case("deflate") {
+            Some(Some(Deflate))
+        } else {
+            None
+        }
+    }
+}
+
+/// The `Accept-Encoding` request header value matching what `Decoder` supports.
+pub static ACCEPT_ENCODING: &'static str = "gzip, deflate";
+
+/// Incrementally decompresses a body that arrives in arbitrary chunks.
+pub struct Decoder {
+    priv encoding: ContentEncoding,
+    /// Boxed because zlib keeps a pointer back to the stream.
+    priv stream: ~ZStream,
+    priv initialized: bool,
+    priv finished: bool,
+}
+
+impl Decoder {
+    pub fn new(encoding: ContentEncoding) -> Decoder {
+        Decoder {
+            encoding: encoding,
+            stream: ~ZStream {
+                next_in: ptr::null(),
+                avail_in: 0,
+                total_in: 0,
+                next_out: ptr::mut_null(),
+                avail_out: 0,
+                total_out: 0,
+                msg: ptr::null(),
+                state: ptr::null(),
+                zalloc: ptr::null(),
+                zfree: ptr::null(),
+                opaque: ptr::null(),
+                data_type: 0,
+                adler: 0,
+                reserved: 0,
+            },
+            initialized: false,
+            finished: false,
+        }
+    }
+
+    /// Initializes zlib on the first chunk of input. Servers disagree on whether `deflate`
+    /// means a zlib stream or a raw deflate stream, so we look for a zlib header.
+    fn initialize(&mut self, first_chunk: &[u8]) -> Result<(), ()> {
+        let window_bits = match self.encoding {
+            Gzip => GZIP_WINDOW_BITS,
+            Deflate if first_chunk.len() < 2 || is_zlib_header(first_chunk[0], first_chunk[1]) => {
+                ZLIB_WINDOW_BITS
+            }
+            Deflate => RAW_DEFLATE_WINDOW_BITS,
+        };
+        let result = "1.2.3".with_c_str(|version| unsafe {
+            inflateInit2_(&mut *self.stream, window_bits, version,
+                          mem::size_of::<ZStream>() as c_int)
+        });
+        if result != Z_OK {
+            return Err(());
+        }
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// Decompresses the next chunk of the body, returning whatever output is available.
+    pub fn decode(&mut self, input: &[u8]) -> Result<~[u8], ()> {
+        if self.finished || input.is_empty() {
+            return Ok(~[]);
+        }
+        if !self.initialized {
+            match self.initialize(input) {
+                Ok(()) => (),
+                Err(()) => return Err(()),
+            }
+        }
+
+        let mut output = ~[];
+        let mut buf = [0u8, ..CHUNK_SIZE];
+        self.stream.next_in = input.as_ptr();
+        self.stream.avail_in = input.len() as c_uint;
+        loop {
+            self.stream.next_out = buf.as_mut_ptr();
+            self.stream.avail_out = CHUNK_SIZE as c_uint;
+            let result = unsafe { inflate(&mut *self.stream, Z_NO_FLUSH) };
+            let produced = CHUNK_SIZE - self.stream.avail_out as uint;
+            output.push_all(buf.slice_to(produced));
+
+            match result {
+                Z_STREAM_END => {
+                    self.finished = true;
+                    break;
+                }
+                // Z_BUF_ERROR just means no progress was possible without more input.
+                Z_OK | Z_BUF_ERROR => {
+                    if self.stream.avail_in == 0 && self.stream.avail_out != 0 {
+                        break;
+                    }
+                }
+                _ => {
+                    debug!("content_encoding: inflate failed with {}", result);
+                    return Err(());
+                }
+            }
+        }
+        self.stream.next_in = ptr::null();
+        Ok(output)
+    }
+
+    /// Whether the compressed stream has ended. A body that stops before this point has been
+    /// truncated.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        if self.initialized {
+            unsafe {
+                inflateEnd(&mut *self.stream);
+            }
+        }
+    }
+}
+
+fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    (cmf & 0x0f) == 8 && ((cmf as u16 << 8) | flg as u16) % 31 == 0
+}
+
+#[cfg(test)]
+pub fn test_plain_body() -> ~str {
+    format!("<html><body>{:s}</body></html>", "Hello, compressed world! ".repeat(20))
+}
+
+#[cfg(test)]
+pub static test_gzip_body: [u8, ..70] = [
+    0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xb3, 0xc9, 0x28, 0xc9,
+    0xcd, 0xb1, 0xb3, 0x49, 0xca, 0x4f, 0xa9, 0xb4, 0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7,
+    0x51, 0x48, 0xce, 0xcf, 0x2d, 0x28, 0x4a, 0x2d, 0x2e, 0x4e, 0x4d, 0x51, 0x28, 0xcf,
+    0x2f, 0xca, 0x49, 0x51, 0x54, 0x18, 0x95, 0x18, 0xb6, 0x12, 0x36, 0xfa, 0xe0, 0xa8,
+    0xb7, 0xd1, 0x07, 0xa7, 0x03, 0x00, 0x9d, 0xb6, 0xce, 0x55, 0x0e, 0x02, 0x00, 0x00,
+];
+
+#[cfg(test)]
+pub static test_zlib_body: [u8, ..58] = [
+    0x78, 0x9c, 0xb3, 0xc9, 0x28, 0xc9, 0xcd, 0xb1, 0xb3, 0x49, 0xca, 0x4f, 0xa9, 0xb4,
+    0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x48, 0xce, 0xcf, 0x2d, 0x28, 0x4a, 0x2d,
+    0x2e, 0x4e, 0x4d, 0x51, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x51, 0x54, 0x18, 0x95, 0x18,
+    0xb6, 0x12, 0x36, 0xfa, 0xe0, 0xa8, 0xb7, 0xd1, 0x07, 0xa7, 0x03, 0x00, 0x08, 0xde,
+    0xbc, 0xe5,
+];
+
+#[cfg(test)]
+fn decode_in_chunks(encoding: ContentEncoding, body: &[u8], chunk_size: uint) -> ~[u8] {
+    let mut decoder = Decoder::new(encoding);
+    let mut output = ~[];
+    for chunk in body.chunks(chunk_size) {
+        output.push_all(decoder.decode(chunk).unwrap());
+    }
+    assert!(decoder.is_finished());
+    output
+}
+
+#[test]
+fn test_gzip() {
+    for &chunk_size in [1u, 7, 64].iter() {
+        let output = decode_in_chunks(Gzip, test_gzip_body.as_slice(), chunk_size);
+        assert_eq!(output.as_slice(), test_plain_body().as_bytes());
+    }
+}
+
+#[test]
+fn test_deflate_zlib_and_raw() {
+    let output = decode_in_chunks(Deflate, test_zlib_body.as_slice(), 5);
+    assert_eq!(output.as_slice(), test_plain_body().as_bytes());
+
+    // The same stream without its two byte header and four byte checksum.
+    let raw = test_zlib_body.slice(2, test_zlib_body.len() - 4);
+    let output = decode_in_chunks(Deflate, raw, 5);
+    assert_eq!(output.as_slice(), test_plain_body().as_bytes());
+}
+
+#[test]
+fn test_corrupt() {
+    let mut decoder = Decoder::new(Gzip);
+    assert!(decoder.decode(bytes!("definitely not gzip")).is_err());
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(ContentEncoding::parse("gzip"), Some(Some(Gzip)));
+    assert_eq!(ContentEncoding::parse(" deflate "), Some(Some(Deflate)));
+    assert_eq!(ContentEncoding::parse("\tGZip "), Some(Some(Gzip)));
+    assert_eq!(ContentEncoding::parse("Identity"), Some(None));
+    assert_eq!(ContentEncoding::parse("identity"), Some(None));
+    assert_eq!(ContentEncoding::parse("br"), None);
+}
@@ -0,0 +1,366 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Cookie parsing, storage and matching, as described in RFC 6265.
+
+use util::parse_http_date;
+
+use std::ascii::StrAsciiExt;
+use std::from_str::from_str;
+use extra::time;
+use extra::url::Url;
+
+/// Registry-controlled suffixes under which anyone can register a domain, so that a cookie may
+/// not be set for the whole suffix. Every top-level domain is one; these are the common public
+/// suffixes with more than one label, from http://publicsuffix.org/.
+// FIXME: Generate this from the full public suffix list.
+static PUBLIC_SUFFIXES: &'static [&'static str] = &[
+    "ac.uk", "co.uk", "gov.uk", "ltd.uk", "me.uk", "net.uk", "org.uk", "plc.uk", "sch.uk",
+    "com.au", "edu.au", "gov.au", "net.au", "org.au", "id.au",
+    "co.jp", "ne.jp", "or.jp", "ac.jp", "go.jp", "gr.jp",
+    "co.nz", "net.nz", "org.nz", "govt.nz", "ac.nz",
+    "com.br", "net.br", "org.br", "gov.br",
+    "com.cn", "net.cn", "org.cn", "gov.cn", "edu.cn",
+    "co.in", "net.in", "org.in", "gov.in", "ac.in",
+    "co.kr", "or.kr", "ne.kr", "go.kr", "ac.kr",
+    "com.mx", "org.mx", "gob.mx",
+    "co.za", "org.za", "gov.za",
+    "com.tw", "org.tw", "net.tw", "gov.tw",
+    "com.hk", "org.hk", "net.hk", "gov.hk",
+    "com.sg", "org.sg", "net.sg", "gov.sg",
+    "com.tr", "org.tr", "net.tr", "gov.tr",
+    "com.ar", "org.ar", "net.ar", "gov.ar",
+    "com.ru", "org.ru", "net.ru",
+    "appspot.com", "blogspot.com", "github.io", "herokuapp.com", "cloudfront.net",
+];
+
+/// Where a cookie is being set or read from. Script must not see or replace `HttpOnly` cookies.
+#[deriving(Eq, Clone)]
+pub enum CookieSource {
+    /// An HTTP API, i.e. `Set-Cookie` and `Cookie` headers.
+    HTTP,
+    /// A non-HTTP API, such as `document.cookie`.
+    NonHTTP,
+}
+
+/// A single cookie, with its attributes resolved against the URL that set it.
+#[deriving(Clone)]
+pub struct Cookie {
+    name: ~str,
+    value: ~str,
+    /// The lowercased domain, without any leading dot.
+    domain: ~str,
+    /// True if no `Domain` attribute was given, so the cookie only matches the exact host.
+    host_only: bool,
+    path: ~str,
+    /// Expiry time in seconds since the epoch, or `None` for a session cookie.
+    expiry_time: Option<i64>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` header value received from `request_url`. Returns `None` if the
+    /// header is malformed or the cookie may not be set by that URL.
+    pub fn parse(header: &str, request_url: &Url, source: CookieSource) -> Option<Cookie> {
+        if header.chars().any(|c| c == '\r' || c == '\n' || c == '\x00') {
+            return None;
+        }
+        let mut attributes = header.split(';');
+        let (name, value) = match attributes.next() {
+            Some(pair) => match pair.find('=') {
+                Some(index) => (pair.slice_to(index).trim(), pair.slice_from(index + 1).trim()),
+                None => return None,
+            },
+            None => return None,
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        let request_host = request_url.host.to_ascii_lower();
+        let mut cookie = Cookie {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            domain: request_host.clone(),
+            host_only: true,
+            path: default_path(request_url.path.as_slice()),
+            expiry_time: None,
+            secure: false,
+            http_only: false,
+        };
+
+        let mut max_age = None;
+        let mut expires = None;
+        for attribute in attributes {
+            let (key, value) = match attribute.find('=') {
+                Some(index) => (attribute.slice_to(index).trim(),
+                                attribute.slice_from(index + 1).trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lower().as_slice() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_left_chars(&'.').to_ascii_lower();
+                    if is_public_suffix(domain.as_slice()) {
+                        // A public suffix may only name the request host itself, in which case
+                        // the cookie stays host-only.
+                        if domain != request_host {
+                            debug!("cookie: rejecting {:s} for public suffix {:s} from {:s}",
+                                   cookie.name, domain, request_host);
+                            return None;
+                        }
+                        continue;
+                    }
+                    if !domain_match(request_host.as_slice(), domain.as_slice()) {
+                        debug!("cookie: rejecting {:s} for domain {:s} from {:s}",
+                               cookie.name, domain, request_host);
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with("/") => cookie.path = value.to_owned(),
+                "max-age" => max_age = from_str::<i64>(value),
+                "expires" => expires = parse_http_date(value),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => (),
+            }
+        }
+
+        // Max-Age takes precedence over Expires.
+        cookie.expiry_time = match (max_age, expires) {
+            (Some(seconds), _) => Some(time::get_time().sec + seconds),
+            (None, expires) => expires,
+        };
+
+        if cookie.http_only && source == NonHTTP {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expiry_time {
+            Some(expiry) => expiry <= time::get_time().sec,
+            None => false,
+        }
+    }
+
+    /// Whether this cookie should be sent with a request to `url`.
+    pub fn appropriate_for_url(&self, url: &Url, source: CookieSource) -> bool {
+        let host = url.host.to_ascii_lower();
+        if self.host_only {
+            if self.domain != host {
+                return false;
+            }
+        } else if !domain_match(host.as_slice(), self.domain.as_slice()) {
+            return false;
+        }
+        if !path_match(url.path.as_slice(), self.path.as_slice()) {
+            return false;
+        }
+        if self.secure && url.scheme.as_slice() != "https" {
+            return false;
+        }
+        if self.http_only && source == NonHTTP {
+            return false;
+        }
+        true
+    }
+
+    /// Whether `other` would replace this cookie when stored.
+    fn same_identity(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// The set of cookies known to a resource task, shared by every pipeline that uses it.
+pub struct CookieStorage {
+    priv cookies: ~[Cookie],
+}
+
+impl CookieStorage {
+    pub fn new() -> CookieStorage {
+        CookieStorage {
+            cookies: ~[],
+        }
+    }
+
+    /// Stores `cookie`, replacing any cookie with the same name, domain and path. An already
+    /// expired cookie just removes the one it replaces.
+    pub fn push(&mut self, cookie: Cookie, source: CookieSource) {
+        match self.cookies.iter().position(|c| c.same_identity(&cookie)) {
+            Some(index) => {
+                if self.cookies[index].http_only && source == NonHTTP {
+                    return;
+                }
+                self.cookies.remove(index);
+            }
+            None => (),
+        }
+        if !cookie.is_expired() {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// Stores the cookies set from `url`. A `Set-Cookie` value may hold several cookies, one
+    /// per line, but a `document.cookie` assignment always sets exactly one, so a value with
+    /// line breaks in it is rejected rather than split.
+    pub fn set_cookies_for_url(&mut self, url: &Url, header: &str, source: CookieSource) {
+        match source {
+            HTTP => {
+                for line in header.lines() {
+                    self.set_cookie_for_url(url, line, source)
+                }
+            }
+            NonHTTP => self.set_cookie_for_url(url, header, source),
+        }
+    }
+
+    fn set_cookie_for_url(&mut self, url: &Url, header: &str, source: CookieSource) {
+        match Cookie::parse(header, url, source) {
+            Some(cookie) => self.push(cookie, source),
+            None => (),
+        }
+    }
+
+    /// Returns the serialized `name=value` pairs of every cookie that matches `url`, longest
+    /// paths first, or `None` if there are none.
+    pub fn cookies_for_url(&mut self, url: &Url, source: CookieSource) -> Option<~str> {
+        self.cookies.retain(|cookie| !cookie.is_expired());
+
+        let mut matching: ~[&Cookie] = self.cookies.iter()
+            .filter(|cookie| cookie.appropriate_for_url(url, source))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+
+        let pairs: ~[~str] = matching.iter().map(|cookie| {
+            format!("{:s}={:s}", cookie.name, cookie.value)
+        }).collect();
+        Some(pairs.connect("; "))
+    }
+}
+
+/// The default cookie path: the request path up to, but not including, its last `/`.
+fn default_path(request_path: &str) -> ~str {
+    if !request_path.starts_with("/") {
+        return ~"/";
+    }
+    match request_path.rfind('/') {
+        Some(0) | None => ~"/",
+        Some(index) => request_path.slice_to(index).to_owned(),
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain ||
+        (host.ends_with(domain) &&
+         host.len() > domain.len() &&
+         host.char_at(host.len() - domain.len() - 1) == '.')
+}
+
+fn is_public_suffix(domain: &str) -> bool {
+    !domain.contains_char('.') || PUBLIC_SUFFIXES.iter().any(|&suffix| suffix == domain)
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    let request_path = if request_path.is_empty() { "/" } else { request_path };
+    request_path == cookie_path ||
+        (request_path.starts_with(cookie_path) &&
+         (cookie_path.ends_with("/") || request_path.char_at(cookie_path.len()) == '/'))
+}
+
+#[cfg(test)]
+fn url(s: &str) -> Url {
+    from_str(s).unwrap()
+}
+
+#[test]
+fn test_default_path() {
+    assert_eq!(default_path(""), ~"/");
+    assert_eq!(default_path("/"), ~"/");
+    assert_eq!(default_path("/foo"), ~"/");
+    assert_eq!(default_path("/foo/bar"), ~"/foo");
+    assert_eq!(default_path("/foo/bar/"), ~"/foo/bar");
+}
+
+#[test]
+fn test_domain_and_path_match() {
+    assert!(domain_match("example.com", "example.com"));
+    assert!(domain_match("www.example.com", "example.com"));
+    assert!(!domain_match("badexample.com", "example.com"));
+    assert!(path_match("/foo/bar", "/foo"));
+    assert!(path_match("/foo/bar", "/foo/"));
+    assert!(!path_match("/foobar", "/foo"));
+}
+
+#[test]
+fn test_parse_attributes() {
+    let cookie = Cookie::parse("SID=31d4d96e; Path=/; Domain=.example.com; Secure; HttpOnly",
+                               &url("http://www.example.com/login"), HTTP).unwrap();
+    assert_eq!(cookie.name, ~"SID");
+    assert_eq!(cookie.value, ~"31d4d96e");
+    assert_eq!(cookie.domain, ~"example.com");
+    assert!(!cookie.host_only);
+    assert_eq!(cookie.path, ~"/");
+    assert!(cookie.secure);
+    assert!(cookie.http_only);
+    assert!(cookie.expiry_time.is_none());
+}
+
+#[test]
+fn test_reject_foreign_domain_and_script_http_only() {
+    let u = url("http://example.com/");
+    assert!(Cookie::parse("a=b; Domain=other.com", &u, HTTP).is_none());
+    assert!(Cookie::parse("a=b; HttpOnly", &u, NonHTTP).is_none());
+    assert!(Cookie::parse("novalue", &u, HTTP).is_none());
+}
+
+#[test]
+fn test_public_suffix_domains() {
+    assert!(Cookie::parse("a=b; Domain=com", &url("http://example.com/"), HTTP).is_none());
+    assert!(Cookie::parse("a=b; Domain=.co.uk", &url("http://www.bbc.co.uk/"), HTTP).is_none());
+    assert!(Cookie::parse("a=b; Domain=github.io", &url("http://a.github.io/"), HTTP).is_none());
+    assert!(Cookie::parse("a=b; Domain=bbc.co.uk", &url("http://www.bbc.co.uk/"), HTTP).is_some());
+
+    // A public suffix that is the host itself only gives a host-only cookie.
+    let cookie = Cookie::parse("a=b; Domain=github.io", &url("http://github.io/"), HTTP).unwrap();
+    assert!(cookie.host_only);
+    assert_eq!(cookie.domain, ~"github.io");
+}
+
+#[test]
+fn test_script_sets_one_cookie() {
+    let mut storage = CookieStorage::new();
+    let u = url("http://example.com/");
+    storage.set_cookies_for_url(&u, "a=1\nb=2", NonHTTP);
+    assert_eq!(storage.cookies_for_url(&u, NonHTTP), None);
+    storage.set_cookies_for_url(&u, "a=1\nb=2", HTTP);
+    assert_eq!(storage.cookies_for_url(&u, NonHTTP), Some(~"a=1; b=2"));
+}
+
+#[test]
+fn test_storage_matching() {
+    let mut storage = CookieStorage::new();
+    let u = url("http://www.example.com/a/b");
+    storage.set_cookies_for_url(&u, "short=1; Path=/", HTTP);
+    storage.set_cookies_for_url(&u, "long=2; Path=/a", HTTP);
+    storage.set_cookies_for_url(&u, "secret=3; Secure", HTTP);
+    storage.set_cookies_for_url(&u, "hidden=4; HttpOnly", HTTP);
+
+    assert_eq!(storage.cookies_for_url(&u, HTTP), Some(~"long=2; hidden=4; short=1"));
+    assert_eq!(storage.cookies_for_url(&u, NonHTTP), Some(~"long=2; short=1"));
+    assert_eq!(storage.cookies_for_url(&url("https://www.example.com/a/b"), NonHTTP),
+               Some(~"long=2; secret=3; short=1"));
+    assert_eq!(storage.cookies_for_url(&url("http://example.com/"), HTTP), None);
+
+    // Replacing and expiring.
+    storage.set_cookies_for_url(&u, "short=5; Path=/", HTTP);
+    storage.set_cookies_for_url(&u, "long=; Path=/a; Max-Age=0", HTTP);
+    assert_eq!(storage.cookies_for_url(&u, NonHTTP), Some(~"short=5"));
+}
@@ -2,66 +2,163 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use resource_task::{Done, Payload, Metadata, LoadResponse, LoaderTask, start_sending};
+use resource_task::{Done, Payload, Metadata, LoadData, LoadResponse, LoaderTask, ResourceTask};
+use resource_task::{LoadError, InvalidUrl, DecodeError, start_sending};
 
-use extra::url::Url;
 use extra::base64::FromBase64;
+use extra::url::Url;
+use std::ascii::StrAsciiExt;
+use std::str;
 
 use http::headers::test_utils::from_stream_with_str;
 use http::headers::content_type::MediaType;
 
-pub fn factory() -> LoaderTask {
-    proc(url, start_chan) {
+pub fn factory(_resource_task: ResourceTask) -> LoaderTask {
+    proc(load_data, start_chan) {
         // NB: we don't spawn a new task.
         // Hypothesis: data URLs are too small for parallel base64 etc. to be worth it.
         // Should be tested at some point.
-        load(url, start_chan)
+        load(load_data, start_chan)
     }
 }
 
-fn load(url: Url, start_chan: Chan<LoadResponse>) {
+fn load(load_data: LoadData, start_chan: Chan<LoadResponse>) {
+    let url = load_data.url;
     assert!("data" == url.scheme);
 
     let mut metadata = Metadata::default(url.clone());
+    match parse(&url) {
+        Ok((content_type, data)) => {
+            metadata.set_content_type(&Some(content_type));
+            let progress_chan = start_sending(start_chan, metadata);
+            progress_chan.send(Payload(data));
+            progress_chan.send(Done(Ok(())));
+        }
+        Err(error) => {
+            info!("data_loader: can't load {:s}: {:s}", url.to_str(), error.to_str());
+            start_sending(start_chan, metadata).send(Done(Err(error)));
+        }
+    }
+}
+
+/// Processes a `data:` URL as described in the Fetch standard, returning its content type and
+/// body.
+fn parse(url: &Url) -> Result<(MediaType, ~[u8]), LoadError> {
+    // The standard works on the serialized URL, so that the query is part of the data and only
+    // the fragment is left out.
+    let mut url = url.clone();
+    url.fragment = None;
+    let serialized = url.to_str();
+    let serialized = serialized.slice_from(url.scheme.len() + 1);
 
     // Split out content type and data.
-    let parts: ~[&str] = url.path.splitn(',', 1).to_owned_vec();
+    let parts: ~[&str] = serialized.splitn(',', 1).to_owned_vec();
     if parts.len() != 2 {
-        start_sending(start_chan, metadata).send(Done(Err(())));
-        return;
+        return Err(InvalidUrl(~"no comma between the content type and the data"));
     }
+    let mut data = percent_decode(parts[1].as_bytes());
+
+    let ct_str = match str::from_utf8_owned_opt(percent_decode(parts[0].as_bytes())) {
+        Some(ct_str) => ct_str,
+        None => return Err(InvalidUrl(~"the content type isn't UTF-8")),
+    };
 
     // ";base64" must come at the end of the content type, per RFC 2397.
     // rust-http will fail to parse it because there's no =value part.
-    let mut is_base64 = false;
-    let mut ct_str = parts[0];
-    if ct_str.ends_with(";base64") {
-        is_base64 = true;
-        ct_str = ct_str.slice_to(ct_str.as_bytes().len() - 7);
+    let mut ct_str = ct_str.trim();
+    let is_base64 = match strip_base64_marker(ct_str) {
+        Some(stripped) => {
+            ct_str = stripped.trim();
+            true
+        }
+        None => false,
+    };
+
+    if is_base64 {
+        // Whitespace is allowed anywhere in the encoded data, e.g. to wrap long lines.
+        let encoded: ~str = data.iter().filter(|&&b| !is_ascii_whitespace(b))
+                                       .map(|&b| b as char).collect();
+        data = match encoded.as_slice().from_base64() {
+            Ok(decoded) => decoded,
+            Err(..) => return Err(DecodeError(~"invalid base64 data")),
+        };
     }
 
-    // Parse the content type using rust-http.
+    let ct_str = if ct_str.starts_with(";") {
+        "text/plain" + ct_str
+    } else {
+        ct_str.to_owned()
+    };
+
+    // Parse the content type using rust-http, falling back to the default if it is missing or
+    // invalid.
     // FIXME: this can go into an infinite loop! (rust-http #25)
-    let content_type: Option<MediaType> = from_stream_with_str(ct_str);
-    metadata.set_content_type(&content_type);
+    let content_type: Option<MediaType> = if ct_str.is_empty() {
+        None
+    } else {
+        from_stream_with_str(ct_str.as_slice())
+    };
+    match content_type {
+        Some(content_type) => Ok((content_type, data)),
+        None => Ok((MediaType {
+            type_: ~"text",
+            subtype: ~"plain",
+            parameters: ~[(~"charset", ~"US-ASCII")],
+        }, data)),
+    }
+}
 
-    let progress_chan = start_sending(start_chan, metadata);
+/// If the content type ends with `;base64`, ignoring case and spaces before `base64`, returns
+/// the content type without it.
+fn strip_base64_marker<'a>(ct_str: &'a str) -> Option<&'a str> {
+    let marker = "base64";
+    if ct_str.len() < marker.len() ||
+            !ct_str.slice_from(ct_str.len() - marker.len()).eq_ignore_ascii_case(marker) {
+        return None;
+    }
+    let rest = ct_str.slice_to(ct_str.len() - marker.len()).trim_right_chars(&' ');
+    if rest.ends_with(";") {
+        Some(rest.slice_to(rest.len() - 1))
+    } else {
+        None
+    }
+}
 
-    if is_base64 {
-        match parts[1].from_base64() {
-            Err(..) => {
-                progress_chan.send(Done(Err(())));
-            }
-            Ok(data) => {
-                progress_chan.send(Payload(data));
-                progress_chan.send(Done(Ok(())));
+/// Replaces each `%` escape in the UTF-8 bytes of the URL with the byte it stands for. Escapes
+/// that aren't followed by two hex digits are kept as they are.
+fn percent_decode(input: &[u8]) -> ~[u8] {
+    let mut bytes = ~[];
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == '%' as u8 && i + 2 < input.len() {
+            match (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                (Some(high), Some(low)) => {
+                    bytes.push(high * 16 + low);
+                    i += 3;
+                    continue;
+                }
+                _ => (),
             }
         }
-    } else {
-        // FIXME: Since the %-decoded URL is already a str, we can't
-        // handle UTF8-incompatible encodings.
-        progress_chan.send(Payload(parts[1].as_bytes().into_owned()));
-        progress_chan.send(Done(Ok(())));
+        bytes.push(input[i]);
+        i += 1;
+    }
+    bytes
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b as char {
+        '0'..'9' => Some(b - '0' as u8),
+        'a'..'f' => Some(b - 'a' as u8 + 10),
+        'A'..'F' => Some(b - 'A' as u8 + 10),
+        _ => None,
+    }
+}
+
+fn is_ascii_whitespace(b: u8) -> bool {
+    match b as char {
+        ' ' | '\t' | '\n' | '\x0c' | '\r' => true,
+        _ => false,
     }
 }
 
@@ -69,11 +166,11 @@ fn load(url: Url, start_chan: Chan<LoadResponse>) {
 fn assert_parse(url:          &'static str,
                 content_type: Option<(~str, ~str)>,
                 charset:      Option<~str>,
-                data:         Option<~[u8]>) {
-    use std::from_str::FromStr;
+                data:         Result<~[u8], LoadError>) {
+    use servo_util::url::make_url;
 
     let (start_port, start_chan) = Chan::new();
-    load(FromStr::from_str(url).unwrap(), start_chan);
+    load(LoadData::new(make_url(url, None)), start_chan);
 
     let response = start_port.recv();
     assert_eq!(&response.metadata.content_type, &content_type);
@@ -82,10 +179,10 @@ fn assert_parse(url:          &'static str,
     let progress = response.progress_port.recv();
 
     match data {
-        None => {
-            assert_eq!(progress, Done(Err(())));
+        Err(error) => {
+            assert_eq!(progress, Done(Err(error)));
         }
-        Some(dat) => {
+        Ok(dat) => {
             assert_eq!(progress, Payload(dat));
             assert_eq!(response.progress_port.recv(), Done(Ok(())));
         }
@@ -94,40 +191,100 @@ fn assert_parse(url:          &'static str,
 
 #[test]
 fn empty_invalid() {
-    assert_parse("data:", None, None, None);
+    assert_parse("data:", None, None,
+        Err(InvalidUrl(~"no comma between the content type and the data")));
 }
 
 #[test]
 fn plain() {
-    assert_parse("data:,hello%20world", None, None, Some(bytes!("hello world").into_owned()));
+    assert_parse("data:,hello%20world", Some((~"text", ~"plain")), Some(~"US-ASCII"),
+        Ok(bytes!("hello world").into_owned()));
 }
 
 #[test]
 fn plain_ct() {
     assert_parse("data:text/plain,hello",
-        Some((~"text", ~"plain")), None, Some(bytes!("hello").into_owned()));
+        Some((~"text", ~"plain")), None, Ok(bytes!("hello").into_owned()));
 }
 
 #[test]
 fn plain_charset() {
     assert_parse("data:text/plain;charset=latin1,hello",
-        Some((~"text", ~"plain")), Some(~"latin1"), Some(bytes!("hello").into_owned()));
+        Some((~"text", ~"plain")), Some(~"latin1"), Ok(bytes!("hello").into_owned()));
 }
 
 #[test]
 fn base64() {
-    assert_parse("data:;base64,C62+7w==", None, None, Some(~[0x0B, 0xAD, 0xBE, 0xEF]));
+    assert_parse("data:;base64,C62+7w==", Some((~"text", ~"plain")), Some(~"US-ASCII"),
+        Ok(~[0x0B, 0xAD, 0xBE, 0xEF]));
 }
 
 #[test]
 fn base64_ct() {
     assert_parse("data:application/octet-stream;base64,C62+7w==",
-        Some((~"application", ~"octet-stream")), None, Some(~[0x0B, 0xAD, 0xBE, 0xEF]));
+        Some((~"application", ~"octet-stream")), None, Ok(~[0x0B, 0xAD, 0xBE, 0xEF]));
 }
 
 #[test]
 fn base64_charset() {
     assert_parse("data:text/plain;charset=koi8-r;base64,8PLl9+XkIO3l5Pfl5A==",
         Some((~"text", ~"plain")), Some(~"koi8-r"),
-        Some(~[0xF0, 0xF2, 0xE5, 0xF7, 0xE5, 0xE4, 0x20, 0xED, 0xE5, 0xE4, 0xF7, 0xE5, 0xE4]));
+        Ok(~[0xF0, 0xF2, 0xE5, 0xF7, 0xE5, 0xE4, 0x20, 0xED, 0xE5, 0xE4, 0xF7, 0xE5, 0xE4]));
+}
+
+#[test]
+fn plain_non_utf8() {
+    assert_parse("data:text/plain;charset=latin1,caf%E9%20au%20lait",
+        Some((~"text", ~"plain")), Some(~"latin1"),
+        Ok(~[0x63, 0x61, 0x66, 0xE9, 0x20, 0x61, 0x75, 0x20, 0x6C, 0x61, 0x69, 0x74]));
+}
+
+#[test]
+fn plain_query() {
+    assert_parse("data:,a?b=c#fragment", Some((~"text", ~"plain")), Some(~"US-ASCII"),
+        Ok(bytes!("a?b=c").into_owned()));
+}
+
+#[test]
+fn query_kept_verbatim() {
+    assert_parse("data:,a?b=&c=%3F", Some((~"text", ~"plain")), Some(~"US-ASCII"),
+        Ok(bytes!("a?b=&c=?").into_owned()));
+    assert_parse("data:,a?b=caf\u00e9", Some((~"text", ~"plain")), Some(~"US-ASCII"),
+        Ok(~[0x61, 0x3F, 0x62, 0x3D, 0x63, 0x61, 0x66, 0xC3, 0xA9]));
+}
+
+#[test]
+fn query_of_any_url() {
+    use std::from_str::from_str;
+
+    // A URL that didn't go through `make_url` has its query split off the path.
+    let url: Url = from_str("data:,a?b=c%3F#fragment").unwrap();
+    match parse(&url) {
+        Ok((_, data)) => assert_eq!(data, bytes!("a?b=c?").into_owned()),
+        Err(error) => fail!("couldn't parse {:s}: {:s}", url.to_str(), error.to_str()),
+    }
+}
+
+#[test]
+fn utf8() {
+    assert_parse("data:text/plain;charset=utf-8,caf\u00e9%C3%A9",
+        Some((~"text", ~"plain")), Some(~"utf-8"),
+        Ok(~[0x63, 0x61, 0x66, 0xC3, 0xA9, 0xC3, 0xA9]));
+}
+
+#[test]
+fn charset_without_type() {
+    assert_parse("data:;charset=koi8-r,hello",
+        Some((~"text", ~"plain")), Some(~"koi8-r"), Ok(bytes!("hello").into_owned()));
+}
+
+#[test]
+fn base64_whitespace() {
+    assert_parse("data:application/octet-stream%20;%20BASE64,C62%20+7w%0A==",
+        Some((~"application", ~"octet-stream")), None, Ok(~[0x0B, 0xAD, 0xBE, 0xEF]));
+}
+
+#[test]
+fn base64_invalid() {
+    assert_parse("data:;base64,!!!", None, None, Err(DecodeError(~"invalid base64 data")));
 }
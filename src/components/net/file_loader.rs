@@ -2,18 +2,31 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use resource_task::{ProgressMsg, Metadata, Payload, Done, LoaderTask, start_sending};
+use resource_task::{ProgressMsg, Metadata, Payload, Done, LoaderTask, ResourceTask, start_sending};
+use resource_task::{CancellationHandle, LoadError, NetworkError, Cancelled};
+use sniffer::guess_from_extension;
+use util::escape_html;
 use servo_util::io::result;
 
+use extra::time;
+use extra::time::Timespec;
+use extra::url;
 use std::io;
 use std::io::File;
+use std::io::fs;
 use servo_util::task::spawn_named;
 
+#[cfg(test)]
+use resource_task::LoadData;
+
 static READ_SIZE: uint = 1024;
 
-fn read_all(reader: &mut io::Stream, progress_chan: &SharedChan<ProgressMsg>)
-        -> Result<(), ()> {
+fn read_all(reader: &mut io::Stream, cancellation: &CancellationHandle,
+            progress_chan: &SharedChan<ProgressMsg>) -> Result<(), LoadError> {
     loop {
+        if cancellation.is_cancelled() {
+            return Err(Cancelled);
+        }
         match (result(|| {
             let data = reader.read_bytes(READ_SIZE);
             progress_chan.send(Payload(data));
@@ -21,29 +34,149 @@ fn read_all(reader: &mut io::Stream, progress_chan: &SharedChan<ProgressMsg>)
             Ok(()) => (),
             Err(e) => match e.kind {
                 io::EndOfFile => return Ok(()),
-                _ => return Err(()),
+                _ => return Err(NetworkError(e.desc.to_owned())),
             }
         }
     }
 }
 
-pub fn factory() -> LoaderTask {
-    let f: LoaderTask = proc(url, start_chan) {
+pub fn factory(_resource_task: ResourceTask) -> LoaderTask {
+    let f: LoaderTask = proc(load_data, start_chan) {
+        let url = load_data.url;
+        let cancellation = load_data.cancellation;
         assert!("file" == url.scheme);
-        let progress_chan = start_sending(start_chan, Metadata::default(url.clone()));
         spawn_named("file_loader", proc() {
             // ignore_io_error causes us to get None instead of a task failure.
             let _guard = io::ignore_io_error();
-            match File::open_mode(&Path::new(url.path), io::Open, io::Read) {
+            let mut path = Path::new(url.path.as_slice());
+            let mut metadata = Metadata::default(url.clone());
+            if path.is_dir() {
+                // Resolve relative links against the directory rather than its parent.
+                if !url.path.ends_with("/") {
+                    metadata.final_url.path.push_char('/');
+                }
+                let index = path.join("index.html");
+                if !index.is_file() {
+                    metadata.content_type = Some((~"text", ~"html"));
+                    metadata.charset = Some(~"utf-8");
+                    let progress_chan = start_sending(start_chan, metadata);
+                    let listing = directory_listing(&path);
+                    progress_chan.send(Payload(listing.into_bytes()));
+                    progress_chan.send(Done(Ok(())));
+                    return;
+                }
+                path = index;
+            }
+
+            metadata.content_type = guess_from_extension(path.as_str().unwrap_or(""));
+            let progress_chan = start_sending(start_chan, metadata);
+            match File::open_mode(&path, io::Open, io::Read) {
                 Some(ref mut reader) => {
-                    let res = read_all(reader as &mut io::Stream, &progress_chan);
+                    let res = read_all(reader as &mut io::Stream, &cancellation, &progress_chan);
                     progress_chan.send(Done(res));
                 }
                 None => {
-                    progress_chan.send(Done(Err(())));
+                    let error = NetworkError(format!("couldn't open {:s}", url.path));
+                    progress_chan.send(Done(Err(error)));
                 }
             };
         });
     };
     f
 }
+
+/// An index page for a directory without an `index.html`, listing its subdirectories and then
+/// its files, with their sizes and modification times. Links are relative to the directory.
+fn directory_listing(dir: &Path) -> ~str {
+    let mut entries = ~[];
+    for entry in fs::readdir(dir).iter() {
+        let name = match entry.filename_str() {
+            Some(name) => name.to_owned(),
+            None => {
+                debug!("file_loader: skipping {:s}, whose name isn't UTF-8",
+                       entry.display().to_str());
+                continue;
+            }
+        };
+        let stat = entry.stat();
+        let is_dir = match stat.kind {
+            io::TypeDirectory => true,
+            _ => false,
+        };
+        // Directories sort first.
+        entries.push((!is_dir, name, stat.size, stat.modified));
+    }
+    entries.sort_by(|a, b| a.cmp(b));
+
+    let title = escape_html(dir.display().to_str());
+    let mut rows = ~[];
+    if dir.dirname() != dir.as_vec() {
+        rows.push(~"<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>");
+    }
+    for &(is_file, ref name, size, modified) in entries.iter() {
+        let (suffix, size) = if is_file { ("", size.to_str()) } else { ("/", ~"") };
+        let modified = time::at_utc(Timespec::new((modified / 1000) as i64, 0));
+        rows.push(format!("<tr><td><a href=\"{:s}{:s}\">{:s}{:s}</a></td>\
+                           <td>{:s}</td><td>{:s}</td></tr>",
+                          url::encode_component(name.as_slice()), suffix,
+                          escape_html(name.as_slice()), suffix, size,
+                          modified.strftime("%Y-%m-%d %H:%M UTC")));
+    }
+    format!("<html>\n<head><title>Index of {:s}</title></head>\n<body>\n\
+             <h1>Index of {:s}</h1>\n<table>\n\
+             <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n\
+             {:s}\n</table>\n</body>\n</html>\n",
+            title, title, rows.connect("\n"))
+}
+
+#[cfg(test)]
+fn load_path(path: &Path) -> (Metadata, ~str) {
+    use std::from_str::FromStr;
+    use std::str;
+
+    let (_control_port, resource_task) = SharedChan::new();
+    let (start_port, start_chan) = Chan::new();
+    let url = FromStr::from_str(format!("file://{:s}", path.display().to_str())).unwrap();
+    factory(resource_task)(LoadData::new(url), start_chan);
+
+    let response = start_port.recv();
+    let mut body = ~[];
+    loop {
+        match response.progress_port.recv() {
+            Payload(data) => body.push_all(data),
+            Done(result) => {
+                assert!(result.is_ok());
+                return (response.metadata, str::from_utf8_owned(body));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_directory_listing() {
+    use extra::tempfile::TempDir;
+
+    let dir = TempDir::new("file_loader").unwrap();
+    File::create(&dir.path().join("a&b.txt")).unwrap().write(bytes!("hello"));
+    fs::mkdir(&dir.path().join("sub"), io::UserRWX);
+
+    let (metadata, body) = load_path(dir.path());
+    assert_eq!(metadata.content_type, Some((~"text", ~"html")));
+    assert!(metadata.final_url.path.ends_with("/"));
+    assert!(body.contains("<a href=\"../\">"));
+    assert!(body.contains("<a href=\"sub/\">sub/</a>"));
+    assert!(body.contains("<a href=\"a%26b.txt\">a&amp;b.txt</a></td><td>5</td>"));
+    assert!(body.find_str("sub/").unwrap() < body.find_str("a&amp;b.txt").unwrap());
+}
+
+#[test]
+fn test_directory_index() {
+    use extra::tempfile::TempDir;
+
+    let dir = TempDir::new("file_loader").unwrap();
+    File::create(&dir.path().join("index.html")).unwrap().write(bytes!("<p>index</p>"));
+
+    let (metadata, body) = load_path(dir.path());
+    assert_eq!(metadata.content_type, Some((~"text", ~"html")));
+    assert_eq!(body, ~"<p>index</p>");
+}
@@ -0,0 +1,917 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A private HTTP cache that sits in front of the HTTP loader.
+//!
+//! Lookups in memory happen on the resource task. Loads that miss there or need revalidation go
+//! through a `fill` task, which asks the disk cache task, if there is one, before loading from the
+//! network. It forwards responses to the consumer and sends anything storable back to the
+//! resource task in a `StoreCachedResponse` message.
+
+use resource_task::{LoadData, LoadResponse, Metadata, Payload, Done, ResourceTask, LoaderTask};
+use resource_task::{StoreCachedResponse, RememberCachedResponse, start_sending};
+use stored_response::StoredResponse;
+use util::{parse_http_date, to_status};
+
+use std::ascii::StrAsciiExt;
+use std::cmp::max;
+use std::from_str::from_str;
+use std::hash::Hash;
+use std::hashmap::HashMap;
+use std::io;
+use std::io::{File, Reader, Writer};
+use std::io::fs;
+use std::str;
+use extra::time;
+use extra::time::precise_time_ns;
+use extra::url::Url;
+use http::headers::HeaderEnum;
+use http::headers::request::HeaderCollection;
+use http::method::Get;
+use ResponseHeaderCollection = http::headers::response::HeaderCollection;
+use servo_util::task::spawn_named;
+use servo_util::time::{ProfilerChan, TimeMsg, NetworkCacheHitCategory, NetworkCacheMissCategory};
+use servo_util::time::NetworkCacheRevalidatedCategory;
+
+/// How many bytes of response bodies the cache keeps in memory. Beyond this, the least recently
+/// used URLs are evicted; they can still be read back from disk if there is a disk cache.
+static MAX_MEMORY_SIZE: uint = 32 * 1024 * 1024;
+
+/// How many bytes of files the disk cache keeps. Beyond this, the least recently used files are
+/// deleted.
+static MAX_DISK_SIZE: uint = 256 * 1024 * 1024;
+
+/// A stored response, along with what is needed to decide whether it may be reused.
+#[deriving(Clone)]
+pub struct CachedResponse {
+    url: Url,
+    status: u16,
+    content_type: Option<(~str, ~str)>,
+    charset: Option<~str>,
+    /// The response headers, as (name, value) pairs.
+    headers: ~[(~str, ~str)],
+    /// The request header values named by the response's `Vary` header.
+    vary: ~[(~str, Option<~str>)],
+    /// Time in seconds since the epoch at which the response was received or last revalidated.
+    response_time: i64,
+    /// Time in seconds since the epoch after which the response must be revalidated.
+    expires: i64,
+    body: ~[u8],
+}
+
+impl CachedResponse {
+    /// Builds a cache entry for a response to `load_data`, or returns `None` if the response
+    /// must not be stored.
+    pub fn new(load_data: &LoadData, metadata: &Metadata) -> Option<CachedResponse> {
+        if metadata.status.code() != 200 || metadata.final_url != load_data.url {
+            return None;
+        }
+        let headers: ~[(~str, ~str)] = match metadata.headers {
+            Some(ref headers) => headers.iter().map(|header| {
+                (header.header_name(), header.header_value())
+            }).collect(),
+            None => return None,
+        };
+
+        let mut entry = CachedResponse {
+            url: load_data.url.clone(),
+            status: metadata.status.code(),
+            content_type: metadata.content_type.clone(),
+            charset: metadata.charset.clone(),
+            headers: headers,
+            vary: ~[],
+            response_time: time::get_time().sec,
+            expires: 0,
+            body: ~[],
+        };
+
+        if entry.has_directive("no-store") {
+            return None;
+        }
+        match entry.header("Vary").map(|vary| vary.to_owned()) {
+            Some(vary) => {
+                for name in vary.split(',').map(|name| name.trim()) {
+                    if name == "*" {
+                        return None;
+                    }
+                    entry.vary.push((name.to_ascii_lower(),
+                                     request_header(&load_data.headers, name)));
+                }
+            }
+            None => (),
+        }
+        entry.expires = entry.compute_expiry();
+
+        // A response that is stale on arrival is only worth keeping if it can be revalidated.
+        if entry.is_stale() && entry.validators().is_empty() {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// Looks up a response header by case-insensitive name.
+    fn header<'a>(&'a self, name: &str) -> Option<&'a str> {
+        self.headers.iter()
+            .find(|&&(ref key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_slice())
+    }
+
+    fn has_directive(&self, directive: &str) -> bool {
+        match self.header("Cache-Control") {
+            Some(value) => value.split(',').any(|d| d.trim().eq_ignore_ascii_case(directive)),
+            None => false,
+        }
+    }
+
+    fn max_age(&self) -> Option<i64> {
+        match self.header("Cache-Control") {
+            Some(value) => {
+                for directive in value.split(',').map(|d| d.trim()) {
+                    if directive.to_ascii_lower().starts_with("max-age=") {
+                        return from_str(directive.slice_from(8));
+                    }
+                }
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// The absolute time after which the response is stale: the time it was received, less how
+    /// old it already was then, plus its freshness lifetime.
+    fn compute_expiry(&self) -> i64 {
+        self.response_time - self.initial_age() + self.freshness_lifetime()
+    }
+
+    /// The response's own idea of when it was generated, or the time it was received.
+    fn date(&self) -> i64 {
+        self.header("Date").and_then(|date| parse_http_date(date)).unwrap_or(self.response_time)
+    }
+
+    /// How old the response already was when it was received, from its `Date` and `Age`
+    /// headers. See RFC 7234, section 4.2.3.
+    fn initial_age(&self) -> i64 {
+        let apparent_age = max(0, self.response_time - self.date());
+        let age = self.header("Age").and_then(|age| from_str::<i64>(age.trim())).unwrap_or(0);
+        max(apparent_age, age)
+    }
+
+    /// How long the response stays fresh after it was generated, from `Cache-Control`,
+    /// `Expires` or, failing those, the usual 10%-of-age heuristic on `Last-Modified`.
+    fn freshness_lifetime(&self) -> i64 {
+        if self.has_directive("no-cache") {
+            return 0;
+        }
+        match self.max_age() {
+            Some(max_age) => return max_age,
+            None => (),
+        }
+        let date = self.date();
+        match self.header("Expires").and_then(|expires| parse_http_date(expires)) {
+            Some(expires) => return max(0, expires - date),
+            None => (),
+        }
+        match self.header("Last-Modified").and_then(|modified| parse_http_date(modified)) {
+            Some(last_modified) if last_modified < date => (date - last_modified) / 10,
+            _ => 0,
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.expires <= time::get_time().sec
+    }
+
+    /// The conditional request headers that revalidate this response.
+    fn validators(&self) -> ~[(~str, ~str)] {
+        let mut validators = ~[];
+        match self.header("ETag") {
+            Some(etag) => validators.push((~"If-None-Match", etag.to_owned())),
+            None => (),
+        }
+        match self.header("Last-Modified") {
+            Some(date) => validators.push((~"If-Modified-Since", date.to_owned())),
+            None => (),
+        }
+        validators
+    }
+
+    /// Whether this response was stored for a request with the same values of the headers it
+    /// varies on.
+    fn matches_request(&self, headers: &HeaderCollection) -> bool {
+        self.vary.iter().all(|&(ref name, ref value)| {
+            request_header(headers, name.as_slice()) == *value
+        })
+    }
+
+    /// Replaces the headers of this entry with those of a `304 Not Modified` response and
+    /// recomputes its freshness.
+    fn refresh(&mut self, metadata: &Metadata) {
+        match metadata.headers {
+            Some(ref headers) => {
+                for header in headers.iter() {
+                    let name = header.header_name();
+                    self.headers.retain(|&(ref key, _)| !key.eq_ignore_ascii_case(name.as_slice()));
+                    self.headers.push((name, header.header_value()));
+                }
+            }
+            None => (),
+        }
+        self.response_time = time::get_time().sec;
+        self.expires = self.compute_expiry();
+    }
+
+    /// Sends this response to a consumer as if it had just been loaded.
+    pub fn send(&self, start_chan: Chan<LoadResponse>) {
+        let mut metadata = Metadata::default(self.url.clone());
+        metadata.status = to_status(self.status);
+        metadata.content_type = self.content_type.clone();
+        metadata.charset = self.charset.clone();
+        // Stored headers are untyped, but iterate like the originals.
+        let mut headers = ResponseHeaderCollection::new();
+        for &(ref name, ref value) in self.headers.iter() {
+            headers.extensions.insert(name.clone(), value.clone());
+        }
+        metadata.headers = Some(headers);
+        let progress_chan = start_sending(start_chan, metadata);
+        progress_chan.send(Payload(self.body.clone()));
+        progress_chan.send(Done(Ok(())));
+    }
+}
+
+/// The outcome of looking up a request in the cache.
+pub enum CacheLookup {
+    /// A fresh response that can be served without contacting the server.
+    Fresh(CachedResponse),
+    /// A stale response that can be served if the server confirms it is still valid.
+    Stale(CachedResponse),
+    Miss,
+}
+
+impl CacheLookup {
+    /// Whether `entry` can be served as it is, after revalidation or not at all.
+    fn from_entry(entry: CachedResponse) -> CacheLookup {
+        if !entry.is_stale() {
+            Fresh(entry)
+        } else if !entry.validators().is_empty() {
+            Stale(entry)
+        } else {
+            Miss
+        }
+    }
+}
+
+/// The in-memory cache, owned by the resource task.
+pub struct HttpCache {
+    /// The stored variants of each URL.
+    priv entries: HashMap<~str, ~[CachedResponse]>,
+    /// The URLs in `entries`, least recently used first.
+    priv recency: ~[~str],
+    /// The total size of the bodies in `entries`.
+    priv size: uint,
+    priv max_size: uint,
+    /// The task that also keeps responses across sessions, if there is a disk cache.
+    priv disk: Option<DiskCacheTask>,
+}
+
+impl HttpCache {
+    pub fn new(disk_dir: Option<Path>) -> HttpCache {
+        HttpCache {
+            entries: HashMap::new(),
+            recency: ~[],
+            size: 0,
+            max_size: MAX_MEMORY_SIZE,
+            disk: disk_dir.map(|dir| spawn_disk_cache(dir)),
+        }
+    }
+
+    /// Whether a request may be answered from, or stored in, the cache.
+    pub fn is_cacheable(load_data: &LoadData) -> bool {
+        let is_get = match load_data.method {
+            Get => true,
+            _ => false,
+        };
+        let bypass = match request_header(&load_data.headers, "Cache-Control") {
+            Some(value) => {
+                let value = value.to_ascii_lower();
+                value.contains("no-cache") || value.contains("no-store")
+            }
+            None => false,
+        };
+        "http" == load_data.url.scheme && is_get && load_data.data.is_none() && !bypass
+    }
+
+    /// Looks up a request in memory. A miss may still be answered by the disk cache, which
+    /// `fill` asks so that the resource task never waits for the disk.
+    pub fn lookup(&mut self, load_data: &LoadData) -> CacheLookup {
+        let key = load_data.url.to_str();
+        let entry = self.entries.find(&key).and_then(|variants| {
+            variants.iter().find(|entry| entry.matches_request(&load_data.headers))
+        }).map(|entry| entry.clone());
+        match entry {
+            Some(entry) => {
+                self.touch(key);
+                CacheLookup::from_entry(entry)
+            }
+            None => Miss,
+        }
+    }
+
+    /// The disk cache task, if there is one.
+    pub fn disk(&self) -> Option<DiskCacheTask> {
+        self.disk.clone()
+    }
+
+    /// Stores `entry` in memory and on disk, replacing any variant of the same URL that matches
+    /// the same request.
+    pub fn store(&mut self, entry: CachedResponse) {
+        match self.disk {
+            Some(ref disk) => disk.send(WriteToDisk(entry.clone())),
+            None => (),
+        }
+        self.insert(entry);
+    }
+
+    /// Stores `entry` in memory only, as for responses that were read back from disk.
+    pub fn insert(&mut self, entry: CachedResponse) {
+        let key = entry.url.to_str();
+        {
+            let variants = self.entries.find_or_insert(key.clone(), ~[]);
+            match variants.iter().position(|variant| variant.vary == entry.vary) {
+                Some(index) => self.size -= variants.remove(index).body.len(),
+                None => (),
+            }
+            self.size += entry.body.len();
+            variants.push(entry);
+        }
+        self.touch(key);
+        self.evict();
+    }
+
+    /// Marks `key` as the most recently used URL.
+    fn touch(&mut self, key: ~str) {
+        self.recency.retain(|other| *other != key);
+        self.recency.push(key);
+    }
+
+    /// Drops the least recently used URLs until the cache fits in its budget again. The most
+    /// recently used one is always kept, however large it is.
+    fn evict(&mut self) {
+        while self.size > self.max_size && self.recency.len() > 1 {
+            let key = self.recency.shift();
+            debug!("http_cache: evicting {:s}", key);
+            match self.entries.pop(&key) {
+                Some(variants) => {
+                    for variant in variants.iter() {
+                        self.size -= variant.body.len();
+                    }
+                }
+                None => (),
+            }
+        }
+    }
+}
+
+/// Messages to the disk cache task.
+pub enum DiskCacheMsg {
+    /// Read the stored variant of a URL that matches a request, if there is one
+    ReadFromDisk(LoadData, Chan<Option<CachedResponse>>),
+    /// Store a response, replacing the variant of its URL that matches the same request
+    WriteToDisk(CachedResponse),
+}
+
+pub type DiskCacheTask = SharedChan<DiskCacheMsg>;
+
+/// Starts the task that owns the disk cache in `dir`. It exits once every handle to it is gone.
+fn spawn_disk_cache(dir: Path) -> DiskCacheTask {
+    let (port, chan) = SharedChan::new();
+    spawn_named("http_disk_cache", proc() {
+        let mut disk = DiskCache::new(dir, MAX_DISK_SIZE);
+        loop {
+            match port.recv_opt() {
+                Some(ReadFromDisk(load_data, consumer)) => {
+                    consumer.try_send(disk.read(&load_data));
+                }
+                Some(WriteToDisk(entry)) => disk.write(&entry),
+                None => break,
+            }
+        }
+    });
+    chan
+}
+
+/// The files of the disk cache, kept within a budget the same way as the in-memory cache.
+///
+/// Each variant of a URL is stored in its own file, named after the URL and the request header
+/// values it varies on. An index file per URL names the headers its latest response varies on,
+/// which is what picks the variant file for a request. Both are lists of tab-separated fields;
+/// variant files have a blank line and the body after them.
+struct DiskCache {
+    dir: Path,
+    /// The size of each file in `dir`, by file name.
+    sizes: HashMap<~str, uint>,
+    /// The file names in `sizes`, least recently used first.
+    recency: ~[~str],
+    /// The total size of the files in `dir`.
+    size: uint,
+    max_size: uint,
+}
+
+impl DiskCache {
+    /// Opens the disk cache in `dir`, creating the directory if needed. Files left by earlier
+    /// sessions count against the budget, oldest first.
+    fn new(dir: Path, max_size: uint) -> DiskCache {
+        let mut files = ~[];
+        {
+            let _guard = io::ignore_io_error();
+            fs::mkdir_recursive(&dir, io::UserRWX);
+            for path in fs::readdir(&dir).iter() {
+                let stat = path.stat();
+                match (stat.kind, path.filename_str()) {
+                    (io::TypeFile, Some(name)) => {
+                        files.push((stat.modified, name.to_owned(), stat.size as uint));
+                    }
+                    _ => (),
+                }
+            }
+        }
+        files.sort_by(|a, b| a.cmp(b));
+
+        let mut disk = DiskCache {
+            dir: dir,
+            sizes: HashMap::new(),
+            recency: ~[],
+            size: 0,
+            max_size: max_size,
+        };
+        for (_, name, size) in files.move_iter() {
+            disk.size += size;
+            disk.sizes.insert(name.clone(), size);
+            disk.recency.push(name);
+        }
+        disk.evict();
+        disk
+    }
+
+    fn file_name(key: &str) -> ~str {
+        format!("{:016x}", key.hash())
+    }
+
+    /// Marks a file as the most recently used one.
+    fn touch(&mut self, name: ~str) {
+        self.recency.retain(|other| *other != name);
+        self.recency.push(name);
+    }
+
+    /// Deletes the least recently used files until the cache fits in its budget again. The
+    /// index and variant files that were used last are always kept, however large they are.
+    fn evict(&mut self) {
+        let _guard = io::ignore_io_error();
+        while self.size > self.max_size && self.recency.len() > 2 {
+            let name = self.recency.shift();
+            debug!("http_cache: deleting {:s} from the disk cache", name);
+            self.size -= self.sizes.pop(&name).unwrap_or(0);
+            fs::unlink(&self.dir.join(name));
+        }
+    }
+
+    fn write_file(&mut self, key: &str, parts: &[&[u8]]) {
+        let name = DiskCache::file_name(key);
+        let path = self.dir.join(name.as_slice());
+        let written = {
+            let _guard = io::ignore_io_error();
+            match File::create(&path) {
+                Some(ref mut file) => {
+                    for part in parts.iter() {
+                        file.write(*part);
+                    }
+                    true
+                }
+                None => false,
+            }
+        };
+        if !written {
+            debug!("http_cache: couldn't write {:s}", path.display().to_str());
+            return;
+        }
+        let size = parts.iter().fold(0, |size, part| size + part.len());
+        self.size -= self.sizes.pop(&name).unwrap_or(0);
+        self.size += size;
+        self.sizes.insert(name.clone(), size);
+        self.touch(name);
+    }
+
+    fn read_file(&mut self, key: &str) -> Option<~[u8]> {
+        let name = DiskCache::file_name(key);
+        let data = {
+            let _guard = io::ignore_io_error();
+            match File::open(&self.dir.join(name.as_slice())) {
+                Some(ref mut file) => file.read_to_end(),
+                None => return None,
+            }
+        };
+        self.touch(name);
+        Some(data)
+    }
+
+    fn write(&mut self, entry: &CachedResponse) {
+        let url = entry.url.to_str();
+        let mut vary_names = ~[format!("url\t{:s}", url)];
+        for &(ref name, _) in entry.vary.iter() {
+            vary_names.push(format!("vary\t{:s}", *name));
+        }
+
+        let mut stored = StoredResponse::new(entry.status, entry.content_type.clone(),
+                                             entry.charset.clone(), entry.headers.clone());
+        stored.push_field(~[~"url", url.clone()]);
+        stored.push_field(~[~"response-time", entry.response_time.to_str()]);
+        stored.push_field(~[~"expires", entry.expires.to_str()]);
+        for &(ref name, ref value) in entry.vary.iter() {
+            match *value {
+                Some(ref value) => stored.push_field(~[~"vary", name.clone(), value.clone()]),
+                None => stored.push_field(~[~"vary", name.clone()]),
+            }
+        }
+
+        let vary_names = vary_names.connect("\n");
+        let head = stored.head();
+        self.write_file(index_key(url.as_slice()), &[vary_names.as_bytes()]);
+        self.write_file(variant_key(url.as_slice(), entry.vary),
+                        &[head.as_slice(), entry.body.as_slice()]);
+        self.evict();
+    }
+
+    /// Reads the variant of `load_data`'s URL that matches its headers, if it was stored.
+    fn read(&mut self, load_data: &LoadData) -> Option<CachedResponse> {
+        let url = load_data.url.to_str();
+        let index = match self.read_file(index_key(url.as_slice())) {
+            Some(index) => index,
+            None => return None,
+        };
+        let mut vary = ~[];
+        match str::from_utf8_opt(index) {
+            Some(index) => {
+                for line in index.lines() {
+                    let parts: ~[&str] = line.splitn('\t', 1).collect();
+                    match (parts[0], parts.len()) {
+                        // Guard against hash collisions.
+                        ("url", 2) if parts[1] != url.as_slice() => return None,
+                        ("vary", 2) => {
+                            vary.push((parts[1].to_owned(),
+                                       request_header(&load_data.headers, parts[1])));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            None => return None,
+        }
+
+        let data = match self.read_file(variant_key(url.as_slice(), vary)) {
+            Some(data) => data,
+            None => return None,
+        };
+        let (stored, body) = match StoredResponse::parse(data) {
+            Some(parsed) => parsed,
+            None => return None,
+        };
+
+        let StoredResponse { status, content_type, charset, headers, fields } = stored;
+        let mut entry = CachedResponse {
+            url: load_data.url.clone(),
+            status: status,
+            content_type: content_type,
+            charset: charset,
+            headers: headers,
+            vary: ~[],
+            response_time: 0,
+            expires: 0,
+            body: body.to_owned(),
+        };
+        for values in fields.iter() {
+            let parts: ~[&str] = values.iter().map(|value| value.as_slice()).collect();
+            match (parts[0], parts.len()) {
+                // Guard against hash collisions.
+                ("url", 2) if parts[1] != url.as_slice() => return None,
+                ("response-time", 2) => entry.response_time = from_str(parts[1]).unwrap_or(0),
+                ("expires", 2) => entry.expires = from_str(parts[1]).unwrap_or(0),
+                ("vary", 2) => entry.vary.push((parts[1].to_owned(), None)),
+                ("vary", 3) => entry.vary.push((parts[1].to_owned(), Some(parts[2].to_owned()))),
+                _ => (),
+            }
+        }
+        if !entry.matches_request(&load_data.headers) {
+            return None;
+        }
+        Some(entry)
+    }
+}
+
+/// The key of the disk cache file that names the headers a URL's responses vary on.
+fn index_key(url: &str) -> ~str {
+    format!("index {:s}", url)
+}
+
+/// The key of the disk cache file for the variant of a URL with the given request header values.
+fn variant_key(url: &str, vary: &[(~str, Option<~str>)]) -> ~str {
+    let mut key = url.to_owned();
+    for &(ref name, ref value) in vary.iter() {
+        match *value {
+            Some(ref value) => key.push_str(format!("\n{:s}: {:s}", *name, *value)),
+            None => key.push_str(format!("\n{:s}", *name)),
+        }
+    }
+    key
+}
+
+/// Looks up a request header by case-insensitive name.
+fn request_header(headers: &HeaderCollection, name: &str) -> Option<~str> {
+    headers.iter()
+           .find(|header| header.header_name().eq_ignore_ascii_case(name))
+           .map(|header| header.header_value())
+}
+
+/// Adds the headers that revalidate `entry` to an outgoing request.
+fn add_validators(load_data: &mut LoadData, entry: &CachedResponse) {
+    for (name, value) in entry.validators().move_iter() {
+        load_data.headers.extensions.insert(name, value);
+    }
+}
+
+/// Asks the disk cache for a response to `load_data`, keeping whatever it finds in memory too.
+fn read_from_disk(disk: &DiskCacheTask, load_data: &LoadData, resource_task: &ResourceTask)
+                  -> CacheLookup {
+    let (port, chan) = Chan::new();
+    disk.send(ReadFromDisk(load_data.clone(), chan));
+    match port.recv_opt() {
+        Some(Some(entry)) => {
+            resource_task.send(RememberCachedResponse(entry.clone()));
+            CacheLookup::from_entry(entry)
+        }
+        _ => Miss,
+    }
+}
+
+/// Answers a load that missed the in-memory cache or needs revalidation. A miss is looked up on
+/// disk first; then `loader` fetches the response, which is forwarded to `start_chan`.
+/// `304 Not Modified` responses are answered from the stale entry that was revalidated and
+/// storable responses are sent back to the resource task.
+pub fn fill(load_data: LoadData,
+            stale: Option<CachedResponse>,
+            disk: Option<DiskCacheTask>,
+            loader: LoaderTask,
+            start_chan: Chan<LoadResponse>,
+            resource_task: ResourceTask,
+            profiler_chan: ProfilerChan) {
+    let stale = match (stale, disk) {
+        (None, Some(disk)) => {
+            let start_time = precise_time_ns();
+            match read_from_disk(&disk, &load_data, &resource_task) {
+                Fresh(entry) => {
+                    debug!("http_cache: disk cache hit for {:s}", load_data.url.to_str());
+                    entry.send(start_chan);
+                    let ms = (precise_time_ns() - start_time) as f64 / 1000000f64;
+                    profiler_chan.send(TimeMsg(NetworkCacheHitCategory, ms));
+                    return;
+                }
+                Stale(entry) => Some(entry),
+                Miss => None,
+            }
+        }
+        (stale, _) => stale,
+    };
+
+    let mut load_data = load_data;
+    match stale {
+        Some(ref entry) => {
+            debug!("http_cache: revalidating {:s}", load_data.url.to_str());
+            add_validators(&mut load_data, entry);
+        }
+        None => (),
+    }
+    let (from_loader, to_cache) = Chan::new();
+    loader(load_data.clone(), to_cache);
+
+    let start_time = precise_time_ns();
+    let response = from_loader.recv();
+    let ms = (precise_time_ns() - start_time) as f64 / 1000000f64;
+
+    if response.metadata.status.code() == 304 {
+        match stale {
+            Some(mut entry) => {
+                profiler_chan.send(TimeMsg(NetworkCacheRevalidatedCategory, ms));
+                entry.refresh(&response.metadata);
+                entry.send(start_chan);
+                resource_task.send(StoreCachedResponse(entry));
+                return;
+            }
+            None => (),
+        }
+    }
+    profiler_chan.send(TimeMsg(NetworkCacheMissCategory, ms));
+
+    let mut entry = CachedResponse::new(&load_data, &response.metadata);
+    let progress_chan = start_sending(start_chan, response.metadata);
+    loop {
+        match response.progress_port.recv() {
+            Payload(data) => {
+                match entry {
+                    Some(ref mut entry) => entry.body.push_all(data),
+                    None => (),
+                }
+                progress_chan.send(Payload(data));
+            }
+            Done(result) => {
+                if result.is_ok() {
+                    match entry.take() {
+                        Some(entry) => resource_task.send(StoreCachedResponse(entry)),
+                        None => (),
+                    }
+                }
+                progress_chan.send(Done(result));
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn entry_with_headers(headers: ~[(~str, ~str)]) -> CachedResponse {
+    CachedResponse {
+        url: from_str("http://example.com/").unwrap(),
+        status: 200,
+        content_type: None,
+        charset: None,
+        headers: headers,
+        vary: ~[],
+        response_time: time::get_time().sec,
+        expires: 0,
+        body: ~[],
+    }
+}
+
+#[test]
+fn test_max_age_beats_expires() {
+    let entry = entry_with_headers(~[(~"Cache-Control", ~"public, max-age=60"),
+                                     (~"Expires", ~"Thu, 01 Jan 1970 00:00:00 GMT")]);
+    let now = time::get_time().sec;
+    let expires = entry.compute_expiry();
+    assert!(expires >= now + 60 && expires <= now + 61);
+}
+
+#[test]
+fn test_no_cache_is_immediately_stale() {
+    let mut entry = entry_with_headers(~[(~"cache-control", ~"no-cache, max-age=60"),
+                                         (~"ETag", ~"\"abc\"")]);
+    entry.expires = entry.compute_expiry();
+    assert!(entry.is_stale());
+    assert_eq!(entry.validators(), ~[(~"If-None-Match", ~"\"abc\"")]);
+}
+
+#[test]
+fn test_age_and_date_count_against_freshness() {
+    let mut entry = entry_with_headers(~[(~"Cache-Control", ~"max-age=60"), (~"Age", ~"50")]);
+    let now = entry.response_time;
+    let expires = entry.compute_expiry();
+    assert!(expires >= now + 10 && expires <= now + 11);
+
+    // The response is 100 seconds old on arrival, and lives for 300 seconds after its `Date`.
+    entry.headers = ~[(~"Date", ~"Thu, 01 Jan 1970 00:00:00 GMT"),
+                      (~"Expires", ~"Thu, 01 Jan 1970 00:05:00 GMT")];
+    entry.response_time = 100;
+    assert_eq!(entry.compute_expiry(), 300);
+
+    entry.headers = ~[(~"Date", ~"Thu, 01 Jan 1970 00:00:00 GMT"),
+                      (~"Cache-Control", ~"max-age=60")];
+    entry.response_time = now;
+    entry.expires = entry.compute_expiry();
+    assert!(entry.is_stale());
+}
+
+#[test]
+fn test_store_and_lookup() {
+    let mut cache = HttpCache::new(None);
+    let load_data = LoadData::new(from_str("http://example.com/").unwrap());
+    match cache.lookup(&load_data) {
+        Miss => (),
+        _ => fail!("empty cache should miss"),
+    }
+
+    let mut entry = entry_with_headers(~[(~"Cache-Control", ~"max-age=3600")]);
+    entry.expires = entry.compute_expiry();
+    entry.body = ~[1, 2, 3];
+    cache.store(entry);
+    match cache.lookup(&load_data) {
+        Fresh(entry) => assert_eq!(entry.body, ~[1, 2, 3]),
+        _ => fail!("expected a fresh entry"),
+    }
+}
+
+#[test]
+fn test_hit_keeps_metadata() {
+    let mut entry = entry_with_headers(~[(~"Cache-Control", ~"max-age=3600"),
+                                         (~"X-Custom", ~"1")]);
+    entry.content_type = Some((~"text", ~"css"));
+    entry.charset = Some(~"utf-8");
+    entry.expires = entry.compute_expiry();
+
+    let (port, chan) = Chan::new();
+    entry.send(chan);
+    let metadata = port.recv().metadata;
+    assert_eq!(metadata.status.code(), 200);
+    assert_eq!(metadata.content_type, Some((~"text", ~"css")));
+    assert_eq!(metadata.charset, Some(~"utf-8"));
+    let headers = metadata.headers.unwrap();
+    assert!(headers.iter().any(|header| {
+        header.header_name() == ~"X-Custom" && header.header_value() == ~"1"
+    }));
+}
+
+#[test]
+fn test_eviction() {
+    let mut cache = HttpCache::new(None);
+    cache.max_size = 5;
+    for path in ["/a", "/b", "/c"].iter() {
+        let mut entry = entry_with_headers(~[(~"Cache-Control", ~"max-age=3600")]);
+        entry.url = from_str(format!("http://example.com{:s}", *path)).unwrap();
+        entry.expires = entry.compute_expiry();
+        entry.body = ~[0, 0];
+        cache.store(entry);
+        if *path == "/b" {
+            // Using `/a` makes `/b` the least recently used URL.
+            cache.lookup(&LoadData::new(from_str("http://example.com/a").unwrap()));
+        }
+    }
+    let is_cached = |cache: &mut HttpCache, url: &str| {
+        match cache.lookup(&LoadData::new(from_str(url).unwrap())) {
+            Miss => false,
+            _ => true,
+        }
+    };
+    assert!(is_cached(&mut cache, "http://example.com/a"));
+    assert!(!is_cached(&mut cache, "http://example.com/b"));
+    assert!(is_cached(&mut cache, "http://example.com/c"));
+}
+
+#[test]
+fn test_disk_variants() {
+    use extra::tempfile::TempDir;
+
+    let dir = TempDir::new("http_cache").unwrap();
+    let mut english = LoadData::new(from_str("http://example.com/").unwrap());
+    english.headers.extensions.insert(~"Accept-Language", ~"en");
+    let mut french = english.clone();
+    french.headers.extensions.insert(~"Accept-Language", ~"fr");
+
+    let mut disk = DiskCache::new(dir.path().clone(), MAX_DISK_SIZE);
+    for &(ref load_data, body) in [(english.clone(), 1u8), (french.clone(), 2u8)].iter() {
+        let mut entry = entry_with_headers(~[(~"Cache-Control", ~"max-age=3600"),
+                                             (~"Vary", ~"Accept-Language")]);
+        entry.vary = ~[(~"accept-language",
+                        request_header(&load_data.headers, "Accept-Language"))];
+        entry.expires = entry.compute_expiry();
+        entry.body = ~[body];
+        disk.write(&entry);
+    }
+
+    // A new disk cache only has what was written to disk.
+    let mut disk = DiskCache::new(dir.path().clone(), MAX_DISK_SIZE);
+    assert_eq!(disk.read(&english).unwrap().body, ~[1]);
+    assert_eq!(disk.read(&french).unwrap().body, ~[2]);
+}
+
+#[test]
+fn test_disk_eviction() {
+    use extra::tempfile::TempDir;
+    use std::vec;
+
+    let dir = TempDir::new("http_cache").unwrap();
+    let load_data = |path: &str| {
+        LoadData::new(from_str(format!("http://example.com{:s}", path)).unwrap())
+    };
+    let mut disk = DiskCache::new(dir.path().clone(), MAX_DISK_SIZE);
+    for path in ["/a", "/b", "/c"].iter() {
+        let mut entry = entry_with_headers(~[(~"Cache-Control", ~"max-age=3600")]);
+        entry.url = from_str(format!("http://example.com{:s}", *path)).unwrap();
+        entry.expires = entry.compute_expiry();
+        entry.body = vec::from_elem(100, 0u8);
+        disk.write(&entry);
+        match *path {
+            // Every URL takes the same room, and there is room for two and a half.
+            "/a" => disk.max_size = disk.size * 5 / 2,
+            // Using `/a` makes `/b` the least recently used URL.
+            "/b" => assert!(disk.read(&load_data("/a")).is_some()),
+            _ => (),
+        }
+    }
+    assert!(disk.size <= disk.max_size);
+    assert!(disk.read(&load_data("/a")).is_some());
+    assert!(disk.read(&load_data("/b")).is_none());
+    assert!(disk.read(&load_data("/c")).is_some());
+
+    // The files of `/b` are gone, and those left count against the budget of a new disk cache.
+    let disk_size = disk.size;
+    let disk = DiskCache::new(dir.path().clone(), MAX_DISK_SIZE);
+    assert_eq!(disk.size, disk_size);
+    assert_eq!(disk.sizes.len(), 4);
+}
@@ -2,29 +2,60 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use resource_task::{Metadata, Payload, Done, LoadResponse, LoaderTask, start_sending};
+use connection_pool;
+use connection_pool::PooledStream;
+use content_encoding::{ContentEncoding, Decoder, ACCEPT_ENCODING};
+use cookie::HTTP;
+use resource_task::{Metadata, Payload, Done, LoadData, LoadResponse, LoaderTask, ProgressMsg};
+use resource_task::{LoadError, NetworkError, Cancelled, DecodeError, UnsupportedScheme};
+use resource_task::CancellationHandle;
+use resource_task::start_sending;
+use resource_task::{ResourceTask, GetCookiesForUrl, SetCookiesForUrl};
 
+use std::ascii::StrAsciiExt;
 use std::vec;
 use std::hashmap::HashSet;
+use std::util;
 use extra::url::Url;
-use http::client::RequestWriter;
-use http::method::Get;
+use http::client::{RequestWriter, ResponseReader};
+use http::method::{Get, Head, Post};
 use http::headers::HeaderEnum;
-use std::io::Reader;
+use std::io::{Reader, Writer};
 use servo_util::task::spawn_named;
+use servo_util::url::make_url;
 
-pub fn factory() -> LoaderTask {
-    let f: LoaderTask = proc(url, start_chan) {
-        spawn_named("http_loader", proc() load(url, start_chan))
+#[cfg(test)]
+use resource_task::{Exit, Load, HttpStatusError, load_whole_resource};
+#[cfg(test)]
+use servo_util::time::Profiler;
+#[cfg(test)]
+use std::from_str::FromStr;
+#[cfg(test)]
+use std::io;
+#[cfg(test)]
+use std::io::net::ip::SocketAddr;
+#[cfg(test)]
+use std::io::net::tcp::{TcpListener, TcpStream};
+#[cfg(test)]
+use std::io::{Acceptor, Listener};
+#[cfg(test)]
+use std::str;
+
+/// The largest body we will read through to reuse a connection after a redirect.
+static MAX_DRAIN_SIZE: uint = 64 * 1024;
+
+pub fn factory(resource_task: ResourceTask) -> LoaderTask {
+    let f: LoaderTask = proc(load_data, start_chan) {
+        spawn_named("http_loader", proc() load(load_data, start_chan, resource_task))
     };
     f
 }
 
-fn send_error(url: Url, start_chan: Chan<LoadResponse>) {
-    start_sending(start_chan, Metadata::default(url)).send(Done(Err(())));
+fn send_error(url: Url, error: LoadError, start_chan: Chan<LoadResponse>) {
+    start_sending(start_chan, Metadata::default(url)).send(Done(Err(error)));
 }
 
-fn load(mut url: Url, start_chan: Chan<LoadResponse>) {
+fn load(mut load_data: LoadData, start_chan: Chan<LoadResponse>, resource_task: ResourceTask) {
     // FIXME: At the time of writing this FIXME, servo didn't have any central
     //        location for configuration. If you're reading this and such a
     //        repository DOES exist, please update this constant to use it.
@@ -32,6 +63,8 @@ fn load(mut url: Url, start_chan: Chan<LoadResponse>) {
     let mut iters = 0u;
 
     let mut redirected_to = HashSet::new();
+    let mut redirect_chain = ~[];
+    let mut url = load_data.url.clone();
 
     // Loop to handle redirects.
     loop {
@@ -39,27 +72,32 @@ fn load(mut url: Url, start_chan: Chan<LoadResponse>) {
 
         if iters > max_redirects {
             info!("too many redirects");
-            send_error(url, start_chan);
+            send_error(url, NetworkError(~"too many redirects"), start_chan);
             return;
         }
 
         if redirected_to.contains(&url) {
             info!("redirect loop");
-            send_error(url, start_chan);
+            send_error(url, NetworkError(~"redirect loop"), start_chan);
             return;
         }
 
         redirected_to.insert(url.clone());
 
+        if load_data.cancellation.is_cancelled() {
+            info!("load cancelled");
+            send_error(url, Cancelled, start_chan);
+            return;
+        }
+
         assert!("http" == url.scheme);
 
         info!("requesting {:s}", url.to_str());
 
-        let request = ~RequestWriter::new(Get, url.clone());
-        let mut response = match request.read_response() {
+        let mut response = match send_request(&load_data, &url, &resource_task) {
             Ok(r) => r,
-            Err(_) => {
-                send_error(url, start_chan);
+            Err(error) => {
+                send_error(url, error, start_chan);
                 return;
             }
         };
@@ -71,38 +109,546 @@ fn load(mut url: Url, start_chan: Chan<LoadResponse>) {
                 info!(" - {:s}: {:s}", header.header_name(), header.header_value());
             });
 
+        // Cookies are stored even when we are about to follow a redirect.
+        for header in response.headers.iter() {
+            if header.header_name().eq_ignore_ascii_case("Set-Cookie") {
+                resource_task.send(SetCookiesForUrl(url.clone(), header.header_value(), HTTP));
+            }
+        }
+
         if 3 == (response.status.code() / 100) {
-            match response.headers.location {
+            // rust-http only parses absolute URLs, so resolve the raw header ourselves.
+            let location = response.headers.iter()
+                .find(|header| header.header_name().eq_ignore_ascii_case("Location"))
+                .map(|header| make_url(header.header_value().as_slice(), Some(url.clone())));
+            match location {
                 Some(new_url) => {
-                    info!("redirecting to {:s}", new_url.to_str());
-                    url = new_url;
-                    continue;
+                    if prepare_redirect(response.status.code(), &mut load_data) {
+                        // This loader only speaks plain HTTP, and a server must not be able
+                        // to send us to a data: or file: URL anyway.
+                        if "http" != new_url.scheme {
+                            info!("not following redirect to {:s}", new_url.to_str());
+                            connection_pool::finish_exchange(false);
+                            let error = UnsupportedScheme(new_url.scheme.clone());
+                            send_error(new_url, error, start_chan);
+                            return;
+                        }
+                        info!("redirecting to {:s}", new_url.to_str());
+                        let reusable = drain(&mut response) && can_keep_alive(&response);
+                        connection_pool::finish_exchange(reusable);
+                        redirect_chain.push((url, response.status.clone()));
+                        url = new_url;
+                        continue;
+                    }
                 }
                 None => ()
             }
         }
 
+        let content_encoding = response.headers.iter()
+            .find(|header| header.header_name().eq_ignore_ascii_case("Content-Encoding"))
+            .map_default(Some(None), |header| {
+                ContentEncoding::parse(header.header_value().as_slice())
+            });
+        let decoder = match content_encoding {
+            Some(encoding) => encoding.map(|encoding| Decoder::new(encoding)),
+            None => {
+                info!("unsupported content encoding");
+                send_error(url, DecodeError(~"unsupported content encoding"), start_chan);
+                return;
+            }
+        };
+
         let mut metadata = Metadata::default(url);
         metadata.set_content_type(&response.headers.content_type);
+        metadata.headers = Some(*response.headers.clone());
+        metadata.status = response.status.clone();
+        metadata.redirect_chain = redirect_chain.clone();
 
         let progress_chan = start_sending(start_chan, metadata);
-        loop {
-            let mut buf = vec::with_capacity(1024);
+        let mut result =
+            send_body(&mut response, decoder, &load_data.cancellation, &progress_chan);
+        if result.is_ok() && connection_pool::connection_broke() {
+            info!("connection broke off during the response body");
+            result = Err(NetworkError(~"connection broke off"));
+        }
+
+        // Hand the connection back before the consumer hears that the load is over, so that
+        // its next load can use it.
+        connection_pool::finish_exchange(result.is_ok() && can_keep_alive(&response));
+        util::ignore(response);
+        progress_chan.send(Done(result));
+
+        // We didn't get redirected.
+        break;
+    }
+}
+
+/// Rewrites the request for the next hop of a redirect with the given status, following
+/// http://fetch.spec.whatwg.org/#http-redirect-fetch. Returns false for statuses that aren't
+/// followed, such as `300 Multiple Choices` and `304 Not Modified`.
+fn prepare_redirect(code: u16, load_data: &mut LoadData) -> bool {
+    let change_to_get = match code {
+        // Historically, browsers turn a POST into a GET on these, so servers expect it.
+        301 | 302 => load_data.method == Post,
+        303 => load_data.method != Head,
+        307 | 308 => false,
+        _ => return false,
+    };
+    if change_to_get {
+        load_data.method = Get;
+        load_data.data = None;
+        load_data.headers.content_type = None;
+        load_data.headers.content_length = None;
+    }
+    true
+}
+
+/// Makes one request over a pooled connection. A connection that sat idle in the pool may have
+/// been closed by the server, so requests without a body are retried until one gets an answer
+/// or has to use a new connection.
+fn send_request(load_data: &LoadData, url: &Url, resource_task: &ResourceTask)
+                -> Result<ResponseReader<PooledStream>, LoadError> {
+    loop {
+        connection_pool::start_exchange(resource_task.clone());
+
+        let mut request: ~RequestWriter<PooledStream> =
+            ~RequestWriter::new(load_data.method.clone(), url.clone());
+        request.headers = ~load_data.headers.clone();
+        match load_data.referrer {
+            Some(ref referrer) => {
+                request.headers.extensions.insert(~"Referer", referrer.to_str());
+            }
+            None => ()
+        }
+        let (cookie_port, cookie_chan) = Chan::new();
+        resource_task.send(GetCookiesForUrl(url.clone(), cookie_chan, HTTP));
+        match cookie_port.recv() {
+            Some(cookies) => {
+                request.headers.extensions.insert(~"Cookie", cookies);
+            }
+            None => ()
+        }
+        if !request.headers.extensions.contains_key(&~"Accept-Encoding") {
+            request.headers.extensions.insert(~"Accept-Encoding", ACCEPT_ENCODING.to_owned());
+        }
+        match load_data.data {
+            Some(ref data) => {
+                request.headers.content_length = Some(data.len());
+                request.write(data.as_slice());
+            }
+            None => ()
+        }
 
-            unsafe { buf.set_len(1024); }
-            match response.read(buf) {
-                Some(len) => {
-                    unsafe { buf.set_len(len); }
-                    progress_chan.send(Payload(buf));
+        match request.read_response() {
+            Ok(response) => return Ok(response),
+            Err(_) if connection_pool::connection_was_reused() && load_data.data.is_none() => {
+                info!("pooled connection was closed; retrying");
+            }
+            Err(_) => {
+                return Err(NetworkError(format!("no response from {:s}", url.host)));
+            }
+        }
+    }
+}
+
+/// Whether the server will keep the connection open after this response, and the response
+/// says where its body ends, so that the connection can carry another request.
+fn can_keep_alive(response: &ResponseReader<PooledStream>) -> bool {
+    let header_contains = |name: &str, value: &str| {
+        response.headers.iter().any(|header| {
+            header.header_name().eq_ignore_ascii_case(name) &&
+                header.header_value().to_ascii_lower().contains(value)
+        })
+    };
+    let persistent = match response.version {
+        (1, 0) => header_contains("Connection", "keep-alive"),
+        _ => !header_contains("Connection", "close"),
+    };
+    let code = response.status.code();
+    let delimited = code == 204 || code == 304 ||
+        header_contains("Content-Length", "") || header_contains("Transfer-Encoding", "chunked");
+    persistent && delimited
+}
+
+/// Reads and discards the body of a response we aren't interested in, such as a redirect, so
+/// that its connection can be reused. Gives up on bodies that are too large to be worth it.
+fn drain<R: Reader>(reader: &mut R) -> bool {
+    let mut buf = [0u8, ..1024];
+    let mut total = 0u;
+    loop {
+        match reader.read(buf) {
+            Some(len) => {
+                total += len;
+                if total > MAX_DRAIN_SIZE {
+                    return false;
+                }
+            }
+            None => return true,
+        }
+    }
+}
+
+/// Streams a response body to the consumer, decompressing it first if it has a content coding.
+/// Returns whether the whole body was sent; the caller sends `Done`.
+fn send_body<R: Reader>(reader: &mut R,
+                        mut decoder: Option<Decoder>,
+                        cancellation: &CancellationHandle,
+                        progress_chan: &SharedChan<ProgressMsg>) -> Result<(), LoadError> {
+    // Responses without a body, like those to `HEAD` or a 304, can still name a coding.
+    let mut empty = true;
+    loop {
+        if cancellation.is_cancelled() {
+            info!("load cancelled");
+            return Err(Cancelled);
+        }
+
+        let mut buf = vec::with_capacity(1024);
+
+        unsafe { buf.set_len(1024); }
+        match reader.read(buf) {
+            Some(len) => {
+                unsafe { buf.set_len(len); }
+                empty = empty && len == 0;
+                match decoder {
+                    Some(ref mut decoder) => match decoder.decode(buf) {
+                        Ok(data) => {
+                            if !data.is_empty() {
+                                progress_chan.send(Payload(data));
+                            }
+                        }
+                        Err(()) => {
+                            info!("error decoding response body");
+                            return Err(DecodeError(~"corrupt compressed body"));
+                        }
+                    },
+                    None => progress_chan.send(Payload(buf)),
                 }
-                None => {
-                    progress_chan.send(Done(Ok(())));
-                    break;
+            }
+            None => {
+                let truncated = !empty && decoder.as_ref().map_default(false, |decoder| {
+                    !decoder.is_finished()
+                });
+                if truncated {
+                    info!("compressed response body was truncated");
+                    return Err(DecodeError(~"truncated compressed body"));
                 }
+                return Ok(());
             }
         }
+    }
+}
 
-        // We didn't get redirected.
-        break;
+/// A stand-in for a server connection that hands out a body a few bytes at a time, the way
+/// a chunked response arrives.
+#[cfg(test)]
+struct ChunkedReader {
+    data: ~[u8],
+    position: uint,
+    chunk_size: uint,
+}
+
+#[cfg(test)]
+impl Reader for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Option<uint> {
+        if self.position == self.data.len() {
+            return None;
+        }
+        let len = ::std::cmp::min(::std::cmp::min(buf.len(), self.chunk_size),
+                                  self.data.len() - self.position);
+        buf.mut_slice_to(len).copy_from(self.data.slice(self.position, self.position + len));
+        self.position += len;
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+fn read_body(data: &[u8], decoder: Option<Decoder>) -> (~[u8], Result<(), LoadError>) {
+    let mut reader = ChunkedReader { data: data.to_owned(), position: 0, chunk_size: 9 };
+    let (port, chan) = SharedChan::new();
+    let result = send_body(&mut reader, decoder, &CancellationHandle::new(), &chan);
+    let mut body = ~[];
+    loop {
+        match port.try_recv() {
+            Some(Payload(data)) => body.push_all(data),
+            Some(Done(_)) => fail!("send_body shouldn't send Done"),
+            None => return (body, result),
+        }
+    }
+}
+
+#[test]
+fn test_send_chunked_gzip_body() {
+    use content_encoding::{Gzip, test_gzip_body, test_plain_body};
+
+    let (body, result) = read_body(test_gzip_body.as_slice(), Some(Decoder::new(Gzip)));
+    assert!(result.is_ok());
+    assert_eq!(body.as_slice(), test_plain_body().as_bytes());
+}
+
+#[test]
+fn test_send_identity_body() {
+    let (body, result) = read_body(bytes!("plain"), None);
+    assert!(result.is_ok());
+    assert_eq!(body.as_slice(), bytes!("plain"));
+}
+
+#[test]
+fn test_send_truncated_gzip_body() {
+    use content_encoding::{Gzip, test_gzip_body};
+
+    let truncated = test_gzip_body.slice_to(30);
+    let (_, result) = read_body(truncated, Some(Decoder::new(Gzip)));
+    assert_eq!(result, Err(DecodeError(~"truncated compressed body")));
+}
+
+#[test]
+fn test_send_empty_gzip_body() {
+    use content_encoding::Gzip;
+
+    let (body, result) = read_body([], Some(Decoder::new(Gzip)));
+    assert!(result.is_ok());
+    assert!(body.is_empty());
+}
+
+/// A local stand-in for an HTTP server. It answers each request with whatever `respond` makes
+/// of its head and body, and reports each connection it accepts. Sending on the returned
+/// channel stops it after its next connection.
+#[cfg(test)]
+fn start_test_server(respond: extern "Rust" fn(head: &str, body: &[u8]) -> ~str)
+                     -> (SocketAddr, Port<()>, Chan<()>) {
+    let mut listener = TcpListener::bind(FromStr::from_str("127.0.0.1:0").unwrap()).unwrap();
+    let addr = listener.socket_name().unwrap();
+    let acceptor = listener.listen().unwrap();
+    let (connection_port, connection_chan) = Chan::new();
+    let (stop_port, stop_chan) = Chan::new();
+    spawn_named("test_server", proc() {
+        let mut acceptor = acceptor;
+        loop {
+            let stream = acceptor.accept().unwrap();
+            if stop_port.try_recv().is_some() {
+                break;
+            }
+            connection_chan.send(());
+            spawn_named("test_server_connection", proc() serve_test_connection(stream, respond));
+        }
+    });
+    (addr, connection_port, stop_chan)
+}
+
+#[cfg(test)]
+fn serve_test_connection(mut stream: TcpStream,
+                         respond: extern "Rust" fn(head: &str, body: &[u8]) -> ~str) {
+    let _guard = io::ignore_io_error();
+    let mut request = ~[];
+    let mut buf = [0u8, ..1024];
+    loop {
+        match stream.read(buf) {
+            Some(len) => request.push_all(buf.slice_to(len)),
+            None => return,
+        }
+        loop {
+            let head_len = match request.windows(4).position(|window| {
+                window == bytes!("\r\n\r\n")
+            }) {
+                Some(end) => end + 4,
+                None => break,
+            };
+            let head = str::from_utf8(request.slice_to(head_len)).to_owned();
+            let body_len = head.to_ascii_lower().lines()
+                .find(|line| line.starts_with("content-length:"))
+                .and_then(|line| from_str::<uint>(line.slice_from(15).trim()))
+                .unwrap_or(0);
+            if request.len() < head_len + body_len {
+                break;
+            }
+            let response = respond(head, request.slice(head_len, head_len + body_len));
+            stream.write(response.as_bytes());
+            request = request.slice_from(head_len + body_len).to_owned();
+        }
+    }
+}
+
+#[cfg(test)]
+fn hello_response(_head: &str, _body: &[u8]) -> ~str {
+    ~"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+}
+
+/// Redirects `/see-other` with a 303 and `/temporary` with a 307, both to `/result`, which
+/// echoes the method and body it was requested with. `/to-file`, `/to-data` and `/to-https`
+/// redirect to URLs with those schemes.
+#[cfg(test)]
+fn redirecting_response(head: &str, body: &[u8]) -> ~str {
+    let request_line: ~[&str] = head.lines().next().unwrap().split(' ').collect();
+    match request_line[1] {
+        "/see-other" => {
+            ~"HTTP/1.1 303 See Other\r\nLocation: /result\r\nContent-Length: 0\r\n\r\n"
+        }
+        "/temporary" => {
+            ~"HTTP/1.1 307 Temporary Redirect\r\nLocation: result\r\nContent-Length: 0\r\n\r\n"
+        }
+        "/to-file" => {
+            ~"HTTP/1.1 302 Found\r\nLocation: file:///etc/passwd\r\nContent-Length: 0\r\n\r\n"
+        }
+        "/to-data" => {
+            ~"HTTP/1.1 302 Found\r\nLocation: data:,hi\r\nContent-Length: 0\r\n\r\n"
+        }
+        "/to-https" => {
+            ~"HTTP/1.1 301 Moved Permanently\r\nLocation: https://example.com/\r\n\
+              Content-Length: 0\r\n\r\n"
+        }
+        _ => {
+            let echo = format!("{:s} {:s}", request_line[0], str::from_utf8(body));
+            format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{:s}", echo.len(), echo)
+        }
+    }
+}
+
+#[cfg(test)]
+fn load_from_test_server(resource_task: &ResourceTask, load_data: LoadData)
+                         -> (Metadata, ~[u8]) {
+    let (start_port, start_chan) = Chan::new();
+    resource_task.send(Load(load_data, start_chan));
+    let response = start_port.recv();
+    let mut body = ~[];
+    loop {
+        match response.progress_port.recv() {
+            Payload(data) => body.push_all(data),
+            Done(Ok(())) => return (response.metadata, body),
+            Done(Err(error)) => fail!("load failed: {:s}", error.to_str()),
+        }
+    }
+}
+
+#[test]
+fn test_reuses_connection() {
+    let (addr, connections, stop) = start_test_server(hello_response);
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
+    for path in ["/one", "/two", "/three"].iter() {
+        let url = FromStr::from_str(format!("http://{:s}{:s}", addr.to_str(), *path)).unwrap();
+        match load_whole_resource(&resource_task, url) {
+            Ok((_, body)) => assert_eq!(body.as_slice(), bytes!("hello")),
+            Err(error) => fail!("load failed: {:s}", error.to_str()),
+        }
+    }
+    resource_task.send(Exit);
+
+    stop.send(());
+    TcpStream::connect(addr);
+    connections.recv();
+    assert!(connections.try_recv().is_none());
+}
+
+#[test]
+fn test_prepare_redirect() {
+    let url = FromStr::from_str("http://example.com/").unwrap();
+    let mut post = LoadData::new(url);
+    post.method = Post;
+    post.data = Some(bytes!("data").to_owned());
+
+    let mut load_data = post.clone();
+    assert!(prepare_redirect(301, &mut load_data));
+    assert!(load_data.method == Get && load_data.data.is_none());
+
+    let mut load_data = post.clone();
+    assert!(prepare_redirect(308, &mut load_data));
+    assert!(load_data.method == Post && load_data.data.is_some());
+
+    let mut load_data = post.clone();
+    load_data.method = Head;
+    assert!(prepare_redirect(303, &mut load_data));
+    assert!(load_data.method == Head);
+
+    let mut load_data = post.clone();
+    assert!(!prepare_redirect(304, &mut load_data));
+    assert!(!prepare_redirect(300, &mut load_data));
+}
+
+#[test]
+fn test_redirects() {
+    let (addr, _connections, stop) = start_test_server(redirecting_response);
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
+    let post_to = |path: &str| {
+        let url = FromStr::from_str(format!("http://{:s}{:s}", addr.to_str(), path)).unwrap();
+        let mut load_data = LoadData::new(url);
+        load_data.method = Post;
+        load_data.data = Some(bytes!("data").to_owned());
+        load_from_test_server(&resource_task, load_data)
+    };
+
+    let (metadata, body) = post_to("/see-other");
+    assert_eq!(body.as_slice(), bytes!("GET "));
+    assert_eq!(metadata.final_url.path, ~"/result");
+    assert_eq!(metadata.redirect_chain.len(), 1);
+    let (ref redirect_url, ref redirect_status) = metadata.redirect_chain[0];
+    assert_eq!(redirect_url.path, ~"/see-other");
+    assert_eq!(redirect_status.code(), 303);
+
+    // The relative Location is resolved against the URL that sent it.
+    let (metadata, body) = post_to("/temporary");
+    assert_eq!(body.as_slice(), bytes!("POST data"));
+    assert_eq!(metadata.final_url.path, ~"/result");
+    assert_eq!(metadata.redirect_chain.len(), 1);
+
+    // Redirects to other schemes fail the load instead of being followed.
+    for &(path, scheme) in [("/to-file", "file"), ("/to-data", "data"),
+                            ("/to-https", "https")].iter() {
+        let url = FromStr::from_str(format!("http://{:s}{:s}", addr.to_str(), path)).unwrap();
+        match load_whole_resource(&resource_task, url) {
+            Err(error) => assert_eq!(error, UnsupportedScheme(scheme.to_owned())),
+            Ok(..) => fail!("followed a redirect to {:s}:", scheme),
+        }
+    }
+    resource_task.send(Exit);
+
+    stop.send(());
+    TcpStream::connect(addr);
+}
+
+#[cfg(test)]
+fn empty_gzip_response(_head: &str, _body: &[u8]) -> ~str {
+    ~"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 0\r\n\r\n"
+}
+
+#[test]
+fn test_head_with_content_encoding() {
+    let (addr, _connections, stop) = start_test_server(empty_gzip_response);
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
+    let url = FromStr::from_str(format!("http://{:s}/", addr.to_str())).unwrap();
+    let mut load_data = LoadData::new(url);
+    load_data.method = Head;
+    let (metadata, body) = load_from_test_server(&resource_task, load_data);
+    assert_eq!(metadata.status.code(), 200);
+    assert!(body.is_empty());
+    resource_task.send(Exit);
+
+    stop.send(());
+    TcpStream::connect(addr);
+}
+
+#[cfg(test)]
+fn not_found_response(_head: &str, _body: &[u8]) -> ~str {
+    ~"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found"
+}
+
+#[test]
+fn test_error_status() {
+    let (addr, _connections, stop) = start_test_server(not_found_response);
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
+    let url: Url = FromStr::from_str(format!("http://{:s}/missing", addr.to_str())).unwrap();
+
+    // The load itself succeeds, so that the error page can be shown...
+    let (metadata, body) = load_from_test_server(&resource_task, LoadData::new(url.clone()));
+    assert_eq!(metadata.status.code(), 404);
+    assert_eq!(body.as_slice(), bytes!("not found"));
+
+    // ...but consumers that only want the resource see an error.
+    match load_whole_resource(&resource_task, url) {
+        Err(error) => assert_eq!(error, HttpStatusError(404)),
+        Ok(..) => fail!("404 response was treated as a success"),
     }
+    resource_task.send(Exit);
+
+    stop.send(());
+    TcpStream::connect(addr);
 }
@@ -2,7 +2,11 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use image::{bmp, gif, ico, jpeg, partial_png, webp};
+use sniffer::sniff_image;
+
 use std::iter::range_step;
+use std::vec;
 use stb_image = stb_image::image;
 use png;
 
@@ -19,6 +23,37 @@ pub fn Image(width: u32, height: u32, color_type: png::ColorType, data: ~[u8]) -
     }
 }
 
+/// The most memory the decoded pixels of one image may take up. Decoders check the size an
+/// image says it has against this before allocating anything for it.
+pub static MAX_DECODED_BYTES: uint = 256 * 1024 * 1024;
+
+/// Reads a little-endian 16-bit value, if `data` is long enough.
+pub fn u16_at(data: &[u8], offset: uint) -> Option<uint> {
+    if offset + 2 <= data.len() {
+        Some(data[offset] as uint | (data[offset + 1] as uint << 8))
+    } else {
+        None
+    }
+}
+
+/// Reads a little-endian 32-bit value, if `data` is long enough.
+pub fn u32_at(data: &[u8], offset: uint) -> Option<uint> {
+    match (u16_at(data, offset), u16_at(data, offset + 2)) {
+        (Some(low), Some(high)) => Some(low | (high << 16)),
+        _ => None,
+    }
+}
+
+/// Reads a big-endian 32-bit value, if `data` is long enough.
+pub fn u32_be_at(data: &[u8], offset: uint) -> Option<uint> {
+    if offset + 4 <= data.len() {
+        Some((data[offset] as uint << 24) | (data[offset + 1] as uint << 16) |
+             (data[offset + 2] as uint << 8) | data[offset + 3] as uint)
+    } else {
+        None
+    }
+}
+
 static TEST_IMAGE: &'static [u8] = include_bin!("test.jpeg");
 
 pub fn test_image_bin() -> ~[u8] {
@@ -40,28 +75,216 @@ fn byte_swap(color_type: png::ColorType, data: &mut [u8]) {
     }
 }
 
+/// Decodes an image with the decoder for the format its first bytes say it is in. Images we
+/// can't identify are left to stb_image, which knows a few more formats.
 pub fn load_from_memory(buffer: &[u8]) -> Option<Image> {
-    if png::is_png(buffer) {
-        match png::load_png_from_memory(buffer) {
-            Ok(mut png_image) => {
-                byte_swap(png_image.color_type, png_image.pixels);
-                Some(png_image)
+    let subtype = sniff_image(buffer).map(|(_, subtype)| subtype);
+    let image = match subtype.as_ref().map(|subtype| subtype.as_slice()) {
+        Some("png") => {
+            match png::load_png_from_memory(buffer) {
+                Ok(png_image) => Some(png_image),
+                Err(_err) => None,
             }
-            Err(_err) => None,
         }
-    } else {
-        // For non-png images, we use stb_image
-        // Can't remember why we do this. Maybe it's what cairo wants
-        static FORCE_DEPTH: uint = 4;
-
-        match stb_image::load_from_memory_with_depth(buffer, FORCE_DEPTH, true) {
-            stb_image::ImageU8(mut image) => {
-                assert!(image.depth == 4);
-                byte_swap(png::RGBA8, image.data);
-                Some(Image(image.width as u32, image.height as u32, png::RGBA8, image.data))
+        // The same decoder as for the partial images, so the pixels don't change when the
+        // last of the image arrives.
+        Some("jpeg") => jpeg::decode(buffer),
+        Some("bmp") => bmp::decode(buffer),
+        Some("x-icon") => ico::decode(buffer),
+        Some("webp") => webp::decode(buffer),
+        _ => load_with_stb_image(buffer),
+    };
+    match image {
+        Some(mut image) => {
+            byte_swap(image.color_type, image.pixels);
+            Some(image)
+        }
+        None => None,
+    }
+}
+
+/// Decodes what has arrived of an image that is still loading, so that it can be shown while
+/// the rest comes in. Returns `None` when there is nothing to show yet, and for formats that
+/// can't be shown in part.
+pub fn load_partial_from_memory(buffer: &[u8]) -> Option<Image> {
+    let subtype = sniff_image(buffer).map(|(_, subtype)| subtype);
+    let image = match subtype.as_ref().map(|subtype| subtype.as_slice()) {
+        Some("jpeg") => jpeg::decode(buffer),
+        Some("png") => partial_png::decode(buffer),
+        Some("gif") => {
+            gif::decode_partial(buffer).map(|gif| {
+                let (width, height) = (gif.width as u32, gif.height as u32);
+                let frame = gif.frames.move_iter().next().unwrap();
+                Image(width, height, png::RGBA8, frame.pixels)
+            })
+        }
+        _ => None,
+    };
+    match image {
+        Some(mut image) => {
+            byte_swap(image.color_type, image.pixels);
+            Some(image)
+        }
+        None => None,
+    }
+}
+
+/// Decodes an image to RGBA with stb_image.
+fn load_with_stb_image(buffer: &[u8]) -> Option<Image> {
+    // Can't remember why we do this. Maybe it's what cairo wants
+    static FORCE_DEPTH: uint = 4;
+
+    match stb_image::load_from_memory_with_depth(buffer, FORCE_DEPTH, true) {
+        stb_image::ImageU8(image) => {
+            assert!(image.depth == 4);
+            Some(Image(image.width as u32, image.height as u32, png::RGBA8, image.data))
+        }
+        stb_image::ImageF32(image) => {
+            // HDR images are in linear light; tone them down to 8 bit sRGB-ish values the way
+            // stb_image does for its own 8 bit loads.
+            assert!(image.depth == 4);
+            let data = image.data.iter().enumerate().map(|(i, &value)| {
+                let value = if i % 4 == 3 { value } else { value.powf(&(1.0 / 2.2)) };
+                let value = value * 255.0 + 0.5;
+                if value < 0.0 { 0 } else if value > 255.0 { 255 } else { value as u8 }
+            }).collect();
+            Some(Image(image.width as u32, image.height as u32, png::RGBA8, data))
+        }
+        stb_image::Error => None
+    }
+}
+
+/// Scales an image down to `width` by `height` pixels by averaging the pixels that fall into
+/// each new one, which keeps fine detail from turning into noise the way sampling would.
+/// Colors are weighted by alpha so transparent pixels don't darken the edges. Returns `None`
+/// for sizes bigger than the image, and for images that aren't RGBA.
+pub fn scale_down(image: &Image, width: u32, height: u32) -> Option<Image> {
+    match image.color_type {
+        png::RGBA8 => (),
+        _ => return None,
+    }
+    if width == 0 || height == 0 || width > image.width || height > image.height {
+        return None;
+    }
+
+    let (source_width, source_height) = (image.width as uint, image.height as uint);
+    let (width, height) = (width as uint, height as uint);
+    let mut pixels = vec::with_capacity(width * height * 4);
+    for y in range(0, height) {
+        let (top, bottom) = (y * source_height / height, (y + 1) * source_height / height);
+        for x in range(0, width) {
+            let (left, right) = (x * source_width / width, (x + 1) * source_width / width);
+            let mut sums = [0u, 0, 0, 0];
+            for source_y in range(top, bottom) {
+                for source_x in range(left, right) {
+                    let pixel = (source_y * source_width + source_x) * 4;
+                    let alpha = image.pixels[pixel + 3] as uint;
+                    for channel in range(0u, 3) {
+                        sums[channel] += image.pixels[pixel + channel] as uint * alpha;
+                    }
+                    sums[3] += alpha;
+                }
+            }
+
+            if sums[3] == 0 {
+                pixels.push_all(&[0u8, 0, 0, 0]);
+                continue;
+            }
+            for channel in range(0u, 3) {
+                pixels.push(((sums[channel] + sums[3] / 2) / sums[3]) as u8);
+            }
+            let count = (bottom - top) * (right - left);
+            pixels.push(((sums[3] + count / 2) / count) as u8);
+        }
+    }
+    Some(Image(width as u32, height as u32, png::RGBA8, pixels))
+}
+
+/// Browsers show frames with very short delays for 0.1 seconds instead, since many GIFs have
+/// delays of zero or 0.01 seconds that were never meant literally.
+static MIN_FRAME_DELAY_MS: uint = 20;
+static DEFAULT_FRAME_DELAY_MS: uint = 100;
+
+/// One frame of an image, shown for `delay_ms` before the next.
+pub struct ImageFrame {
+    image: Image,
+    delay_ms: uint,
+}
+
+/// The frames of an image. Still images have one frame.
+pub struct Animation {
+    frames: ~[ImageFrame],
+    /// How many times the frames repeat after playing once, or `None` to loop forever.
+    repetitions: Option<uint>,
+}
+
+/// Decodes every frame of an image. Only GIFs can have more than one.
+pub fn load_animation_from_memory(buffer: &[u8]) -> Option<Animation> {
+    if sniff_image(buffer) == Some((~"image", ~"gif")) {
+        match gif::decode(buffer) {
+            Some(gif) => {
+                let (width, height) = (gif.width as u32, gif.height as u32);
+                let frames = gif.frames.move_iter().map(|frame| {
+                    let mut pixels = frame.pixels;
+                    byte_swap(png::RGBA8, pixels);
+                    let delay_ms = frame.delay_cs * 10;
+                    ImageFrame {
+                        image: Image(width, height, png::RGBA8, pixels),
+                        delay_ms: if delay_ms < MIN_FRAME_DELAY_MS {
+                            DEFAULT_FRAME_DELAY_MS
+                        } else {
+                            delay_ms
+                        },
+                    }
+                }).collect();
+                return Some(Animation {
+                    frames: frames,
+                    repetitions: gif.repetitions,
+                });
             }
-            stb_image::ImageF32(_image) => fail!(~"HDR images not implemented"),
-            stb_image::Error => None
+            None => debug!("image: falling back to stb_image for a GIF we couldn't decode"),
         }
     }
+    load_from_memory(buffer).map(|image| {
+        Animation {
+            frames: ~[ImageFrame { image: image, delay_ms: 0 }],
+            repetitions: Some(0),
+        }
+    })
+}
+
+#[test]
+fn test_load_animation() {
+    let animation = load_animation_from_memory(gif::test_animated_gif()).unwrap();
+    assert_eq!(animation.repetitions, Some(1));
+    assert_eq!(animation.frames.len(), 2);
+    // Red, in BGRA order.
+    assert_eq!(animation.frames[0].image.pixels, ~[0, 0, 0xFF, 0xFF]);
+    assert_eq!(animation.frames[0].delay_ms, 100);
+    assert_eq!(animation.frames[1].delay_ms, 200);
+
+    let still = load_animation_from_memory(test_image_bin()).unwrap();
+    assert_eq!(still.frames.len(), 1);
+}
+
+#[test]
+fn test_load_partial() {
+    let jpeg = test_image_bin();
+    let image = load_partial_from_memory(jpeg.slice_to(jpeg.len() * 2 / 3)).unwrap();
+    assert_eq!((image.width, image.height), (450, 337));
+    // Too little to know the size.
+    assert!(load_partial_from_memory(jpeg.slice_to(16)).is_none());
+}
+
+#[test]
+fn test_scale_down() {
+    // Opaque red and blue over transparent green.
+    let image = Image(2, 2, png::RGBA8, ~[0xFF, 0, 0, 0xFF,  0, 0, 0xFF, 0xFF,
+                                          0, 0xFF, 0, 0,     0, 0xFF, 0, 0]);
+    // The transparent pixels thin out the color without tinting it.
+    let scaled = scale_down(&image, 2, 1).unwrap();
+    assert_eq!(scaled.pixels, ~[0xFF, 0, 0, 0x80, 0, 0, 0xFF, 0x80]);
+    let scaled = scale_down(&image, 1, 1).unwrap();
+    assert_eq!(scaled.pixels, ~[0x80, 0, 0x80, 0x80]);
+    assert!(scale_down(&image, 3, 1).is_none());
 }
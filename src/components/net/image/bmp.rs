@@ -0,0 +1,395 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A decoder for Windows bitmaps: BMP files, and the bitmaps stored inside ICO files. Handles
+//! the core, info and V2 to V5 headers with 1 to 32 bits per pixel, bit fields and RLE
+//! compression.
+
+use image::base::{Image, u16_at, u32_at};
+
+use png;
+use std::vec;
+
+static BI_RGB: uint = 0;
+static BI_RLE8: uint = 1;
+static BI_RLE4: uint = 2;
+static BI_BITFIELDS: uint = 3;
+static BI_ALPHABITFIELDS: uint = 6;
+
+/// The largest width or height we decode, which keeps the pixel buffer a sane size.
+static MAX_DIMENSION: uint = 16384;
+
+/// Decodes a BMP file.
+pub fn decode(buffer: &[u8]) -> Option<Image> {
+    if !buffer.starts_with(bytes!("BM")) {
+        return None;
+    }
+    match u32_at(buffer, 10) {
+        Some(pixel_offset) => decode_dib(buffer, 14, Some(pixel_offset), false),
+        None => None,
+    }
+}
+
+/// Decodes a bitmap from an ICO file. These have no file header, are twice as tall as the
+/// image, and end with a one bit transparency mask.
+pub fn decode_icon_bitmap(data: &[u8]) -> Option<Image> {
+    decode_dib(data, 0, None, true)
+}
+
+/// A channel of a pixel stored in the bits set in a mask.
+struct BitField {
+    shift: uint,
+    bits: uint,
+}
+
+impl BitField {
+    fn new(mask: uint) -> BitField {
+        if mask == 0 {
+            return BitField { shift: 0, bits: 0 };
+        }
+        let mut shift = 0;
+        while (mask >> shift) & 1 == 0 {
+            shift += 1;
+        }
+        let mut bits = 0;
+        while bits + shift < 32 && (mask >> (shift + bits)) & 1 == 1 {
+            bits += 1;
+        }
+        BitField { shift: shift, bits: bits }
+    }
+
+    /// The value of the channel, scaled to eight bits.
+    fn read(&self, pixel: uint) -> u8 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let value = (pixel >> self.shift) & ((1 << self.bits) - 1);
+        if self.bits >= 8 {
+            (value >> (self.bits - 8)) as u8
+        } else {
+            (value * 255 / ((1 << self.bits) - 1)) as u8
+        }
+    }
+}
+
+/// Decodes the bitmap whose header starts at `header_offset`. The pixels start at
+/// `pixel_offset`, or straight after the color table if that isn't given.
+fn decode_dib(data: &[u8],
+              header_offset: uint,
+              pixel_offset: Option<uint>,
+              icon: bool)
+              -> Option<Image> {
+    let header_size = match u32_at(data, header_offset) {
+        Some(size) if size == 12 || (size >= 40 && size <= 124) => size,
+        _ => return None,
+    };
+    let header = data.slice_from(header_offset);
+
+    let (width, signed_height, bpp, compression, colors_used) = if header_size == 12 {
+        match (u16_at(header, 4), u16_at(header, 6), u16_at(header, 10)) {
+            (Some(w), Some(h), Some(b)) => (w as int, h as i16 as int, b, BI_RGB, 0),
+            _ => return None,
+        }
+    } else {
+        match (u32_at(header, 4), u32_at(header, 8), u16_at(header, 14), u32_at(header, 16),
+               u32_at(header, 32)) {
+            (Some(w), Some(h), Some(b), Some(c), Some(colors)) => {
+                (w as i32 as int, h as i32 as int, b, c, colors)
+            }
+            _ => return None,
+        }
+    };
+
+    // Icon bitmaps store the height of the image and its mask together.
+    let signed_height = if icon { signed_height / 2 } else { signed_height };
+    let top_down = signed_height < 0;
+    let height = if top_down { -signed_height } else { signed_height };
+    if width <= 0 || height <= 0 ||
+            width as uint > MAX_DIMENSION || height as uint > MAX_DIMENSION {
+        return None;
+    }
+    let (width, height) = (width as uint, height as uint);
+
+    // Bit field masks are part of the newer headers, and follow the info header.
+    let mut position = header_offset + header_size;
+    let masks = match compression {
+        BI_BITFIELDS | BI_ALPHABITFIELDS if bpp == 16 || bpp == 32 => {
+            let count = if compression == BI_ALPHABITFIELDS { 4 } else { 3 };
+            let masks_offset = if header_size >= 52 {
+                header_offset + 40
+            } else {
+                position += count * 4;
+                header_offset + header_size
+            };
+            let mut masks = ~[];
+            for i in range(0, 4) {
+                if i < count || header_size >= 56 {
+                    match u32_at(data, masks_offset + i * 4) {
+                        Some(mask) => masks.push(mask),
+                        None => return None,
+                    }
+                } else {
+                    masks.push(0);
+                }
+            }
+            masks
+        }
+        BI_RGB if bpp == 16 => ~[0x7C00, 0x03E0, 0x001F, 0],
+        BI_RGB if bpp == 24 => ~[0xFF0000, 0x00FF00, 0x0000FF, 0],
+        BI_RGB if bpp == 32 => ~[0xFF0000, 0x00FF00, 0x0000FF, 0xFF000000],
+        BI_RGB if bpp == 1 || bpp == 4 || bpp == 8 => ~[],
+        BI_RLE8 if bpp == 8 => ~[],
+        BI_RLE4 if bpp == 4 => ~[],
+        _ => {
+            debug!("bmp: unsupported compression {:u} at {:u} bits per pixel", compression, bpp);
+            return None;
+        }
+    };
+
+    let mut palette = ~[];
+    if bpp <= 8 {
+        let entry_size = if header_size == 12 { 3 } else { 4 };
+        let max_colors = 1u << bpp;
+        let count = if colors_used == 0 || colors_used > max_colors {
+            max_colors
+        } else {
+            colors_used
+        };
+        for i in range(0, count) {
+            let entry = position + i * entry_size;
+            if entry + 3 > data.len() {
+                return None;
+            }
+            palette.push([data[entry + 2], data[entry + 1], data[entry], 0xFF]);
+        }
+        position += count * entry_size;
+    }
+
+    let pixel_offset = pixel_offset.unwrap_or(position);
+    if pixel_offset > data.len() {
+        return None;
+    }
+    let pixel_data = data.slice_from(pixel_offset);
+
+    // Rows are stored bottom-up unless the height is negative.
+    let rows: ~[uint] = range(0, height).map(|row| {
+        if top_down { row } else { height - 1 - row }
+    }).collect();
+    let mut pixels = vec::from_elem(width * height * 4, 0u8);
+    let mut has_alpha = false;
+
+    if compression == BI_RLE8 || compression == BI_RLE4 {
+        if !decode_rle(pixel_data, compression == BI_RLE4, width, height, |x, row, index| {
+            let color = if (index as uint) < palette.len() {
+                palette[index as uint]
+            } else {
+                [0, 0, 0, 0xFF]
+            };
+            let offset = (rows[row] * width + x) * 4;
+            pixels.mut_slice(offset, offset + 4).copy_from(&color);
+        }) {
+            return None;
+        }
+        // Pixels the runs skip over stay transparent.
+    } else {
+        let stride = (width * bpp + 31) / 32 * 4;
+        if pixel_data.len() < stride * height {
+            return None;
+        }
+        let fields: ~[BitField] = masks.iter().map(|&mask| BitField::new(mask)).collect();
+        for row in range(0, height) {
+            let source = pixel_data.slice(row * stride, (row + 1) * stride);
+            let y = rows[row];
+            for x in range(0, width) {
+                let color = match bpp {
+                    1 | 4 | 8 => {
+                        let bit = x * bpp;
+                        let byte = source[bit / 8] as uint;
+                        let index = (byte >> (8 - bpp - bit % 8)) & ((1 << bpp) - 1);
+                        if index < palette.len() { palette[index] } else { [0, 0, 0, 0xFF] }
+                    }
+                    16 | 24 | 32 => {
+                        let pixel = match bpp {
+                            16 => source[x * 2] as uint | (source[x * 2 + 1] as uint << 8),
+                            24 => {
+                                source[x * 3] as uint | (source[x * 3 + 1] as uint << 8) |
+                                    (source[x * 3 + 2] as uint << 16)
+                            }
+                            _ => {
+                                source[x * 4] as uint | (source[x * 4 + 1] as uint << 8) |
+                                    (source[x * 4 + 2] as uint << 16) |
+                                    (source[x * 4 + 3] as uint << 24)
+                            }
+                        };
+                        let alpha = if fields[3].bits > 0 {
+                            let alpha = fields[3].read(pixel);
+                            has_alpha = has_alpha || alpha != 0;
+                            alpha
+                        } else {
+                            0xFF
+                        };
+                        [fields[0].read(pixel), fields[1].read(pixel), fields[2].read(pixel),
+                         alpha]
+                    }
+                    _ => return None,
+                };
+                let offset = (y * width + x) * 4;
+                pixels.mut_slice(offset, offset + 4).copy_from(&color);
+            }
+        }
+
+        // Most 32 bit bitmaps leave the alpha byte zeroed, meaning they are opaque.
+        if fields.len() == 4 && fields[3].bits > 0 && !has_alpha {
+            for i in range(0, width * height) {
+                pixels[i * 4 + 3] = 0xFF;
+            }
+        }
+
+        // The mask of an icon makes pixels transparent, unless the colors have their own alpha.
+        if icon && !has_alpha {
+            let mask_stride = (width + 31) / 32 * 4;
+            let mask_data = pixel_data.slice_from(stride * height);
+            if mask_data.len() >= mask_stride * height {
+                for row in range(0, height) {
+                    let y = rows[row];
+                    for x in range(0, width) {
+                        let mask = mask_data[row * mask_stride + x / 8] as uint;
+                        if mask & (0x80 >> (x % 8)) != 0 {
+                            let offset = (y * width + x) * 4;
+                            pixels.mut_slice(offset, offset + 4).copy_from(&[0, 0, 0, 0]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Some(Image(width as u32, height as u32, png::RGBA8, pixels))
+}
+
+/// Decodes run-length encoded palette indices, calling `set_pixel(x, row, index)` for each
+/// pixel that is drawn. Rows count from the first one stored. Returns false if the data is
+/// corrupt; running out of data early just leaves the rest of the image empty.
+fn decode_rle(data: &[u8],
+              four_bit: bool,
+              width: uint,
+              height: uint,
+              set_pixel: |uint, uint, u8|)
+              -> bool {
+    let (mut x, mut row, mut i) = (0u, 0u, 0u);
+    let index_at = |byte: u8, n: uint| {
+        if four_bit {
+            if n % 2 == 0 { byte >> 4 } else { byte & 0x0F }
+        } else {
+            byte
+        }
+    };
+    while i + 1 < data.len() && row < height {
+        let (count, value) = (data[i] as uint, data[i + 1]);
+        i += 2;
+        if count > 0 {
+            for n in range(0, count) {
+                if x < width {
+                    set_pixel(x, row, index_at(value, n));
+                }
+                x += 1;
+            }
+            continue;
+        }
+        match value {
+            0 => {
+                x = 0;
+                row += 1;
+            }
+            1 => return true,
+            2 => {
+                if i + 1 >= data.len() {
+                    return false;
+                }
+                x += data[i] as uint;
+                row += data[i + 1] as uint;
+                i += 2;
+            }
+            _ => {
+                // An absolute run, padded to a whole number of 16 bit words.
+                let count = value as uint;
+                let len = if four_bit { (count + 1) / 2 } else { count };
+                if i + len > data.len() {
+                    return false;
+                }
+                for n in range(0, count) {
+                    let byte = if four_bit { data[i + n / 2] } else { data[i + n] };
+                    if x < width {
+                        set_pixel(x, row, index_at(byte, n));
+                    }
+                    x += 1;
+                }
+                i += (len + 1) & !1;
+            }
+        }
+    }
+    true
+}
+
+/// A 2x2 bitmap with the given bits per pixel and pixel data, and a two color palette when
+/// `bpp` is below 16.
+#[cfg(test)]
+fn test_bitmap(bpp: u16, compression: u32, palette: &[u8], pixels: &[u8]) -> ~[u8] {
+    fn le16(value: u16) -> ~[u8] {
+        ~[value as u8, (value >> 8) as u8]
+    }
+    fn le32(value: u32) -> ~[u8] {
+        ~[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+    }
+    let pixel_offset = 14 + 40 + palette.len() as u32;
+    let mut data = bytes!("BM").to_owned();
+    data.push_all(le32(pixel_offset + pixels.len() as u32));
+    data.push_all(le32(0));
+    data.push_all(le32(pixel_offset));
+    data.push_all(le32(40));
+    data.push_all(le32(2));
+    data.push_all(le32(2));
+    data.push_all(le16(1));
+    data.push_all(le16(bpp));
+    data.push_all(le32(compression));
+    data.push_all(le32(pixels.len() as u32));
+    data.push_all(le32(0));
+    data.push_all(le32(0));
+    data.push_all(le32((palette.len() / 4) as u32));
+    data.push_all(le32(0));
+    data.push_all(palette);
+    data.push_all(pixels);
+    data
+}
+
+#[test]
+fn test_decode_24bit() {
+    // Bottom-up rows of blue-green-red pixels, padded to four bytes.
+    let bitmap = test_bitmap(24, BI_RGB as u32, &[], &[0, 0, 0xFF, 0, 0xFF, 0, 0, 0,
+                                                       0xFF, 0, 0, 0xFF, 0xFF, 0xFF, 0, 0]);
+    let image = decode(bitmap).unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(image.pixels, ~[0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+                               0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF]);
+}
+
+#[test]
+fn test_decode_paletted() {
+    let palette = &[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0];
+    let bitmap = test_bitmap(1, BI_RGB as u32, palette, &[0x40, 0, 0, 0, 0x80, 0, 0, 0]);
+    let image = decode(bitmap).unwrap();
+    assert_eq!(image.pixels, ~[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF,
+                               0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+    // The same image, run-length encoded.
+    let bitmap = test_bitmap(8, BI_RLE8 as u32, palette, &[1, 0, 1, 1, 0, 0, 1, 1, 1, 0, 0, 1]);
+    let rle = decode(bitmap).unwrap();
+    assert_eq!(rle.pixels, image.pixels);
+}
+
+#[test]
+fn test_decode_truncated() {
+    let bitmap = test_bitmap(24, BI_RGB as u32, &[], &[0, 0, 0xFF, 0]);
+    assert!(decode(bitmap).is_none());
+}
@@ -0,0 +1,572 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A GIF decoder that produces every frame of an animation, composited onto the logical screen
+//! the way http://www.w3.org/Graphics/GIF/spec-gif89a.txt describes. stb_image only decodes
+//! the first frame.
+
+use image::base::MAX_DECODED_BYTES;
+
+use std::vec;
+
+/// The largest LZW code; codes are at most 12 bits wide.
+static MAX_CODES: uint = 4096;
+
+/// The largest width or height we decode, which keeps the canvas a sane size.
+static MAX_DIMENSION: uint = 16384;
+
+/// A decoded frame, as RGBA pixels covering the whole logical screen.
+pub struct GifFrame {
+    pixels: ~[u8],
+    /// How long the frame is shown, in hundredths of a second.
+    delay_cs: uint,
+}
+
+pub struct Gif {
+    width: uint,
+    height: uint,
+    frames: ~[GifFrame],
+    /// How many times the animation repeats after playing once, or `None` to loop forever.
+    repetitions: Option<uint>,
+}
+
+pub fn is_gif(buffer: &[u8]) -> bool {
+    buffer.starts_with(bytes!("GIF87a")) || buffer.starts_with(bytes!("GIF89a"))
+}
+
+/// How a frame is cleared away before the next one is drawn.
+#[deriving(Eq)]
+enum Disposal {
+    /// Leave the frame in place.
+    Keep,
+    /// Clear the frame's area to transparent.
+    Background,
+    /// Put back what was there before the frame was drawn.
+    Previous,
+}
+
+/// The state set by a graphic control extension, which applies to the next image.
+struct GraphicControl {
+    disposal: Disposal,
+    transparent_index: Option<u8>,
+    delay_cs: uint,
+}
+
+impl GraphicControl {
+    fn new() -> GraphicControl {
+        GraphicControl {
+            disposal: Keep,
+            transparent_index: None,
+            delay_cs: 0,
+        }
+    }
+}
+
+struct GifReader<'a> {
+    data: &'a [u8],
+    position: uint,
+    /// Whether the data may have been cut short because it is still loading, in which case
+    /// image data that ends early is taken as it is.
+    partial: bool,
+}
+
+impl<'a> GifReader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        if self.position < self.data.len() {
+            self.position += 1;
+            Some(self.data[self.position - 1])
+        } else {
+            None
+        }
+    }
+
+    fn u16(&mut self) -> Option<uint> {
+        match (self.byte(), self.byte()) {
+            (Some(low), Some(high)) => Some(low as uint | (high as uint << 8)),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self, len: uint) -> Option<&'a [u8]> {
+        if self.position + len <= self.data.len() {
+            self.position += len;
+            Some(self.data.slice(self.position - len, self.position))
+        } else {
+            None
+        }
+    }
+
+    /// Reads a sequence of data sub-blocks, up to the empty block that ends it.
+    fn sub_blocks(&mut self) -> Option<~[u8]> {
+        let mut data = ~[];
+        loop {
+            let len = match self.byte() {
+                Some(len) => len as uint,
+                None if self.partial => return Some(data),
+                None => return None,
+            };
+            if len == 0 {
+                return Some(data);
+            }
+            match self.bytes(len) {
+                Some(block) => data.push_all(block),
+                None if self.partial => {
+                    data.push_all(self.data.slice_from(self.position));
+                    self.position = self.data.len();
+                    return Some(data);
+                }
+                None => return None,
+            }
+        }
+    }
+
+    fn color_table(&mut self, flags: u8) -> Option<&'a [u8]> {
+        let size = 1u << ((flags & 0x07) as uint + 1);
+        self.bytes(size * 3)
+    }
+}
+
+/// Decodes all the frames of a GIF. Returns `None` if the data is malformed before the first
+/// frame is complete, or if the frames would take up more than `MAX_DECODED_BYTES`; frames after
+/// a corrupt one, or one that reaches outside the logical screen, are dropped.
+pub fn decode(buffer: &[u8]) -> Option<Gif> {
+    read(buffer, false)
+}
+
+/// Decodes as much of the first frame of a GIF as has arrived, leaving the rest transparent.
+pub fn decode_partial(buffer: &[u8]) -> Option<Gif> {
+    read(buffer, true)
+}
+
+fn read(buffer: &[u8], partial: bool) -> Option<Gif> {
+    if !is_gif(buffer) {
+        return None;
+    }
+    let mut reader = GifReader { data: buffer, position: 6, partial: partial };
+
+    let (width, height) = match (reader.u16(), reader.u16()) {
+        (Some(width), Some(height)) if width > 0 && height > 0 &&
+                width <= MAX_DIMENSION && height <= MAX_DIMENSION => (width, height),
+        _ => return None,
+    };
+    // The canvas and at least one frame have to fit. Each frame is a copy of the whole canvas,
+    // so a long animation on a large screen is refused.
+    let canvas_bytes = width * height * 4;
+    if canvas_bytes * 2 > MAX_DECODED_BYTES {
+        return None;
+    }
+    let flags = match reader.bytes(3) {
+        Some(screen) => screen[0],
+        None => return None,
+    };
+    let global_colors = if flags & 0x80 != 0 {
+        match reader.color_table(flags) {
+            Some(table) => Some(table),
+            None => return None,
+        }
+    } else {
+        None
+    };
+
+    let mut canvas = vec::from_elem(canvas_bytes, 0u8);
+    let mut decoded_bytes = canvas_bytes;
+    let mut frames = ~[];
+    let mut repetitions = Some(0);
+    let mut control = GraphicControl::new();
+    loop {
+        match reader.byte() {
+            // Extension.
+            Some(0x21) => {
+                let label = reader.byte();
+                let data = match reader.sub_blocks() {
+                    Some(data) => data,
+                    None => break,
+                };
+                match label {
+                    Some(0xF9) if data.len() >= 4 => {
+                        control.disposal = match (data[0] >> 2) & 0x07 {
+                            2 => Background,
+                            3 => Previous,
+                            _ => Keep,
+                        };
+                        control.delay_cs = data[1] as uint | (data[2] as uint << 8);
+                        control.transparent_index = if data[0] & 0x01 != 0 {
+                            Some(data[3])
+                        } else {
+                            None
+                        };
+                    }
+                    // The Netscape looping extension: the identifier, then a sub-block of 1
+                    // and the repeat count, where 0 means forever.
+                    Some(0xFF) if data.len() >= 14 && data.starts_with(bytes!("NETSCAPE2.0")) &&
+                            data[11] == 1 => {
+                        repetitions = match data[12] as uint | (data[13] as uint << 8) {
+                            0 => None,
+                            count => Some(count),
+                        };
+                    }
+                    _ => (),
+                }
+            }
+            // Image.
+            Some(0x2C) => {
+                decoded_bytes += canvas_bytes;
+                if decoded_bytes > MAX_DECODED_BYTES {
+                    return None;
+                }
+                match decode_frame(&mut reader, global_colors, &control, width, height,
+                                   canvas.mut_slice_from(0)) {
+                    Some(frame) => frames.push(frame),
+                    None => break,
+                }
+                if partial {
+                    break;
+                }
+                control = GraphicControl::new();
+            }
+            // Trailer, or anything we don't understand.
+            _ => break,
+        }
+    }
+
+    if frames.is_empty() {
+        return None;
+    }
+    Some(Gif {
+        width: width,
+        height: height,
+        frames: frames,
+        repetitions: repetitions,
+    })
+}
+
+/// Draws the image that starts at the reader's position onto the canvas, returns the canvas as
+/// a frame, and then disposes of the image as its graphic control says.
+fn decode_frame(reader: &mut GifReader,
+                global_colors: Option<&[u8]>,
+                control: &GraphicControl,
+                width: uint,
+                height: uint,
+                canvas: &mut [u8])
+                -> Option<GifFrame> {
+    let (left, top, frame_width, frame_height) =
+        match (reader.u16(), reader.u16(), reader.u16(), reader.u16()) {
+            (Some(left), Some(top), Some(frame_width), Some(frame_height))
+                    if left + frame_width <= width && top + frame_height <= height => {
+                (left, top, frame_width, frame_height)
+            }
+            _ => return None,
+        };
+    // The frame lies within the screen, so its color indices take up no more than a quarter of
+    // the canvas, which the caller has already made room for.
+    let len = frame_width * frame_height;
+    if len > MAX_DECODED_BYTES {
+        return None;
+    }
+    let flags = match reader.byte() {
+        Some(flags) => flags,
+        None => return None,
+    };
+    let colors = if flags & 0x80 != 0 {
+        match reader.color_table(flags) {
+            Some(table) => table,
+            None => return None,
+        }
+    } else {
+        match global_colors {
+            Some(table) => table,
+            None => return None,
+        }
+    };
+    let interlaced = flags & 0x40 != 0;
+    let min_code_size = match reader.byte() {
+        Some(size) => size,
+        None => return None,
+    };
+    let data = match reader.sub_blocks() {
+        Some(data) => data,
+        None => return None,
+    };
+    let indices = if reader.partial {
+        lzw_decode_prefix(min_code_size, data, len)
+    } else {
+        lzw_decode(min_code_size, data, len)
+    };
+    let indices = match indices {
+        Some(indices) => indices,
+        None => return None,
+    };
+
+    let saved = if control.disposal == Previous { Some(canvas.to_owned()) } else { None };
+    let rows = if interlaced {
+        interlaced_rows(frame_height)
+    } else {
+        range(0, frame_height).collect()
+    };
+    for (i, &row) in rows.iter().enumerate() {
+        let y = top + row;
+        for column in range(0, frame_width) {
+            let x = left + column;
+            if i * frame_width + column >= indices.len() {
+                break;
+            }
+            let index = indices[i * frame_width + column];
+            if Some(index) == control.transparent_index {
+                continue;
+            }
+            let color = index as uint * 3;
+            if color + 2 >= colors.len() {
+                continue;
+            }
+            let pixel = (y * width + x) * 4;
+            canvas[pixel] = colors[color];
+            canvas[pixel + 1] = colors[color + 1];
+            canvas[pixel + 2] = colors[color + 2];
+            canvas[pixel + 3] = 0xFF;
+        }
+    }
+    let frame = GifFrame {
+        pixels: canvas.to_owned(),
+        delay_cs: control.delay_cs,
+    };
+
+    match control.disposal {
+        Keep => (),
+        Background => {
+            for y in range(top, top + frame_height) {
+                for x in range(left, left + frame_width) {
+                    let pixel = (y * width + x) * 4;
+                    canvas.mut_slice(pixel, pixel + 4).copy_from([0, 0, 0, 0]);
+                }
+            }
+        }
+        Previous => {
+            canvas.copy_from(saved.unwrap());
+        }
+    }
+    Some(frame)
+}
+
+/// The rows of an interlaced image, in the order they are stored.
+fn interlaced_rows(height: uint) -> ~[uint] {
+    let mut rows = ~[];
+    for &(start, step) in [(0u, 8u), (4, 8), (2, 4), (1, 2)].iter() {
+        let mut row = start;
+        while row < height {
+            rows.push(row);
+            row += step;
+        }
+    }
+    rows
+}
+
+/// Decompresses GIF image data into `len` color indices. Data that ends early leaves the rest
+/// of the image as index 0, as other decoders do.
+fn lzw_decode(min_code_size: u8, data: &[u8], len: uint) -> Option<~[u8]> {
+    lzw_decode_prefix(min_code_size, data, len).map(|mut output| {
+        while output.len() < len {
+            output.push(0);
+        }
+        output
+    })
+}
+
+/// Decompresses up to `len` color indices, as many as the data holds.
+fn lzw_decode_prefix(min_code_size: u8, data: &[u8], len: uint) -> Option<~[u8]> {
+    if min_code_size < 1 || min_code_size > 11 {
+        return None;
+    }
+    let clear_code = 1u << (min_code_size as uint);
+    let end_code = clear_code + 1;
+
+    // Each code is a string made of an earlier code's string plus one byte.
+    let mut prefix = vec::from_elem(MAX_CODES, 0u16);
+    let mut suffix = vec::from_elem(MAX_CODES, 0u8);
+    let mut first = vec::from_elem(MAX_CODES, 0u8);
+    for code in range(0, clear_code) {
+        suffix[code] = code as u8;
+        first[code] = code as u8;
+    }
+
+    let mut output = vec::with_capacity(len);
+    let mut stack = ~[];
+    let mut code_size = min_code_size as uint + 1;
+    let mut next_code = clear_code + 2;
+    let mut previous: Option<uint> = None;
+    let mut bits = 0u32;
+    let mut bit_count = 0u;
+    let mut position = 0u;
+
+    while output.len() < len {
+        // Codes are packed least significant bit first.
+        while bit_count < code_size && position < data.len() {
+            bits |= data[position] as u32 << bit_count;
+            bit_count += 8;
+            position += 1;
+        }
+        if bit_count < code_size {
+            break;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as uint;
+        bits >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear_code {
+            code_size = min_code_size as uint + 1;
+            next_code = clear_code + 2;
+            previous = None;
+            continue;
+        }
+        if code == end_code {
+            break;
+        }
+
+        let previous_code = match previous {
+            None => {
+                if code >= clear_code {
+                    return None;
+                }
+                output.push(code as u8);
+                previous = Some(code);
+                continue;
+            }
+            Some(previous_code) => previous_code,
+        };
+
+        // A code that isn't in the table yet is the previous string plus its own first byte.
+        let (string_code, first_byte) = if code < next_code {
+            (code, first[code])
+        } else if code == next_code {
+            (previous_code, first[previous_code])
+        } else {
+            return None;
+        };
+        let mut walk = string_code;
+        loop {
+            stack.push(suffix[walk]);
+            if walk < clear_code {
+                break;
+            }
+            walk = prefix[walk] as uint;
+        }
+        while !stack.is_empty() {
+            output.push(stack.pop());
+        }
+        if code == next_code {
+            output.push(first_byte);
+        }
+
+        if next_code < MAX_CODES {
+            prefix[next_code] = previous_code as u16;
+            suffix[next_code] = first_byte;
+            first[next_code] = first[previous_code];
+            next_code += 1;
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        previous = Some(code);
+    }
+
+    output.truncate(len);
+    Some(output)
+}
+
+/// A 1x1 GIF with a red frame shown for 0.1 seconds, then a blue frame shown for 0.2 seconds,
+/// which repeats once.
+#[cfg(test)]
+pub fn test_animated_gif() -> ~[u8] {
+    let mut gif = ~[];
+    gif.push_all(bytes!("GIF89a"));
+    // Logical screen: 1x1, with a global color table of two colors.
+    gif.push_all([1, 0, 1, 0, 0x80, 0, 0]);
+    gif.push_all([0xFF, 0, 0, 0, 0, 0xFF]);
+    // Repeat once.
+    gif.push_all([0x21, 0xFF, 11]);
+    gif.push_all(bytes!("NETSCAPE2.0"));
+    gif.push_all([3, 1, 1, 0, 0]);
+    // Each frame: a graphic control extension with its delay, then an image descriptor and
+    // the LZW codes for clear, the color index and end.
+    gif.push_all([0x21, 0xF9, 4, 0, 10, 0, 0, 0]);
+    gif.push_all([0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2, 0x44, 0x01, 0]);
+    gif.push_all([0x21, 0xF9, 4, 0, 20, 0, 0, 0]);
+    gif.push_all([0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2, 0x4C, 0x01, 0]);
+    gif.push(0x3B);
+    gif
+}
+
+#[test]
+fn test_decode_animation() {
+    let gif = decode(test_animated_gif()).unwrap();
+    assert_eq!((gif.width, gif.height), (1, 1));
+    assert_eq!(gif.repetitions, Some(1));
+    assert_eq!(gif.frames.len(), 2);
+    assert_eq!(gif.frames[0].pixels, ~[0xFF, 0, 0, 0xFF]);
+    assert_eq!(gif.frames[0].delay_cs, 10);
+    assert_eq!(gif.frames[1].pixels, ~[0, 0, 0xFF, 0xFF]);
+    assert_eq!(gif.frames[1].delay_cs, 20);
+}
+
+#[test]
+fn test_lzw_decode() {
+    // Clear, 1, 1 (adding "1 1" as code 6), 6 (adding "1 1 1" as code 7, after which codes
+    // are 4 bits wide), 0 and end.
+    let codes = [(4u, 3u), (1, 3), (1, 3), (6, 3), (0, 4), (5, 4)];
+    let mut data = ~[];
+    let mut bits = 0u;
+    let mut bit_count = 0u;
+    for &(code, width) in codes.iter() {
+        bits |= code << bit_count;
+        bit_count += width;
+        while bit_count >= 8 {
+            data.push((bits & 0xFF) as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    }
+    data.push(bits as u8);
+    assert_eq!(lzw_decode(2, data, 5), Some(~[1, 1, 1, 1, 0]));
+    // Missing data is treated as index 0.
+    assert_eq!(lzw_decode(2, data, 7), Some(~[1, 1, 1, 1, 0, 0, 0]));
+}
+
+#[test]
+fn test_decode_partial() {
+    // Cut the first frame off after the first byte of its codes, which holds clear and red;
+    // the second frame, its graphic control, the trailer and two bytes of the first go.
+    let gif = test_animated_gif();
+    let cut = gif.len() - 15 - 8 - 1 - 2;
+    let partial = decode_partial(gif.slice_to(cut)).unwrap();
+    assert_eq!(partial.frames.len(), 1);
+    assert_eq!(partial.frames[0].pixels, ~[0xFF, 0, 0, 0xFF]);
+    assert!(decode(gif.slice_to(cut)).is_none());
+}
+
+#[test]
+fn test_too_large() {
+    // Each frame of a 8192x8192 screen takes up the whole budget, and a 16385 pixel wide screen
+    // is too wide. Neither is allocated.
+    for &screen in [[0x00, 0x20, 0x00, 0x20], [0x01, 0x40, 1, 0]].iter() {
+        let mut gif = test_animated_gif();
+        gif.mut_slice(6, 10).copy_from(screen);
+        assert!(decode(gif).is_none());
+    }
+}
+
+#[test]
+fn test_frame_outside_screen() {
+    // A 16384x16384 frame on the 1x1 screen is refused before its indices are allocated, and so
+    // is a 1x1 frame one pixel to the right of the screen.
+    for &descriptor in [[0, 0, 0, 0, 0x00, 0x40, 0x00, 0x40], [1, 0, 0, 0, 1, 0, 1, 0]].iter() {
+        let mut gif = test_animated_gif();
+        let first_frame = 6 + 7 + 6 + 19 + 8 + 1;
+        gif.mut_slice(first_frame, first_frame + 8).copy_from(descriptor);
+        assert!(decode(gif).is_none());
+    }
+}
+
+#[test]
+fn test_interlaced_rows() {
+    assert_eq!(interlaced_rows(10), ~[0, 8, 4, 2, 6, 1, 3, 5, 7, 9]);
+}
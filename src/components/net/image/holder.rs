@@ -3,25 +3,30 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use image::base::Image;
-use image_cache_task::{ImageReady, ImageNotReady, ImageFailed};
+use image_cache_task::{ImageReady, ImagePartial, ImageNotReady, ImageFailed, ImageResponseMsg};
 use local_image_cache::LocalImageCache;
 
 use extra::arc::{Arc, MutexArc};
 use extra::url::Url;
 use geom::size::Size2D;
+use std::comm::Port;
 use std::util;
 
 // FIXME: Nasty coupling here This will be a problem if we want to factor out image handling from
 // the network stack. This should probably be factored out into an interface and use dependency
 // injection.
 
-/// A struct to store image data. The image will be loaded once the first time it is requested,
-/// and an Arc will be stored.  Clones of this Arc are given out on demand.
+/// A struct to store image data. The image is requested from the local image cache each time it
+/// is needed, so that animated images show their current frame, and an Arc to the latest one is
+/// stored.  Clones of this Arc are given out on demand.
 #[deriving(Clone)]
 pub struct ImageHolder {
     url: Url,
     image: Option<Arc<~Image>>,
     cached_size: Size2D<int>,
+    /// Whether `cached_size` is the size of the image, which is known as soon as any of it has
+    /// been decoded and doesn't change after.
+    size_known: bool,
     local_image_cache: MutexArc<LocalImageCache>,
 }
 
@@ -32,6 +37,7 @@ impl ImageHolder {
             url: url,
             image: None,
             cached_size: Size2D(0,0),
+            size_known: false,
             local_image_cache: local_image_cache.clone(),
         };
 
@@ -62,35 +68,70 @@ impl ImageHolder {
     /// Query and update the current image size.
     pub fn get_size(&mut self) -> Option<Size2D<int>> {
         debug!("get_size() {}", self.url.to_str());
-        self.get_image().map(|img| {
-            let img_ref = img.get();
-            self.cached_size = Size2D(img_ref.width as int,
-                                      img_ref.height as int);
-            self.cached_size.clone()
-        })
+        // Once the size is known there is no need to ask for the image at full size, which
+        // would keep it from being let go when it is shown scaled
+        if !self.size_known {
+            self.get_image();
+        }
+        if self.size_known {
+            Some(self.cached_size.clone())
+        } else {
+            None
+        }
     }
 
     pub fn get_image(&mut self) -> Option<Arc<~Image>> {
         debug!("get_image() {}", self.url.to_str());
 
-        // If this is the first time we've called this function, load
-        // the image and store it for the future
-        if self.image.is_none() {
-            let port = unsafe {
-                self.local_image_cache.unsafe_access(|local_image_cache| {
-                    local_image_cache.get_image(&self.url)
-                })
-            };
-            match port.recv() {
-                ImageReady(image) => {
-                    self.image = Some(image);
-                }
-                ImageNotReady => {
-                    debug!("image not ready for {:s}", self.url.to_str());
+        // Ask the cache every time: the local cache answers from its own state once the image
+        // has loaded, and hands out the next frame when an animated image has moved on
+        let port = unsafe {
+            self.local_image_cache.unsafe_access(|local_image_cache| {
+                local_image_cache.get_image(&self.url)
+            })
+        };
+        self.update_image(port, true)
+    }
+
+    /// Gets the image to draw at `size` pixels. Images drawn smaller than they are are scaled
+    /// down once in the image cache instead of on every paint, and only the smaller copy is kept.
+    pub fn get_image_at_size(&mut self, size: Size2D<int>) -> Option<Arc<~Image>> {
+        debug!("get_image_at_size() {}", self.url.to_str());
+        if size.width <= 0 || size.height <= 0 {
+            return self.get_image();
+        }
+
+        let size = Size2D(size.width as uint, size.height as uint);
+        let port = unsafe {
+            self.local_image_cache.unsafe_access(|local_image_cache| {
+                local_image_cache.get_scaled_image(&self.url, size.clone())
+            })
+        };
+        self.update_image(port, false)
+    }
+
+    /// Keeps the image in the response, and its size if it is the image at full size.
+    fn update_image(&mut self, port: Port<ImageResponseMsg>, full_size: bool)
+                    -> Option<Arc<~Image>> {
+        match port.recv() {
+            ImageReady(image) => {
+                if full_size {
+                    self.update_size(image.get());
                 }
-                ImageFailed => {
-                    debug!("image decoding failed for {:s}", self.url.to_str());
+                self.image = Some(image);
+            }
+            ImagePartial(image) => {
+                debug!("image partially loaded for {:s}", self.url.to_str());
+                if full_size {
+                    self.update_size(image.get());
                 }
+                self.image = Some(image);
+            }
+            ImageNotReady => {
+                debug!("image not ready for {:s}", self.url.to_str());
+            }
+            ImageFailed => {
+                debug!("image decoding failed for {:s}", self.url.to_str());
             }
         }
 
@@ -101,5 +142,9 @@ impl ImageHolder {
 
         return result;
     }
-}
 
+    fn update_size(&mut self, image: &Image) {
+        self.cached_size = Size2D(image.width as int, image.height as int);
+        self.size_known = true;
+    }
+}
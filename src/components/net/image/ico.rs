@@ -0,0 +1,133 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A decoder for ICO and CUR files, as used for favicons. These hold several sizes of the same
+//! icon, each either a bitmap or a PNG; we decode the largest.
+
+use image::base::{Image, u16_at, u32_at};
+use image::bmp;
+
+use png;
+
+/// The size of the file header and of each directory entry.
+static HEADER_SIZE: uint = 6;
+static ENTRY_SIZE: uint = 16;
+
+struct IconEntry {
+    /// The size in the directory, where 0 means 256.
+    width: uint,
+    height: uint,
+    bpp: uint,
+    offset: uint,
+    size: uint,
+}
+
+pub fn decode(buffer: &[u8]) -> Option<Image> {
+    // The type is 1 for icons and 2 for cursors, which differ only in the directory.
+    let cursor = match (u16_at(buffer, 0), u16_at(buffer, 2)) {
+        (Some(0), Some(1)) => false,
+        (Some(0), Some(2)) => true,
+        _ => return None,
+    };
+    let count = match u16_at(buffer, 4) {
+        Some(count) if count > 0 => count,
+        _ => return None,
+    };
+
+    let mut best: Option<IconEntry> = None;
+    for i in range(0, count) {
+        let start = HEADER_SIZE + i * ENTRY_SIZE;
+        if start + ENTRY_SIZE > buffer.len() {
+            break;
+        }
+        let bpp = if cursor {
+            // Cursors keep their hotspot where icons keep their bit depth.
+            0
+        } else {
+            u16_at(buffer, start + 6).unwrap()
+        };
+        let entry = IconEntry {
+            width: if buffer[start] == 0 { 256 } else { buffer[start] as uint },
+            height: if buffer[start + 1] == 0 { 256 } else { buffer[start + 1] as uint },
+            bpp: bpp,
+            size: u32_at(buffer, start + 8).unwrap(),
+            offset: u32_at(buffer, start + 12).unwrap(),
+        };
+        if entry.offset + entry.size > buffer.len() {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some(ref best) => {
+                (entry.width * entry.height, entry.bpp) > (best.width * best.height, best.bpp)
+            }
+        };
+        if better {
+            best = Some(entry);
+        }
+    }
+
+    let entry = match best {
+        Some(entry) => entry,
+        None => return None,
+    };
+    let data = buffer.slice(entry.offset, entry.offset + entry.size);
+    if png::is_png(data) {
+        match png::load_png_from_memory(data) {
+            Ok(image) => Some(image),
+            Err(_err) => None,
+        }
+    } else {
+        bmp::decode_icon_bitmap(data)
+    }
+}
+
+#[test]
+fn test_decode_largest_bitmap() {
+    fn le16(value: u16) -> ~[u8] {
+        ~[value as u8, (value >> 8) as u8]
+    }
+    fn le32(value: u32) -> ~[u8] {
+        ~[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+    }
+
+    // A 1x1 24 bit bitmap of the given color, with a mask that leaves it opaque.
+    fn bitmap(blue: u8, green: u8, red: u8) -> ~[u8] {
+        let mut data = ~[];
+        data.push_all(le32(40));
+        data.push_all(le32(1));
+        data.push_all(le32(2));
+        data.push_all(le16(1));
+        data.push_all(le16(24));
+        data.push_all(&[0u8, ..20]);
+        data.push_all(&[blue, green, red, 0]);
+        data.push_all(&[0u8, 0, 0, 0]);
+        data
+    }
+    let small = bitmap(0xFF, 0, 0);
+    let large = bitmap(0, 0, 0xFF);
+
+    let mut icon = ~[];
+    icon.push_all(le16(0));
+    icon.push_all(le16(1));
+    icon.push_all(le16(2));
+    // Claim the second bitmap is bigger.
+    let offset = (HEADER_SIZE + 2 * ENTRY_SIZE) as u32;
+    icon.push_all(&[16u8, 16, 0, 0]);
+    icon.push_all(le16(1));
+    icon.push_all(le16(24));
+    icon.push_all(le32(small.len() as u32));
+    icon.push_all(le32(offset));
+    icon.push_all(&[32u8, 32, 0, 0]);
+    icon.push_all(le16(1));
+    icon.push_all(le16(24));
+    icon.push_all(le32(large.len() as u32));
+    icon.push_all(le32(offset + small.len() as u32));
+    icon.push_all(small);
+    icon.push_all(large);
+
+    let image = decode(icon).unwrap();
+    assert_eq!((image.width, image.height), (1, 1));
+    assert_eq!(image.pixels, ~[0xFF, 0, 0, 0xFF]);
+}
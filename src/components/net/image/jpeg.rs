@@ -0,0 +1,944 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A decoder for baseline and progressive JPEGs that decodes as much of an image as has
+//! arrived. stb_image can't decode progressive JPEGs at all, and needs the whole file for the
+//! others. The inverse DCT, the upsampling of the chroma planes and the color conversion are
+//! done the way libjpeg does them by default, so the pixels match what other browsers show.
+
+use image::base::{Image, MAX_DECODED_BYTES};
+
+use png;
+use std::vec;
+
+/// The position in an 8x8 block, in row order, of each coefficient in the order they are
+/// stored.
+static ZIGZAG: [uint, ..64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// The constants of libjpeg's accurate integer inverse DCT, scaled by 2^13.
+static FIX_0_298631336: int = 2446;
+static FIX_0_390180644: int = 3196;
+static FIX_0_541196100: int = 4433;
+static FIX_0_765366865: int = 6270;
+static FIX_0_899976223: int = 7373;
+static FIX_1_175875602: int = 9633;
+static FIX_1_501321110: int = 12299;
+static FIX_1_847759065: int = 15137;
+static FIX_1_961570560: int = 16069;
+static FIX_2_053119869: int = 16819;
+static FIX_2_562915447: int = 20995;
+static FIX_3_072711026: int = 25172;
+static CONST_BITS: uint = 13;
+static PASS1_BITS: uint = 2;
+
+/// Reads the entropy coded data of a scan, most significant bit first, skipping the zero
+/// bytes that follow 0xFF bytes.
+struct EntropyReader<'a> {
+    data: &'a [u8],
+    position: uint,
+    byte: uint,
+    bits_left: uint,
+}
+
+impl<'a> EntropyReader<'a> {
+    /// Fails at the end of the data, and at a marker, which ends the scan.
+    fn read_bit(&mut self) -> Option<uint> {
+        if self.bits_left == 0 {
+            if self.position >= self.data.len() {
+                return None;
+            }
+            let byte = self.data[self.position];
+            if byte == 0xFF {
+                if self.position + 1 >= self.data.len() || self.data[self.position + 1] != 0 {
+                    return None;
+                }
+                self.position += 1;
+            }
+            self.position += 1;
+            self.byte = byte as uint;
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+        Some((self.byte >> self.bits_left) & 1)
+    }
+
+    fn read_bits(&mut self, count: uint) -> Option<uint> {
+        let mut value = 0;
+        for _ in range(0, count) {
+            value = (value << 1) | match self.read_bit() {
+                Some(bit) => bit,
+                None => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Reads a value of `size` bits, where values below the middle of the range stand for
+    /// negative numbers.
+    fn read_extended(&mut self, size: uint) -> Option<int> {
+        if size == 0 {
+            return Some(0);
+        }
+        self.read_bits(size).map(|bits| {
+            let value = bits as int;
+            if value < 1 << (size - 1) { value - (1 << size) + 1 } else { value }
+        })
+    }
+
+    /// Drops the bits left of the current byte and skips the restart marker after them.
+    fn restart(&mut self) {
+        self.bits_left = 0;
+        if self.position + 1 < self.data.len() && self.data[self.position] == 0xFF &&
+                self.data[self.position + 1] >= 0xD0 && self.data[self.position + 1] <= 0xD7 {
+            self.position += 2;
+        }
+    }
+}
+
+/// A Huffman table, read one bit at a time from the first bit of the code.
+struct HuffmanTable {
+    /// The number of codes of each length.
+    counts: [uint, ..17],
+    /// The symbols ordered by code.
+    symbols: ~[u8],
+}
+
+impl HuffmanTable {
+    fn read_symbol(&self, reader: &mut EntropyReader) -> Option<uint> {
+        let (mut code, mut first, mut index) = (0u, 0u, 0u);
+        for length in range(1u, 17) {
+            code |= match reader.read_bit() {
+                Some(bit) => bit,
+                None => return None,
+            };
+            let count = self.counts[length];
+            if code < first + count {
+                return Some(self.symbols[index + code - first] as uint);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+struct Component {
+    id: u8,
+    horizontal_sampling: uint,
+    vertical_sampling: uint,
+    quantization_table: uint,
+    /// The size of the component's samples, before they are upsampled to the image size.
+    width: uint,
+    height: uint,
+    /// The size of the component in blocks, padded out to a whole number of MCUs.
+    blocks_wide: uint,
+    blocks_high: uint,
+    /// The coefficients of each block, in row order.
+    coefficients: ~[i16],
+    dc_prediction: int,
+}
+
+struct Frame {
+    width: uint,
+    height: uint,
+    progressive: bool,
+    components: ~[Component],
+    max_horizontal_sampling: uint,
+    max_vertical_sampling: uint,
+    mcus_wide: uint,
+    mcus_high: uint,
+}
+
+/// The components of a scan and the part of each coefficient it holds.
+struct Scan {
+    /// The indices of the components in the frame, and the indices of their DC and AC tables.
+    components: ~[(uint, uint, uint)],
+    spectral_start: uint,
+    spectral_end: uint,
+    /// The bits of the coefficients that earlier scans held, if this scan refines them.
+    previous_low_bit: uint,
+    low_bit: uint,
+}
+
+fn u16_at(data: &[u8], offset: uint) -> uint {
+    (data[offset] as uint << 8) | data[offset + 1] as uint
+}
+
+fn read_frame(data: &[u8], progressive: bool) -> Option<Frame> {
+    if data.len() < 6 || data[0] != 8 {
+        return None;
+    }
+    let (height, width, count) = (u16_at(data, 1), u16_at(data, 3), data[5] as uint);
+    if width == 0 || height == 0 || (count != 1 && count != 3) || data.len() < 6 + count * 3 ||
+            width > MAX_DECODED_BYTES / 4 / height {
+        return None;
+    }
+    let mut components = ~[];
+    for i in range(0, count) {
+        let offset = 6 + i * 3;
+        let sampling = data[offset + 1] as uint;
+        let (horizontal, vertical) = (sampling >> 4, sampling & 15);
+        if horizontal < 1 || horizontal > 4 || vertical < 1 || vertical > 4 ||
+                data[offset + 2] > 3 {
+            return None;
+        }
+        components.push(Component {
+            id: data[offset],
+            horizontal_sampling: horizontal,
+            vertical_sampling: vertical,
+            quantization_table: data[offset + 2] as uint,
+            width: 0,
+            height: 0,
+            blocks_wide: 0,
+            blocks_high: 0,
+            coefficients: ~[],
+            dc_prediction: 0,
+        });
+    }
+
+    let max_horizontal = components.iter().fold(1, |max, c| {
+        if c.horizontal_sampling > max { c.horizontal_sampling } else { max }
+    });
+    let max_vertical = components.iter().fold(1, |max, c| {
+        if c.vertical_sampling > max { c.vertical_sampling } else { max }
+    });
+    let mcus_wide = (width + 8 * max_horizontal - 1) / (8 * max_horizontal);
+    let mcus_high = (height + 8 * max_vertical - 1) / (8 * max_vertical);
+    let mut size = 0;
+    for component in components.mut_iter() {
+        component.width = (width * component.horizontal_sampling + max_horizontal - 1) /
+            max_horizontal;
+        component.height = (height * component.vertical_sampling + max_vertical - 1) /
+            max_vertical;
+        component.blocks_wide = mcus_wide * component.horizontal_sampling;
+        component.blocks_high = mcus_high * component.vertical_sampling;
+        size += component.blocks_wide * component.blocks_high * 64 * 2;
+    }
+    if size > MAX_DECODED_BYTES {
+        return None;
+    }
+    for component in components.mut_iter() {
+        component.coefficients = vec::from_elem(component.blocks_wide * component.blocks_high * 64,
+                                                0i16);
+    }
+    Some(Frame {
+        width: width,
+        height: height,
+        progressive: progressive,
+        components: components,
+        max_horizontal_sampling: max_horizontal,
+        max_vertical_sampling: max_vertical,
+        mcus_wide: mcus_wide,
+        mcus_high: mcus_high,
+    })
+}
+
+fn read_huffman_tables(data: &[u8],
+                       dc_tables: &mut [Option<HuffmanTable>],
+                       ac_tables: &mut [Option<HuffmanTable>])
+                       -> bool {
+    let mut position = 0;
+    while position < data.len() {
+        if position + 17 > data.len() {
+            return false;
+        }
+        let (class, index) = (data[position] >> 4, (data[position] & 15) as uint);
+        let mut counts = [0u, ..17];
+        let mut total = 0;
+        for length in range(1u, 17) {
+            counts[length] = data[position + length] as uint;
+            total += counts[length];
+        }
+        position += 17;
+        if class > 1 || index > 3 || total > 256 || position + total > data.len() {
+            return false;
+        }
+        let table = HuffmanTable {
+            counts: counts,
+            symbols: data.slice(position, position + total).to_owned(),
+        };
+        position += total;
+        if class == 0 {
+            dc_tables[index] = Some(table);
+        } else {
+            ac_tables[index] = Some(table);
+        }
+    }
+    true
+}
+
+fn read_quantization_tables(data: &[u8], tables: &mut [[u16, ..64], ..4]) -> bool {
+    let mut position = 0;
+    while position < data.len() {
+        let (precision, index) = (data[position] >> 4, (data[position] & 15) as uint);
+        let size = if precision == 0 { 64 } else { 128 };
+        if precision > 1 || index > 3 || position + 1 + size > data.len() {
+            return false;
+        }
+        for i in range(0u, 64) {
+            tables[index][ZIGZAG[i]] = if precision == 0 {
+                data[position + 1 + i] as u16
+            } else {
+                u16_at(data, position + 1 + i * 2) as u16
+            };
+        }
+        position += 1 + size;
+    }
+    true
+}
+
+fn read_scan(data: &[u8], frame: &Frame) -> Option<Scan> {
+    if data.len() < 1 || data.len() < 4 + data[0] as uint * 2 {
+        return None;
+    }
+    let count = data[0] as uint;
+    let mut components = ~[];
+    for i in range(0, count) {
+        let (id, tables) = (data[1 + i * 2], data[2 + i * 2] as uint);
+        match frame.components.iter().position(|component| component.id == id) {
+            Some(index) if tables >> 4 < 4 && tables & 15 < 4 => {
+                components.push((index, tables >> 4, tables & 15));
+            }
+            _ => return None,
+        }
+    }
+    let offset = 1 + count * 2;
+    let scan = Scan {
+        components: components,
+        spectral_start: data[offset] as uint,
+        spectral_end: data[offset + 1] as uint,
+        previous_low_bit: data[offset + 2] as uint >> 4,
+        low_bit: data[offset + 2] as uint & 15,
+    };
+    if count == 0 || count > 4 || scan.low_bit > 13 {
+        return None;
+    }
+    if frame.progressive {
+        // Only DC coefficients may be interleaved, and they come alone.
+        let dc = scan.spectral_start == 0;
+        if scan.spectral_end > 63 || scan.spectral_start > scan.spectral_end ||
+                (dc && scan.spectral_end != 0) || (!dc && count != 1) {
+            return None;
+        }
+    }
+    Some(scan)
+}
+
+/// Whether the Huffman tables that a scan needs have been defined. Scans that refine DC
+/// coefficients need none, and the other scans of progressive JPEGs need one kind.
+fn has_tables(frame: &Frame,
+              scan: &Scan,
+              dc_tables: &[Option<HuffmanTable>],
+              ac_tables: &[Option<HuffmanTable>])
+              -> bool {
+    let dc = scan.spectral_start == 0;
+    let needs_dc = !frame.progressive || (dc && scan.previous_low_bit == 0);
+    let needs_ac = !frame.progressive || !dc;
+    scan.components.iter().all(|&(_, dc_table, ac_table)| {
+        (!needs_dc || dc_tables[dc_table].is_some()) && (!needs_ac || ac_tables[ac_table].is_some())
+    })
+}
+
+/// Decodes a block of a sequential JPEG.
+fn decode_block(reader: &mut EntropyReader,
+                block: &mut [i16],
+                dc_table: &HuffmanTable,
+                ac_table: &HuffmanTable,
+                prediction: &mut int)
+                -> Option<()> {
+    let size = match dc_table.read_symbol(reader) {
+        Some(size) => size,
+        None => return None,
+    };
+    match reader.read_extended(size) {
+        Some(difference) => *prediction += difference,
+        None => return None,
+    }
+    block[0] = *prediction as i16;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = match ac_table.read_symbol(reader) {
+            Some(symbol) => symbol,
+            None => return None,
+        };
+        let (run, size) = (symbol >> 4, symbol & 15);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            return None;
+        }
+        match reader.read_extended(size) {
+            Some(value) => block[ZIGZAG[k]] = value as i16,
+            None => return None,
+        }
+        k += 1;
+    }
+    Some(())
+}
+
+/// Decodes the first bits of the DC coefficient of a block of a progressive JPEG.
+fn decode_dc_first(reader: &mut EntropyReader,
+                   block: &mut [i16],
+                   dc_table: &HuffmanTable,
+                   low_bit: uint,
+                   prediction: &mut int)
+                   -> Option<()> {
+    let size = match dc_table.read_symbol(reader) {
+        Some(size) => size,
+        None => return None,
+    };
+    match reader.read_extended(size) {
+        Some(difference) => *prediction += difference,
+        None => return None,
+    }
+    block[0] = (*prediction << low_bit) as i16;
+    Some(())
+}
+
+/// Decodes the next bit of the DC coefficient of a block of a progressive JPEG.
+fn decode_dc_refine(reader: &mut EntropyReader, block: &mut [i16], low_bit: uint) -> Option<()> {
+    reader.read_bit().map(|bit| {
+        if bit == 1 {
+            block[0] |= 1 << low_bit;
+        }
+    })
+}
+
+/// Decodes the first bits of some AC coefficients of a block of a progressive JPEG.
+/// `end_of_bands` counts the blocks left in a run of blocks with none of them.
+fn decode_ac_first(reader: &mut EntropyReader,
+                   block: &mut [i16],
+                   ac_table: &HuffmanTable,
+                   scan: &Scan,
+                   end_of_bands: &mut uint)
+                   -> Option<()> {
+    if *end_of_bands > 0 {
+        *end_of_bands -= 1;
+        return Some(());
+    }
+    let mut k = scan.spectral_start;
+    while k <= scan.spectral_end {
+        let symbol = match ac_table.read_symbol(reader) {
+            Some(symbol) => symbol,
+            None => return None,
+        };
+        let (run, size) = (symbol >> 4, symbol & 15);
+        if size == 0 {
+            if run < 15 {
+                *end_of_bands = match reader.read_bits(run) {
+                    Some(bits) => (1 << run) + bits - 1,
+                    None => return None,
+                };
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            return None;
+        }
+        match reader.read_extended(size) {
+            Some(value) => block[ZIGZAG[k]] = (value << scan.low_bit) as i16,
+            None => return None,
+        }
+        k += 1;
+    }
+    Some(())
+}
+
+/// Adds the next bit to a coefficient that earlier scans found to be nonzero.
+fn refine_ac(reader: &mut EntropyReader, coefficient: &mut i16, low_bit: uint) -> Option<()> {
+    let bit = 1 << low_bit;
+    reader.read_bit().map(|refine| {
+        if refine == 1 && *coefficient & bit == 0 {
+            if *coefficient >= 0 {
+                *coefficient += bit;
+            } else {
+                *coefficient -= bit;
+            }
+        }
+    })
+}
+
+/// Decodes the next bit of some AC coefficients of a block of a progressive JPEG, following
+/// libjpeg's `decode_mcu_AC_refine`.
+fn decode_ac_refine(reader: &mut EntropyReader,
+                    block: &mut [i16],
+                    ac_table: &HuffmanTable,
+                    scan: &Scan,
+                    end_of_bands: &mut uint)
+                    -> Option<()> {
+    let mut k = scan.spectral_start;
+    if *end_of_bands == 0 {
+        while k <= scan.spectral_end {
+            let symbol = match ac_table.read_symbol(reader) {
+                Some(symbol) => symbol,
+                None => return None,
+            };
+            let (mut run, size) = ((symbol >> 4) as int, symbol & 15);
+            let mut value = 0;
+            if size != 0 {
+                // The coefficient newly becomes nonzero, with a magnitude of one bit.
+                value = match reader.read_bit() {
+                    Some(1) => 1 << scan.low_bit,
+                    Some(_) => -1 << scan.low_bit,
+                    None => return None,
+                };
+            } else if run != 15 {
+                *end_of_bands = match reader.read_bits(run as uint) {
+                    Some(bits) => (1 << run) + bits,
+                    None => return None,
+                };
+                break;
+            }
+            // Skip as many zero coefficients as the run says, refining the nonzero ones on
+            // the way.
+            while k <= scan.spectral_end {
+                let coefficient = &mut block[ZIGZAG[k]];
+                if *coefficient != 0 {
+                    if refine_ac(reader, coefficient, scan.low_bit).is_none() {
+                        return None;
+                    }
+                } else {
+                    run -= 1;
+                    if run < 0 {
+                        break;
+                    }
+                }
+                k += 1;
+            }
+            if value != 0 && k <= scan.spectral_end {
+                block[ZIGZAG[k]] = value as i16;
+            }
+            k += 1;
+        }
+    }
+    if *end_of_bands > 0 {
+        while k <= scan.spectral_end {
+            let coefficient = &mut block[ZIGZAG[k]];
+            if *coefficient != 0 && refine_ac(reader, coefficient, scan.low_bit).is_none() {
+                return None;
+            }
+            k += 1;
+        }
+        *end_of_bands -= 1;
+    }
+    Some(())
+}
+
+/// Decodes the blocks of a scan until the scan ends or the data runs out, leaving the blocks
+/// that haven't arrived as they were.
+fn decode_scan(reader: &mut EntropyReader,
+               frame: &mut Frame,
+               scan: &Scan,
+               dc_tables: &[Option<HuffmanTable>],
+               ac_tables: &[Option<HuffmanTable>],
+               restart_interval: uint)
+               -> Option<()> {
+    // A scan of one component has a block per MCU, and doesn't pad the component out.
+    let interleaved = scan.components.len() > 1;
+    let (mcus_wide, mcus_high) = if interleaved {
+        (frame.mcus_wide, frame.mcus_high)
+    } else {
+        let (index, _, _) = scan.components[0];
+        let component = &frame.components[index];
+        ((component.width + 7) / 8, (component.height + 7) / 8)
+    };
+    let progressive = frame.progressive;
+    let mut end_of_bands = 0;
+    for &(index, _, _) in scan.components.iter() {
+        frame.components[index].dc_prediction = 0;
+    }
+
+    for mcu in range(0, mcus_wide * mcus_high) {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            reader.restart();
+            end_of_bands = 0;
+            for &(index, _, _) in scan.components.iter() {
+                frame.components[index].dc_prediction = 0;
+            }
+        }
+        let (mcu_x, mcu_y) = (mcu % mcus_wide, mcu / mcus_wide);
+        for &(index, dc_table, ac_table) in scan.components.iter() {
+            let component = &mut frame.components[index];
+            let (horizontal, vertical) = if interleaved {
+                (component.horizontal_sampling, component.vertical_sampling)
+            } else {
+                (1, 1)
+            };
+            for y in range(0, vertical) {
+                for x in range(0, horizontal) {
+                    let row = mcu_y * vertical + y;
+                    let column = mcu_x * horizontal + x;
+                    let offset = (row * component.blocks_wide + column) * 64;
+                    let block = component.coefficients.mut_slice(offset, offset + 64);
+                    let prediction = &mut component.dc_prediction;
+                    let result = if !progressive {
+                        decode_block(reader, block, dc_tables[dc_table].get_ref(),
+                                     ac_tables[ac_table].get_ref(), prediction)
+                    } else if scan.spectral_start == 0 && scan.previous_low_bit == 0 {
+                        decode_dc_first(reader, block, dc_tables[dc_table].get_ref(),
+                                        scan.low_bit, prediction)
+                    } else if scan.spectral_start == 0 {
+                        decode_dc_refine(reader, block, scan.low_bit)
+                    } else if scan.previous_low_bit == 0 {
+                        decode_ac_first(reader, block, ac_tables[ac_table].get_ref(), scan,
+                                        &mut end_of_bands)
+                    } else {
+                        decode_ac_refine(reader, block, ac_tables[ac_table].get_ref(), scan,
+                                         &mut end_of_bands)
+                    };
+                    if result.is_none() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+fn descale(value: int, bits: uint) -> int {
+    (value + (1 << (bits - 1))) >> bits
+}
+
+fn clamp_sample(value: int) -> u8 {
+    if value < 0 { 0 } else if value > 255 { 255 } else { value as u8 }
+}
+
+/// The even and odd parts of libjpeg's `jpeg_idct_islow`, for a row or column of eight values.
+/// Returns the outputs scaled up by 2^13, before rounding.
+fn idct_1d(input: &[int, ..8]) -> [int, ..8] {
+    let (z2, z3) = (input[2], input[6]);
+    let z1 = (z2 + z3) * FIX_0_541196100;
+    let tmp2 = z1 - z3 * FIX_1_847759065;
+    let tmp3 = z1 + z2 * FIX_0_765366865;
+    let tmp0 = (input[0] + input[4]) << CONST_BITS;
+    let tmp1 = (input[0] - input[4]) << CONST_BITS;
+    let (tmp10, tmp13) = (tmp0 + tmp3, tmp0 - tmp3);
+    let (tmp11, tmp12) = (tmp1 + tmp2, tmp1 - tmp2);
+
+    let (tmp0, tmp1, tmp2, tmp3) = (input[7], input[5], input[3], input[1]);
+    let z1 = (tmp0 + tmp3) * -FIX_0_899976223;
+    let z2 = (tmp1 + tmp2) * -FIX_2_562915447;
+    let z5 = (tmp0 + tmp1 + tmp2 + tmp3) * FIX_1_175875602;
+    let z3 = (tmp0 + tmp2) * -FIX_1_961570560 + z5;
+    let z4 = (tmp1 + tmp3) * -FIX_0_390180644 + z5;
+    let tmp0 = tmp0 * FIX_0_298631336 + z1 + z3;
+    let tmp1 = tmp1 * FIX_2_053119869 + z2 + z4;
+    let tmp2 = tmp2 * FIX_3_072711026 + z2 + z3;
+    let tmp3 = tmp3 * FIX_1_501321110 + z1 + z4;
+
+    [tmp10 + tmp3, tmp11 + tmp2, tmp12 + tmp1, tmp13 + tmp0,
+     tmp13 - tmp0, tmp12 - tmp1, tmp11 - tmp2, tmp10 - tmp3]
+}
+
+/// Turns the coefficients of a block into samples, with libjpeg's accurate integer inverse
+/// DCT.
+fn inverse_dct(block: &[i16], quantization: &[u16, ..64], output: &mut [u8], stride: uint) {
+    let mut workspace = [0i, ..64];
+    for x in range(0u, 8) {
+        let mut column = [0i, ..8];
+        for y in range(0u, 8) {
+            column[y] = block[y * 8 + x] as int * quantization[y * 8 + x] as int;
+        }
+        let result = idct_1d(&column);
+        for y in range(0u, 8) {
+            workspace[y * 8 + x] = descale(result[y], CONST_BITS - PASS1_BITS);
+        }
+    }
+    for y in range(0u, 8) {
+        let mut row = [0i, ..8];
+        for x in range(0u, 8) {
+            row[x] = workspace[y * 8 + x];
+        }
+        let result = idct_1d(&row);
+        for x in range(0u, 8) {
+            let value = descale(result[x], CONST_BITS + PASS1_BITS + 3);
+            output[y * stride + x] = clamp_sample(value + 128);
+        }
+    }
+}
+
+/// The sum of three times a sample and the one above or below it, which upsampling in both
+/// directions interpolates between.
+fn column_sum(row: &[u8], other_row: &[u8], x: uint) -> int {
+    row[x] as int * 3 + other_row[x] as int
+}
+
+/// Scales a component's samples up to the size of the image. Halved planes are interpolated
+/// the way libjpeg's "fancy" upsampling does it, and others are scaled up by repeating
+/// samples. Returns `None` if the image isn't a whole multiple of the component's size.
+fn upsample(frame: &Frame, component: &Component, samples: &[u8]) -> Option<~[u8]> {
+    let (width, height) = (frame.width, frame.height);
+    let (horizontal, vertical) = (component.horizontal_sampling, component.vertical_sampling);
+    if frame.max_horizontal_sampling % horizontal != 0 ||
+            frame.max_vertical_sampling % vertical != 0 {
+        return None;
+    }
+    let x_ratio = frame.max_horizontal_sampling / horizontal;
+    let y_ratio = frame.max_vertical_sampling / vertical;
+    let stride = component.blocks_wide * 8;
+    let input_width = component.width;
+
+    let mut output = vec::with_capacity(width * height);
+    for y in range(0, height) {
+        let input_y = y / y_ratio;
+        // The nearer of the rows above and below, for output rows between two input rows. The
+        // first and last rows stand in for the ones past the edges.
+        let lower = y % 2 == 1;
+        let other_y = if lower {
+            if input_y + 1 == component.height { input_y } else { input_y + 1 }
+        } else {
+            if input_y == 0 { 0 } else { input_y - 1 }
+        };
+        let row = samples.slice(input_y * stride, (input_y + 1) * stride);
+        let other_row = samples.slice(other_y * stride, (other_y + 1) * stride);
+        match (x_ratio, y_ratio) {
+            (1, 1) => output.push_all(row.slice_to(width)),
+            (2, 1) if input_width > 2 => {
+                // Each output sample is 3/4 of the nearer input sample and 1/4 of the other.
+                for x in range(0, width) {
+                    let input_x = x / 2;
+                    let value = if x == 0 || x == input_width * 2 - 1 {
+                        row[input_x] as int
+                    } else if x % 2 == 0 {
+                        (row[input_x] as int * 3 + row[input_x - 1] as int + 1) >> 2
+                    } else {
+                        (row[input_x] as int * 3 + row[input_x + 1] as int + 2) >> 2
+                    };
+                    output.push(value as u8);
+                }
+            }
+            (1, 2) => {
+                let bias = if lower { 2 } else { 1 };
+                for x in range(0, width) {
+                    output.push(((column_sum(row, other_row, x) + bias) >> 2) as u8);
+                }
+            }
+            (2, 2) if input_width > 2 => {
+                for x in range(0, width) {
+                    let input_x = x / 2;
+                    let sum = column_sum(row, other_row, input_x);
+                    let value = if x == 0 {
+                        (sum * 4 + 8) >> 4
+                    } else if x == input_width * 2 - 1 {
+                        (sum * 4 + 7) >> 4
+                    } else if x % 2 == 0 {
+                        (sum * 3 + column_sum(row, other_row, input_x - 1) + 8) >> 4
+                    } else {
+                        (sum * 3 + column_sum(row, other_row, input_x + 1) + 7) >> 4
+                    };
+                    output.push(value as u8);
+                }
+            }
+            _ => {
+                for x in range(0, width) {
+                    output.push(row[x / x_ratio]);
+                }
+            }
+        }
+    }
+    Some(output)
+}
+
+/// Turns the coefficients decoded so far into RGBA pixels.
+fn render(frame: &Frame, quantization_tables: &[[u16, ..64], ..4], ycc: bool) -> Option<Image> {
+    let mut planes = ~[];
+    for component in frame.components.iter() {
+        let stride = component.blocks_wide * 8;
+        let mut samples = vec::from_elem(stride * component.blocks_high * 8, 0u8);
+        let quantization = &quantization_tables[component.quantization_table];
+        for row in range(0, component.blocks_high) {
+            for column in range(0, component.blocks_wide) {
+                let block = (row * component.blocks_wide + column) * 64;
+                let offset = row * 8 * stride + column * 8;
+                inverse_dct(component.coefficients.slice(block, block + 64), quantization,
+                            samples.mut_slice_from(offset), stride);
+            }
+        }
+        match upsample(frame, component, samples) {
+            Some(plane) => planes.push(plane),
+            None => return None,
+        }
+    }
+
+    let mut pixels = vec::with_capacity(frame.width * frame.height * 4);
+    for i in range(0, frame.width * frame.height) {
+        if planes.len() == 1 {
+            let gray = planes[0][i];
+            pixels.push_all(&[gray, gray, gray, 0xFF]);
+        } else if !ycc {
+            pixels.push_all(&[planes[0][i], planes[1][i], planes[2][i], 0xFF]);
+        } else {
+            // libjpeg's fixed point conversion from YCbCr, with the factors scaled by 2^16.
+            let y = planes[0][i] as int;
+            let (cb, cr) = (planes[1][i] as int - 128, planes[2][i] as int - 128);
+            let red = y + ((91881 * cr + 32768) >> 16);
+            let green = y + ((-22554 * cb - 46802 * cr + 32768) >> 16);
+            let blue = y + ((116130 * cb + 32768) >> 16);
+            pixels.push_all(&[clamp_sample(red), clamp_sample(green), clamp_sample(blue), 0xFF]);
+        }
+    }
+    Some(Image(frame.width as u32, frame.height as u32, png::RGBA8, pixels))
+}
+
+/// Finds the marker at or after `position`, skipping anything that isn't one, and returns it
+/// with the position after it.
+fn next_marker(data: &[u8], mut position: uint) -> Option<(u8, uint)> {
+    while position + 1 < data.len() {
+        let marker = data[position + 1];
+        if data[position] == 0xFF && marker != 0 && marker != 0xFF &&
+                (marker < 0xD0 || marker > 0xD7) {
+            return Some((marker, position + 2));
+        }
+        position += 1;
+    }
+    None
+}
+
+/// Decodes a JPEG into RGBA pixels, as much of it as has arrived. The coefficients that
+/// haven't arrived are left at zero, which leaves the rest of a sequential image gray and a
+/// progressive one blurry. Returns `None` until the first scan starts, and for images that are
+/// broken or use features we don't support: arithmetic coding, hierarchical and lossless
+/// images, more than eight bits per sample, and the CMYK color space.
+pub fn decode(data: &[u8]) -> Option<Image> {
+    if !data.starts_with([0xFF, 0xD8]) {
+        return None;
+    }
+    let mut frame = None;
+    let mut quantization_tables = [[1u16, ..64], ..4];
+    let mut dc_tables: ~[Option<HuffmanTable>] = vec::from_fn(4, |_| None);
+    let mut ac_tables: ~[Option<HuffmanTable>] = vec::from_fn(4, |_| None);
+    let mut restart_interval = 0;
+    let mut jfif = false;
+    let mut adobe_transform = None;
+    let mut scanned = false;
+    let mut position = 2;
+    loop {
+        let marker = match next_marker(data, position) {
+            Some((0xD9, _)) | None => break,
+            Some((marker, after)) => {
+                position = after;
+                marker
+            }
+        };
+        if marker == 0x01 {
+            continue;
+        }
+        if position + 2 > data.len() || u16_at(data, position) < 2 ||
+                position + u16_at(data, position) > data.len() {
+            break;
+        }
+        let segment = data.slice(position + 2, position + u16_at(data, position));
+        position += u16_at(data, position);
+        match marker {
+            0xC0 | 0xC1 | 0xC2 => {
+                if frame.is_some() {
+                    return None;
+                }
+                frame = read_frame(segment, marker == 0xC2);
+                if frame.is_none() {
+                    return None;
+                }
+            }
+            0xC3 | 0xC5..0xC7 | 0xC9..0xCB | 0xCD..0xCF => {
+                debug!("jpeg: unsupported frame type {:x}", marker);
+                return None;
+            }
+            0xC4 => {
+                if !read_huffman_tables(segment, dc_tables, ac_tables) {
+                    return None;
+                }
+            }
+            0xDB => {
+                if !read_quantization_tables(segment, &mut quantization_tables) {
+                    return None;
+                }
+            }
+            0xDD if segment.len() >= 2 => restart_interval = u16_at(segment, 0),
+            0xE0 => jfif = jfif || segment.starts_with(bytes!("JFIF", 0)),
+            0xEE if segment.len() >= 12 && segment.starts_with(bytes!("Adobe")) => {
+                adobe_transform = Some(segment[11]);
+            }
+            0xDA => {
+                let frame = match frame {
+                    Some(ref mut frame) => frame,
+                    None => return None,
+                };
+                let scan = match read_scan(segment, frame) {
+                    Some(scan) => scan,
+                    None => return None,
+                };
+                if !has_tables(frame, &scan, dc_tables, ac_tables) {
+                    return None;
+                }
+                let mut reader = EntropyReader {
+                    data: data,
+                    position: position,
+                    byte: 0,
+                    bits_left: 0,
+                };
+                decode_scan(&mut reader, frame, &scan, dc_tables, ac_tables, restart_interval);
+                position = reader.position;
+                scanned = true;
+            }
+            _ => (),
+        }
+    }
+
+    let frame = match frame {
+        Some(ref frame) if scanned => frame,
+        _ => return None,
+    };
+    // Three components are YCbCr unless the Adobe marker or the component IDs say they are
+    // RGB, as libjpeg has it.
+    let ids: ~[u8] = frame.components.iter().map(|component| component.id).collect();
+    let ycc = jfif || match adobe_transform {
+        Some(transform) => transform != 0,
+        None => ids != ~[0x52, 0x47, 0x42],
+    };
+    render(frame, &quantization_tables, ycc)
+}
+
+// The expected pixels were decoded by libjpeg.
+#[cfg(test)]
+static TEST_PROGRESSIVE: &'static [u8] = include_bin!("test_progressive.jpeg");
+#[cfg(test)]
+static TEST_PROGRESSIVE_PIXELS: &'static [u8] = include_bin!("test_progressive.rgba");
+
+#[test]
+fn test_decode_progressive() {
+    let image = decode(TEST_PROGRESSIVE).unwrap();
+    assert_eq!((image.width, image.height), (23, 17));
+    assert!(image.pixels.as_slice() == TEST_PROGRESSIVE_PIXELS);
+}
+
+#[test]
+fn test_decode_partial() {
+    // With only the first scans in, the whole image is there, without its finer details.
+    let partial = decode(TEST_PROGRESSIVE.slice_to(TEST_PROGRESSIVE.len() / 2)).unwrap();
+    assert_eq!((partial.width, partial.height), (23, 17));
+    assert!(partial.pixels.as_slice() != TEST_PROGRESSIVE_PIXELS);
+    // Nothing to show before the first scan.
+    assert!(decode(TEST_PROGRESSIVE.slice_to(100)).is_none());
+}
@@ -0,0 +1,393 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A PNG decoder for images that are still loading. rust-png only decodes whole files, so this
+//! draws the rows that have arrived, and fills in interlaced images a pass at a time with each
+//! pixel stretched over the area that later passes fill in, as other browsers do.
+
+use content_encoding::{Decoder, Deflate};
+use image::base::{Image, MAX_DECODED_BYTES, u32_be_at};
+
+use png;
+use std::vec;
+
+static SIGNATURE: &'static [u8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+
+/// How much compressed image data is inflated at a time, which bounds how far past the end of
+/// the image data a broken stream can make us inflate.
+static INFLATE_CHUNK_SIZE: uint = 4096;
+
+/// Where the pixels of a pass of the image data start and how far apart they are, and the
+/// size of the block each one covers until the later passes come in.
+struct Pass {
+    x: uint,
+    y: uint,
+    x_step: uint,
+    y_step: uint,
+    block_width: uint,
+    block_height: uint,
+}
+
+static WHOLE_IMAGE: &'static [Pass] = &[
+    Pass { x: 0, y: 0, x_step: 1, y_step: 1, block_width: 1, block_height: 1 },
+];
+
+/// The seven passes of Adam7 interlacing.
+static ADAM7: &'static [Pass] = &[
+    Pass { x: 0, y: 0, x_step: 8, y_step: 8, block_width: 8, block_height: 8 },
+    Pass { x: 4, y: 0, x_step: 8, y_step: 8, block_width: 4, block_height: 8 },
+    Pass { x: 0, y: 4, x_step: 4, y_step: 8, block_width: 4, block_height: 4 },
+    Pass { x: 2, y: 0, x_step: 4, y_step: 4, block_width: 2, block_height: 4 },
+    Pass { x: 0, y: 2, x_step: 2, y_step: 4, block_width: 2, block_height: 2 },
+    Pass { x: 1, y: 0, x_step: 2, y_step: 2, block_width: 1, block_height: 2 },
+    Pass { x: 0, y: 1, x_step: 1, y_step: 2, block_width: 1, block_height: 1 },
+];
+
+/// The fields of the IHDR chunk that decoding needs.
+struct Header {
+    width: uint,
+    height: uint,
+    bit_depth: uint,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn channels(&self) -> uint {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> uint {
+        self.channels() * self.bit_depth
+    }
+
+    /// The number of bytes a row of `width` pixels takes up, not counting its filter type.
+    fn row_bytes(&self, width: uint) -> uint {
+        (width * self.bits_per_pixel() + 7) / 8
+    }
+
+    /// The width and height of the pixels of `pass`.
+    fn pass_size(&self, pass: &Pass) -> (uint, uint) {
+        let (width, height) = (self.width, self.height);
+        if width <= pass.x || height <= pass.y {
+            return (0, 0);
+        }
+        ((width - pass.x + pass.x_step - 1) / pass.x_step,
+         (height - pass.y + pass.y_step - 1) / pass.y_step)
+    }
+
+    /// The number of bytes of image data, once inflated: every row of every pass, each with its
+    /// filter type.
+    fn data_size(&self, passes: &[Pass]) -> uint {
+        let mut size = 0;
+        for pass in passes.iter() {
+            let (width, height) = self.pass_size(pass);
+            if width > 0 {
+                size += (self.row_bytes(width) + 1) * height;
+            }
+        }
+        size
+    }
+}
+
+fn read_header(data: &[u8]) -> Option<Header> {
+    if data.len() < 13 {
+        return None;
+    }
+    let header = Header {
+        width: u32_be_at(data, 0).unwrap(),
+        height: u32_be_at(data, 4).unwrap(),
+        bit_depth: data[8] as uint,
+        color_type: data[9],
+        interlaced: data[12] == 1,
+    };
+    let depth_allowed = match (header.color_type, header.bit_depth) {
+        (0, 1) | (0, 2) | (0, 4) | (3, 1) | (3, 2) | (3, 4) | (3, 8) => true,
+        (0, 8) | (0, 16) | (2, 8) | (2, 16) | (4, 8) | (4, 16) | (6, 8) | (6, 16) => true,
+        _ => false,
+    };
+    if !depth_allowed || data[10] != 0 || data[11] != 0 || data[12] > 1 ||
+            header.width == 0 || header.height == 0 ||
+            header.width > MAX_DECODED_BYTES / 4 / header.height {
+        return None;
+    }
+    Some(header)
+}
+
+/// Reads sample `index` of a row, at the image's bit depth.
+fn sample(row: &[u8], index: uint, bit_depth: uint) -> uint {
+    match bit_depth {
+        16 => (row[index * 2] as uint << 8) | row[index * 2 + 1] as uint,
+        8 => row[index] as uint,
+        _ => {
+            let bit = index * bit_depth;
+            (row[bit / 8] as uint >> (8 - bit_depth - bit % 8)) & ((1 << bit_depth) - 1)
+        }
+    }
+}
+
+/// Scales a sample to eight bits. Sixteen bit samples lose their low byte, as with libpng's
+/// `png_set_strip_16`.
+fn to_u8(value: uint, bit_depth: uint) -> u8 {
+    match bit_depth {
+        16 => (value >> 8) as u8,
+        8 => value as u8,
+        _ => (value * 255 / ((1 << bit_depth) - 1)) as u8,
+    }
+}
+
+/// Undoes the filter of a row, given the unfiltered row above it.
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bytes_per_pixel: uint) -> bool {
+    let length = row.len();
+    match filter {
+        0 => (),
+        1 => {
+            for i in range(bytes_per_pixel, length) {
+                row[i] += row[i - bytes_per_pixel];
+            }
+        }
+        2 => {
+            for i in range(0, length) {
+                row[i] += previous[i];
+            }
+        }
+        3 => {
+            for i in range(0, length) {
+                let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] as uint } else { 0 };
+                row[i] += ((left + previous[i] as uint) / 2) as u8;
+            }
+        }
+        4 => {
+            for i in range(0, length) {
+                let (left, upper_left) = if i >= bytes_per_pixel {
+                    (row[i - bytes_per_pixel] as int, previous[i - bytes_per_pixel] as int)
+                } else {
+                    (0, 0)
+                };
+                let above = previous[i] as int;
+                let estimate = left + above - upper_left;
+                let (left_distance, above_distance, upper_left_distance) =
+                    ((estimate - left).abs(), (estimate - above).abs(),
+                     (estimate - upper_left).abs());
+                row[i] += if left_distance <= above_distance &&
+                        left_distance <= upper_left_distance {
+                    left as u8
+                } else if above_distance <= upper_left_distance {
+                    above as u8
+                } else {
+                    upper_left as u8
+                };
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Reads a sample of the transparent color of an image without a palette.
+fn transparent_sample(transparency: &[u8], channel: uint) -> Option<uint> {
+    if transparency.len() < channel * 2 + 2 {
+        return None;
+    }
+    Some(transparency[channel * 2] as uint << 8 | transparency[channel * 2 + 1] as uint)
+}
+
+/// Turns the samples of a pixel into RGBA, looking colors up in the palette and making the
+/// color that the tRNS chunk names transparent.
+fn to_rgba(header: &Header, values: &[uint], palette: &[u8], transparency: &[u8]) -> [u8, ..4] {
+    let depth = header.bit_depth;
+    match header.color_type {
+        0 => {
+            let gray = to_u8(values[0], depth);
+            let transparent = transparent_sample(transparency, 0) == Some(values[0]);
+            [gray, gray, gray, if transparent { 0 } else { 0xFF }]
+        }
+        2 => {
+            let transparent = range(0u, 3).all(|i| {
+                transparent_sample(transparency, i) == Some(values[i])
+            });
+            [to_u8(values[0], depth), to_u8(values[1], depth), to_u8(values[2], depth),
+             if transparent { 0 } else { 0xFF }]
+        }
+        3 => {
+            let index = values[0];
+            let alpha = if index < transparency.len() { transparency[index] } else { 0xFF };
+            if index * 3 + 2 < palette.len() {
+                [palette[index * 3], palette[index * 3 + 1], palette[index * 3 + 2], alpha]
+            } else {
+                [0, 0, 0, 0xFF]
+            }
+        }
+        4 => {
+            let gray = to_u8(values[0], depth);
+            [gray, gray, gray, to_u8(values[1], depth)]
+        }
+        _ => {
+            [to_u8(values[0], depth), to_u8(values[1], depth), to_u8(values[2], depth),
+             to_u8(values[3], depth)]
+        }
+    }
+}
+
+/// Inflates the next chunk of image data onto `data`, until it holds `size` bytes. Returns
+/// `false` if the compressed stream is broken.
+fn inflate_into(decoder: &mut Decoder, chunk: &[u8], size: uint, data: &mut ~[u8]) -> bool {
+    let mut position = 0;
+    while position < chunk.len() && data.len() < size && !decoder.is_finished() {
+        let end = ::std::cmp::min(position + INFLATE_CHUNK_SIZE, chunk.len());
+        match decoder.decode(chunk.slice(position, end)) {
+            Ok(output) => data.push_all_move(output),
+            Err(()) => return false,
+        }
+        position = end;
+    }
+    true
+}
+
+/// Decodes the part of a PNG that has arrived into RGBA pixels, leaving the pixels that haven't
+/// arrived transparent. Returns `None` until at least one row of pixels is in, and for broken
+/// images.
+pub fn decode(buffer: &[u8]) -> Option<Image> {
+    if !buffer.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut decoder = Decoder::new(Deflate);
+    let mut data = ~[];
+    let mut position = SIGNATURE.len();
+    while position + 8 <= buffer.len() {
+        let length = u32_be_at(buffer, position).unwrap();
+        let kind = buffer.slice(position + 4, position + 8);
+        let start = position + 8;
+        let complete = length <= buffer.len() - start;
+        let chunk = if complete {
+            buffer.slice(start, start + length)
+        } else {
+            buffer.slice_from(start)
+        };
+        if kind == bytes!("IHDR") {
+            header = read_header(chunk);
+            if header.is_none() {
+                return None;
+            }
+        } else if kind == bytes!("PLTE") && complete {
+            palette = chunk;
+        } else if kind == bytes!("tRNS") && complete {
+            transparency = chunk;
+        } else if kind == bytes!("IDAT") {
+            let size = match header {
+                Some(ref header) => {
+                    header.data_size(if header.interlaced { ADAM7 } else { WHOLE_IMAGE })
+                }
+                None => return None,
+            };
+            if !inflate_into(&mut decoder, chunk, size, &mut data) {
+                return None;
+            }
+        } else if kind == bytes!("IEND") {
+            break;
+        }
+        if !complete {
+            break;
+        }
+        // Skip the CRC.
+        position = start + length + 4;
+    }
+    let header = match header {
+        Some(header) => header,
+        None => return None,
+    };
+    if header.color_type == 3 && palette.is_empty() {
+        return None;
+    }
+
+    let passes = if header.interlaced { ADAM7 } else { WHOLE_IMAGE };
+    data.truncate(header.data_size(passes));
+
+    let (width, height) = (header.width, header.height);
+    let bytes_per_pixel = (header.bits_per_pixel() + 7) / 8;
+    let channels = header.channels();
+    let mut pixels = vec::from_elem(width * height * 4, 0u8);
+    let mut position = 0;
+    let mut rows = 0;
+    for pass in passes.iter() {
+        let (pass_width, pass_height) = header.pass_size(pass);
+        if pass_width == 0 {
+            continue;
+        }
+        let row_bytes = header.row_bytes(pass_width);
+        let mut previous = vec::from_elem(row_bytes, 0u8);
+        let mut pass_y = 0;
+        while pass_y < pass_height && position + 1 + row_bytes <= data.len() {
+            let mut row = data.slice(position + 1, position + 1 + row_bytes).to_owned();
+            if !unfilter(data[position], row, previous, bytes_per_pixel) {
+                return None;
+            }
+            position += 1 + row_bytes;
+            rows += 1;
+
+            let y = pass.y + pass_y * pass.y_step;
+            let bottom = ::std::cmp::min(y + pass.block_height, height);
+            for pass_x in range(0, pass_width) {
+                let mut values = [0u, ..4];
+                for channel in range(0, channels) {
+                    values[channel] = sample(row, pass_x * channels + channel, header.bit_depth);
+                }
+                let color = to_rgba(&header, &values, palette, transparency);
+                let x = pass.x + pass_x * pass.x_step;
+                let right = ::std::cmp::min(x + pass.block_width, width);
+                for block_y in range(y, bottom) {
+                    for block_x in range(x, right) {
+                        let pixel = (block_y * width + block_x) * 4;
+                        pixels.mut_slice(pixel, pixel + 4).copy_from(&color);
+                    }
+                }
+            }
+            previous = row;
+            pass_y += 1;
+        }
+        // The rest of the image data hasn't arrived yet.
+        if pass_y < pass_height {
+            break;
+        }
+    }
+    if rows == 0 {
+        return None;
+    }
+    Some(Image(width as u32, height as u32, png::RGBA8, pixels))
+}
+
+#[cfg(test)]
+static TEST_INTERLACED_PNG: &'static [u8] = include_bin!("test_interlaced.png");
+
+#[test]
+fn test_decode_interlaced() {
+    // The image is 10 by 9, and each pixel is the color (x * 25, y * 28, 200, 255).
+    let image = decode(TEST_INTERLACED_PNG).unwrap();
+    assert_eq!((image.width, image.height), (10, 9));
+    for y in range(0u, 9) {
+        for x in range(0u, 10) {
+            let pixel = (y * 10 + x) * 4;
+            assert_eq!(image.pixels.slice(pixel, pixel + 4).to_owned(),
+                       ~[(x * 25) as u8, (y * 28) as u8, 200, 255]);
+        }
+    }
+
+    // The image data is stored uncompressed from byte 48. With only the first five passes in,
+    // the first pass fills in the pixels that the sixth has yet to bring.
+    let partial = decode(TEST_INTERLACED_PNG.slice_to(48 + 85)).unwrap();
+    assert_eq!(partial.pixels.slice(0, 12).to_owned(), ~[0, 0, 200, 255, 0, 0, 200, 255,
+                                                         50, 0, 200, 255]);
+    assert!(partial.pixels != image.pixels);
+
+    // Nothing to show before the image data.
+    assert!(decode(TEST_INTERLACED_PNG.slice_to(40)).is_none());
+}
@@ -0,0 +1,1718 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A decoder for VP8 key frames, which hold the image data of lossy WebP files, following
+//! RFC 6386. The chroma planes are upsampled the way libwebp does by default, so the pixels
+//! match what other browsers show.
+
+use image::base::{Image, MAX_DECODED_BYTES};
+
+use png;
+use std::vec;
+
+// Intra prediction modes for whole macroblocks.
+static DC_PRED: uint = 0;
+static V_PRED: uint = 1;
+static H_PRED: uint = 2;
+static TM_PRED: uint = 3;
+static B_PRED: uint = 4;
+
+// Intra prediction modes for the 4x4 sub-blocks of a B_PRED macroblock.
+static B_DC_PRED: uint = 0;
+static B_TM_PRED: uint = 1;
+static B_VE_PRED: uint = 2;
+static B_HE_PRED: uint = 3;
+static B_LD_PRED: uint = 4;
+static B_RD_PRED: uint = 5;
+static B_VR_PRED: uint = 6;
+static B_VL_PRED: uint = 7;
+static B_HD_PRED: uint = 8;
+static B_HU_PRED: uint = 9;
+
+/// The trees that code the modes and segments. A positive entry is the index of the next pair
+/// of branches, and anything else is a leaf holding the negated value.
+static Y_MODE_TREE: [int, ..8] = [-4, 2, 4, 6, -0, -1, -2, -3];
+static Y_MODE_PROBABILITIES: [u8, ..4] = [145, 156, 163, 128];
+static UV_MODE_TREE: [int, ..6] = [-0, 2, -1, 4, -2, -3];
+static UV_MODE_PROBABILITIES: [u8, ..3] = [142, 114, 183];
+static B_MODE_TREE: [int, ..18] = [-0, 2, -1, 4, -2, 6, 8, 12, -3, 10, -5, -6, -4, 14, -7, 16,
+                                   -8, -9];
+static SEGMENT_TREE: [int, ..6] = [2, 4, -0, -1, -2, -3];
+
+/// The band of each coefficient position, which picks its probabilities. The extra entry is
+/// looked up after the last coefficient, and never used.
+static BANDS: [uint, ..17] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7, 0];
+static ZIGZAG: [uint, ..16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+/// The probabilities of the extra bits of the larger coefficient categories.
+static CAT3_PROBABILITIES: [u8, ..3] = [173, 148, 140];
+static CAT4_PROBABILITIES: [u8, ..4] = [176, 155, 140, 135];
+static CAT5_PROBABILITIES: [u8, ..5] = [180, 157, 141, 134, 130];
+static CAT6_PROBABILITIES: [u8, ..11] = [254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129];
+
+/// The width of the work area for a luma macroblock: the pixel to the left, the 16 pixels of
+/// the macroblock and the 4 pixels above and to its right.
+static LUMA_STRIDE: uint = 21;
+static CHROMA_STRIDE: uint = 9;
+
+/// Reads the boolean entropy coded data of the frame header and the token partitions.
+struct BoolDecoder<'a> {
+    data: &'a [u8],
+    position: uint,
+    value: uint,
+    range: uint,
+    bit_count: uint,
+}
+
+impl<'a> BoolDecoder<'a> {
+    fn new(data: &'a [u8]) -> BoolDecoder<'a> {
+        let mut decoder = BoolDecoder {
+            data: data,
+            position: 0,
+            value: 0,
+            range: 255,
+            bit_count: 0,
+        };
+        let high = decoder.next_byte();
+        let low = decoder.next_byte();
+        decoder.value = (high << 8) | low;
+        decoder
+    }
+
+    /// Data past the end reads as zeroes, as libvpx does; `is_truncated` says whether the
+    /// decoder read so far past the end that the frame must be broken.
+    fn next_byte(&mut self) -> uint {
+        self.position += 1;
+        if self.position <= self.data.len() {
+            self.data[self.position - 1] as uint
+        } else {
+            0
+        }
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.position > self.data.len() + 2
+    }
+
+    /// Reads a bit that is zero with probability `probability / 256`.
+    fn read_bool(&mut self, probability: u8) -> bool {
+        let split = 1 + (((self.range - 1) * probability as uint) >> 8);
+        let big_split = split << 8;
+        let bit = if self.value >= big_split {
+            self.range -= split;
+            self.value -= big_split;
+            true
+        } else {
+            self.range = split;
+            false
+        };
+        while self.range < 128 {
+            self.value <<= 1;
+            self.range <<= 1;
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.bit_count = 0;
+                self.value |= self.next_byte();
+            }
+        }
+        bit
+    }
+
+    fn read_flag(&mut self) -> bool {
+        self.read_bool(128)
+    }
+
+    fn read_literal(&mut self, bits: uint) -> uint {
+        let mut value = 0;
+        for _ in range(0, bits) {
+            value = (value << 1) | if self.read_flag() { 1 } else { 0 };
+        }
+        value
+    }
+
+    /// Reads a magnitude followed by a sign.
+    fn read_signed(&mut self, bits: uint) -> int {
+        let value = self.read_literal(bits) as int;
+        if self.read_flag() { -value } else { value }
+    }
+
+    /// Reads a signed value if the flag before it is set, and zero otherwise.
+    fn read_optional_signed(&mut self, bits: uint) -> int {
+        if self.read_flag() { self.read_signed(bits) } else { 0 }
+    }
+
+    fn read_tree(&mut self, tree: &[int], probabilities: &[u8]) -> uint {
+        let mut i = 0;
+        loop {
+            let node = tree[i + if self.read_bool(probabilities[i >> 1]) { 1 } else { 0 }];
+            if node <= 0 {
+                return (-node) as uint;
+            }
+            i = node as uint;
+        }
+    }
+}
+
+/// The dequantization factors of a segment, for the DC and AC coefficients of each kind of
+/// block.
+struct Quantizer {
+    y_dc: i32,
+    y_ac: i32,
+    y2_dc: i32,
+    y2_ac: i32,
+    uv_dc: i32,
+    uv_ac: i32,
+}
+
+/// How strongly to smooth the edges of a macroblock. A limit of zero turns filtering off.
+struct Filter {
+    limit: i32,
+    interior_limit: i32,
+    hev_threshold: i32,
+    /// Whether to filter the edges between the blocks inside the macroblock too.
+    inner: bool,
+}
+
+struct Macroblock {
+    luma_mode: uint,
+    sub_modes: [uint, ..16],
+    chroma_mode: uint,
+}
+
+/// The decoded frame, padded to a whole number of macroblocks.
+struct Planes {
+    y: ~[u8],
+    u: ~[u8],
+    v: ~[u8],
+    y_stride: uint,
+    uv_stride: uint,
+}
+
+fn clamp(value: int, low: int, high: int) -> int {
+    if value < low { low } else if value > high { high } else { value }
+}
+
+fn clip(value: i32) -> u8 {
+    if value < 0 { 0 } else if value > 255 { 255 } else { value as u8 }
+}
+
+fn u24_at(data: &[u8], offset: uint) -> uint {
+    data[offset] as uint | (data[offset + 1] as uint << 8) | (data[offset + 2] as uint << 16)
+}
+
+/// Decodes the contents of a `VP8 ` chunk into opaque RGBA pixels.
+pub fn decode(data: &[u8]) -> Option<Image> {
+    if data.len() < 10 {
+        return None;
+    }
+    let tag = u24_at(data, 0);
+    let key_frame = tag & 1 == 0;
+    let version = (tag >> 1) & 7;
+    let show_frame = (tag >> 4) & 1 == 1;
+    let first_partition_size = tag >> 5;
+    if !key_frame || version > 3 || !show_frame || data[3] != 0x9D || data[4] != 0x01 ||
+            data[5] != 0x2A {
+        return None;
+    }
+    // The top two bits of each dimension are an upscaling hint, which we ignore like libwebp.
+    let width = (data[6] as uint | (data[7] as uint << 8)) & 0x3FFF;
+    let height = (data[8] as uint | (data[9] as uint << 8)) & 0x3FFF;
+    if width == 0 || height == 0 || width * height * 4 > MAX_DECODED_BYTES ||
+            10 + first_partition_size > data.len() {
+        return None;
+    }
+    let mut header = BoolDecoder::new(data.slice(10, 10 + first_partition_size));
+
+    // The color space and clamping type, neither of which changes how a key frame decodes.
+    header.read_literal(2);
+
+    let segmentation = header.read_flag();
+    let mut update_segment_map = false;
+    let mut absolute_segment_values = false;
+    let mut segment_quantizers = [0i, ..4];
+    let mut segment_filter_levels = [0i, ..4];
+    let mut segment_probabilities = [255u8, ..3];
+    if segmentation {
+        update_segment_map = header.read_flag();
+        if header.read_flag() {
+            absolute_segment_values = header.read_flag();
+            for i in range(0, 4) {
+                segment_quantizers[i] = header.read_optional_signed(7);
+            }
+            for i in range(0, 4) {
+                segment_filter_levels[i] = header.read_optional_signed(6);
+            }
+        }
+        if update_segment_map {
+            for i in range(0, 3) {
+                if header.read_flag() {
+                    segment_probabilities[i] = header.read_literal(8) as u8;
+                }
+            }
+        }
+    }
+
+    let simple_filter = header.read_flag();
+    let filter_level = header.read_literal(6) as int;
+    let sharpness = header.read_literal(3) as int;
+    let filter_deltas = header.read_flag();
+    // Only the deltas for intra prediction and for B_PRED apply to a key frame.
+    let mut reference_delta = 0;
+    let mut mode_delta = 0;
+    if filter_deltas && header.read_flag() {
+        for i in range(0, 4) {
+            let delta = header.read_optional_signed(6);
+            if i == 0 {
+                reference_delta = delta;
+            }
+        }
+        for i in range(0, 4) {
+            let delta = header.read_optional_signed(6);
+            if i == 0 {
+                mode_delta = delta;
+            }
+        }
+    }
+
+    // The tokens of each row of macroblocks are in one of the partitions, in turn. The sizes
+    // of all but the last partition come first.
+    let partition_count = 1 << header.read_literal(2);
+    let sizes_start = 10 + first_partition_size;
+    let mut position = sizes_start + 3 * (partition_count - 1);
+    if position > data.len() {
+        return None;
+    }
+    let mut partitions = ~[];
+    for i in range(0, partition_count) {
+        let size = if i + 1 < partition_count {
+            u24_at(data, sizes_start + 3 * i)
+        } else {
+            data.len() - position
+        };
+        if position + size > data.len() {
+            return None;
+        }
+        partitions.push(BoolDecoder::new(data.slice(position, position + size)));
+        position += size;
+    }
+
+    let base_quantizer = header.read_literal(7) as int;
+    let y_dc_delta = header.read_optional_signed(4);
+    let y2_dc_delta = header.read_optional_signed(4);
+    let y2_ac_delta = header.read_optional_signed(4);
+    let uv_dc_delta = header.read_optional_signed(4);
+    let uv_ac_delta = header.read_optional_signed(4);
+    let mut quantizers = ~[];
+    for segment in range(0, 4) {
+        let mut q = base_quantizer;
+        if segmentation {
+            q = segment_quantizers[segment];
+            if !absolute_segment_values {
+                q += base_quantizer;
+            }
+        }
+        let y2_ac = AC_QUANTIZERS[clamp(q + y2_ac_delta, 0, 127) as uint] * 155 / 100;
+        quantizers.push(Quantizer {
+            y_dc: DC_QUANTIZERS[clamp(q + y_dc_delta, 0, 127) as uint],
+            y_ac: AC_QUANTIZERS[clamp(q, 0, 127) as uint],
+            y2_dc: DC_QUANTIZERS[clamp(q + y2_dc_delta, 0, 127) as uint] * 2,
+            y2_ac: if y2_ac < 8 { 8 } else { y2_ac },
+            uv_dc: DC_QUANTIZERS[clamp(q + uv_dc_delta, 0, 117) as uint],
+            uv_ac: AC_QUANTIZERS[clamp(q + uv_ac_delta, 0, 127) as uint],
+        });
+    }
+
+    // Whether to keep the probabilities for the next frame, of which there are none.
+    header.read_flag();
+
+    let mut coefficient_probabilities = DEFAULT_COEFFICIENT_PROBABILITIES;
+    for i in range(0, 4) {
+        for j in range(0, 8) {
+            for k in range(0, 3) {
+                for l in range(0, 11) {
+                    if header.read_bool(COEFFICIENT_UPDATE_PROBABILITIES[i][j][k][l]) {
+                        coefficient_probabilities[i][j][k][l] = header.read_literal(8) as u8;
+                    }
+                }
+            }
+        }
+    }
+    let skip_probability = if header.read_flag() {
+        Some(header.read_literal(8) as u8)
+    } else {
+        None
+    };
+
+    // The filter of each segment, for macroblocks without and then with B_PRED.
+    let mut filters = ~[];
+    for segment in range(0, 4) {
+        let mut base_level = filter_level;
+        if segmentation {
+            base_level = segment_filter_levels[segment];
+            if !absolute_segment_values {
+                base_level += filter_level;
+            }
+        }
+        for &sub_blocks in [false, true].iter() {
+            let mut level = base_level;
+            if filter_deltas {
+                level += reference_delta;
+                if sub_blocks {
+                    level += mode_delta;
+                }
+            }
+            filters.push(filter_for_level(clamp(level, 0, 63), sharpness, sub_blocks));
+        }
+    }
+
+    let mb_width = (width + 15) / 16;
+    let mb_height = (height + 15) / 16;
+    let mut planes = Planes {
+        y: vec::from_elem(mb_width * 16 * mb_height * 16, 0u8),
+        u: vec::from_elem(mb_width * 8 * mb_height * 8, 0u8),
+        v: vec::from_elem(mb_width * 8 * mb_height * 8, 0u8),
+        y_stride: mb_width * 16,
+        uv_stride: mb_width * 8,
+    };
+
+    // The sub-block modes of the blocks above and to the left, and whether the blocks above
+    // and to the left had any coefficients: the Y2 block, four Y, two U and two V blocks.
+    let mut top_modes = vec::from_elem(mb_width * 4, B_DC_PRED);
+    let mut top_nonzero = vec::from_fn(mb_width, |_| [false, ..9]);
+    let mut macroblock_filters = vec::with_capacity(mb_width * mb_height);
+    for mb_y in range(0, mb_height) {
+        let mut left_modes = [B_DC_PRED, ..4];
+        let mut left_nonzero = [false, ..9];
+        let partition = &mut partitions[mb_y & (partition_count - 1)];
+        for mb_x in range(0, mb_width) {
+            let segment = if update_segment_map {
+                header.read_tree(&SEGMENT_TREE, &segment_probabilities)
+            } else {
+                0
+            };
+            let skip = match skip_probability {
+                Some(probability) => header.read_bool(probability),
+                None => false,
+            };
+
+            let luma_mode = header.read_tree(&Y_MODE_TREE, &Y_MODE_PROBABILITIES);
+            let mut sub_modes = [B_DC_PRED, ..16];
+            if luma_mode == B_PRED {
+                for y in range(0, 4) {
+                    for x in range(0, 4) {
+                        let top = top_modes[mb_x * 4 + x];
+                        let mode = header.read_tree(&B_MODE_TREE,
+                                                    &B_MODE_PROBABILITIES[top][left_modes[y]]);
+                        sub_modes[y * 4 + x] = mode;
+                        top_modes[mb_x * 4 + x] = mode;
+                        left_modes[y] = mode;
+                    }
+                }
+            } else {
+                // Later sub-blocks are predicted as if this macroblock's were coded with the
+                // equivalent mode.
+                let mode = match luma_mode {
+                    V_PRED => B_VE_PRED,
+                    H_PRED => B_HE_PRED,
+                    TM_PRED => B_TM_PRED,
+                    _ => B_DC_PRED,
+                };
+                for i in range(0, 4) {
+                    top_modes[mb_x * 4 + i] = mode;
+                    left_modes[i] = mode;
+                }
+            }
+            let macroblock = Macroblock {
+                luma_mode: luma_mode,
+                sub_modes: sub_modes,
+                chroma_mode: header.read_tree(&UV_MODE_TREE, &UV_MODE_PROBABILITIES),
+            };
+
+            let mut coefficients = [0i32, ..384];
+            let nonzero = if skip {
+                for i in range(1, 9) {
+                    top_nonzero[mb_x][i] = false;
+                    left_nonzero[i] = false;
+                }
+                // The Y2 context carries over B_PRED macroblocks, which have no Y2 block.
+                if luma_mode != B_PRED {
+                    top_nonzero[mb_x][0] = false;
+                    left_nonzero[0] = false;
+                }
+                false
+            } else {
+                read_residuals(&mut *partition, &coefficient_probabilities, &quantizers[segment],
+                               luma_mode, &mut top_nonzero[mb_x], &mut left_nonzero,
+                               &mut coefficients)
+            };
+
+            reconstruct(&mut planes, mb_x, mb_y, mb_width, &macroblock, &coefficients);
+
+            let mut filter = filters[segment * 2 + if luma_mode == B_PRED { 1 } else { 0 }];
+            filter.inner = filter.inner || nonzero;
+            macroblock_filters.push(filter);
+        }
+        if partition.is_truncated() {
+            return None;
+        }
+    }
+    if header.is_truncated() {
+        return None;
+    }
+
+    // Intra prediction uses the unfiltered pixels, so the loop filter runs once the whole frame
+    // has been reconstructed.
+    if filter_level > 0 {
+        for mb_y in range(0, mb_height) {
+            for mb_x in range(0, mb_width) {
+                loop_filter(&mut planes, mb_x, mb_y, simple_filter,
+                            &macroblock_filters[mb_y * mb_width + mb_x]);
+            }
+        }
+    }
+
+    Some(Image(width as u32, height as u32, png::RGBA8, to_rgba(&planes, width, height)))
+}
+
+fn filter_for_level(level: int, sharpness: int, inner: bool) -> Filter {
+    let mut filter = Filter {
+        limit: 0,
+        interior_limit: 0,
+        hev_threshold: 0,
+        inner: inner,
+    };
+    if level > 0 {
+        let mut interior_limit = level;
+        if sharpness > 0 {
+            interior_limit >>= if sharpness > 4 { 2 } else { 1 };
+            if interior_limit > 9 - sharpness {
+                interior_limit = 9 - sharpness;
+            }
+        }
+        if interior_limit < 1 {
+            interior_limit = 1;
+        }
+        filter.limit = (2 * level + interior_limit) as i32;
+        filter.interior_limit = interior_limit as i32;
+        filter.hev_threshold = if level >= 40 { 2 } else if level >= 15 { 1 } else { 0 };
+    }
+    filter
+}
+
+/// Reads the tokens of one block into `block`, dequantized and in raster order. Returns the
+/// position after the last coefficient read, or `first` if there were none.
+fn read_coefficients(decoder: &mut BoolDecoder,
+                     probabilities: &[[[u8, ..11], ..3], ..8],
+                     first: uint,
+                     context: uint,
+                     dc_quantizer: i32,
+                     ac_quantizer: i32,
+                     block: &mut [i32])
+                     -> uint {
+    let mut n = first;
+    let mut p = &probabilities[BANDS[n]][context];
+    while n < 16 {
+        if !decoder.read_bool(p[0]) {
+            // The end of the block.
+            return n;
+        }
+        while !decoder.read_bool(p[1]) {
+            n += 1;
+            if n == 16 {
+                return 16;
+            }
+            p = &probabilities[BANDS[n]][0];
+        }
+        let (value, next_context) = if !decoder.read_bool(p[2]) {
+            (1, 1)
+        } else {
+            (read_large_value(decoder, p), 2)
+        };
+        let value = if decoder.read_flag() { -value } else { value };
+        block[ZIGZAG[n]] = value * if n > 0 { ac_quantizer } else { dc_quantizer };
+        n += 1;
+        p = &probabilities[BANDS[n]][next_context];
+    }
+    16
+}
+
+fn read_large_value(decoder: &mut BoolDecoder, p: &[u8, ..11]) -> i32 {
+    if !decoder.read_bool(p[3]) {
+        if !decoder.read_bool(p[4]) {
+            return 2;
+        }
+        return 3 + if decoder.read_bool(p[5]) { 1 } else { 0 };
+    }
+    if !decoder.read_bool(p[6]) {
+        if !decoder.read_bool(p[7]) {
+            return 5 + if decoder.read_bool(159) { 1 } else { 0 };
+        }
+        let high = if decoder.read_bool(165) { 2 } else { 0 };
+        return 7 + high + if decoder.read_bool(145) { 1 } else { 0 };
+    }
+    let high = if decoder.read_bool(p[8]) { 1 } else { 0 };
+    let low = if decoder.read_bool(p[9 + high]) { 1 } else { 0 };
+    let category = 2 * high + low;
+    let extra_probabilities: &[u8] = match category {
+        0 => &CAT3_PROBABILITIES,
+        1 => &CAT4_PROBABILITIES,
+        2 => &CAT5_PROBABILITIES,
+        _ => &CAT6_PROBABILITIES,
+    };
+    let mut extra = 0;
+    for &probability in extra_probabilities.iter() {
+        extra = extra * 2 + if decoder.read_bool(probability) { 1 } else { 0 };
+    }
+    3 + (8 << category) + extra
+}
+
+/// Reads the coefficients of the 16 Y, 4 U and 4 V blocks of a macroblock, in that order.
+/// Returns whether any block has more than a DC coefficient of zero.
+fn read_residuals(decoder: &mut BoolDecoder,
+                  probabilities: &[[[[u8, ..11], ..3], ..8], ..4],
+                  quantizer: &Quantizer,
+                  luma_mode: uint,
+                  top: &mut [bool, ..9],
+                  left: &mut [bool, ..9],
+                  coefficients: &mut [i32, ..384])
+                  -> bool {
+    let mut nonzero = false;
+    let (first, luma_plane) = if luma_mode != B_PRED {
+        // The DC coefficients of the luma blocks are coded together, in the Y2 block.
+        let mut dc = [0i32, ..16];
+        let context = count_true(top[0], left[0]);
+        let count = read_coefficients(decoder, &probabilities[1], 0, context, quantizer.y2_dc,
+                                      quantizer.y2_ac, &mut dc);
+        top[0] = count > 0;
+        left[0] = count > 0;
+        if count > 1 {
+            inverse_walsh_hadamard(&dc, coefficients);
+        } else {
+            let dc = (dc[0] + 3) >> 3;
+            for i in range(0, 16) {
+                coefficients[i * 16] = dc;
+            }
+        }
+        (1, 0)
+    } else {
+        (0, 3)
+    };
+
+    for y in range(0, 4) {
+        let mut has_left = left[1 + y];
+        for x in range(0, 4) {
+            let index = y * 4 + x;
+            let block = coefficients.mut_slice(index * 16, index * 16 + 16);
+            let count = read_coefficients(decoder, &probabilities[luma_plane], first,
+                                          count_true(has_left, top[1 + x]), quantizer.y_dc,
+                                          quantizer.y_ac, block);
+            has_left = count > first;
+            top[1 + x] = has_left;
+            nonzero = nonzero || count > 1 || block[0] != 0;
+        }
+        left[1 + y] = has_left;
+    }
+
+    for &(context, start) in [(5u, 16u), (7, 20)].iter() {
+        for y in range(0, 2) {
+            let mut has_left = left[context + y];
+            for x in range(0, 2) {
+                let index = start + y * 2 + x;
+                let block = coefficients.mut_slice(index * 16, index * 16 + 16);
+                let count = read_coefficients(decoder, &probabilities[2], 0,
+                                              count_true(has_left, top[context + x]),
+                                              quantizer.uv_dc, quantizer.uv_ac, block);
+                has_left = count > 0;
+                top[context + x] = has_left;
+                nonzero = nonzero || count > 1 || block[0] != 0;
+            }
+            left[context + y] = has_left;
+        }
+    }
+    nonzero
+}
+
+fn count_true(a: bool, b: bool) -> uint {
+    (if a { 1 } else { 0 }) + (if b { 1 } else { 0 })
+}
+
+/// Turns the Y2 block into the DC coefficients of the 16 luma blocks.
+fn inverse_walsh_hadamard(input: &[i32, ..16], output: &mut [i32, ..384]) {
+    let mut temp = [0i32, ..16];
+    for i in range(0, 4) {
+        let a0 = input[i] + input[12 + i];
+        let a1 = input[4 + i] + input[8 + i];
+        let a2 = input[4 + i] - input[8 + i];
+        let a3 = input[i] - input[12 + i];
+        temp[i] = a0 + a1;
+        temp[8 + i] = a0 - a1;
+        temp[4 + i] = a3 + a2;
+        temp[12 + i] = a3 - a2;
+    }
+    for i in range(0, 4) {
+        let dc = temp[i * 4] + 3;
+        let a0 = dc + temp[i * 4 + 3];
+        let a1 = temp[i * 4 + 1] + temp[i * 4 + 2];
+        let a2 = temp[i * 4 + 1] - temp[i * 4 + 2];
+        let a3 = dc - temp[i * 4 + 3];
+        output[(i * 4) * 16] = (a0 + a1) >> 3;
+        output[(i * 4 + 1) * 16] = (a3 + a2) >> 3;
+        output[(i * 4 + 2) * 16] = (a0 - a1) >> 3;
+        output[(i * 4 + 3) * 16] = (a3 - a2) >> 3;
+    }
+}
+
+/// Multiplies by sqrt(2) * cos(pi / 8) and sqrt(2) * sin(pi / 8) in fixed point.
+fn mul_cos(a: i32) -> i32 {
+    ((a * 20091) >> 16) + a
+}
+
+fn mul_sin(a: i32) -> i32 {
+    (a * 35468) >> 16
+}
+
+/// Adds the inverse DCT of the coefficients to the 4x4 block at `offset`.
+fn add_inverse_dct(coefficients: &[i32], pixels: &mut [u8], offset: uint, stride: uint) {
+    let mut temp = [0i32, ..16];
+    for i in range(0, 4) {
+        let a = coefficients[i] + coefficients[8 + i];
+        let b = coefficients[i] - coefficients[8 + i];
+        let c = mul_sin(coefficients[4 + i]) - mul_cos(coefficients[12 + i]);
+        let d = mul_cos(coefficients[4 + i]) + mul_sin(coefficients[12 + i]);
+        temp[i * 4] = a + d;
+        temp[i * 4 + 1] = b + c;
+        temp[i * 4 + 2] = b - c;
+        temp[i * 4 + 3] = a - d;
+    }
+    for i in range(0, 4) {
+        let dc = temp[i] + 4;
+        let a = dc + temp[8 + i];
+        let b = dc - temp[8 + i];
+        let c = mul_sin(temp[4 + i]) - mul_cos(temp[12 + i]);
+        let d = mul_cos(temp[4 + i]) + mul_sin(temp[12 + i]);
+        let row = offset + i * stride;
+        pixels[row] = clip(pixels[row] as i32 + ((a + d) >> 3));
+        pixels[row + 1] = clip(pixels[row + 1] as i32 + ((b + c) >> 3));
+        pixels[row + 2] = clip(pixels[row + 2] as i32 + ((b - c) >> 3));
+        pixels[row + 3] = clip(pixels[row + 3] as i32 + ((a - d) >> 3));
+    }
+}
+
+/// Predicts a macroblock from the pixels around it and adds the residuals.
+fn reconstruct(planes: &mut Planes,
+               mb_x: uint,
+               mb_y: uint,
+               mb_width: uint,
+               macroblock: &Macroblock,
+               coefficients: &[i32, ..384]) {
+    let mut work = [0u8, ..17 * LUMA_STRIDE];
+    fill_edges(planes.y, planes.y_stride, mb_x, mb_y, 16, &mut work, LUMA_STRIDE);
+    // The pixels above and to the right. There are none past the right edge of the frame, so
+    // the last pixel above is repeated.
+    for x in range(0, 4) {
+        work[17 + x] = if mb_y == 0 {
+            127
+        } else if mb_x == mb_width - 1 {
+            planes.y[(mb_y * 16 - 1) * planes.y_stride + mb_x * 16 + 15]
+        } else {
+            planes.y[(mb_y * 16 - 1) * planes.y_stride + mb_x * 16 + 16 + x]
+        };
+    }
+    // The sub-blocks down the right edge haven't got decoded pixels above and to their right,
+    // so they use the macroblock's.
+    for &row in [4u, 8, 12].iter() {
+        for x in range(17, LUMA_STRIDE) {
+            work[row * LUMA_STRIDE + x] = work[x];
+        }
+    }
+
+    if macroblock.luma_mode != B_PRED {
+        predict(&mut work, LUMA_STRIDE, 16, macroblock.luma_mode, mb_x > 0, mb_y > 0);
+    }
+    for i in range(0, 16) {
+        let offset = (1 + (i / 4) * 4) * LUMA_STRIDE + 1 + (i % 4) * 4;
+        if macroblock.luma_mode == B_PRED {
+            predict_sub_block(&mut work, offset, macroblock.sub_modes[i]);
+        }
+        add_inverse_dct(coefficients.slice(i * 16, i * 16 + 16), &mut work, offset,
+                        LUMA_STRIDE);
+    }
+    store(&work, LUMA_STRIDE, planes.y, planes.y_stride, mb_x, mb_y, 16);
+
+    for &(is_u, start) in [(true, 16u), (false, 20)].iter() {
+        let plane = if is_u { &mut planes.u } else { &mut planes.v };
+        let mut work = [0u8, ..9 * CHROMA_STRIDE];
+        fill_edges(*plane, planes.uv_stride, mb_x, mb_y, 8, &mut work, CHROMA_STRIDE);
+        predict(&mut work, CHROMA_STRIDE, 8, macroblock.chroma_mode, mb_x > 0, mb_y > 0);
+        for i in range(0, 4) {
+            let offset = (1 + (i / 2) * 4) * CHROMA_STRIDE + 1 + (i % 2) * 4;
+            let block = start + i;
+            add_inverse_dct(coefficients.slice(block * 16, block * 16 + 16), &mut work, offset,
+                            CHROMA_STRIDE);
+        }
+        store(&work, CHROMA_STRIDE, *plane, planes.uv_stride, mb_x, mb_y, 8);
+    }
+}
+
+/// Copies the pixels above and to the left of a macroblock into its work area. Pixels above
+/// the frame are 127, and those to its left are 129.
+fn fill_edges(plane: &[u8],
+              stride: uint,
+              mb_x: uint,
+              mb_y: uint,
+              size: uint,
+              work: &mut [u8],
+              work_stride: uint) {
+    let (left, top) = (mb_x * size, mb_y * size);
+    work[0] = if mb_y == 0 {
+        127
+    } else if mb_x == 0 {
+        129
+    } else {
+        plane[(top - 1) * stride + left - 1]
+    };
+    for x in range(0, size) {
+        work[1 + x] = if mb_y == 0 { 127 } else { plane[(top - 1) * stride + left + x] };
+    }
+    for y in range(0, size) {
+        work[(1 + y) * work_stride] = if mb_x == 0 {
+            129
+        } else {
+            plane[(top + y) * stride + left - 1]
+        };
+    }
+}
+
+fn store(work: &[u8],
+         work_stride: uint,
+         plane: &mut [u8],
+         stride: uint,
+         mb_x: uint,
+         mb_y: uint,
+         size: uint) {
+    for y in range(0, size) {
+        let row = (mb_y * size + y) * stride + mb_x * size;
+        for x in range(0, size) {
+            plane[row + x] = work[(1 + y) * work_stride + 1 + x];
+        }
+    }
+}
+
+/// Predicts a whole luma or chroma block in its work area.
+fn predict(work: &mut [u8], stride: uint, size: uint, mode: uint, has_left: bool,
+           has_top: bool) {
+    if mode == DC_PRED {
+        // The average of the pixels above and to the left, leaving out those outside the frame.
+        let mut sum = 0u;
+        let mut shift = if size == 16 { 3 } else { 2 };
+        if has_top {
+            for x in range(0, size) {
+                sum += work[1 + x] as uint;
+            }
+            shift += 1;
+        }
+        if has_left {
+            for y in range(0, size) {
+                sum += work[(1 + y) * stride] as uint;
+            }
+            shift += 1;
+        }
+        let value = if has_top || has_left {
+            ((sum + (1 << (shift - 1))) >> shift) as u8
+        } else {
+            128
+        };
+        for y in range(0, size) {
+            for x in range(0, size) {
+                work[(1 + y) * stride + 1 + x] = value;
+            }
+        }
+        return;
+    }
+
+    for y in range(0, size) {
+        let left = work[(1 + y) * stride];
+        for x in range(0, size) {
+            let top = work[1 + x];
+            work[(1 + y) * stride + 1 + x] = match mode {
+                V_PRED => top,
+                H_PRED => left,
+                _ => clip(left as i32 + top as i32 - work[0] as i32),
+            };
+        }
+    }
+}
+
+fn average2(a: u8, b: u8) -> u8 {
+    ((a as uint + b as uint + 1) >> 1) as u8
+}
+
+fn average3(a: u8, b: u8, c: u8) -> u8 {
+    ((a as uint + 2 * b as uint + c as uint + 2) >> 2) as u8
+}
+
+/// Predicts the 4x4 luma sub-block at `offset` in the work area.
+fn predict_sub_block(work: &mut [u8], offset: uint, mode: uint) {
+    // The edge around the block: the pixels to the left from the bottom up, the one above and
+    // to the left, then the 8 above and above to the right.
+    let mut edge = [0u8, ..13];
+    for i in range(0, 4) {
+        edge[i] = work[offset + (3 - i) * LUMA_STRIDE - 1];
+    }
+    for i in range(0, 9) {
+        edge[4 + i] = work[offset - LUMA_STRIDE - 1 + i];
+    }
+    let (left, corner, top) = (edge.slice(0, 4), edge[4], edge.slice(5, 13));
+    // The pixels to the left, from the top down.
+    let l = [left[3], left[2], left[1], left[0]];
+
+    let mut block = [0u8, ..16];
+    match mode {
+        B_TM_PRED => {
+            for y in range(0, 4) {
+                for x in range(0, 4) {
+                    block[y * 4 + x] = clip(l[y] as i32 + top[x] as i32 - corner as i32);
+                }
+            }
+        }
+        B_VE_PRED => {
+            for x in range(0, 4) {
+                let value = average3(edge[4 + x], edge[5 + x], edge[6 + x]);
+                for y in range(0, 4) {
+                    block[y * 4 + x] = value;
+                }
+            }
+        }
+        B_HE_PRED => {
+            for y in range(0, 4) {
+                let value = if y == 3 {
+                    average3(l[2], l[3], l[3])
+                } else {
+                    average3(edge[4 - y], edge[3 - y], edge[2 - y])
+                };
+                for x in range(0, 4) {
+                    block[y * 4 + x] = value;
+                }
+            }
+        }
+        B_LD_PRED => {
+            for y in range(0, 4) {
+                for x in range(0, 4) {
+                    let i = x + y;
+                    block[y * 4 + x] = if i == 6 {
+                        average3(top[6], top[7], top[7])
+                    } else {
+                        average3(top[i], top[i + 1], top[i + 2])
+                    };
+                }
+            }
+        }
+        B_RD_PRED => {
+            for y in range(0, 4) {
+                for x in range(0, 4) {
+                    // Along the edge, from the bottom left up and to the right.
+                    let i = 4 + x - y;
+                    block[y * 4 + x] = average3(edge[i - 1], edge[i], edge[i + 1]);
+                }
+            }
+        }
+        B_VR_PRED => {
+            block[12] = average3(edge[1], edge[2], edge[3]);
+            block[8] = average3(edge[2], edge[3], edge[4]);
+            block[13] = average3(edge[3], edge[4], edge[5]);
+            block[4] = block[13];
+            block[9] = average2(edge[4], edge[5]);
+            block[0] = block[9];
+            block[14] = average3(edge[4], edge[5], edge[6]);
+            block[5] = block[14];
+            block[10] = average2(edge[5], edge[6]);
+            block[1] = block[10];
+            block[15] = average3(edge[5], edge[6], edge[7]);
+            block[6] = block[15];
+            block[11] = average2(edge[6], edge[7]);
+            block[2] = block[11];
+            block[7] = average3(edge[6], edge[7], edge[8]);
+            block[3] = average2(edge[7], edge[8]);
+        }
+        B_VL_PRED => {
+            block[0] = average2(top[0], top[1]);
+            block[4] = average3(top[0], top[1], top[2]);
+            block[8] = average2(top[1], top[2]);
+            block[1] = block[8];
+            block[5] = average3(top[1], top[2], top[3]);
+            block[12] = block[5];
+            block[9] = average2(top[2], top[3]);
+            block[2] = block[9];
+            block[13] = average3(top[2], top[3], top[4]);
+            block[6] = block[13];
+            block[10] = average2(top[3], top[4]);
+            block[3] = block[10];
+            block[14] = average3(top[3], top[4], top[5]);
+            block[7] = block[14];
+            block[11] = average3(top[4], top[5], top[6]);
+            block[15] = average3(top[5], top[6], top[7]);
+        }
+        B_HD_PRED => {
+            block[12] = average2(edge[0], edge[1]);
+            block[13] = average3(edge[0], edge[1], edge[2]);
+            block[8] = average2(edge[1], edge[2]);
+            block[14] = block[8];
+            block[9] = average3(edge[1], edge[2], edge[3]);
+            block[15] = block[9];
+            block[10] = average2(edge[2], edge[3]);
+            block[4] = block[10];
+            block[11] = average3(edge[2], edge[3], edge[4]);
+            block[5] = block[11];
+            block[6] = average2(edge[3], edge[4]);
+            block[0] = block[6];
+            block[7] = average3(edge[3], edge[4], edge[5]);
+            block[1] = block[7];
+            block[2] = average3(edge[4], edge[5], edge[6]);
+            block[3] = average3(edge[5], edge[6], edge[7]);
+        }
+        B_HU_PRED => {
+            block[0] = average2(l[0], l[1]);
+            block[1] = average3(l[0], l[1], l[2]);
+            block[2] = average2(l[1], l[2]);
+            block[4] = block[2];
+            block[3] = average3(l[1], l[2], l[3]);
+            block[5] = block[3];
+            block[6] = average2(l[2], l[3]);
+            block[8] = block[6];
+            block[7] = average3(l[2], l[3], l[3]);
+            block[9] = block[7];
+            for i in range(10, 16) {
+                block[i] = l[3];
+            }
+        }
+        _ => {
+            let mut sum = 4;
+            for i in range(0, 4) {
+                sum += top[i] as uint + l[i] as uint;
+            }
+            for i in range(0, 16) {
+                block[i] = (sum >> 3) as u8;
+            }
+        }
+    }
+    for y in range(0, 4) {
+        for x in range(0, 4) {
+            work[offset + y * LUMA_STRIDE + x] = block[y * 4 + x];
+        }
+    }
+}
+
+/// Smooths the left and top edges of a macroblock, and the edges between its blocks.
+fn loop_filter(planes: &mut Planes, mb_x: uint, mb_y: uint, simple: bool, filter: &Filter) {
+    if filter.limit == 0 {
+        return;
+    }
+    let (y_stride, uv_stride) = (planes.y_stride, planes.uv_stride);
+    let y_origin = mb_y * 16 * y_stride + mb_x * 16;
+    let uv_origin = mb_y * 8 * uv_stride + mb_x * 8;
+    let edge_limit = filter.limit + 4;
+    if simple {
+        // Only the luma plane is filtered.
+        if mb_x > 0 {
+            simple_filter(planes.y, y_origin, 1, y_stride, edge_limit);
+        }
+        if filter.inner {
+            for &x in [4u, 8, 12].iter() {
+                simple_filter(planes.y, y_origin + x, 1, y_stride, filter.limit);
+            }
+        }
+        if mb_y > 0 {
+            simple_filter(planes.y, y_origin, y_stride, 1, edge_limit);
+        }
+        if filter.inner {
+            for &y in [4u, 8, 12].iter() {
+                simple_filter(planes.y, y_origin + y * y_stride, y_stride, 1, filter.limit);
+            }
+        }
+        return;
+    }
+
+    if mb_x > 0 {
+        normal_filter(planes.y, y_origin, 1, y_stride, 16, edge_limit, filter, true);
+        normal_filter(planes.u, uv_origin, 1, uv_stride, 8, edge_limit, filter, true);
+        normal_filter(planes.v, uv_origin, 1, uv_stride, 8, edge_limit, filter, true);
+    }
+    if filter.inner {
+        for &x in [4u, 8, 12].iter() {
+            normal_filter(planes.y, y_origin + x, 1, y_stride, 16, filter.limit, filter, false);
+        }
+        normal_filter(planes.u, uv_origin + 4, 1, uv_stride, 8, filter.limit, filter, false);
+        normal_filter(planes.v, uv_origin + 4, 1, uv_stride, 8, filter.limit, filter, false);
+    }
+    if mb_y > 0 {
+        normal_filter(planes.y, y_origin, y_stride, 1, 16, edge_limit, filter, true);
+        normal_filter(planes.u, uv_origin, uv_stride, 1, 8, edge_limit, filter, true);
+        normal_filter(planes.v, uv_origin, uv_stride, 1, 8, edge_limit, filter, true);
+    }
+    if filter.inner {
+        for &y in [4u, 8, 12].iter() {
+            normal_filter(planes.y, y_origin + y * y_stride, y_stride, 1, 16, filter.limit,
+                          filter, false);
+        }
+        normal_filter(planes.u, uv_origin + 4 * uv_stride, uv_stride, 1, 8, filter.limit,
+                      filter, false);
+        normal_filter(planes.v, uv_origin + 4 * uv_stride, uv_stride, 1, 8, filter.limit,
+                      filter, false);
+    }
+}
+
+fn clamp_signed(value: i32) -> i32 {
+    if value < -128 { -128 } else if value > 127 { 127 } else { value }
+}
+
+fn absolute(value: i32) -> i32 {
+    if value < 0 { -value } else { value }
+}
+
+/// Whether the difference across the edge at `position` is too big to be a coding artifact.
+/// `step` crosses the edge.
+fn exceeds_edge_limit(pixels: &[u8], position: uint, step: uint, limit: i32) -> bool {
+    let p1 = pixels[position - 2 * step] as i32;
+    let p0 = pixels[position - step] as i32;
+    let q0 = pixels[position] as i32;
+    let q1 = pixels[position + step] as i32;
+    4 * absolute(p0 - q0) + absolute(p1 - q1) > 2 * limit + 1
+}
+
+/// Moves the two pixels on either side of the edge towards each other. Returns how far the
+/// pixel after the edge moved.
+fn adjust(pixels: &mut [u8], position: uint, step: uint, use_outer_taps: bool) -> i32 {
+    let p1 = pixels[position - 2 * step] as i32;
+    let p0 = pixels[position - step] as i32;
+    let q0 = pixels[position] as i32;
+    let q1 = pixels[position + step] as i32;
+    let outer = if use_outer_taps { clamp_signed(p1 - q1) } else { 0 };
+    let a = clamp_signed(outer + 3 * (q0 - p0));
+    let a1 = clamp_signed(a + 4) >> 3;
+    let a2 = clamp_signed(a + 3) >> 3;
+    pixels[position] = clip(q0 - a1);
+    pixels[position - step] = clip(p0 + a2);
+    a1
+}
+
+/// Filters the 16 pixels along an edge of the luma plane, starting at `offset`. `step` crosses
+/// the edge and `along` follows it.
+fn simple_filter(pixels: &mut [u8], offset: uint, step: uint, along: uint, limit: i32) {
+    for i in range(0, 16) {
+        let position = offset + i * along;
+        if !exceeds_edge_limit(pixels, position, step, limit) {
+            adjust(pixels, position, step, true);
+        }
+    }
+}
+
+fn normal_filter(pixels: &mut [u8],
+                 offset: uint,
+                 step: uint,
+                 along: uint,
+                 length: uint,
+                 limit: i32,
+                 filter: &Filter,
+                 macroblock_edge: bool) {
+    for i in range(0, length) {
+        let position = offset + i * along;
+        if exceeds_edge_limit(pixels, position, step, limit) {
+            continue;
+        }
+        let p3 = pixels[position - 4 * step] as i32;
+        let p2 = pixels[position - 3 * step] as i32;
+        let p1 = pixels[position - 2 * step] as i32;
+        let p0 = pixels[position - step] as i32;
+        let q0 = pixels[position] as i32;
+        let q1 = pixels[position + step] as i32;
+        let q2 = pixels[position + 2 * step] as i32;
+        let q3 = pixels[position + 3 * step] as i32;
+        let interior = filter.interior_limit;
+        if absolute(p3 - p2) > interior || absolute(p2 - p1) > interior ||
+                absolute(p1 - p0) > interior || absolute(q3 - q2) > interior ||
+                absolute(q2 - q1) > interior || absolute(q1 - q0) > interior {
+            continue;
+        }
+
+        let high_edge_variance = absolute(p1 - p0) > filter.hev_threshold ||
+                                 absolute(q1 - q0) > filter.hev_threshold;
+        if high_edge_variance {
+            adjust(pixels, position, step, true);
+        } else if macroblock_edge {
+            // Spread the adjustment over three pixels on each side.
+            let w = clamp_signed(clamp_signed(p1 - q1) + 3 * (q0 - p0));
+            let a = (27 * w + 63) >> 7;
+            pixels[position] = clip(q0 - a);
+            pixels[position - step] = clip(p0 + a);
+            let a = (18 * w + 63) >> 7;
+            pixels[position + step] = clip(q1 - a);
+            pixels[position - 2 * step] = clip(p1 + a);
+            let a = (9 * w + 63) >> 7;
+            pixels[position + 2 * step] = clip(q2 - a);
+            pixels[position - 3 * step] = clip(p2 + a);
+        } else {
+            let a = (adjust(pixels, position, step, false) + 1) >> 1;
+            pixels[position + step] = clip(q1 - a);
+            pixels[position - 2 * step] = clip(p1 + a);
+        }
+    }
+}
+
+/// Converts the planes to RGBA, interpolating the chroma of each pixel between the four
+/// nearest chroma samples like libwebp's "fancy upsampling".
+fn to_rgba(planes: &Planes, width: uint, height: uint) -> ~[u8] {
+    let mut rgba = vec::with_capacity(width * height * 4);
+    let chroma_height = (height + 1) / 2;
+    for y in range(0, height) {
+        // The nearest row of chroma samples, and the next nearest.
+        let (near, far) = if y == 0 {
+            (0, 0)
+        } else if y & 1 == 1 {
+            ((y - 1) / 2, (y + 1) / 2)
+        } else {
+            (y / 2, y / 2 - 1)
+        };
+        let far = if far >= chroma_height { near } else { far };
+        let u = upsample_row(planes.u, planes.uv_stride, near, far, width);
+        let v = upsample_row(planes.v, planes.uv_stride, near, far, width);
+        for x in range(0, width) {
+            let (r, g, b) = yuv_to_rgb(planes.y[y * planes.y_stride + x] as i32, u[x], v[x]);
+            rgba.push_all(&[r, g, b, 255]);
+        }
+    }
+    rgba
+}
+
+/// Interpolates a row of chroma samples for each pixel of a row.
+fn upsample_row(plane: &[u8], stride: uint, near: uint, far: uint, width: uint) -> ~[i32] {
+    let near = plane.slice(near * stride, near * stride + stride);
+    let far = plane.slice(far * stride, far * stride + stride);
+    let mut row = vec::with_capacity(width);
+    for x in range(0, width) {
+        row.push(if x == 0 || (x == width - 1 && width & 1 == 0) {
+            let column = x / 2;
+            (3 * near[column] as i32 + far[column] as i32 + 2) >> 2
+        } else {
+            // The sample nearest the pixel, and the one on its other side.
+            let (close, other) = if x & 1 == 1 {
+                ((x - 1) / 2, (x + 1) / 2)
+            } else {
+                (x / 2, x / 2 - 1)
+            };
+            let diagonal = (near[close] as i32 + 3 * near[other] as i32 +
+                            3 * far[close] as i32 + far[other] as i32 + 8) >> 3;
+            (diagonal + near[close] as i32) >> 1
+        });
+    }
+    row
+}
+
+/// Converts from BT.601 YUV in fixed point, rounding like libwebp.
+fn yuv_to_rgb(y: i32, u: i32, v: i32) -> (u8, u8, u8) {
+    fn mult_high(value: i32, coefficient: i32) -> i32 {
+        (value * coefficient) >> 8
+    }
+    fn clip_scaled(value: i32) -> u8 {
+        if value & !16383 == 0 {
+            (value >> 6) as u8
+        } else if value < 0 {
+            0
+        } else {
+            255
+        }
+    }
+    let luma = mult_high(y, 19077);
+    (clip_scaled(luma + mult_high(v, 26149) - 14234),
+     clip_scaled(luma - mult_high(u, 6419) - mult_high(v, 13320) + 8708),
+     clip_scaled(luma + mult_high(u, 33050) - 17685))
+}
+
+// The probability and quantizer tables of RFC 6386.
+
+static B_MODE_PROBABILITIES: [[[u8, ..9], ..10], ..10] = [
+    [
+        [231, 120, 48, 89, 115, 113, 120, 152, 112],
+        [152, 179, 64, 126, 170, 118, 46, 70, 95],
+        [175, 69, 143, 80, 85, 82, 72, 155, 103],
+        [56, 58, 10, 171, 218, 189, 17, 13, 152],
+        [144, 71, 10, 38, 171, 213, 144, 34, 26],
+        [114, 26, 17, 163, 44, 195, 21, 10, 173],
+        [121, 24, 80, 195, 26, 62, 44, 64, 85],
+        [170, 46, 55, 19, 136, 160, 33, 206, 71],
+        [63, 20, 8, 114, 114, 208, 12, 9, 226],
+        [81, 40, 11, 96, 182, 84, 29, 16, 36],
+    ],
+    [
+        [134, 183, 89, 137, 98, 101, 106, 165, 148],
+        [72, 187, 100, 130, 157, 111, 32, 75, 80],
+        [66, 102, 167, 99, 74, 62, 40, 234, 128],
+        [41, 53, 9, 178, 241, 141, 26, 8, 107],
+        [104, 79, 12, 27, 217, 255, 87, 17, 7],
+        [74, 43, 26, 146, 73, 166, 49, 23, 157],
+        [65, 38, 105, 160, 51, 52, 31, 115, 128],
+        [87, 68, 71, 44, 114, 51, 15, 186, 23],
+        [47, 41, 14, 110, 182, 183, 21, 17, 194],
+        [66, 45, 25, 102, 197, 189, 23, 18, 22],
+    ],
+    [
+        [88, 88, 147, 150, 42, 46, 45, 196, 205],
+        [43, 97, 183, 117, 85, 38, 35, 179, 61],
+        [39, 53, 200, 87, 26, 21, 43, 232, 171],
+        [56, 34, 51, 104, 114, 102, 29, 93, 77],
+        [107, 54, 32, 26, 51, 1, 81, 43, 31],
+        [39, 28, 85, 171, 58, 165, 90, 98, 64],
+        [34, 22, 116, 206, 23, 34, 43, 166, 73],
+        [68, 25, 106, 22, 64, 171, 36, 225, 114],
+        [34, 19, 21, 102, 132, 188, 16, 76, 124],
+        [62, 18, 78, 95, 85, 57, 50, 48, 51],
+    ],
+    [
+        [193, 101, 35, 159, 215, 111, 89, 46, 111],
+        [60, 148, 31, 172, 219, 228, 21, 18, 111],
+        [112, 113, 77, 85, 179, 255, 38, 120, 114],
+        [40, 42, 1, 196, 245, 209, 10, 25, 109],
+        [100, 80, 8, 43, 154, 1, 51, 26, 71],
+        [88, 43, 29, 140, 166, 213, 37, 43, 154],
+        [61, 63, 30, 155, 67, 45, 68, 1, 209],
+        [142, 78, 78, 16, 255, 128, 34, 197, 171],
+        [41, 40, 5, 102, 211, 183, 4, 1, 221],
+        [51, 50, 17, 168, 209, 192, 23, 25, 82],
+    ],
+    [
+        [125, 98, 42, 88, 104, 85, 117, 175, 82],
+        [95, 84, 53, 89, 128, 100, 113, 101, 45],
+        [75, 79, 123, 47, 51, 128, 81, 171, 1],
+        [57, 17, 5, 71, 102, 57, 53, 41, 49],
+        [115, 21, 2, 10, 102, 255, 166, 23, 6],
+        [38, 33, 13, 121, 57, 73, 26, 1, 85],
+        [41, 10, 67, 138, 77, 110, 90, 47, 114],
+        [101, 29, 16, 10, 85, 128, 101, 196, 26],
+        [57, 18, 10, 102, 102, 213, 34, 20, 43],
+        [117, 20, 15, 36, 163, 128, 68, 1, 26],
+    ],
+    [
+        [138, 31, 36, 171, 27, 166, 38, 44, 229],
+        [67, 87, 58, 169, 82, 115, 26, 59, 179],
+        [63, 59, 90, 180, 59, 166, 93, 73, 154],
+        [40, 40, 21, 116, 143, 209, 34, 39, 175],
+        [57, 46, 22, 24, 128, 1, 54, 17, 37],
+        [47, 15, 16, 183, 34, 223, 49, 45, 183],
+        [46, 17, 33, 183, 6, 98, 15, 32, 183],
+        [65, 32, 73, 115, 28, 128, 23, 128, 205],
+        [40, 3, 9, 115, 51, 192, 18, 6, 223],
+        [87, 37, 9, 115, 59, 77, 64, 21, 47],
+    ],
+    [
+        [104, 55, 44, 218, 9, 54, 53, 130, 226],
+        [64, 90, 70, 205, 40, 41, 23, 26, 57],
+        [54, 57, 112, 184, 5, 41, 38, 166, 213],
+        [30, 34, 26, 133, 152, 116, 10, 32, 134],
+        [75, 32, 12, 51, 192, 255, 160, 43, 51],
+        [39, 19, 53, 221, 26, 114, 32, 73, 255],
+        [31, 9, 65, 234, 2, 15, 1, 118, 73],
+        [88, 31, 35, 67, 102, 85, 55, 186, 85],
+        [56, 21, 23, 111, 59, 205, 45, 37, 192],
+        [55, 38, 70, 124, 73, 102, 1, 34, 98],
+    ],
+    [
+        [102, 61, 71, 37, 34, 53, 31, 243, 192],
+        [69, 60, 71, 38, 73, 119, 28, 222, 37],
+        [68, 45, 128, 34, 1, 47, 11, 245, 171],
+        [62, 17, 19, 70, 146, 85, 55, 62, 70],
+        [75, 15, 9, 9, 64, 255, 184, 119, 16],
+        [37, 43, 37, 154, 100, 163, 85, 160, 1],
+        [63, 9, 92, 136, 28, 64, 32, 201, 85],
+        [86, 6, 28, 5, 64, 255, 25, 248, 1],
+        [56, 8, 17, 132, 137, 255, 55, 116, 128],
+        [58, 15, 20, 82, 135, 57, 26, 121, 40],
+    ],
+    [
+        [164, 50, 31, 137, 154, 133, 25, 35, 218],
+        [51, 103, 44, 131, 131, 123, 31, 6, 158],
+        [86, 40, 64, 135, 148, 224, 45, 183, 128],
+        [22, 26, 17, 131, 240, 154, 14, 1, 209],
+        [83, 12, 13, 54, 192, 255, 68, 47, 28],
+        [45, 16, 21, 91, 64, 222, 7, 1, 197],
+        [56, 21, 39, 155, 60, 138, 23, 102, 213],
+        [85, 26, 85, 85, 128, 128, 32, 146, 171],
+        [18, 11, 7, 63, 144, 171, 4, 4, 246],
+        [35, 27, 10, 146, 174, 171, 12, 26, 128],
+    ],
+    [
+        [190, 80, 35, 99, 180, 80, 126, 54, 45],
+        [85, 126, 47, 87, 176, 51, 41, 20, 32],
+        [101, 75, 128, 139, 118, 146, 116, 128, 85],
+        [56, 41, 15, 176, 236, 85, 37, 9, 62],
+        [146, 36, 19, 30, 171, 255, 97, 27, 20],
+        [71, 30, 17, 119, 118, 255, 17, 18, 138],
+        [101, 38, 60, 138, 55, 70, 43, 26, 142],
+        [138, 45, 61, 62, 219, 1, 81, 188, 64],
+        [32, 41, 20, 117, 151, 142, 20, 21, 163],
+        [112, 19, 12, 61, 195, 128, 48, 4, 24],
+    ],
+];
+
+static COEFFICIENT_UPDATE_PROBABILITIES: [[[[u8, ..11], ..3], ..8], ..4] = [
+    [
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [176, 246, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 241, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 244, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 246, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [239, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 254, 255, 255, 255, 255, 255, 255],
+            [250, 255, 254, 255, 254, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [217, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [225, 252, 241, 253, 255, 255, 254, 255, 255, 255, 255],
+            [234, 250, 241, 250, 253, 255, 253, 254, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [238, 253, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [247, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [186, 251, 250, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 251, 244, 254, 255, 255, 255, 255, 255, 255, 255],
+            [251, 251, 243, 253, 254, 255, 254, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [236, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 253, 253, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [248, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 254, 252, 254, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 249, 253, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [246, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 254, 251, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [245, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 252, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+];
+
+static DEFAULT_COEFFICIENT_PROBABILITIES: [[[[u8, ..11], ..3], ..8], ..4] = [
+    [
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [253, 136, 254, 255, 228, 219, 128, 128, 128, 128, 128],
+            [189, 129, 242, 255, 227, 213, 255, 219, 128, 128, 128],
+            [106, 126, 227, 252, 214, 209, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 98, 248, 255, 236, 226, 255, 255, 128, 128, 128],
+            [181, 133, 238, 254, 221, 234, 255, 154, 128, 128, 128],
+            [78, 134, 202, 247, 198, 180, 255, 219, 128, 128, 128],
+        ],
+        [
+            [1, 185, 249, 255, 243, 255, 128, 128, 128, 128, 128],
+            [184, 150, 247, 255, 236, 224, 128, 128, 128, 128, 128],
+            [77, 110, 216, 255, 236, 230, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 101, 251, 255, 241, 255, 128, 128, 128, 128, 128],
+            [170, 139, 241, 252, 236, 209, 255, 255, 128, 128, 128],
+            [37, 116, 196, 243, 228, 255, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 204, 254, 255, 245, 255, 128, 128, 128, 128, 128],
+            [207, 160, 250, 255, 238, 128, 128, 128, 128, 128, 128],
+            [102, 103, 231, 255, 211, 171, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 152, 252, 255, 240, 255, 128, 128, 128, 128, 128],
+            [177, 135, 243, 255, 234, 225, 128, 128, 128, 128, 128],
+            [80, 129, 211, 255, 194, 224, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [246, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [255, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [198, 35, 237, 223, 193, 187, 162, 160, 145, 155, 62],
+            [131, 45, 198, 221, 172, 176, 220, 157, 252, 221, 1],
+            [68, 47, 146, 208, 149, 167, 221, 162, 255, 223, 128],
+        ],
+        [
+            [1, 149, 241, 255, 221, 224, 255, 255, 128, 128, 128],
+            [184, 141, 234, 253, 222, 220, 255, 199, 128, 128, 128],
+            [81, 99, 181, 242, 176, 190, 249, 202, 255, 255, 128],
+        ],
+        [
+            [1, 129, 232, 253, 214, 197, 242, 196, 255, 255, 128],
+            [99, 121, 210, 250, 201, 198, 255, 202, 128, 128, 128],
+            [23, 91, 163, 242, 170, 187, 247, 210, 255, 255, 128],
+        ],
+        [
+            [1, 200, 246, 255, 234, 255, 128, 128, 128, 128, 128],
+            [109, 178, 241, 255, 231, 245, 255, 255, 128, 128, 128],
+            [44, 130, 201, 253, 205, 192, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 132, 239, 251, 219, 209, 255, 165, 128, 128, 128],
+            [94, 136, 225, 251, 218, 190, 255, 255, 128, 128, 128],
+            [22, 100, 174, 245, 186, 161, 255, 199, 128, 128, 128],
+        ],
+        [
+            [1, 182, 249, 255, 232, 235, 128, 128, 128, 128, 128],
+            [124, 143, 241, 255, 227, 234, 128, 128, 128, 128, 128],
+            [35, 77, 181, 251, 193, 211, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 157, 247, 255, 236, 231, 255, 255, 128, 128, 128],
+            [121, 141, 235, 255, 225, 227, 255, 255, 128, 128, 128],
+            [45, 99, 188, 251, 195, 217, 255, 224, 128, 128, 128],
+        ],
+        [
+            [1, 1, 251, 255, 213, 255, 128, 128, 128, 128, 128],
+            [203, 1, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [137, 1, 177, 255, 224, 255, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [253, 9, 248, 251, 207, 208, 255, 192, 128, 128, 128],
+            [175, 13, 224, 243, 193, 185, 249, 198, 255, 255, 128],
+            [73, 17, 171, 221, 161, 179, 236, 167, 255, 234, 128],
+        ],
+        [
+            [1, 95, 247, 253, 212, 183, 255, 255, 128, 128, 128],
+            [239, 90, 244, 250, 211, 209, 255, 255, 128, 128, 128],
+            [155, 77, 195, 248, 188, 195, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 24, 239, 251, 218, 219, 255, 205, 128, 128, 128],
+            [201, 51, 219, 255, 196, 186, 128, 128, 128, 128, 128],
+            [69, 46, 190, 239, 201, 218, 255, 228, 128, 128, 128],
+        ],
+        [
+            [1, 191, 251, 255, 255, 128, 128, 128, 128, 128, 128],
+            [223, 165, 249, 255, 213, 255, 128, 128, 128, 128, 128],
+            [141, 124, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 16, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [190, 36, 230, 255, 236, 255, 128, 128, 128, 128, 128],
+            [149, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 226, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [247, 192, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [240, 128, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 134, 252, 255, 255, 128, 128, 128, 128, 128, 128],
+            [213, 62, 250, 255, 255, 128, 128, 128, 128, 128, 128],
+            [55, 93, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [202, 24, 213, 235, 186, 191, 220, 160, 240, 175, 255],
+            [126, 38, 182, 232, 169, 184, 228, 174, 255, 187, 128],
+            [61, 46, 138, 219, 151, 178, 240, 170, 255, 216, 128],
+        ],
+        [
+            [1, 112, 230, 250, 199, 191, 247, 159, 255, 255, 128],
+            [166, 109, 228, 252, 211, 215, 255, 174, 128, 128, 128],
+            [39, 77, 162, 232, 172, 180, 245, 178, 255, 255, 128],
+        ],
+        [
+            [1, 52, 220, 246, 198, 199, 249, 220, 255, 255, 128],
+            [124, 74, 191, 243, 183, 193, 250, 221, 255, 255, 128],
+            [24, 71, 130, 219, 154, 170, 243, 182, 255, 255, 128],
+        ],
+        [
+            [1, 182, 225, 249, 219, 240, 255, 224, 128, 128, 128],
+            [149, 150, 226, 252, 216, 205, 255, 171, 128, 128, 128],
+            [28, 108, 170, 242, 183, 194, 254, 223, 255, 255, 128],
+        ],
+        [
+            [1, 81, 230, 252, 204, 203, 255, 192, 128, 128, 128],
+            [123, 102, 209, 247, 188, 196, 255, 233, 128, 128, 128],
+            [20, 95, 153, 243, 164, 173, 255, 203, 128, 128, 128],
+        ],
+        [
+            [1, 222, 248, 255, 216, 213, 128, 128, 128, 128, 128],
+            [168, 175, 246, 252, 235, 205, 255, 255, 128, 128, 128],
+            [47, 116, 215, 255, 211, 212, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 121, 236, 253, 212, 214, 255, 255, 128, 128, 128],
+            [141, 84, 213, 252, 201, 202, 255, 219, 128, 128, 128],
+            [42, 80, 160, 240, 162, 185, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [244, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [238, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+];
+
+static DC_QUANTIZERS: [i32, ..128] = [
+      4,   5,   6,   7,   8,   9,  10,  10,  11,  12,  13,  14,  15,  16,  17,  17,
+     18,  19,  20,  20,  21,  21,  22,  22,  23,  23,  24,  25,  25,  26,  27,  28,
+     29,  30,  31,  32,  33,  34,  35,  36,  37,  37,  38,  39,  40,  41,  42,  43,
+     44,  45,  46,  46,  47,  48,  49,  50,  51,  52,  53,  54,  55,  56,  57,  58,
+     59,  60,  61,  62,  63,  64,  65,  66,  67,  68,  69,  70,  71,  72,  73,  74,
+     75,  76,  76,  77,  78,  79,  80,  81,  82,  83,  84,  85,  86,  87,  88,  89,
+     91,  93,  95,  96,  98, 100, 101, 102, 104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136, 138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+static AC_QUANTIZERS: [i32, ..128] = [
+      4,   5,   6,   7,   8,   9,  10,  11,  12,  13,  14,  15,  16,  17,  18,  19,
+     20,  21,  22,  23,  24,  25,  26,  27,  28,  29,  30,  31,  32,  33,  34,  35,
+     36,  37,  38,  39,  40,  41,  42,  43,  44,  45,  46,  47,  48,  49,  50,  51,
+     52,  53,  54,  55,  56,  57,  58,  60,  62,  64,  66,  68,  70,  72,  74,  76,
+     78,  80,  82,  84,  86,  88,  90,  92,  94,  96,  98, 100, 102, 104, 106, 108,
+    110, 112, 114, 116, 119, 122, 125, 128, 131, 134, 137, 140, 143, 146, 149, 152,
+    155, 158, 161, 164, 167, 170, 173, 177, 181, 185, 189, 193, 197, 201, 205, 209,
+    213, 217, 221, 225, 229, 234, 239, 245, 249, 254, 259, 264, 269, 274, 279, 284,
+];
@@ -0,0 +1,886 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A WebP decoder, following https://developers.google.com/speed/webp/docs/riff_container and
+//! https://developers.google.com/speed/webp/docs/webp_lossless_bitstream_specification. The
+//! image data of lossy files is decoded by `image::vp8`.
+
+use image::base::{Image, MAX_DECODED_BYTES, u32_at};
+use image::vp8;
+
+use png;
+use std::vec;
+
+/// The VP8L signature byte.
+static VP8L_SIGNATURE: u8 = 0x2F;
+
+static PREDICTOR_TRANSFORM: uint = 0;
+static CROSS_COLOR_TRANSFORM: uint = 1;
+static SUBTRACT_GREEN_TRANSFORM: uint = 2;
+static COLOR_INDEXING_TRANSFORM: uint = 3;
+
+// How the values of an ALPH chunk are predicted from their neighbors.
+static ALPHA_NO_FILTER: u8 = 0;
+static ALPHA_HORIZONTAL_FILTER: u8 = 1;
+static ALPHA_VERTICAL_FILTER: u8 = 2;
+
+/// The number of length prefix codes in the green alphabet, after the 256 literals.
+static NUM_LENGTH_CODES: uint = 24;
+static NUM_DISTANCE_CODES: uint = 40;
+
+/// The order in which the lengths of the code length code are stored.
+static CODE_LENGTH_ORDER: [uint, ..19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12,
+                                         13, 14, 15];
+
+/// The (x, y) offsets of the 120 short distance codes, nearest first.
+static DISTANCE_MAP: [(int, int), ..120] = [
+    (0, 1), (1, 0), (1, 1), (-1, 1), (0, 2), (2, 0), (1, 2), (-1, 2),
+    (2, 1), (-2, 1), (2, 2), (-2, 2), (0, 3), (3, 0), (1, 3), (-1, 3),
+    (3, 1), (-3, 1), (2, 3), (-2, 3), (3, 2), (-3, 2), (0, 4), (4, 0),
+    (1, 4), (-1, 4), (4, 1), (-4, 1), (3, 3), (-3, 3), (2, 4), (-2, 4),
+    (4, 2), (-4, 2), (0, 5), (3, 4), (-3, 4), (4, 3), (-4, 3), (5, 0),
+    (1, 5), (-1, 5), (5, 1), (-5, 1), (2, 5), (-2, 5), (5, 2), (-5, 2),
+    (4, 4), (-4, 4), (3, 5), (-3, 5), (5, 3), (-5, 3), (0, 6), (6, 0),
+    (1, 6), (-1, 6), (6, 1), (-6, 1), (2, 6), (-2, 6), (6, 2), (-6, 2),
+    (4, 5), (-4, 5), (5, 4), (-5, 4), (3, 6), (-3, 6), (6, 3), (-6, 3),
+    (0, 7), (7, 0), (1, 7), (-1, 7), (5, 5), (-5, 5), (7, 1), (-7, 1),
+    (4, 6), (-4, 6), (6, 4), (-6, 4), (2, 7), (-2, 7), (7, 2), (-7, 2),
+    (3, 7), (-3, 7), (7, 3), (-7, 3), (5, 6), (-5, 6), (6, 5), (-6, 5),
+    (8, 0), (4, 7), (-4, 7), (7, 4), (-7, 4), (8, 1), (8, 2), (6, 6),
+    (-6, 6), (8, 3), (5, 7), (-5, 7), (7, 5), (-7, 5), (8, 4), (6, 7),
+    (-6, 7), (7, 6), (-7, 6), (8, 5), (7, 7), (-7, 7), (8, 6), (8, 7),
+];
+
+pub fn decode(buffer: &[u8]) -> Option<Image> {
+    if buffer.len() < 12 || !buffer.starts_with(bytes!("RIFF")) ||
+            buffer.slice(8, 12) != bytes!("WEBP") {
+        return None;
+    }
+
+    // Simple files hold one VP8 or VP8L chunk; extended ones start with a VP8X chunk and may
+    // hold metadata chunks before the image, and an ALPH chunk before a VP8 one.
+    let mut alpha = None;
+    let mut position = 12;
+    while position + 8 <= buffer.len() {
+        let fourcc = buffer.slice(position, position + 4);
+        let size = u32_at(buffer, position + 4).unwrap();
+        let start = position + 8;
+        if start + size > buffer.len() {
+            return None;
+        }
+        let chunk = buffer.slice(start, start + size);
+        if fourcc == bytes!("VP8L") {
+            return decode_lossless(chunk);
+        } else if fourcc == bytes!("VP8 ") {
+            let mut image = match vp8::decode(chunk) {
+                Some(image) => image,
+                None => return None,
+            };
+            match alpha {
+                Some(chunk) => {
+                    let (width, height) = (image.width as uint, image.height as uint);
+                    match decode_alpha(chunk, width, height) {
+                        Some(values) => {
+                            for i in range(0, width * height) {
+                                image.pixels[i * 4 + 3] = values[i];
+                            }
+                        }
+                        None => return None,
+                    }
+                }
+                None => (),
+            }
+            return Some(image);
+        } else if fourcc == bytes!("ALPH") {
+            alpha = Some(chunk);
+        } else if fourcc == bytes!("ANIM") {
+            debug!("webp: animated images aren't supported");
+            return None;
+        }
+        // Chunks are padded to an even size.
+        position = start + size + (size & 1);
+    }
+    None
+}
+
+/// Reads the bits of a VP8L stream, least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: uint,
+    bit: uint,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bits(&mut self, count: uint) -> Option<uint> {
+        let mut value = 0;
+        for i in range(0, count) {
+            if self.position >= self.data.len() {
+                return None;
+            }
+            let bit = (self.data[self.position] as uint >> self.bit) & 1;
+            value |= bit << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Some(value)
+    }
+}
+
+/// A canonical prefix code, read one bit at a time from the first bit of the code.
+struct PrefixCode {
+    /// The number of codes of each length.
+    counts: ~[uint],
+    /// The symbols ordered by code.
+    symbols: ~[uint],
+}
+
+impl PrefixCode {
+    /// Builds the code from the code length of each symbol. A code with only one symbol uses
+    /// no bits at all.
+    fn new(lengths: &[uint]) -> Option<PrefixCode> {
+        let max_length = lengths.iter().fold(0, |max, &length| {
+            if length > max { length } else { max }
+        });
+        let mut counts = vec::from_elem(max_length + 1, 0u);
+        for &length in lengths.iter() {
+            counts[length] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = ~[];
+        for length in range(1, max_length + 1) {
+            for (symbol, &symbol_length) in lengths.iter().enumerate() {
+                if symbol_length == length {
+                    symbols.push(symbol);
+                }
+            }
+        }
+        if symbols.is_empty() {
+            return None;
+        }
+
+        // Reject over-subscribed codes, which would decode ambiguously.
+        if symbols.len() > 1 {
+            let mut left = 1i;
+            for length in range(1, max_length + 1) {
+                left = left * 2 - counts[length] as int;
+                if left < 0 {
+                    return None;
+                }
+            }
+        }
+        Some(PrefixCode { counts: counts, symbols: symbols })
+    }
+
+    fn read_symbol(&self, reader: &mut BitReader) -> Option<uint> {
+        if self.symbols.len() == 1 {
+            return Some(self.symbols[0]);
+        }
+        let (mut code, mut first, mut index) = (0u, 0u, 0u);
+        for length in range(1, self.counts.len()) {
+            code |= match reader.read_bits(1) {
+                Some(bit) => bit,
+                None => return None,
+            };
+            let count = self.counts[length];
+            if code < first + count {
+                return Some(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn read_code_lengths(reader: &mut BitReader,
+                     code_length_code: &PrefixCode,
+                     alphabet_size: uint)
+                     -> Option<~[uint]> {
+    let mut max_symbol = alphabet_size;
+    match reader.read_bits(1) {
+        Some(1) => {
+            let length_bits = match reader.read_bits(3) {
+                Some(bits) => 2 + 2 * bits,
+                None => return None,
+            };
+            max_symbol = match reader.read_bits(length_bits) {
+                Some(value) if value + 2 <= alphabet_size => value + 2,
+                _ => return None,
+            };
+        }
+        Some(_) => (),
+        None => return None,
+    }
+
+    let mut lengths = vec::from_elem(alphabet_size, 0u);
+    let mut previous = 8;
+    let mut symbol = 0;
+    while symbol < alphabet_size {
+        if max_symbol == 0 {
+            break;
+        }
+        max_symbol -= 1;
+        let code = match code_length_code.read_symbol(reader) {
+            Some(code) => code,
+            None => return None,
+        };
+        if code < 16 {
+            lengths[symbol] = code;
+            symbol += 1;
+            if code != 0 {
+                previous = code;
+            }
+            continue;
+        }
+        let (extra_bits, offset, value) = match code {
+            16 => (2, 3, previous),
+            17 => (3, 3, 0),
+            _ => (7, 11, 0),
+        };
+        let repeat = match reader.read_bits(extra_bits) {
+            Some(bits) => bits + offset,
+            None => return None,
+        };
+        if symbol + repeat > alphabet_size {
+            return None;
+        }
+        for _ in range(0, repeat) {
+            lengths[symbol] = value;
+            symbol += 1;
+        }
+    }
+    Some(lengths)
+}
+
+fn read_prefix_code(reader: &mut BitReader, alphabet_size: uint) -> Option<PrefixCode> {
+    let mut lengths = vec::from_elem(alphabet_size, 0u);
+    match reader.read_bits(1) {
+        Some(1) => {
+            // A simple code of one or two symbols below 256.
+            let (count, first_bits) = match (reader.read_bits(1), reader.read_bits(1)) {
+                (Some(count), Some(is_8_bits)) => (count + 1, 1 + 7 * is_8_bits),
+                _ => return None,
+            };
+            let symbols = [reader.read_bits(first_bits),
+                           if count == 2 { reader.read_bits(8) } else { Some(0) }];
+            for i in range(0, count) {
+                match symbols[i] {
+                    Some(symbol) if symbol < alphabet_size => lengths[symbol] = 1,
+                    _ => return None,
+                }
+            }
+        }
+        Some(_) => {
+            let count = match reader.read_bits(4) {
+                Some(count) => count + 4,
+                None => return None,
+            };
+            let mut code_length_lengths = [0u, ..19];
+            for i in range(0, count) {
+                match reader.read_bits(3) {
+                    Some(length) => code_length_lengths[CODE_LENGTH_ORDER[i]] = length,
+                    None => return None,
+                }
+            }
+            let code_length_code = match PrefixCode::new(&code_length_lengths) {
+                Some(code) => code,
+                None => return None,
+            };
+            lengths = match read_code_lengths(reader, &code_length_code, alphabet_size) {
+                Some(lengths) => lengths,
+                None => return None,
+            };
+        }
+        None => return None,
+    }
+    PrefixCode::new(lengths)
+}
+
+/// The five codes used for the pixels of one area of the image.
+struct PrefixCodeGroup {
+    /// Green, length prefixes and color cache indices.
+    green: PrefixCode,
+    red: PrefixCode,
+    blue: PrefixCode,
+    alpha: PrefixCode,
+    distance: PrefixCode,
+}
+
+fn read_prefix_code_group(reader: &mut BitReader, cache_size: uint) -> Option<PrefixCodeGroup> {
+    let green = read_prefix_code(reader, 256 + NUM_LENGTH_CODES + cache_size);
+    let red = read_prefix_code(reader, 256);
+    let blue = read_prefix_code(reader, 256);
+    let alpha = read_prefix_code(reader, 256);
+    let distance = read_prefix_code(reader, NUM_DISTANCE_CODES);
+    match (green, red, blue, alpha, distance) {
+        (Some(green), Some(red), Some(blue), Some(alpha), Some(distance)) => {
+            Some(PrefixCodeGroup {
+                green: green,
+                red: red,
+                blue: blue,
+                alpha: alpha,
+                distance: distance,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Turns a length or distance prefix code into its value, reading any extra bits.
+fn prefix_value(reader: &mut BitReader, prefix: uint) -> Option<uint> {
+    if prefix < 4 {
+        return Some(prefix + 1);
+    }
+    let extra_bits = (prefix - 2) >> 1;
+    let offset = (2 + (prefix & 1)) << extra_bits;
+    reader.read_bits(extra_bits).map(|bits| offset + bits + 1)
+}
+
+fn subsample_size(size: uint, bits: uint) -> uint {
+    (size + (1 << bits) - 1) >> bits
+}
+
+/// Decodes an image of ARGB pixels. Only the main image may use several prefix code groups.
+fn decode_entropy_coded_image(reader: &mut BitReader,
+                              width: uint,
+                              height: uint,
+                              is_main_image: bool)
+                              -> Option<~[u32]> {
+    let cache_bits = match reader.read_bits(1) {
+        Some(1) => match reader.read_bits(4) {
+            Some(bits) if bits >= 1 && bits <= 11 => bits,
+            _ => return None,
+        },
+        Some(_) => 0,
+        None => return None,
+    };
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+
+    // The entropy image says which group of prefix codes each block of pixels uses.
+    let (group_bits, group_image) = if is_main_image && reader.read_bits(1) == Some(1) {
+        let bits = match reader.read_bits(3) {
+            Some(bits) => bits + 2,
+            None => return None,
+        };
+        match decode_entropy_coded_image(reader, subsample_size(width, bits),
+                                         subsample_size(height, bits), false) {
+            Some(image) => (bits, Some(image)),
+            None => return None,
+        }
+    } else {
+        (0, None)
+    };
+    let group_count = match group_image {
+        Some(ref image) => image.iter().fold(0, |max, &pixel| {
+            let group = ((pixel >> 8) & 0xFFFF) as uint;
+            if group > max { group } else { max }
+        }) + 1,
+        None => 1,
+    };
+    let mut groups = ~[];
+    for _ in range(0, group_count) {
+        match read_prefix_code_group(reader, cache_size) {
+            Some(group) => groups.push(group),
+            None => return None,
+        }
+    }
+
+    let mut pixels: ~[u32] = vec::with_capacity(width * height);
+    let mut cache = vec::from_elem(cache_size, 0u32);
+    let mut cached = 0;
+    let total = width * height;
+    while pixels.len() < total {
+        let position = pixels.len();
+        let group = match group_image {
+            Some(ref image) => {
+                let (x, y) = (position % width, position / width);
+                let block = (y >> group_bits) * subsample_size(width, group_bits) +
+                    (x >> group_bits);
+                &groups[((image[block] >> 8) & 0xFFFF) as uint]
+            }
+            None => &groups[0],
+        };
+
+        let symbol = match group.green.read_symbol(reader) {
+            Some(symbol) => symbol,
+            None => return None,
+        };
+        if symbol < 256 {
+            let (red, blue, alpha) = match (group.red.read_symbol(reader),
+                                            group.blue.read_symbol(reader),
+                                            group.alpha.read_symbol(reader)) {
+                (Some(red), Some(blue), Some(alpha)) => (red, blue, alpha),
+                _ => return None,
+            };
+            pixels.push(((alpha << 24) | (red << 16) | (symbol << 8) | blue) as u32);
+        } else if symbol < 256 + NUM_LENGTH_CODES {
+            // A backward reference.
+            let length = match prefix_value(reader, symbol - 256) {
+                Some(length) => length,
+                None => return None,
+            };
+            let distance_code = match group.distance.read_symbol(reader) {
+                Some(prefix) => match prefix_value(reader, prefix) {
+                    Some(code) => code,
+                    None => return None,
+                },
+                None => return None,
+            };
+            let distance = if distance_code > 120 {
+                (distance_code - 120) as int
+            } else {
+                let (x, y) = DISTANCE_MAP[distance_code - 1];
+                let distance = x + y * width as int;
+                if distance < 1 { 1 } else { distance }
+            };
+            if distance as uint > position || position + length > total {
+                return None;
+            }
+            for i in range(0, length) {
+                let pixel = pixels[position + i - distance as uint];
+                pixels.push(pixel);
+            }
+        } else {
+            let index = symbol - 256 - NUM_LENGTH_CODES;
+            // Every pixel so far is in the cache.
+            while cached < position {
+                let pixel = pixels[cached];
+                cache[cache_index(pixel, cache_bits)] = pixel;
+                cached += 1;
+            }
+            pixels.push(cache[index]);
+        }
+    }
+    Some(pixels)
+}
+
+fn cache_index(pixel: u32, cache_bits: uint) -> uint {
+    ((0x1E35A7BDu32 * pixel) >> (32 - cache_bits)) as uint
+}
+
+/// Adds two pixels, or subtracts them, channel by channel modulo 256.
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xFF00FF00) + (b & 0xFF00FF00);
+    let red_blue = (a & 0x00FF00FF) + (b & 0x00FF00FF);
+    (alpha_green & 0xFF00FF00) | (red_blue & 0x00FF00FF)
+}
+
+fn channel(pixel: u32, shift: uint) -> int {
+    ((pixel >> shift) & 0xFF) as int
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xFEFEFEFE) >> 1) + (a & b)
+}
+
+fn map_channels(f: |uint| -> int) -> u32 {
+    let mut pixel = 0;
+    for &shift in [24u, 16, 8, 0].iter() {
+        let value = f(shift);
+        let value = if value < 0 { 0 } else if value > 255 { 255 } else { value };
+        pixel |= (value as u32) << shift;
+    }
+    pixel
+}
+
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let mut distance_left = 0;
+    let mut distance_top = 0;
+    for &shift in [24u, 16, 8, 0].iter() {
+        let estimate = channel(left, shift) + channel(top, shift) - channel(top_left, shift);
+        distance_left += (estimate - channel(left, shift)).abs();
+        distance_top += (estimate - channel(top, shift)).abs();
+    }
+    if distance_left < distance_top { left } else { top }
+}
+
+fn predict(mode: uint, left: u32, top: u32, top_right: u32, top_left: u32) -> u32 {
+    match mode {
+        0 => 0xFF000000,
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average2(average2(left, top_right), top),
+        6 => average2(left, top_left),
+        7 => average2(left, top),
+        8 => average2(top_left, top),
+        9 => average2(top, top_right),
+        10 => average2(average2(left, top_left), average2(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => map_channels(|shift| {
+            channel(left, shift) + channel(top, shift) - channel(top_left, shift)
+        }),
+        13 => {
+            let average = average2(left, top);
+            map_channels(|shift| {
+                let a = channel(average, shift);
+                a + (a - channel(top_left, shift)) / 2
+            })
+        }
+        // Modes 14 and 15 aren't defined, but behave like 0 in the reference decoder.
+        _ => 0xFF000000,
+    }
+}
+
+fn inverse_predictor(pixels: &mut [u32], width: uint, height: uint, bits: uint, modes: &[u32]) {
+    let blocks_per_row = subsample_size(width, bits);
+    for y in range(0, height) {
+        for x in range(0, width) {
+            let i = y * width + x;
+            let prediction = if y == 0 {
+                if x == 0 { 0xFF000000 } else { pixels[i - 1] }
+            } else if x == 0 {
+                pixels[i - width]
+            } else {
+                let mode = (modes[(y >> bits) * blocks_per_row + (x >> bits)] >> 8) & 0x0F;
+                // The rightmost pixel's top right neighbor is the first pixel of its own row.
+                predict(mode as uint, pixels[i - 1], pixels[i - width], pixels[i - width + 1],
+                        pixels[i - width - 1])
+            };
+            pixels[i] = add_pixels(pixels[i], prediction);
+        }
+    }
+}
+
+/// The signed product of two channels, as the cross color transform scales them.
+fn color_transform_delta(transform: u32, color: u32) -> u32 {
+    ((transform as u8 as i8 as int * color as u8 as i8 as int) >> 5) as u32
+}
+
+fn inverse_cross_color(pixels: &mut [u32], width: uint, height: uint, bits: uint,
+                       transforms: &[u32]) {
+    let blocks_per_row = subsample_size(width, bits);
+    for y in range(0, height) {
+        for x in range(0, width) {
+            let transform = transforms[(y >> bits) * blocks_per_row + (x >> bits)];
+            let (green_to_red, green_to_blue, red_to_blue) =
+                (transform & 0xFF, (transform >> 8) & 0xFF, (transform >> 16) & 0xFF);
+            let pixel = pixels[y * width + x];
+            let green = (pixel >> 8) & 0xFF;
+            let red = ((pixel >> 16) + color_transform_delta(green_to_red, green)) & 0xFF;
+            let blue = (pixel + color_transform_delta(green_to_blue, green) +
+                        color_transform_delta(red_to_blue, red)) & 0xFF;
+            pixels[y * width + x] = (pixel & 0xFF00FF00) | (red << 16) | blue;
+        }
+    }
+}
+
+fn add_green(pixels: &mut [u32]) {
+    for pixel in pixels.mut_iter() {
+        let green = (*pixel >> 8) & 0xFF;
+        *pixel = add_pixels(*pixel, (green << 16) | green);
+    }
+}
+
+/// Expands pixels packed by the color indexing transform and looks up their colors.
+fn inverse_color_indexing(packed: &[u32], width: uint, height: uint, bits: uint,
+                          palette: &[u32]) -> ~[u32] {
+    let packed_width = subsample_size(width, bits);
+    let bits_per_pixel = 8 >> bits;
+    let mut pixels = vec::with_capacity(width * height);
+    for y in range(0, height) {
+        for x in range(0, width) {
+            let index = packed[y * packed_width + (x >> bits)] >> 8;
+            let shift = (x & ((1 << bits) - 1)) * bits_per_pixel;
+            let index = ((index >> shift) & ((1 << bits_per_pixel) - 1)) as uint;
+            // Out of range indices are transparent black.
+            pixels.push(if index < palette.len() { palette[index] } else { 0 });
+        }
+    }
+    pixels
+}
+
+enum Transform {
+    Predictor(uint, ~[u32]),
+    CrossColor(uint, ~[u32]),
+    SubtractGreen,
+    /// The palette, and the number of bits by which pixels are packed.
+    ColorIndexing(~[u32], uint),
+}
+
+fn decode_lossless(data: &[u8]) -> Option<Image> {
+    if data.len() < 5 || data[0] != VP8L_SIGNATURE {
+        return None;
+    }
+    let mut reader = BitReader { data: data, position: 1, bit: 0 };
+    let (width, height) = match (reader.read_bits(14), reader.read_bits(14)) {
+        (Some(width), Some(height)) if (width + 1) * (height + 1) * 4 <= MAX_DECODED_BYTES => {
+            (width + 1, height + 1)
+        }
+        _ => return None,
+    };
+    match (reader.read_bits(1), reader.read_bits(3)) {
+        (Some(_), Some(0)) => (),
+        _ => return None,
+    }
+    let pixels = match decode_image_stream(&mut reader, width, height) {
+        Some(pixels) => pixels,
+        None => return None,
+    };
+
+    // ARGB to RGBA.
+    let mut rgba = vec::with_capacity(width * height * 4);
+    for &pixel in pixels.iter() {
+        rgba.push((pixel >> 16) as u8);
+        rgba.push((pixel >> 8) as u8);
+        rgba.push(pixel as u8);
+        rgba.push((pixel >> 24) as u8);
+    }
+    Some(Image(width as u32, height as u32, png::RGBA8, rgba))
+}
+
+/// Decodes the transforms and pixels that follow the header of a VP8L stream, into ARGB
+/// pixels. The alpha of lossy images is stored the same way, without the header.
+fn decode_image_stream(reader: &mut BitReader, width: uint, height: uint) -> Option<~[u32]> {
+    // Each transform is listed once, and applies to the image as the transforms before it
+    // left it. Color indexing makes the image narrower by packing pixels together.
+    let mut transforms = ~[];
+    let mut seen = [false, ..4];
+    let mut coded_width = width;
+    while reader.read_bits(1) == Some(1) {
+        let kind = match reader.read_bits(2) {
+            Some(kind) if !seen[kind] => kind,
+            _ => return None,
+        };
+        seen[kind] = true;
+        let transform = match kind {
+            PREDICTOR_TRANSFORM | CROSS_COLOR_TRANSFORM => {
+                let bits = match reader.read_bits(3) {
+                    Some(bits) => bits + 2,
+                    None => return None,
+                };
+                let image = match decode_entropy_coded_image(reader,
+                                                             subsample_size(coded_width, bits),
+                                                             subsample_size(height, bits),
+                                                             false) {
+                    Some(image) => image,
+                    None => return None,
+                };
+                if kind == PREDICTOR_TRANSFORM {
+                    Predictor(bits, image)
+                } else {
+                    CrossColor(bits, image)
+                }
+            }
+            SUBTRACT_GREEN_TRANSFORM => SubtractGreen,
+            COLOR_INDEXING_TRANSFORM => {
+                let size = match reader.read_bits(8) {
+                    Some(size) => size + 1,
+                    None => return None,
+                };
+                let mut palette = match decode_entropy_coded_image(reader, size, 1, false) {
+                    Some(palette) => palette,
+                    None => return None,
+                };
+                // The palette is stored as differences from the previous entry.
+                for i in range(1, size) {
+                    palette[i] = add_pixels(palette[i], palette[i - 1]);
+                }
+                let bits = match size {
+                    1..2 => 3,
+                    3..4 => 2,
+                    5..16 => 1,
+                    _ => 0,
+                };
+                coded_width = subsample_size(coded_width, bits);
+                ColorIndexing(palette, bits)
+            }
+            _ => return None,
+        };
+        transforms.push((transform, coded_width));
+    }
+
+    let mut pixels = match decode_entropy_coded_image(reader, coded_width, height, true) {
+        Some(pixels) => pixels,
+        None => return None,
+    };
+
+    // Undo the transforms in reverse order, each at the width it was applied to.
+    while !transforms.is_empty() {
+        let (transform, _) = transforms.pop();
+        let transform_width = match transforms.last_opt() {
+            Some(&(_, width)) => width,
+            None => width,
+        };
+        match transform {
+            Predictor(bits, modes) => {
+                inverse_predictor(pixels, transform_width, height, bits, modes)
+            }
+            CrossColor(bits, elements) => {
+                inverse_cross_color(pixels, transform_width, height, bits, elements)
+            }
+            SubtractGreen => add_green(pixels),
+            ColorIndexing(palette, bits) => {
+                pixels = inverse_color_indexing(pixels, transform_width, height, bits, palette)
+            }
+        }
+    }
+    Some(pixels)
+}
+
+/// Decodes an `ALPH` chunk into the alpha channel of a `width` by `height` lossy image.
+fn decode_alpha(data: &[u8], width: uint, height: uint) -> Option<~[u8]> {
+    if data.is_empty() {
+        return None;
+    }
+    let compression = data[0] & 3;
+    let filter = (data[0] >> 2) & 3;
+    let size = width * height;
+    let mut alpha = match compression {
+        0 if data.len() > size => data.slice(1, 1 + size).to_owned(),
+        1 => {
+            // The values are in the green channel of a VP8L image stream.
+            let mut reader = BitReader { data: data, position: 1, bit: 0 };
+            match decode_image_stream(&mut reader, width, height) {
+                Some(pixels) => pixels.iter().map(|&pixel| (pixel >> 8) as u8).collect(),
+                None => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    // Undo the filter, which stores each value as the difference from a prediction. The first
+    // row is always predicted from the left, and the first column from above.
+    if filter == ALPHA_NO_FILTER {
+        return Some(alpha);
+    }
+    for y in range(0, height) {
+        for x in range(0, width) {
+            let i = y * width + x;
+            let prediction = if y == 0 {
+                if x == 0 { 0 } else { alpha[i - 1] }
+            } else if x == 0 {
+                alpha[i - width]
+            } else {
+                match filter {
+                    ALPHA_HORIZONTAL_FILTER => alpha[i - 1],
+                    ALPHA_VERTICAL_FILTER => alpha[i - width],
+                    _ => {
+                        let gradient = alpha[i - 1] as int + alpha[i - width] as int -
+                            alpha[i - width - 1] as int;
+                        if gradient < 0 { 0 } else if gradient > 255 { 255 } else { gradient as u8 }
+                    }
+                }
+            };
+            alpha[i] += prediction;
+        }
+    }
+    Some(alpha)
+}
+
+/// Packs `(value, bit count)` pairs into a VP8L chunk in a WebP file.
+#[cfg(test)]
+fn test_lossless_webp(fields: &[(uint, uint)]) -> ~[u8] {
+    let mut chunk = ~[VP8L_SIGNATURE];
+    let (mut byte, mut bit) = (0u8, 0u);
+    for &(value, count) in fields.iter() {
+        for i in range(0, count) {
+            byte |= (((value >> i) & 1) << bit) as u8;
+            bit += 1;
+            if bit == 8 {
+                chunk.push(byte);
+                byte = 0;
+                bit = 0;
+            }
+        }
+    }
+    if bit > 0 {
+        chunk.push(byte);
+    }
+    let size = chunk.len();
+    let mut file = bytes!("RIFF").to_owned();
+    let riff_size = 12 + size + (size & 1);
+    file.push_all(&[riff_size as u8, (riff_size >> 8) as u8, 0, 0]);
+    file.push_all(bytes!("WEBPVP8L"));
+    file.push_all(&[size as u8, (size >> 8) as u8, 0, 0]);
+    file.push_all(chunk);
+    if size & 1 == 1 {
+        file.push(0);
+    }
+    file
+}
+
+#[test]
+fn test_decode_lossless() {
+    // A 2x1 image with the subtract green transform, whose pixels are both literals coded with
+    // single symbol codes, which take no bits.
+    let mut fields = ~[(1, 14), (0, 14), (1, 1), (0, 3),
+                       (1, 1), (SUBTRACT_GREEN_TRANSFORM, 2), (0, 1),
+                       (0, 1), (0, 1)];
+    for &symbol in [0x80u, 0x90, 0xA0, 0xFF, 0].iter() {
+        fields.push_all(&[(1, 1), (0, 1), (1, 1), (symbol, 8)]);
+    }
+    let image = decode(test_lossless_webp(fields)).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.pixels, ~[0x10, 0x80, 0x20, 0xFF, 0x10, 0x80, 0x20, 0xFF]);
+}
+
+#[test]
+fn test_too_large() {
+    // A 16384x16384 lossless image would take up 1 GB, and so would a lossy one a pixel smaller
+    // each way. Neither is allocated.
+    let fields = ~[(16383, 14), (16383, 14), (1, 1), (0, 3), (0, 1)];
+    assert!(decode(test_lossless_webp(fields)).is_none());
+    let frame = [0x10u8, 0, 0, 0x9D, 0x01, 0x2A, 0xFF, 0x3F, 0xFF, 0x3F];
+    assert!(vp8::decode(frame).is_none());
+}
+
+#[test]
+fn test_read_prefix_code() {
+    let code = PrefixCode::new(&[2u, 1, 3, 3]).unwrap();
+    let data = [0x1Du8];
+    let mut reader = BitReader { data: data, position: 0, bit: 0 };
+    assert_eq!(code.read_symbol(&mut reader), Some(0));
+    assert_eq!(code.read_symbol(&mut reader), Some(3));
+    assert_eq!(code.read_symbol(&mut reader), Some(1));
+}
+
+// The expected pixels were decoded by libwebp.
+#[cfg(test)]
+static TEST_LOSSY: &'static [u8] = include_bin!("test_lossy.webp");
+#[cfg(test)]
+static TEST_LOSSY_PIXELS: &'static [u8] = include_bin!("test_lossy.rgba");
+#[cfg(test)]
+static TEST_LOSSY_ALPHA: &'static [u8] = include_bin!("test_lossy_alpha.webp");
+#[cfg(test)]
+static TEST_LOSSY_ALPHA_PIXELS: &'static [u8] = include_bin!("test_lossy_alpha.rgba");
+
+#[test]
+fn test_decode_lossy() {
+    let image = decode(TEST_LOSSY).unwrap();
+    assert_eq!((image.width, image.height), (23, 17));
+    assert!(image.pixels.as_slice() == TEST_LOSSY_PIXELS);
+}
+
+#[test]
+fn test_decode_lossy_with_alpha() {
+    let image = decode(TEST_LOSSY_ALPHA).unwrap();
+    assert_eq!((image.width, image.height), (23, 17));
+    assert!(image.pixels.as_slice() == TEST_LOSSY_ALPHA_PIXELS);
+}
+
+#[test]
+fn test_alpha_filters() {
+    let residuals = [10u8, 5, 250, 20, 3, 7];
+    let mut chunk = ~[ALPHA_VERTICAL_FILTER << 2];
+    chunk.push_all(residuals);
+    assert_eq!(decode_alpha(chunk, 3, 2), Some(~[10u8, 15, 9, 30, 18, 16]));
+    // The gradient filter.
+    chunk[0] = 3 << 2;
+    assert_eq!(decode_alpha(chunk, 3, 2), Some(~[10u8, 15, 9, 30, 38, 39]));
+    assert_eq!(decode_alpha(chunk.slice_to(6), 3, 2), None);
+}
+
+#[test]
+fn test_lossy_truncated() {
+    let mut file = bytes!("RIFF\x0e\x00\x00\x00WEBPVP8 \x02\x00\x00\x00").to_owned();
+    file.push_all(&[0, 0]);
+    assert!(decode(file).is_none());
+    assert!(decode(TEST_LOSSY.slice_to(TEST_LOSSY.len() - 100)).is_none());
+}
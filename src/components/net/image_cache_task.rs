@@ -2,18 +2,37 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use image::base::{Image, load_from_memory};
+use image::base::{Image, load_animation_from_memory, load_partial_from_memory, scale_down};
 use resource_task;
-use resource_task::ResourceTask;
+use resource_task::{LoadData, ResourceTask};
 use servo_util::url::{UrlMap, url_map};
 
 use std::comm::{Chan, Port, SharedChan};
+use std::hashmap::{HashMap, HashSet};
+use std::cmp::max;
+use std::io::timer;
 use std::task::spawn;
 use std::to_str::ToStr;
 use std::util::replace;
 use std::result;
 use extra::arc::{Arc,MutexArc};
 use extra::url::Url;
+use geom::size::Size2D;
+use png;
+
+/// How many bytes of images the cache keeps by default when no one is using them.
+pub static DEFAULT_MEMORY_BUDGET: uint = 64 * 1024 * 1024;
+
+/// Identifies a user of images, such as the layout task of a pipeline, so that its images can be
+/// kept while it is alive and released when it exits.
+pub type ImageOwner = uint;
+
+/// How much of an image has to arrive before we try to show part of it, and how much more it
+/// has to grow by before we try again, so that partial decodes don't repeat for every packet.
+static PARTIAL_DECODE_MIN_BYTES: uint = 16 * 1024;
+
+/// How many scaled copies of an image are kept, for an image shown at a few sizes at once.
+static MAX_SCALED_COPIES: uint = 4;
 
 pub enum Msg {
     /// Tell the cache that we may need a particular image soon. Must be posted
@@ -25,6 +44,11 @@ pub enum Msg {
     /// Used be the prefetch tasks to post back image binaries
     StorePrefetchedImageData(Url, Result<~[u8], ()>),
 
+    /// Used by the prefetch tasks to post the image data received so far while they wait for
+    /// the rest
+    // FIXME: make this priv after visibility rules change
+    StorePartialImageData(Url, ~[u8]),
+
     /// Tell the cache to decode an image. Must be posted before GetImage/WaitForImage
     Decode(Url),
 
@@ -32,13 +56,51 @@ pub enum Msg {
     // FIXME: make this priv after visibility rules change
     StoreImage(Url, Option<Arc<~Image>>),
 
+    /// Used by the decoder tasks to post images decoded from partial data
+    // FIXME: make this priv after visibility rules change
+    StorePartialImage(Url, Option<Arc<~Image>>),
+
+    /// Used by the decoder tasks to post the frames of animated images, with their delays in
+    /// milliseconds and how many times they repeat, before posting the first frame
+    // FIXME: make this priv after visibility rules change
+    StoreAnimation(Url, ~[(Arc<~Image>, uint)], Option<uint>),
+
+    /// Used by animation timers to move animated images on to their next frame. Timers of
+    /// animations that have since been evicted are told apart by the animation id.
+    // FIXME: make this priv after visibility rules change
+    AdvanceFrame(Url, uint),
+
     /// Request an Image object for a URL. If the image is not is not immediately
-    /// available then ImageNotReady is returned.
+    /// available then ImagePartial or ImageNotReady is returned.
     GetImage(Url, Chan<ImageResponseMsg>),
 
+    /// Request a copy of an image scaled down to the given size in pixels, for images shown
+    /// smaller than they are. Until the copy has been made, and for images that are animated or
+    /// no bigger than the size, replies as GetImage does.
+    GetScaledImage(Url, Size2D<uint>, Chan<ImageResponseMsg>),
+
+    /// Used by the scaling tasks to post scaled copies of images
+    // FIXME: make this priv after visibility rules change
+    StoreScaledImage(Url, Size2D<uint>, Option<Arc<~Image>>),
+
     /// Wait for an image to become available (or fail to load).
     WaitForImage(Url, Chan<ImageResponseMsg>),
 
+    /// Wait for more of an image to become available: replies once, with `ImagePartial` when
+    /// more of it has been decoded, or as `WaitForImage` does once it has loaded.
+    WaitForImageUpdate(Url, Chan<ImageResponseMsg>),
+
+    /// Tell the cache that an owner is displaying an image, which keeps it from being evicted
+    /// until the owner is purged. Should be posted before Prefetch.
+    RetainImage(Url, ImageOwner),
+
+    /// Tell the cache that an owner has gone away, so the images it retained may be evicted.
+    PurgeOwner(ImageOwner),
+
+    /// Wait for an animated image to show its next frame, which is then sent as `ImageReady`.
+    /// The channel is dropped for images that aren't animated or have stopped.
+    WaitForNextFrame(Url, Chan<ImageResponseMsg>),
+
     /// Clients must wait for a response before shutting down the ResourceTask
     Exit(Chan<()>),
 
@@ -54,6 +116,8 @@ pub enum Msg {
 #[deriving(Clone)]
 pub enum ImageResponseMsg {
     ImageReady(Arc<~Image>),
+    /// What has been decoded of an image that is still loading
+    ImagePartial(Arc<~Image>),
     ImageNotReady,
     ImageFailed
 }
@@ -63,10 +127,12 @@ impl Eq for ImageResponseMsg {
         // FIXME: Bad copies
         match (self.clone(), other.clone()) {
             (ImageReady(..), ImageReady(..)) => fail!(~"unimplemented comparison"),
+            (ImagePartial(..), ImagePartial(..)) => fail!(~"unimplemented comparison"),
             (ImageNotReady, ImageNotReady) => true,
             (ImageFailed, ImageFailed) => true,
 
-            (ImageReady(..), _) | (ImageNotReady, _) | (ImageFailed, _) => false
+            (ImageReady(..), _) | (ImagePartial(..), _) | (ImageNotReady, _) |
+            (ImageFailed, _) => false
         }
     }
 
@@ -82,7 +148,9 @@ pub struct ImageCacheTask {
 
 type DecoderFactory = fn() -> proc(&[u8]) -> Option<Image>;
 
-pub fn ImageCacheTask(resource_task: ResourceTask) -> ImageCacheTask {
+/// Starts an image cache that evicts the least recently used of the images nobody retains once
+/// they take up more than `memory_budget` bytes.
+pub fn ImageCacheTask(resource_task: ResourceTask, memory_budget: uint) -> ImageCacheTask {
     let (port, chan) = SharedChan::new();
     let chan_clone = chan.clone();
 
@@ -93,6 +161,20 @@ pub fn ImageCacheTask(resource_task: ResourceTask) -> ImageCacheTask {
             chan: chan_clone,
             state_map: url_map(),
             wait_map: url_map(),
+            update_waiters: HashMap::new(),
+            partial_map: url_map(),
+            partial_decoding: HashSet::new(),
+            animations: HashMap::new(),
+            next_animation_id: 0,
+            compressed_map: url_map(),
+            owner_map: url_map(),
+            last_used_map: url_map(),
+            use_count: 0,
+            scaled_map: url_map(),
+            scaling_map: url_map(),
+            full_size_used_map: url_map(),
+            scaled_used_map: url_map(),
+            memory_budget: memory_budget,
             need_exit: None
         };
         cache.run();
@@ -108,7 +190,7 @@ pub fn SyncImageCacheTask(resource_task: ResourceTask) -> ImageCacheTask {
     let (port, chan) = SharedChan::new();
 
     spawn(proc() {
-        let inner_cache = ImageCacheTask(resource_task.clone());
+        let inner_cache = ImageCacheTask(resource_task.clone(), DEFAULT_MEMORY_BUDGET);
 
         loop {
             let msg: Msg = port.recv();
@@ -142,9 +224,47 @@ struct ImageCache {
     state_map: UrlMap<ImageState>,
     /// List of clients waiting on a WaitForImage response
     wait_map: UrlMap<MutexArc<~[Chan<ImageResponseMsg>]>>,
+    /// List of clients waiting on a WaitForImageUpdate response
+    update_waiters: UrlMap<~[Chan<ImageResponseMsg>]>,
+    /// The latest partial decode of each image that is still loading
+    partial_map: UrlMap<Arc<~Image>>,
+    /// The images with a partial decode underway, which are not decoded again until it ends
+    partial_decoding: HashSet<Url>,
+    /// The animated images. The current frame of each is its `Decoded` state.
+    animations: UrlMap<AnimationState>,
+    next_animation_id: uint,
+    /// The encoded data of decoded images, kept so they can be decoded again after eviction
+    compressed_map: UrlMap<~[u8]>,
+    /// The owners that retain each image
+    owner_map: UrlMap<~[ImageOwner]>,
+    /// When each image was last asked for, in messages handled
+    last_used_map: UrlMap<u64>,
+    use_count: u64,
+    /// Copies of images scaled down to the sizes they are shown at, oldest first
+    scaled_map: UrlMap<~[Arc<~Image>]>,
+    /// The sizes that copies of each image are being made at
+    scaling_map: UrlMap<~[Size2D<uint>]>,
+    /// When each image was last given out at full size, and as a scaled copy. The full size
+    /// pixels of retained images that are only shown scaled may be evicted.
+    full_size_used_map: UrlMap<u64>,
+    scaled_used_map: UrlMap<u64>,
+    /// How many bytes of images to keep before evicting the ones nobody retains
+    memory_budget: uint,
     need_exit: Option<Chan<()>>,
 }
 
+struct AnimationState {
+    id: uint,
+    /// The frames, with how long each is shown in milliseconds.
+    frames: ~[(Arc<~Image>, uint)],
+    current: uint,
+    /// How many more times the animation goes back to its first frame, or `None` if it loops
+    /// forever.
+    repetitions_left: Option<uint>,
+    /// Clients waiting for the next frame.
+    frame_waiters: ~[Chan<ImageResponseMsg>],
+}
+
 #[deriving(Clone)]
 enum ImageState {
     Init,
@@ -152,6 +272,9 @@ enum ImageState {
     Prefetched(~[u8]),
     Decoding,
     Decoded(Arc<~Image>),
+    /// Evicted to stay within the memory budget. Decoded again from `compressed_map` when next
+    /// asked for, or fetched again if the encoded data was evicted as well
+    Evicted,
     Failed
 }
 
@@ -172,7 +295,10 @@ impl ImageCache {
             debug!("image_cache_task: received: {:?}", msg);
 
             match msg {
-                Prefetch(url) => self.prefetch(url),
+                Prefetch(url) => {
+                    self.touch(&url);
+                    self.prefetch(url)
+                }
                 StorePrefetchedImageData(url, data) => {
                     store_prefetched_chan.map(|chan| {
                         chan.send(());
@@ -181,7 +307,11 @@ impl ImageCache {
 
                     self.store_prefetched_image_data(url, data);
                 }
-                Decode(url) => self.decode(url),
+                StorePartialImageData(url, data) => self.store_partial_image_data(url, data),
+                Decode(url) => {
+                    self.touch(&url);
+                    self.decode(url)
+                }
                 StoreImage(url, image) => {
                     store_chan.map(|chan| {
                         chan.send(());
@@ -190,10 +320,38 @@ impl ImageCache {
 
                     self.store_image(url, image)
                 }
-                GetImage(url, response) => self.get_image(url, response),
+                StorePartialImage(url, image) => self.store_partial_image(url, image),
+                StoreScaledImage(url, size, image) => {
+                    store_chan.map(|chan| {
+                        chan.send(());
+                    });
+                    store_chan = None;
+
+                    self.store_scaled_image(url, size, image)
+                }
+                StoreAnimation(url, frames, repetitions) => {
+                    self.store_animation(url, frames, repetitions)
+                }
+                AdvanceFrame(url, id) => self.advance_frame(url, id),
+                GetImage(url, response) => {
+                    self.touch_full_size(&url);
+                    self.get_image(url, response)
+                }
+                GetScaledImage(url, size, response) => {
+                    self.touch(&url);
+                    self.get_scaled_image(url, size, response)
+                }
                 WaitForImage(url, response) => {
+                    self.touch_full_size(&url);
                     self.wait_for_image(url, response)
                 }
+                WaitForImageUpdate(url, response) => {
+                    self.touch_full_size(&url);
+                    self.wait_for_image_update(url, response)
+                }
+                RetainImage(url, owner) => self.retain_image(url, owner),
+                PurgeOwner(owner) => self.purge_owner(owner),
+                WaitForNextFrame(url, response) => self.wait_for_next_frame(url, response),
                 WaitForStore(chan) => store_chan = Some(chan),
                 WaitForStorePrefetched(chan) => store_prefetched_chan = Some(chan),
                 Exit(response) => {
@@ -214,7 +372,7 @@ impl ImageCache {
                         Prefetching(..) => can_exit = false,
                         Decoding => can_exit = false,
 
-                        Init | Prefetched(..) | Decoded(..) | Failed => ()
+                        Init | Prefetched(..) | Decoded(..) | Evicted | Failed => ()
                     }
                 }
 
@@ -252,7 +410,9 @@ impl ImageCache {
                     let url = url_clone;
                     debug!("image_cache_task: started fetch for {:s}", url.to_str());
 
-                    let image = load_image_data(url.clone(), resource_task.clone());
+                    let image = load_image_data(url.clone(), resource_task.clone(), |data| {
+                        to_cache.send(StorePartialImageData(url.clone(), data.to_owned()));
+                    });
 
                     let result = if image.is_ok() {
                         Ok(image.unwrap())
@@ -266,7 +426,7 @@ impl ImageCache {
                 self.set_state(url, Prefetching(DoNotDecode));
             }
 
-            Prefetching(..) | Prefetched(..) | Decoding | Decoded(..) | Failed => {
+            Prefetching(..) | Prefetched(..) | Decoding | Decoded(..) | Evicted | Failed => {
                 // We've already begun working on this image
             }
         }
@@ -280,7 +440,7 @@ impl ImageCache {
                 self.set_state(url.clone(), Prefetched(data));
                 match next_step {
                   DoDecode => self.decode(url),
-                  _ => ()
+                  _ => self.evict_to_budget()
                 }
               }
               Err(..) => {
@@ -294,15 +454,72 @@ impl ImageCache {
           | Prefetched(..)
           | Decoding
           | Decoded(..)
+          | Evicted
           | Failed => {
             fail!(~"wrong state for storing prefetched image")
           }
         }
     }
 
+    fn store_partial_image_data(&mut self, url: Url, data: ~[u8]) {
+        match self.get_state(url.clone()) {
+            Prefetching(DoDecode) if !self.partial_decoding.contains(&url) => {
+                self.partial_decoding.insert(url.clone());
+                let to_cache = self.chan.clone();
+                spawn(proc() {
+                    debug!("image_cache_task: started partial decode for {:s}", url.to_str());
+                    let image = load_partial_from_memory(data).map(|image| Arc::new(~image));
+                    // The whole image may have been stored and the cache exited meanwhile.
+                    to_cache.try_send(StorePartialImage(url, image));
+                });
+            }
+
+            // Nobody has asked to see the image yet, or we are still decoding an earlier part;
+            // more data will follow.
+            Prefetching(..) => (),
+
+            Init
+            | Prefetched(..)
+            | Decoding
+            | Decoded(..)
+            | Evicted
+            | Failed => {
+                fail!(~"wrong state for storing partial image data")
+            }
+        }
+    }
+
+    fn store_partial_image(&mut self, url: Url, image: Option<Arc<~Image>>) {
+        self.partial_decoding.remove(&url);
+        let image = match image {
+            Some(image) => image,
+            // Not enough of the image has arrived to show any of it.
+            None => return,
+        };
+        match self.get_state(url.clone()) {
+            Prefetching(..) | Decoding => {
+                self.partial_map.insert(url.clone(), image.clone());
+                match self.update_waiters.pop(&url) {
+                    Some(waiters) => {
+                        for response in waiters.iter() {
+                            response.send(ImagePartial(image.clone()));
+                        }
+                    }
+                    None => ()
+                }
+            }
+            // The whole image got here first.
+            Init | Prefetched(..) | Decoded(..) | Evicted | Failed => ()
+        }
+    }
+
     fn decode(&mut self, url: Url) {
         match self.get_state(url.clone()) {
-            Init => fail!(~"decoding image before prefetch"),
+            Init => {
+                // The image was never prefetched, or was evicted along with its encoded data.
+                self.prefetch(url.clone());
+                self.decode(url)
+            }
 
             Prefetching(DoNotDecode) => {
                 // We don't have the data yet, queue up the decode
@@ -313,24 +530,16 @@ impl ImageCache {
                 // We don't have the data yet, but the decode request is queued up
             }
 
-            Prefetched(data) => {
-                let to_cache = self.chan.clone();
-                let url_clone = url.clone();
-
-                spawn(proc() {
-                    let url = url_clone;
-                    debug!("image_cache_task: started image decode for {:s}", url.to_str());
-                    let image = load_from_memory(data);
-                    let image = if image.is_some() {
-                        Some(Arc::new(~image.unwrap()))
-                    } else {
-                        None
-                    };
-                    to_cache.send(StoreImage(url.clone(), image));
-                    debug!("image_cache_task: ended image decode for {:s}", url.to_str());
-                });
+            Prefetched(data) => self.start_decode(url, data),
 
-                self.set_state(url, Decoding);
+            Evicted => {
+                match self.compressed_map.find(&url).map(|data| data.clone()) {
+                    Some(data) => self.start_decode(url, data),
+                    None => {
+                        self.set_state(url.clone(), Init);
+                        self.decode(url)
+                    }
+                }
             }
 
             Decoding | Decoded(..) | Failed => {
@@ -339,6 +548,37 @@ impl ImageCache {
         }
     }
 
+    fn start_decode(&mut self, url: Url, data: ~[u8]) {
+        let to_cache = self.chan.clone();
+        let url_clone = url.clone();
+        let data_clone = data.clone();
+
+        spawn(proc() {
+            let url = url_clone;
+            debug!("image_cache_task: started image decode for {:s}", url.to_str());
+            match load_animation_from_memory(data_clone) {
+                Some(animation) => {
+                    let repetitions = animation.repetitions;
+                    let frames: ~[(Arc<~Image>, uint)] =
+                        animation.frames.move_iter().map(|frame| {
+                            (Arc::new(~frame.image), frame.delay_ms)
+                        }).collect();
+                    let first = match frames[0] { (ref image, _) => image.clone() };
+                    if frames.len() > 1 {
+                        to_cache.send(StoreAnimation(url.clone(), frames, repetitions));
+                    }
+                    to_cache.send(StoreImage(url.clone(), Some(first)));
+                }
+                None => to_cache.send(StoreImage(url.clone(), None)),
+            }
+            debug!("image_cache_task: ended image decode for {:s}", url.to_str());
+        });
+
+        // Keep the encoded image so the pixels can be evicted and decoded again later.
+        self.compressed_map.insert(url.clone(), data);
+        self.set_state(url, Decoding);
+    }
+
     fn store_image(&mut self, url: Url, image: Option<Arc<~Image>>) {
 
         match self.get_state(url.clone()) {
@@ -346,9 +586,18 @@ impl ImageCache {
             match image {
               Some(image) => {
                 self.set_state(url.clone(), Decoded(image.clone()));
-                self.purge_waiters(url, || ImageReady(image.clone()) );
+                self.purge_waiters(url.clone(), || ImageReady(image.clone()) );
+                match self.animations.find(&url) {
+                  Some(animation) => {
+                    let (_, delay) = animation.frames[0];
+                    self.start_frame_timer(url.clone(), animation.id, delay);
+                  }
+                  None => ()
+                }
+                self.evict_to_budget();
               }
               None => {
+                self.compressed_map.remove(&url);
                 self.set_state(url.clone(), Failed);
                 self.purge_waiters(url, || ImageFailed );
               }
@@ -359,6 +608,7 @@ impl ImageCache {
           | Prefetching(..)
           | Prefetched(..)
           | Decoded(..)
+          | Evicted
           | Failed => {
             fail!(~"incorrect state in store_image")
           }
@@ -366,7 +616,88 @@ impl ImageCache {
 
     }
 
+    fn store_animation(&mut self,
+                       url: Url,
+                       frames: ~[(Arc<~Image>, uint)],
+                       repetitions: Option<uint>) {
+        // The first frame is stored as usual, which starts the animation.
+        self.next_animation_id += 1;
+        self.animations.insert(url, AnimationState {
+            id: self.next_animation_id,
+            frames: frames,
+            current: 0,
+            repetitions_left: repetitions,
+            frame_waiters: ~[],
+        });
+    }
+
+    /// Sends `AdvanceFrame` for `url` once the current frame has been shown for `delay` ms.
+    fn start_frame_timer(&self, url: Url, id: uint, delay: uint) {
+        let to_cache = self.chan.clone();
+        spawn(proc() {
+            timer::sleep(delay as u64);
+            // The cache may have exited in the meantime.
+            to_cache.try_send(AdvanceFrame(url, id));
+        });
+    }
+
+    fn advance_frame(&mut self, url: Url, id: uint) {
+        let mut finished = false;
+        let next = match self.animations.find_mut(&url) {
+            Some(animation) if animation.id == id => {
+                if animation.current + 1 == animation.frames.len() {
+                    match animation.repetitions_left {
+                        Some(0) => finished = true,
+                        Some(count) => animation.repetitions_left = Some(count - 1),
+                        None => (),
+                    }
+                }
+                if finished {
+                    None
+                } else {
+                    animation.current = (animation.current + 1) % animation.frames.len();
+                    let (ref frame, delay) = animation.frames[animation.current];
+                    Some((frame.clone(), delay, replace(&mut animation.frame_waiters, ~[])))
+                }
+            }
+            Some(..) | None => None,
+        };
+        if finished {
+            // Stay on the last frame. Dropping the waiters' channels tells them there are no
+            // more frames.
+            self.animations.remove(&url);
+            return;
+        }
+        let (frame, delay, waiters) = match next {
+            Some(next) => next,
+            None => return,
+        };
+
+        self.set_state(url.clone(), Decoded(frame.clone()));
+        for waiter in waiters.iter() {
+            waiter.try_send(ImageReady(frame.clone()));
+        }
+        self.start_frame_timer(url, id, delay);
+    }
+
+    fn wait_for_next_frame(&mut self, url: Url, response: Chan<ImageResponseMsg>) {
+        match self.animations.find_mut(&url) {
+            Some(animation) => animation.frame_waiters.push(response),
+            // Not animated, at least not yet; dropping the channel says so.
+            None => (),
+        }
+    }
+
     fn purge_waiters(&mut self, url: Url, f: || -> ImageResponseMsg) {
+        self.partial_map.remove(&url);
+        match self.update_waiters.pop(&url) {
+            Some(waiters) => {
+                for response in waiters.iter() {
+                    response.send(f());
+                }
+            }
+            None => ()
+        }
         match self.wait_map.pop(&url) {
             Some(waiters) => {
                 unsafe {
@@ -381,12 +712,23 @@ impl ImageCache {
         }
     }
 
-    fn get_image(&self, url: Url, response: Chan<ImageResponseMsg>) {
+    fn get_image(&mut self, url: Url, response: Chan<ImageResponseMsg>) {
         match self.get_state(url.clone()) {
-            Init => fail!(~"request for image before prefetch"),
-            Prefetching(DoDecode) => response.send(ImageNotReady),
+            Init => {
+                self.decode(url);
+                response.send(ImageNotReady);
+            }
             Prefetching(DoNotDecode) | Prefetched(..) => fail!(~"request for image before decode"),
-            Decoding => response.send(ImageNotReady),
+            Prefetching(DoDecode) | Decoding => {
+                match self.partial_map.find(&url) {
+                    Some(image) => response.send(ImagePartial(image.clone())),
+                    None => response.send(ImageNotReady),
+                }
+            }
+            Evicted => {
+                self.decode(url);
+                response.send(ImageNotReady);
+            }
             Decoded(image) => response.send(ImageReady(image.clone())),
             Failed => response.send(ImageFailed),
         }
@@ -394,7 +736,10 @@ impl ImageCache {
 
     fn wait_for_image(&mut self, url: Url, response: Chan<ImageResponseMsg>) {
         match self.get_state(url.clone()) {
-            Init => fail!(~"request for image before prefetch"),
+            Init => {
+                self.decode(url.clone());
+                self.wait_for_image(url, response);
+            }
 
             Prefetching(DoNotDecode) | Prefetched(..) => fail!(~"request for image before decode"),
 
@@ -415,12 +760,240 @@ impl ImageCache {
                 response.send(ImageReady(image.clone()));
             }
 
+            Evicted => {
+                self.decode(url.clone());
+                self.wait_for_image(url, response);
+            }
+
             Failed => {
                 response.send(ImageFailed);
             }
         }
     }
 
+    fn get_scaled_image(&mut self,
+                        url: Url,
+                        size: Size2D<uint>,
+                        response: Chan<ImageResponseMsg>) {
+        let copy = self.scaled_map.find(&url).and_then(|copies| {
+            copies.iter().find(|copy| {
+                (copy.get().width as uint, copy.get().height as uint) == (size.width, size.height)
+            }).map(|copy| copy.clone())
+        });
+        match copy {
+            Some(copy) => {
+                let was_shown_scaled = self.is_shown_scaled(&url);
+                self.scaled_used_map.insert(url.clone(), self.use_count);
+                response.send(ImageReady(copy));
+                if !was_shown_scaled {
+                    // The full size pixels may have just become evictable.
+                    self.evict_to_budget();
+                }
+                return;
+            }
+            None => ()
+        }
+
+        // Hand out the full size image while the copy is made.
+        self.full_size_used_map.insert(url.clone(), self.use_count);
+        let image = match self.get_state(url.clone()) {
+            Decoded(image) => image,
+            _ => return self.get_image(url, response),
+        };
+        let scalable = {
+            let image = image.get();
+            let rgba = match image.color_type {
+                png::RGBA8 => true,
+                _ => false,
+            };
+            let (width, height) = (image.width as uint, image.height as uint);
+            rgba && !self.animations.contains_key(&url) &&
+                size.width > 0 && size.height > 0 && size.width <= width &&
+                size.height <= height && (size.width < width || size.height < height)
+        };
+        let scaling = self.scaling_map.find(&url).map_default(false, |sizes| sizes.contains(&size));
+        if scalable && !scaling {
+            self.scaling_map.find_or_insert_with(url.clone(), |_| ~[]).push(size.clone());
+            let to_cache = self.chan.clone();
+            let url = url.clone();
+            let image = image.clone();
+            spawn(proc() {
+                debug!("image_cache_task: started scaling {:s}", url.to_str());
+                let copy = scale_down(image.get(), size.width as u32, size.height as u32);
+                // The cache may have exited in the meantime.
+                to_cache.try_send(StoreScaledImage(url, size, copy.map(|copy| Arc::new(~copy))));
+            });
+        }
+        response.send(ImageReady(image));
+    }
+
+    fn store_scaled_image(&mut self, url: Url, size: Size2D<uint>, copy: Option<Arc<~Image>>) {
+        let done = match self.scaling_map.find_mut(&url) {
+            Some(sizes) => {
+                sizes.retain(|other| *other != size);
+                sizes.is_empty()
+            }
+            None => false,
+        };
+        if done {
+            self.scaling_map.remove(&url);
+        }
+
+        let copy = match copy {
+            Some(copy) => copy,
+            None => return,
+        };
+        match self.get_state(url.clone()) {
+            Decoded(..) | Evicted => {
+                let copies = self.scaled_map.find_or_insert_with(url, |_| ~[]);
+                copies.push(copy);
+                if copies.len() > MAX_SCALED_COPIES {
+                    copies.shift();
+                }
+                self.evict_to_budget();
+            }
+            // The image was evicted altogether in the meantime.
+            Init | Prefetching(..) | Prefetched(..) | Decoding | Failed => (),
+        }
+    }
+
+    /// Whether an image has only been given out as scaled copies since it was last given out at
+    /// full size.
+    fn is_shown_scaled(&self, url: &Url) -> bool {
+        match (self.scaled_used_map.find(url), self.full_size_used_map.find(url)) {
+            (Some(scaled), Some(full_size)) => scaled > full_size,
+            (Some(..), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn wait_for_image_update(&mut self, url: Url, response: Chan<ImageResponseMsg>) {
+        match self.get_state(url.clone()) {
+            Prefetching(DoDecode) | Decoding => {
+                self.update_waiters.find_or_insert_with(url, |_| ~[]).push(response);
+            }
+            Init | Prefetching(DoNotDecode) | Prefetched(..) | Decoded(..) | Evicted | Failed => {
+                self.wait_for_image(url, response)
+            }
+        }
+    }
+
+    /// Marks an image as the most recently used.
+    fn touch(&mut self, url: &Url) {
+        self.use_count += 1;
+        self.last_used_map.insert(url.clone(), self.use_count);
+    }
+
+    /// Marks an image as the most recently used, and as needed at full size.
+    fn touch_full_size(&mut self, url: &Url) {
+        self.touch(url);
+        self.full_size_used_map.insert(url.clone(), self.use_count);
+    }
+
+    fn retain_image(&mut self, url: Url, owner: ImageOwner) {
+        let owners = self.owner_map.find_or_insert_with(url, |_| ~[]);
+        if !owners.contains(&owner) {
+            owners.push(owner);
+        }
+    }
+
+    fn purge_owner(&mut self, owner: ImageOwner) {
+        let mut released = ~[];
+        for (url, owners) in self.owner_map.mut_iter() {
+            owners.retain(|&other| other != owner);
+            if owners.is_empty() {
+                released.push(url.clone());
+            }
+        }
+        for url in released.iter() {
+            self.owner_map.remove(url);
+        }
+        self.evict_to_budget();
+    }
+
+    /// The memory held for an image: its pixels, including every frame of an animation, and
+    /// what is kept until the whole image is evicted: its encoded data and scaled copies.
+    fn memory_used_by(&self, url: &Url) -> (uint, uint) {
+        let decoded = match self.animations.find(url) {
+            Some(animation) => animation.frames.iter().fold(0, |total, &(ref frame, _)| {
+                total + frame.get().pixels.len()
+            }),
+            None => match self.state_map.find(url) {
+                Some(&Decoded(ref image)) => image.get().pixels.len(),
+                _ => 0,
+            },
+        };
+        let compressed = match self.state_map.find(url) {
+            Some(&Prefetched(ref data)) => data.len(),
+            _ => self.compressed_map.find(url).map_default(0, |data| data.len()),
+        };
+        let scaled = self.scaled_map.find(url).map_default(0, |copies| {
+            copies.iter().fold(0, |total, copy| total + copy.get().pixels.len())
+        });
+        (decoded, compressed + scaled)
+    }
+
+    /// Evicts the least recently used images that no owner retains until the cache fits its
+    /// budget. Pixels go first, since they are much bigger and can be decoded again from the
+    /// encoded data, which is only dropped once no pixels are left to evict; an image without
+    /// either is fetched again when next asked for. The full size pixels of retained images
+    /// that are only shown scaled can be evicted too.
+    fn evict_to_budget(&mut self) {
+        let mut used = 0;
+        let mut candidates = ~[];
+        for (url, state) in self.state_map.iter() {
+            let (decoded, kept) = self.memory_used_by(url);
+            used += decoded + kept;
+            let (pixels_evictable, entry_evictable) = match *state {
+                Prefetched(..) | Decoded(..) | Evicted => {
+                    if self.wait_map.contains_key(url) {
+                        (false, false)
+                    } else if self.owner_map.contains_key(url) {
+                        (self.is_shown_scaled(url), false)
+                    } else {
+                        (true, true)
+                    }
+                }
+                Init | Prefetching(..) | Decoding | Failed => (false, false),
+            };
+            if (pixels_evictable && decoded > 0) || (entry_evictable && kept > 0) {
+                let last_used = self.last_used_map.find(url).map_default(0, |&count| count);
+                candidates.push((last_used, url.clone(), decoded, kept, entry_evictable));
+            }
+        }
+        if used <= self.memory_budget {
+            return;
+        }
+        candidates.sort_by(|&(a, _, _, _, _), &(b, _, _, _, _)| a.cmp(&b));
+
+        for &(_, ref url, decoded, _, _) in candidates.iter() {
+            if used <= self.memory_budget {
+                return;
+            }
+            if decoded > 0 {
+                debug!("image_cache_task: evicting the pixels of {:s}", url.to_str());
+                self.animations.remove(url);
+                self.set_state(url.clone(), Evicted);
+                used -= decoded;
+            }
+        }
+        for &(_, ref url, _, kept, entry_evictable) in candidates.iter() {
+            if used <= self.memory_budget {
+                return;
+            }
+            if !entry_evictable {
+                continue;
+            }
+            debug!("image_cache_task: evicting {:s}", url.to_str());
+            self.set_state(url.clone(), Evicted);
+            self.compressed_map.remove(url);
+            self.scaled_map.remove(url);
+            self.last_used_map.remove(url);
+            self.full_size_used_map.remove(url);
+            self.scaled_used_map.remove(url);
+            used -= kept;
+        }
+    }
 }
 
 
@@ -456,15 +1029,33 @@ impl ImageCacheTask {
     }
 }
 
-fn load_image_data(url: Url, resource_task: ResourceTask) -> Result<~[u8], ()> {
+/// Loads the whole of an image, passing the data received so far to `on_partial_data` whenever
+/// the load stalls after a good deal more of it has arrived.
+fn load_image_data(url: Url, resource_task: ResourceTask, on_partial_data: |&[u8]|)
+                   -> Result<~[u8], ()> {
     let (response_port, response_chan) = Chan::new();
-    resource_task.send(resource_task::Load(url, response_chan));
+    let mut load_data = LoadData::new(url);
+    load_data.priority = resource_task::ImagePriority;
+    resource_task.send(resource_task::Load(load_data, response_chan));
 
     let mut image_data = ~[];
+    let mut next_partial_len = PARTIAL_DECODE_MIN_BYTES;
 
     let progress_port = response_port.recv().progress_port;
     loop {
-        match progress_port.recv() {
+        let msg = match progress_port.try_recv() {
+            Some(msg) => msg,
+            None => {
+                if image_data.len() >= next_partial_len {
+                    on_partial_data(image_data);
+                    // Grow the step with the image, so the data copied stays linear in its size.
+                    next_partial_len = image_data.len() +
+                        max(PARTIAL_DECODE_MIN_BYTES, image_data.len() / 2);
+                }
+                progress_port.recv()
+            }
+        };
+        match msg {
             resource_task::Payload(data) => {
                 image_data.push_all(data);
             }
@@ -497,7 +1088,8 @@ mod tests {
                         let chan = start_sending(response, Metadata::default(make_url(~"file:///fake", None)));
                         on_load(chan);
                     }
-                    resource_task::Exit => break
+                    resource_task::Exit => break,
+                    _ => ()
                 }
             }
         })
@@ -507,7 +1099,7 @@ mod tests {
     fn should_exit_on_request() {
         let mock_resource_task = mock_resource_task(proc(_response) {});
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let _url = make_url(~"file", None);
 
         image_cache_task.exit();
@@ -515,16 +1107,28 @@ mod tests {
     }
 
     #[test]
-    #[should_fail]
-    fn should_fail_if_unprefetched_image_is_requested() {
-        let mock_resource_task = mock_resource_task(proc(_response) {});
+    fn should_prefetch_image_requested_before_prefetch() {
+        let mock_resource_task = mock_resource_task(proc(response) {
+            response.send(resource_task::Payload(test_image_bin()));
+            response.send(resource_task::Done(Ok(())));
+        });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
-        let (port, chan) = Chan::new();
-        image_cache_task.send(GetImage(url, chan));
-        port.recv();
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url.clone(), response_chan));
+        assert!(response_port.recv() == ImageNotReady);
+
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForImage(url, response_chan));
+        match response_port.recv() {
+          ImageReady(_) => (),
+          _ => fail!("bleh")
+        }
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
     }
 
     #[test]
@@ -536,7 +1140,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url));
@@ -554,7 +1158,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url.clone()));
@@ -577,7 +1181,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url.clone()));
@@ -597,7 +1201,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         let join_port = image_cache_task.wait_for_store();
@@ -626,7 +1230,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         let join_port = image_cache_task.wait_for_store();
@@ -669,11 +1273,12 @@ mod tests {
                         resource_task_exited_chan.send(());
                         break
                     }
+                    _ => ()
                 }
             }
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url.clone()));
@@ -705,18 +1310,19 @@ mod tests {
                     resource_task::Load(_, response) => {
                         let chan = start_sending(response, Metadata::default(make_url(~"file:///fake", None)));
                         chan.send(resource_task::Payload(test_image_bin()));
-                        chan.send(resource_task::Done(Err(())));
+                        chan.send(resource_task::Done(Err(resource_task::NetworkError(~"fake"))));
                         image_bin_sent_chan.send(());
                     }
                     resource_task::Exit => {
                         resource_task_exited_chan.send(());
                         break
                     }
+                    _ => ()
                 }
             }
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url.clone()));
@@ -743,10 +1349,10 @@ mod tests {
         let mock_resource_task = mock_resource_task(proc(response) {
             response.send(resource_task::Payload(test_image_bin()));
             // ERROR fetching image
-            response.send(resource_task::Done(Err(())));
+            response.send(resource_task::Done(Err(resource_task::NetworkError(~"fake"))));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         let join_port = image_cache_task.wait_for_store_prefetched();
@@ -773,10 +1379,10 @@ mod tests {
         let mock_resource_task = mock_resource_task(proc(response) {
             response.send(resource_task::Payload(test_image_bin()));
             // ERROR fetching image
-            response.send(resource_task::Done(Err(())));
+            response.send(resource_task::Done(Err(resource_task::NetworkError(~"fake"))));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         let join_port = image_cache_task.wait_for_store_prefetched();
@@ -814,7 +1420,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         let join_port = image_cache_task.wait_for_store();
@@ -845,7 +1451,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         let join_port = image_cache_task.wait_for_store();
@@ -877,7 +1483,7 @@ mod tests {
             response.send(resource_task::Done(Ok(())));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url.clone()));
@@ -904,10 +1510,10 @@ mod tests {
         let mock_resource_task = mock_resource_task(proc(response) {
             wait_port.recv();
             response.send(resource_task::Payload(test_image_bin()));
-            response.send(resource_task::Done(Err(())));
+            response.send(resource_task::Done(Err(resource_task::NetworkError(~"fake"))));
         });
 
-        let image_cache_task = ImageCacheTask(mock_resource_task.clone());
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
         let url = make_url(~"file", None);
 
         image_cache_task.send(Prefetch(url.clone()));
@@ -927,6 +1533,148 @@ mod tests {
         mock_resource_task.send(resource_task::Exit);
     }
 
+    #[test]
+    fn should_evict_decoded_images_over_budget() {
+        let mock_resource_task = mock_resource_task(proc(response) {
+            response.send(resource_task::Payload(test_image_bin()));
+            response.send(resource_task::Done(Ok(())));
+        });
+
+        // Room for the encoded image, but not its pixels.
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), 10000);
+        let url = make_url(~"file", None);
+
+        let join_port = image_cache_task.wait_for_store();
+        image_cache_task.send(Prefetch(url.clone()));
+        image_cache_task.send(Decode(url.clone()));
+        join_port.recv();
+
+        // The pixels were evicted, so asking for the image decodes it again.
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url.clone(), response_chan));
+        assert!(response_port.recv() == ImageNotReady);
+
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForImage(url, response_chan));
+        match response_port.recv() {
+          ImageReady(_) => (),
+          _ => fail!("bleh")
+        }
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
+    }
+
+    #[test]
+    fn should_fetch_evicted_prefetched_images_again() {
+        let (url_requested, url_requested_chan) = Chan::new();
+
+        let mock_resource_task = mock_resource_task(proc(response) {
+            url_requested_chan.send(());
+            response.send(resource_task::Payload(test_image_bin()));
+            response.send(resource_task::Done(Ok(())));
+        });
+
+        // Not even room for the encoded image.
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), 10);
+        let url = make_url(~"file", None);
+
+        let join_port = image_cache_task.wait_for_store_prefetched();
+        image_cache_task.send(Prefetch(url.clone()));
+        join_port.recv();
+        url_requested.recv();
+
+        // The encoded data was evicted, so decoding the image fetches it again.
+        image_cache_task.send(Decode(url.clone()));
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForImage(url, response_chan));
+        match response_port.recv() {
+          ImageReady(_) => (),
+          _ => fail!("bleh")
+        }
+        url_requested.recv();
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
+    }
+
+    #[test]
+    fn should_not_evict_retained_images() {
+        let mock_resource_task = mock_resource_task(proc(response) {
+            response.send(resource_task::Payload(test_image_bin()));
+            response.send(resource_task::Done(Ok(())));
+        });
+
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), 10000);
+        let url = make_url(~"file", None);
+
+        let join_port = image_cache_task.wait_for_store();
+        image_cache_task.send(RetainImage(url.clone(), 1));
+        image_cache_task.send(Prefetch(url.clone()));
+        image_cache_task.send(Decode(url.clone()));
+        join_port.recv();
+
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url.clone(), response_chan));
+        match response_port.recv() {
+          ImageReady(_) => (),
+          _ => fail!("bleh")
+        }
+
+        // Once its owner is gone, the image is evicted.
+        image_cache_task.send(PurgeOwner(1));
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url, response_chan));
+        assert!(response_port.recv() == ImageNotReady);
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
+    }
+
+    #[test]
+    fn should_evict_full_size_pixels_of_images_shown_scaled() {
+        use geom::size::Size2D;
+
+        let mock_resource_task = mock_resource_task(proc(response) {
+            response.send(resource_task::Payload(test_image_bin()));
+            response.send(resource_task::Done(Ok(())));
+        });
+
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), 10000);
+        let url = make_url(~"file", None);
+
+        let join_port = image_cache_task.wait_for_store();
+        image_cache_task.send(RetainImage(url.clone(), 1));
+        image_cache_task.send(Prefetch(url.clone()));
+        image_cache_task.send(Decode(url.clone()));
+        join_port.recv();
+
+        // The full size image is given out while the copy is made.
+        let join_port = image_cache_task.wait_for_store();
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetScaledImage(url.clone(), Size2D(10u, 10u), response_chan));
+        match response_port.recv() {
+            ImageReady(image) => assert_eq!((image.get().width, image.get().height), (337, 450)),
+            _ => fail!("bleh")
+        }
+        join_port.recv();
+
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetScaledImage(url.clone(), Size2D(10u, 10u), response_chan));
+        match response_port.recv() {
+            ImageReady(image) => assert_eq!((image.get().width, image.get().height), (10, 10)),
+            _ => fail!("bleh")
+        }
+
+        // The image is retained, but only shown scaled, so its full size pixels are evicted.
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url, response_chan));
+        assert!(response_port.recv() == ImageNotReady);
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
+    }
+
     #[test]
     fn sync_cache_should_wait_for_images() {
         let mock_resource_task = mock_resource_task(proc(response) {
@@ -950,4 +1698,104 @@ mod tests {
         image_cache_task.exit();
         mock_resource_task.send(resource_task::Exit);
     }
+
+    #[test]
+    fn should_play_animated_images() {
+        use image::gif::test_animated_gif;
+
+        let mock_resource_task = mock_resource_task(proc(response) {
+            response.send(resource_task::Payload(test_animated_gif()));
+            response.send(resource_task::Done(Ok(())));
+        });
+
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
+        let url = make_url(~"file", None);
+
+        image_cache_task.send(Prefetch(url.clone()));
+        image_cache_task.send(Decode(url.clone()));
+
+        // Frames are BGRA: red, then blue, shown twice in all.
+        let red = ~[0u8, 0, 255, 255];
+        let blue = ~[255u8, 0, 0, 255];
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForImage(url.clone(), response_chan));
+        match response_port.recv() {
+            ImageReady(image) => assert_eq!(image.get().pixels, red),
+            _ => fail!("bleh")
+        }
+        for expected in [&blue, &red, &blue].iter() {
+            let (response_port, response_chan) = Chan::new();
+            image_cache_task.send(WaitForNextFrame(url.clone(), response_chan));
+            match response_port.recv() {
+                ImageReady(image) => assert_eq!(&image.get().pixels, *expected),
+                _ => fail!("bleh")
+            }
+        }
+
+        // The animation has finished.
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForNextFrame(url.clone(), response_chan));
+        assert!(response_port.recv_opt().is_none());
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
+    }
+
+    #[test]
+    fn should_return_partial_image_while_loading() {
+        use std::vec;
+
+        // The test image, with a comment after the start of image marker to make it big enough
+        // to be shown before it has all arrived.
+        let jpeg = test_image_bin();
+        let comment_len = 20000u;
+        let mut image = jpeg.slice_to(2).to_owned();
+        image.push_all([0xFF, 0xFE, (comment_len >> 8) as u8, comment_len as u8]);
+        image.push_all(vec::from_elem(comment_len - 2, 0u8));
+        image.push_all(jpeg.slice_from(2));
+        let split = image.len() - 1000;
+
+        let (wait_port, wait_chan) = Chan::new();
+        let mock_resource_task = mock_resource_task(proc(response) {
+            wait_port.recv();
+            response.send(resource_task::Payload(image.slice_to(split).to_owned()));
+            wait_port.recv();
+            response.send(resource_task::Payload(image.slice_from(split).to_owned()));
+            response.send(resource_task::Done(Ok(())));
+        });
+
+        let image_cache_task = ImageCacheTask(mock_resource_task.clone(), DEFAULT_MEMORY_BUDGET);
+        let url = make_url(~"file", None);
+
+        image_cache_task.send(Prefetch(url.clone()));
+        image_cache_task.send(Decode(url.clone()));
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url.clone(), response_chan));
+        assert!(response_port.recv() == ImageNotReady);
+
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForImageUpdate(url.clone(), response_chan));
+        wait_chan.send(());
+        match response_port.recv() {
+            ImagePartial(image) => assert_eq!((image.get().width, image.get().height), (337, 450)),
+            _ => fail!("bleh")
+        }
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(GetImage(url.clone(), response_chan));
+        match response_port.recv() {
+            ImagePartial(_) => (),
+            _ => fail!("bleh")
+        }
+
+        let (response_port, response_chan) = Chan::new();
+        image_cache_task.send(WaitForImageUpdate(url.clone(), response_chan));
+        wait_chan.send(());
+        match response_port.recv() {
+            ImageReady(_) => (),
+            _ => fail!("bleh")
+        }
+
+        image_cache_task.exit();
+        mock_resource_task.send(resource_task::Exit);
+    }
 }
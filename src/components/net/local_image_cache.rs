@@ -6,23 +6,41 @@
 An adapter for ImageCacheTask that does local caching to avoid
 extra message traffic, it also avoids waiting on the same image
 multiple times and thus triggering reflows multiple times.
+
+Images that are still loading are shown as far as they have been
+decoded, with a reflow each time more of them is available.
+
+Animated images are followed frame by frame: a task waits for the
+next frame of each one that is displayed, keeps it for the next
+`get_image` and triggers a reflow.
+
+Images shown smaller than they are can be asked for at that size
+with `get_scaled_image`, after which only the scaled copy is kept.
 */
 
-use image_cache_task::{Decode, GetImage, ImageCacheTask, ImageFailed, ImageNotReady, ImageReady};
-use image_cache_task::{ImageResponseMsg, Prefetch, WaitForImage};
+use image::base::Image;
+use image_cache_task::{Decode, GetImage, GetScaledImage, ImageCacheTask, ImageFailed};
+use image_cache_task::{ImageNotReady, ImageOwner, ImagePartial, ImageReady, ImageResponseMsg};
+use image_cache_task::{Prefetch, RetainImage, WaitForImageUpdate, WaitForNextFrame};
 
 use std::comm::Port;
+use std::util::replace;
 use servo_util::url::{UrlMap, url_map};
+use extra::arc::{Arc, MutexArc};
 use extra::url::Url;
+use geom::size::Size2D;
 use servo_util::task::spawn_named;
 
 pub trait ImageResponder {
     fn respond(&self) -> proc(ImageResponseMsg);
 }
 
-pub fn LocalImageCache(image_cache_task: ImageCacheTask) -> LocalImageCache {
+/// Creates a local cache whose images are retained in the image cache under `owner` until the
+/// owner is purged.
+pub fn LocalImageCache(image_cache_task: ImageCacheTask, owner: ImageOwner) -> LocalImageCache {
     LocalImageCache {
         image_cache_task: image_cache_task,
+        owner: owner,
         round_number: 1,
         on_image_available: None,
         state_map: url_map()
@@ -31,6 +49,7 @@ pub fn LocalImageCache(image_cache_task: ImageCacheTask) -> LocalImageCache {
 
 pub struct LocalImageCache {
     priv image_cache_task: ImageCacheTask,
+    priv owner: ImageOwner,
     priv round_number: uint,
     priv on_image_available: Option<~ImageResponder:Send>,
     priv state_map: UrlMap<ImageState>
@@ -41,7 +60,12 @@ struct ImageState {
     prefetched: bool,
     decoded: bool,
     last_request_round: uint,
-    last_response: ImageResponseMsg
+    last_response: ImageResponseMsg,
+    /// Where the next frame of an animated image is put when it arrives.
+    next_frame: MutexArc<Option<Arc<~Image>>>,
+    /// The copy of the image scaled to the size it was last shown at, if it is shown smaller
+    /// than it is.
+    scaled_image: Option<Arc<~Image>>,
 }
 
 impl LocalImageCache {
@@ -62,6 +86,7 @@ impl LocalImageCache {
             state.prefetched = true;
         }
 
+        self.image_cache_task.send(RetainImage((*url).clone(), self.owner));
         self.image_cache_task.send(Prefetch((*url).clone()));
     }
 
@@ -79,6 +104,21 @@ impl LocalImageCache {
 
     // FIXME: Should return a Future
     pub fn get_image(&mut self, url: &Url) -> Port<ImageResponseMsg> {
+        let next_frame = {
+            let state = self.get_state(url);
+            let next_frame = unsafe {
+                state.next_frame.unsafe_access(|next_frame| replace(next_frame, None))
+            };
+            match next_frame {
+                Some(ref frame) => state.last_response = ImageReady(frame.clone()),
+                None => (),
+            }
+            next_frame
+        };
+        if next_frame.is_some() {
+            self.wait_for_next_frame(url);
+        }
+
         {
             let state = self.get_state(url);
 
@@ -93,10 +133,10 @@ impl LocalImageCache {
                     chan.send(ImageReady(image.clone()));
                     return port;
                 }
-                ImageNotReady => {
+                ImagePartial(..) | ImageNotReady => {
                     if last_round == self.round_number {
                         let (port, chan) = Chan::new();
-                        chan.send(ImageNotReady);
+                        chan.send(state.last_response.clone());
                         return port;
                     } else {
                         // We haven't requested the image from the
@@ -116,8 +156,8 @@ impl LocalImageCache {
 
         let response = response_port.recv();
         match response {
-            ImageNotReady => {
-                // Need to reflow when the image is available
+            ImagePartial(..) | ImageNotReady => {
+                // Need to reflow when more of the image is available
                 // FIXME: Instead we should be just passing a Future
                 // to the caller, then to the display list. Finally,
                 // the compositor should be resonsible for waiting
@@ -128,7 +168,7 @@ impl LocalImageCache {
                 let url = (*url).clone();
                 do spawn_named("LocalImageCache") {
                     let (response_port, response_chan) = Chan::new();
-                    image_cache_task.send(WaitForImage(url.clone(), response_chan));
+                    image_cache_task.send(WaitForImageUpdate(url.clone(), response_chan));
                     on_image_available(response_port.recv());
                 }
             }
@@ -138,23 +178,114 @@ impl LocalImageCache {
         // Put a copy of the response in the cache
         let response_copy = match response {
             ImageReady(ref image) => ImageReady(image.clone()),
+            ImagePartial(ref image) => ImagePartial(image.clone()),
             ImageNotReady => ImageNotReady,
             ImageFailed => ImageFailed
         };
         self.get_state(url).last_response = response_copy;
 
+        match response {
+            ImageReady(_) => self.wait_for_next_frame(url),
+            ImagePartial(..) | ImageNotReady | ImageFailed => ()
+        }
+
         let (port, chan) = Chan::new();
         chan.send(response);
         return port;
     }
 
+    /// Like `get_image`, but for an image shown at `size`: once the image has loaded, a copy
+    /// scaled down to that size is asked for, and when it arrives it is given out instead and
+    /// the full size image is let go.
+    pub fn get_scaled_image(&mut self, url: &Url, size: Size2D<uint>) -> Port<ImageResponseMsg> {
+        let shown_smaller = {
+            let state = self.get_state(url);
+            let scaled_image = state.scaled_image.as_ref().and_then(|image| {
+                let (width, height) = (image.get().width as uint, image.get().height as uint);
+                if (width, height) == (size.width, size.height) {
+                    Some(image.clone())
+                } else {
+                    None
+                }
+            });
+            match scaled_image {
+                Some(image) => {
+                    let (port, chan) = Chan::new();
+                    chan.send(ImageReady(image));
+                    return port;
+                }
+                None => ()
+            }
+            match state.last_response {
+                ImageReady(ref image) => {
+                    let (width, height) = (image.get().width as uint, image.get().height as uint);
+                    size.width <= width && size.height <= height &&
+                        (size.width < width || size.height < height)
+                }
+                ImagePartial(..) | ImageNotReady | ImageFailed => false,
+            }
+        };
+        if !shown_smaller {
+            return self.get_image(url);
+        }
+
+        let (response_port, response_chan) = Chan::new();
+        self.image_cache_task.send(GetScaledImage((*url).clone(), size.clone(), response_chan));
+        let copy = match response_port.recv() {
+            ImageReady(ref image) if (image.get().width as uint, image.get().height as uint) ==
+                    (size.width, size.height) => Some(image.clone()),
+            _ => None,
+        };
+        match copy {
+            Some(image) => {
+                let state = self.get_state(url);
+                state.scaled_image = Some(image.clone());
+                // The full size image is asked for again should it be needed.
+                state.last_response = ImageNotReady;
+                state.last_request_round = 0;
+
+                let (port, chan) = Chan::new();
+                chan.send(ImageReady(image));
+                port
+            }
+            // The copy isn't ready yet, or the image is animated and isn't scaled.
+            None => self.get_image(url),
+        }
+    }
+
+    /// Keeps the next frame of `url` for the next `get_image` and reflows when it arrives. Does
+    /// nothing for images that aren't animated.
+    fn wait_for_next_frame(&self, url: &Url) {
+        let image_cache_task = self.image_cache_task.clone();
+        assert!(self.on_image_available.is_some());
+        let on_image_available = self.on_image_available.as_ref().unwrap().respond();
+        let next_frame = self.state_map.get(url).next_frame.clone();
+        let url = (*url).clone();
+        do spawn_named("LocalImageCache") {
+            let (response_port, response_chan) = Chan::new();
+            image_cache_task.send(WaitForNextFrame(url.clone(), response_chan));
+            match response_port.recv_opt() {
+                Some(ImageReady(frame)) => {
+                    unsafe {
+                        next_frame.unsafe_access(|next_frame| *next_frame = Some(frame.clone()));
+                    }
+                    on_image_available(ImageReady(frame));
+                }
+                // The image isn't animated, or has finished.
+                Some(ImagePartial(..)) | Some(ImageNotReady) | Some(ImageFailed) | None => ()
+            }
+        }
+    }
+
     fn get_state<'a>(&'a mut self, url: &Url) -> &'a mut ImageState {
         let state = self.state_map.find_or_insert_with(url.clone(), |_| {
             let new_state = ImageState {
                 prefetched: false,
                 decoded: false,
                 last_request_round: 0,
-                last_response: ImageNotReady
+                last_response: ImageNotReady,
+                next_frame: MutexArc::new(None),
+                scaled_image: None,
             };
             new_state
         });
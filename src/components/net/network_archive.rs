@@ -0,0 +1,226 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Recording loads into an archive directory and replaying them from it, so that a page can be
+//! captured once and then rendered deterministically without a network.
+//!
+//! Each response is stored in its own file, named after a hash of the request method and URL,
+//! in the same format as the on-disk HTTP cache (see `stored_response`).
+
+use resource_task::{LoadData, LoadResponse, Metadata, Payload, Done, start_sending};
+use stored_response::StoredResponse;
+use util::to_status;
+
+use extra::url::Url;
+use http::headers::HeaderEnum;
+use std::from_str::from_str;
+use std::hash::Hash;
+use std::io;
+use std::io::{File, Reader, Writer};
+use std::io::fs;
+use ResponseHeaderCollection = http::headers::response::HeaderCollection;
+
+#[deriving(Eq, Clone)]
+pub enum ArchiveMode {
+    /// Load from the network as usual and store every response.
+    Record,
+    /// Serve every load from the archive, failing the ones that weren't recorded.
+    Replay,
+}
+
+#[deriving(Clone)]
+pub struct NetworkArchive {
+    mode: ArchiveMode,
+    priv dir: Path,
+}
+
+impl NetworkArchive {
+    pub fn new(mode: ArchiveMode, dir: Path) -> NetworkArchive {
+        NetworkArchive {
+            mode: mode,
+            dir: dir,
+        }
+    }
+
+    /// Whether a load goes through the archive. `data:` and `about:` URLs are answered from
+    /// the URL itself, so they are deterministic already.
+    pub fn handles(load_data: &LoadData) -> bool {
+        match load_data.url.scheme.as_slice() {
+            "data" | "about" => false,
+            _ => true,
+        }
+    }
+
+    fn path_for(&self, key: &str) -> Path {
+        self.dir.join(format!("{:016x}", key.hash()))
+    }
+
+    pub fn store(&self, entry: &ArchivedResponse) {
+        let mut stored = StoredResponse::new(entry.status, entry.content_type.clone(),
+                                             entry.charset.clone(), entry.headers.clone());
+        stored.push_field(~[~"key", entry.key.clone()]);
+        stored.push_field(~[~"final-url", entry.final_url.to_str()]);
+        for &(ref url, code) in entry.redirect_chain.iter() {
+            stored.push_field(~[~"redirect", code.to_str(), url.to_str()]);
+        }
+
+        let _guard = io::ignore_io_error();
+        if !self.dir.exists() {
+            fs::mkdir_recursive(&self.dir, io::UserRWX);
+        }
+        let path = self.path_for(entry.key.as_slice());
+        match File::create(&path) {
+            Some(ref mut file) => {
+                file.write(stored.head());
+                file.write(entry.body);
+            }
+            None => error!("network_archive: couldn't write {:s}", path.display().to_str()),
+        }
+    }
+
+    pub fn lookup(&self, load_data: &LoadData) -> Option<ArchivedResponse> {
+        let key = archive_key(load_data);
+        let data = {
+            let _guard = io::ignore_io_error();
+            match File::open(&self.path_for(key.as_slice())) {
+                Some(ref mut file) => file.read_to_end(),
+                None => return None,
+            }
+        };
+        let (stored, body) = match StoredResponse::parse(data) {
+            Some(parsed) => parsed,
+            None => return None,
+        };
+
+        let StoredResponse { status, content_type, charset, headers, fields } = stored;
+        let mut entry = ArchivedResponse {
+            key: key.clone(),
+            final_url: load_data.url.clone(),
+            status: status,
+            content_type: content_type,
+            charset: charset,
+            headers: headers,
+            redirect_chain: ~[],
+            body: body.to_owned(),
+        };
+        for values in fields.iter() {
+            let parts: ~[&str] = values.iter().map(|value| value.as_slice()).collect();
+            match (parts[0], parts.len()) {
+                // Guard against hash collisions.
+                ("key", 2) if parts[1] != key.as_slice() => return None,
+                ("final-url", 2) => {
+                    match from_str(parts[1]) {
+                        Some(url) => entry.final_url = url,
+                        None => return None,
+                    }
+                }
+                ("redirect", 3) => {
+                    match (from_str(parts[1]), from_str(parts[2])) {
+                        (Some(code), Some(url)) => entry.redirect_chain.push((url, code)),
+                        _ => return None,
+                    }
+                }
+                _ => (),
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// Requests are told apart by method and URL.
+pub fn archive_key(load_data: &LoadData) -> ~str {
+    format!("{:s} {:s}", load_data.method.to_str(), load_data.url.to_str())
+}
+
+/// A complete response as stored in the archive.
+#[deriving(Clone)]
+pub struct ArchivedResponse {
+    key: ~str,
+    final_url: Url,
+    status: u16,
+    content_type: Option<(~str, ~str)>,
+    charset: Option<~str>,
+    headers: ~[(~str, ~str)],
+    redirect_chain: ~[(Url, u16)],
+    body: ~[u8],
+}
+
+impl ArchivedResponse {
+    /// Starts an entry for the response to the request with the given key. The body is
+    /// appended as it arrives.
+    pub fn new(key: ~str, metadata: &Metadata) -> ArchivedResponse {
+        ArchivedResponse {
+            key: key,
+            final_url: metadata.final_url.clone(),
+            status: metadata.status.code(),
+            content_type: metadata.content_type.clone(),
+            charset: metadata.charset.clone(),
+            headers: match metadata.headers {
+                Some(ref headers) => headers.iter().map(|header| {
+                    (header.header_name(), header.header_value())
+                }).collect(),
+                None => ~[],
+            },
+            redirect_chain: metadata.redirect_chain.iter().map(|&(ref url, ref status)| {
+                (url.clone(), status.code())
+            }).collect(),
+            body: ~[],
+        }
+    }
+
+    /// Replays the response to a consumer.
+    pub fn send(&self, start_chan: Chan<LoadResponse>) {
+        let mut metadata = Metadata::default(self.final_url.clone());
+        metadata.status = to_status(self.status);
+        metadata.content_type = self.content_type.clone();
+        metadata.charset = self.charset.clone();
+        if !self.headers.is_empty() {
+            // Replayed headers are untyped, but iterate like the originals.
+            let mut headers = ResponseHeaderCollection::new();
+            for &(ref name, ref value) in self.headers.iter() {
+                headers.extensions.insert(name.clone(), value.clone());
+            }
+            metadata.headers = Some(headers);
+        }
+        metadata.redirect_chain = self.redirect_chain.iter().map(|&(ref url, code)| {
+            (url.clone(), to_status(code))
+        }).collect();
+
+        let progress_chan = start_sending(start_chan, metadata);
+        if !self.body.is_empty() {
+            progress_chan.send(Payload(self.body.clone()));
+        }
+        progress_chan.send(Done(Ok(())));
+    }
+}
+
+#[test]
+fn test_store_and_lookup() {
+    use extra::tempfile::TempDir;
+    use http::method::Post;
+    use http::status;
+
+    let dir = TempDir::new("network_archive").unwrap();
+    let archive = NetworkArchive::new(Record, dir.path().join("archive"));
+    let load_data = LoadData::new(from_str("http://example.com/a").unwrap());
+    assert!(archive.lookup(&load_data).is_none());
+
+    let mut metadata = Metadata::default(from_str("http://example.com/b").unwrap());
+    metadata.content_type = Some((~"text", ~"css"));
+    metadata.redirect_chain = ~[(load_data.url.clone(), status::MovedPermanently)];
+    let mut entry = ArchivedResponse::new(archive_key(&load_data), &metadata);
+    entry.body.push_all(bytes!("p { color: red }"));
+    archive.store(&entry);
+
+    let replayed = archive.lookup(&load_data).unwrap();
+    assert_eq!(replayed.final_url, metadata.final_url);
+    assert_eq!(replayed.content_type, Some((~"text", ~"css")));
+    assert_eq!(replayed.redirect_chain, ~[(load_data.url.clone(), 301u16)]);
+    assert_eq!(replayed.body.as_slice(), bytes!("p { color: red }"));
+
+    // A POST to the same URL is a different request.
+    let mut post = load_data.clone();
+    post.method = Post;
+    assert!(archive.lookup(&post).is_none());
+}
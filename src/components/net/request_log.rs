@@ -0,0 +1,270 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A log of every load made by a resource task, which can be written out as a HAR file
+//! (http://www.softwareishard.com/blog/har-12-spec/) for offline diagnosis.
+
+use resource_task::{LoadData, Metadata};
+
+#[cfg(test)]
+use extra::json;
+use extra::json::{Json, Number, String, Boolean, List, Object};
+use extra::time;
+use extra::time::{Timespec, precise_time_ns};
+use extra::treemap::TreeMap;
+use extra::url::Url;
+use http::headers::HeaderEnum;
+use std::io;
+use std::io::{File, Writer};
+
+#[cfg(test)]
+use std::from_str::from_str;
+
+/// Everything we know about one load.
+#[deriving(Clone)]
+pub struct LoadRecord {
+    url: Url,
+    method: ~str,
+    request_headers: ~[(~str, ~str)],
+    /// The size of the request body.
+    request_size: uint,
+    /// Wall clock time at which the resource task received the request.
+    requested: Timespec,
+    /// Whether the response came straight from the HTTP cache.
+    from_cache: bool,
+
+    final_url: Option<Url>,
+    status: u16,
+    status_text: ~str,
+    response_headers: ~[(~str, ~str)],
+    mime_type: ~str,
+    redirect_chain: ~[Url],
+    /// The size of the response body after any content coding was removed.
+    response_size: uint,
+    /// Whether the whole response body arrived.
+    succeeded: bool,
+
+    /// `precise_time_ns` when the request was received, started, answered and finished. Loads
+    /// for a busy host wait in a queue between the first two.
+    requested_ns: u64,
+    started_ns: u64,
+    response_ns: u64,
+    finished_ns: u64,
+}
+
+impl LoadRecord {
+    /// Starts a record for a load that was requested at `requested_ns` and is starting now.
+    pub fn new(load_data: &LoadData, requested_ns: u64, from_cache: bool) -> LoadRecord {
+        let now = precise_time_ns();
+        let mut request_headers: ~[(~str, ~str)] = load_data.headers.iter().map(|header| {
+            (header.header_name(), header.header_value())
+        }).collect();
+        match load_data.referrer {
+            Some(ref referrer) => request_headers.push((~"Referer", referrer.to_str())),
+            None => (),
+        }
+        LoadRecord {
+            url: load_data.url.clone(),
+            method: load_data.method.to_str(),
+            request_headers: request_headers,
+            request_size: load_data.data.as_ref().map_default(0, |data| data.len()),
+            requested: time::get_time(),
+            from_cache: from_cache,
+            final_url: None,
+            status: 0,
+            status_text: ~"",
+            response_headers: ~[],
+            mime_type: ~"",
+            redirect_chain: ~[],
+            response_size: 0,
+            succeeded: false,
+            requested_ns: requested_ns,
+            started_ns: now,
+            response_ns: now,
+            finished_ns: now,
+        }
+    }
+
+    /// Records the metadata of the response as the consumer receives it.
+    pub fn record_response(&mut self, metadata: &Metadata) {
+        self.response_ns = precise_time_ns();
+        self.final_url = Some(metadata.final_url.clone());
+        self.status = metadata.status.code();
+        self.status_text = metadata.status.reason();
+        self.response_headers = match metadata.headers {
+            Some(ref headers) => headers.iter().map(|header| {
+                (header.header_name(), header.header_value())
+            }).collect(),
+            None => ~[],
+        };
+        self.mime_type = match metadata.content_type {
+            Some((ref type_, ref subtype)) => format!("{:s}/{:s}", *type_, *subtype),
+            None => ~"",
+        };
+        self.redirect_chain = metadata.redirect_chain.iter().map(|&(ref url, _)| {
+            url.clone()
+        }).collect();
+    }
+
+    pub fn record_payload(&mut self, len: uint) {
+        self.response_size += len;
+    }
+
+    pub fn record_done(&mut self, succeeded: bool) {
+        self.finished_ns = precise_time_ns();
+        self.succeeded = succeeded;
+    }
+
+    /// The record as a HAR `entry` object.
+    pub fn to_har(&self) -> Json {
+        let blocked = ms_between(self.requested_ns, self.started_ns);
+        let wait = ms_between(self.started_ns, self.response_ns);
+        let receive = ms_between(self.response_ns, self.finished_ns);
+
+        let mut request = TreeMap::new();
+        request.insert(~"method", String(self.method.clone()));
+        request.insert(~"url", String(self.url.to_str()));
+        request.insert(~"httpVersion", String(~"HTTP/1.1"));
+        request.insert(~"cookies", List(~[]));
+        request.insert(~"headers", headers_to_har(self.request_headers.as_slice()));
+        request.insert(~"queryString", List(self.url.query.iter().map(|&(ref name, ref value)| {
+            name_value(name.clone(), value.clone())
+        }).collect()));
+        request.insert(~"headersSize", Number(-1.0));
+        request.insert(~"bodySize", Number(self.request_size as f64));
+
+        let mut content = TreeMap::new();
+        content.insert(~"size", Number(self.response_size as f64));
+        content.insert(~"mimeType", String(self.mime_type.clone()));
+
+        let mut response = TreeMap::new();
+        response.insert(~"status", Number(self.status as f64));
+        response.insert(~"statusText", String(self.status_text.clone()));
+        response.insert(~"httpVersion", String(~"HTTP/1.1"));
+        response.insert(~"cookies", List(~[]));
+        response.insert(~"headers", headers_to_har(self.response_headers.as_slice()));
+        response.insert(~"content", Object(~content));
+        response.insert(~"redirectURL", String(~""));
+        response.insert(~"headersSize", Number(-1.0));
+        response.insert(~"bodySize", Number(-1.0));
+
+        let mut timings = TreeMap::new();
+        timings.insert(~"blocked", Number(blocked));
+        timings.insert(~"send", Number(0.0));
+        timings.insert(~"wait", Number(wait));
+        timings.insert(~"receive", Number(receive));
+
+        let mut entry = TreeMap::new();
+        entry.insert(~"startedDateTime", String(time::at_utc(self.requested).rfc3339()));
+        entry.insert(~"time", Number(blocked + wait + receive));
+        entry.insert(~"request", Object(~request));
+        entry.insert(~"response", Object(~response));
+        entry.insert(~"cache", Object(~TreeMap::new()));
+        entry.insert(~"timings", Object(~timings));
+        // Custom fields, which HAR allows with a leading underscore.
+        entry.insert(~"_fromCache", Boolean(self.from_cache));
+        entry.insert(~"_succeeded", Boolean(self.succeeded));
+        entry.insert(~"_finalURL", String(self.final_url.as_ref().map_default(~"", |url| {
+            url.to_str()
+        })));
+        entry.insert(~"_redirectChain", List(self.redirect_chain.iter().map(|url| {
+            String(url.to_str())
+        }).collect()));
+        Object(~entry)
+    }
+}
+
+fn ms_between(start_ns: u64, end_ns: u64) -> f64 {
+    if end_ns > start_ns {
+        (end_ns - start_ns) as f64 / 1000000f64
+    } else {
+        0.0
+    }
+}
+
+fn name_value(name: ~str, value: ~str) -> Json {
+    let mut object = TreeMap::new();
+    object.insert(~"name", String(name));
+    object.insert(~"value", String(value));
+    Object(~object)
+}
+
+fn headers_to_har(headers: &[(~str, ~str)]) -> Json {
+    List(headers.iter().map(|&(ref name, ref value)| {
+        name_value(name.clone(), value.clone())
+    }).collect())
+}
+
+/// The loads of one session, in the order they finished.
+pub struct RequestLog {
+    priv records: ~[LoadRecord],
+    /// Where to write the log when the resource task exits.
+    priv har_path: Path,
+}
+
+impl RequestLog {
+    pub fn new(har_path: Path) -> RequestLog {
+        RequestLog {
+            records: ~[],
+            har_path: har_path,
+        }
+    }
+
+    pub fn push(&mut self, record: LoadRecord) {
+        self.records.push(record);
+    }
+
+    pub fn to_har(&self) -> Json {
+        let mut creator = TreeMap::new();
+        creator.insert(~"name", String(~"Servo"));
+        creator.insert(~"version", String(~"0.1"));
+
+        let mut log = TreeMap::new();
+        log.insert(~"version", String(~"1.2"));
+        log.insert(~"creator", Object(~creator));
+        log.insert(~"pages", List(~[]));
+        log.insert(~"entries", List(self.records.iter().map(|record| record.to_har()).collect()));
+
+        let mut har = TreeMap::new();
+        har.insert(~"log", Object(~log));
+        Object(~har)
+    }
+
+    /// Writes the log to its HAR file, replacing anything already there.
+    pub fn write_har(&self) {
+        let _guard = io::ignore_io_error();
+        match File::create(&self.har_path) {
+            Some(ref mut file) => file.write(self.to_har().to_pretty_str().as_bytes()),
+            None => error!("request_log: couldn't write {:s}", self.har_path.display().to_str()),
+        }
+    }
+}
+
+#[test]
+fn test_har_entry() {
+    use http::method::Post;
+
+    let mut load_data = LoadData::new(from_str("http://example.com/form?q=1").unwrap());
+    load_data.method = Post;
+    load_data.data = Some(~[1, 2, 3]);
+    let mut record = LoadRecord::new(&load_data, precise_time_ns(), false);
+
+    let mut metadata = Metadata::default(from_str("http://example.com/done").unwrap());
+    metadata.content_type = Some((~"text", ~"html"));
+    metadata.redirect_chain = ~[(load_data.url.clone(), ::http::status::SeeOther)];
+    record.record_response(&metadata);
+    record.record_payload(10);
+    record.record_payload(5);
+    record.record_done(true);
+
+    let mut log = RequestLog::new(Path::new("unused.har"));
+    log.push(record);
+    let har = log.to_har().to_str();
+    assert!(json::from_str(har.as_slice()).is_ok());
+    assert!(har.contains("\"method\":\"POST\""));
+    assert!(har.contains("\"bodySize\":3"));
+    assert!(har.contains("\"size\":15"));
+    assert!(har.contains("\"mimeType\":\"text/html\""));
+    assert!(har.contains("\"_redirectChain\":[\"http://example.com/form?q=1\"]"));
+}
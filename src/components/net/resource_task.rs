@@ -4,24 +4,145 @@
 
 //! A task that takes a URL and streams back the binary data.
 
+use about_loader;
+use connection_pool::ConnectionPool;
+use cookie::{CookieSource, CookieStorage};
 use file_loader;
+use http_cache;
+use http_cache::{CachedResponse, HttpCache, Fresh, Stale, Miss};
 use http_loader;
+use network_archive::{ArchivedResponse, NetworkArchive, Record, Replay, archive_key};
+use request_log::{LoadRecord, RequestLog};
 use data_loader;
+use sniffer;
 
+use std::ascii::StrAsciiExt;
 use std::comm::{Chan, Port, SharedChan};
+use std::hashmap::HashMap;
+use std::io::net::tcp::TcpStream;
+use std::sync::arc::UnsafeArc;
+use std::sync::atomics::{AtomicBool, SeqCst};
+use extra::time::precise_time_ns;
 use extra::url::Url;
-use util::spawn_listener;
+use servo_util::task::spawn_named;
+use servo_util::time::{ProfilerChan, TimeMsg, NetworkCacheHitCategory};
 use http::headers::content_type::MediaType;
+use http::headers::request::HeaderCollection;
+use ResponseHeaderCollection = http::headers::response::HeaderCollection;
+use http::status;
+use http::status::Status;
+use http::method::{Method, Get};
 
 #[cfg(test)]
 use std::from_str::FromStr;
+#[cfg(test)]
+use servo_util::time::Profiler;
+#[cfg(test)]
+use std::vec;
+#[cfg(test)]
+use std::sync::atomics::AtomicUint;
+
+/// The most loads that may be in progress for one host at a time. Loads beyond this wait in a
+/// queue, ordered by priority.
+static MAX_LOADS_PER_HOST: uint = 6;
 
 pub enum ControlMsg {
     /// Request the data associated with a particular URL
-    Load(Url, Chan<LoadResponse>),
+    Load(LoadData, Chan<LoadResponse>),
+    /// Store the cookies from a `Set-Cookie` header value received from a URL
+    SetCookiesForUrl(Url, ~str, CookieSource),
+    /// Retrieve the `Cookie` header value to send to a URL, if there are any matching cookies
+    GetCookiesForUrl(Url, Chan<Option<~str>>, CookieSource),
+    /// Store a response in the HTTP cache
+    StoreCachedResponse(CachedResponse),
+    /// Keep a response that was read back from the disk cache in memory as well
+    RememberCachedResponse(CachedResponse),
+    /// Install a loader for a URL scheme, replacing any existing loader for it
+    RegisterLoader(~str, LoaderTaskFactory),
+    /// Sent by the resource task to itself when a load from the given host is over, freeing
+    /// its slot for a queued load
+    LoadFinished(~str),
+    /// Take an idle keep-alive connection to an address, if there is one
+    CheckoutConnection(~str, Chan<Option<TcpStream>>),
+    /// Keep a connection to an address open for reuse by later loads
+    ReturnConnection(~str, TcpStream),
+    /// Add a finished load to the request log
+    LogLoad(LoadRecord),
     Exit
 }
 
+/// A description of a request to be made by a loader: the URL plus everything beyond it that
+/// goes on the wire.
+#[deriving(Clone)]
+pub struct LoadData {
+    url: Url,
+    /// The request method, e.g. `GET` or `POST`.
+    method: Method,
+    /// Additional request headers. Loaders fill in anything they need (`Host`,
+    /// `Content-Length`) themselves.
+    headers: HeaderCollection,
+    /// The request body, if any.
+    data: Option<~[u8]>,
+    /// The URL of the document that issued this request.
+    referrer: Option<Url>,
+    /// How urgently the load is needed, if it has to wait for a busy host.
+    priority: LoadPriority,
+    /// Stops the load. The same handle is returned in the `LoadResponse`, but a consumer can
+    /// keep a clone of this one to cancel a load that hasn't started yet.
+    cancellation: CancellationHandle,
+}
+
+impl LoadData {
+    /// A plain `GET` of `url` with no extra headers, body or referrer.
+    pub fn new(url: Url) -> LoadData {
+        LoadData {
+            url:      url,
+            method:   Get,
+            headers:  HeaderCollection::new(),
+            data:     None,
+            referrer: None,
+            priority: SubresourcePriority,
+            cancellation: CancellationHandle::new(),
+        }
+    }
+}
+
+/// The order in which queued loads are started, lowest first.
+#[deriving(Eq, Ord, Clone)]
+pub enum LoadPriority {
+    ImagePriority,
+    /// Style sheets, scripts and anything else a document needs before it can be shown.
+    SubresourcePriority,
+    DocumentPriority,
+}
+
+/// A shared flag that tells a loader its consumer has lost interest in a load. Loaders check
+/// it between chunks of data and finish with `Done(Err(Cancelled))` once it is set.
+#[deriving(Clone)]
+pub struct CancellationHandle {
+    priv cancelled: UnsafeArc<AtomicBool>,
+}
+
+impl CancellationHandle {
+    pub fn new() -> CancellationHandle {
+        CancellationHandle {
+            cancelled: UnsafeArc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        unsafe {
+            (*self.cancelled.get()).store(true, SeqCst);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        unsafe {
+            (*self.cancelled.get()).load(SeqCst)
+        }
+    }
+}
+
 /// Metadata about a loaded resource, such as is obtained from HTTP headers.
 pub struct Metadata {
     /// Final URL after redirects.
@@ -32,6 +153,15 @@ pub struct Metadata {
 
     /// Character set.
     charset: Option<~str>,
+
+    /// Headers of the response, if it came from a protocol that has them.
+    headers: Option<ResponseHeaderCollection>,
+
+    /// HTTP status, or `Ok` for protocols without one.
+    status: Status,
+
+    /// Each URL that answered with a redirect, and its status, in the order they were followed.
+    redirect_chain: ~[(Url, Status)],
 }
 
 impl Metadata {
@@ -41,6 +171,9 @@ impl Metadata {
             final_url:    url,
             content_type: None,
             charset:      None,
+            headers:      None,
+            status:       status::Ok,
+            redirect_chain: ~[],
         }
     }
 
@@ -72,6 +205,8 @@ pub struct LoadResponse {
     metadata: Metadata,
     /// Port for reading data.
     progress_port: Port<ProgressMsg>,
+    /// Stops the load. Dropping `progress_port` has the same effect.
+    cancellation: CancellationHandle,
 }
 
 /// Messages sent in response to a `Load` message
@@ -80,32 +215,74 @@ pub enum ProgressMsg {
     /// Binary data - there may be multiple of these
     Payload(~[u8]),
     /// Indicates loading is complete, either successfully or not
-    Done(Result<(), ()>)
+    Done(Result<(), LoadError>)
+}
+
+/// Why a load failed.
+#[deriving(Eq, Clone)]
+pub enum LoadError {
+    /// The resource couldn't be fetched, e.g. because its host name didn't resolve, the
+    /// connection broke off or the file doesn't exist.
+    NetworkError(~str),
+    /// The server answered with this error status. Loads themselves succeed with error
+    /// statuses, since browsers show the error pages that come with them; consumers that
+    /// can't use such a response report it with this.
+    HttpStatusError(u16),
+    /// No loader handles this URL scheme.
+    UnsupportedScheme(~str),
+    /// The URL can't be loaded as written, e.g. a `data:` URL without a comma.
+    InvalidUrl(~str),
+    /// The consumer cancelled the load.
+    Cancelled,
+    /// The body couldn't be decoded, e.g. invalid base64 or a corrupt gzip stream.
+    DecodeError(~str),
+}
+
+impl ToStr for LoadError {
+    fn to_str(&self) -> ~str {
+        match *self {
+            NetworkError(ref reason) => format!("Network error: {:s}", *reason),
+            HttpStatusError(code) => format!("The server responded with status {:u}", code),
+            UnsupportedScheme(ref scheme) => format!("Unsupported URL scheme: {:s}", *scheme),
+            InvalidUrl(ref reason) => format!("Invalid URL: {:s}", *reason),
+            Cancelled => ~"The load was cancelled",
+            DecodeError(ref reason) => format!("Couldn't decode the response: {:s}", *reason),
+        }
+    }
 }
 
-/// For use by loaders in responding to a Load message.
+/// For use by loaders in responding to a Load message. The resource task fills in the
+/// response's cancellation handle before it reaches the consumer.
 pub fn start_sending(start_chan: Chan<LoadResponse>,
                      metadata:   Metadata) -> SharedChan<ProgressMsg> {
     let (progress_port, progress_chan) = SharedChan::new();
     start_chan.send(LoadResponse {
         metadata:      metadata,
         progress_port: progress_port,
+        cancellation:  CancellationHandle::new(),
     });
     progress_chan
 }
 
-/// Convenience function for synchronously loading a whole resource.
+/// Convenience function for synchronously loading a whole resource. Responses with error
+/// statuses count as failures.
 pub fn load_whole_resource(resource_task: &ResourceTask, url: Url)
-        -> Result<(Metadata, ~[u8]), ()> {
+        -> Result<(Metadata, ~[u8]), LoadError> {
     let (start_port, start_chan) = Chan::new();
-    resource_task.send(Load(url, start_chan));
+    resource_task.send(Load(LoadData::new(url), start_chan));
     let response = start_port.recv();
 
     let mut buf = ~[];
     loop {
         match response.progress_port.recv() {
             Payload(data) => buf.push_all(data),
-            Done(Ok(()))  => return Ok((response.metadata, buf)),
+            Done(Ok(()))  => {
+                let code = response.metadata.status.code();
+                if code >= 400 {
+                    return Err(HttpStatusError(code));
+                }
+                return Ok((response.metadata, buf));
+            }
             Done(Err(e))  => return Err(e)
         }
     }
@@ -114,83 +291,416 @@ pub fn load_whole_resource(resource_task: &ResourceTask, url: Url)
 /// Handle to a resource task
 pub type ResourceTask = SharedChan<ControlMsg>;
 
-pub type LoaderTask = proc(url: Url, Chan<LoadResponse>);
+pub type LoaderTask = proc(load_data: LoadData, Chan<LoadResponse>);
 
 /**
 Creates a task to load a specific resource
 
 The ResourceManager delegates loading to a different type of loader task for
-each URL scheme
+each URL scheme. Loaders are handed the resource task itself so that they can
+read and update shared state such as cookies.
+
+Embedders can install factories for their own schemes with `register_loader`.
+Factories can keep state that every load of their scheme shares.
 */
-type LoaderTaskFactory = extern "Rust" fn() -> LoaderTask;
+pub trait LoaderFactory {
+    fn create(&self, resource_task: ResourceTask) -> LoaderTask;
+}
+
+pub type LoaderTaskFactory = ~LoaderFactory:Send;
+
+/// A `LoaderFactory` for loaders that need no state beyond the resource task.
+pub struct LoaderFn(extern "Rust" fn(resource_task: ResourceTask) -> LoaderTask);
+
+impl LoaderFactory for LoaderFn {
+    fn create(&self, resource_task: ResourceTask) -> LoaderTask {
+        let LoaderFn(factory) = *self;
+        factory(resource_task)
+    }
+}
+
+/// Wraps a plain factory function, for the loaders that don't need a `LoaderFactory` of their
+/// own.
+pub fn loader_fn(factory: extern "Rust" fn(resource_task: ResourceTask) -> LoaderTask)
+                 -> LoaderTaskFactory {
+    ~LoaderFn(factory) as LoaderTaskFactory
+}
 
-/// Create a ResourceTask with the default loaders
-pub fn ResourceTask() -> ResourceTask {
+/// Create a ResourceTask with the default loaders. HTTP responses are cached in memory and, if
+/// `http_cache_dir` is given, on disk in that directory. If `har_path` is given, every load is
+/// logged and the log is written there as a HAR file when the task exits. If `network_archive`
+/// is given, responses are recorded into it or replayed from it.
+pub fn ResourceTask(profiler_chan: ProfilerChan,
+                    http_cache_dir: Option<Path>,
+                    har_path: Option<Path>,
+                    network_archive: Option<NetworkArchive>) -> ResourceTask {
     let loaders = ~[
-        (~"file", file_loader::factory),
-        (~"http", http_loader::factory),
-        (~"data", data_loader::factory),
+        (~"file", loader_fn(file_loader::factory)),
+        (~"http", loader_fn(http_loader::factory)),
+        (~"data", loader_fn(data_loader::factory)),
+        (~"about", about_loader::factory(profiler_chan.clone())),
     ];
-    create_resource_task_with_loaders(loaders)
+    create_resource_task_with_loaders(loaders, profiler_chan, http_cache_dir, har_path,
+                                      network_archive)
 }
 
-fn create_resource_task_with_loaders(loaders: ~[(~str, LoaderTaskFactory)]) -> ResourceTask {
-    let chan = spawn_listener("ResourceManager", proc(from_client) {
-        // TODO: change copy to move once we can move out of closures
-        ResourceManager(from_client, loaders).start()
+/// Install `factory` as the loader for URLs with the given scheme, such as `app` for bundled
+/// assets. This replaces any existing loader for the scheme, including the built-in ones.
+pub fn register_loader(resource_task: &ResourceTask, scheme: &str, factory: LoaderTaskFactory) {
+    resource_task.send(RegisterLoader(scheme.to_ascii_lower(), factory));
+}
+
+fn create_resource_task_with_loaders(loaders: ~[(~str, LoaderTaskFactory)],
+                                     profiler_chan: ProfilerChan,
+                                     http_cache_dir: Option<Path>,
+                                     har_path: Option<Path>,
+                                     network_archive: Option<NetworkArchive>) -> ResourceTask {
+    let (setup_port, setup_chan) = Chan::new();
+    spawn_named("ResourceManager", proc() {
+        let (from_client, to_self) = SharedChan::new();
+        setup_chan.send(to_self.clone());
+        ResourceManager(from_client, to_self, loaders, profiler_chan, http_cache_dir,
+                        har_path, network_archive).start()
     });
-    chan
+    setup_port.recv()
+}
+
+/// Returns a channel that forwards a response to `start_chan` with `cancellation` attached, and
+/// tells the resource task once the load is over. A consumer that drops its port cancels the
+/// load, so that loaders don't keep streaming into a page that has gone away. If the load is
+/// being logged, `record` is filled in and sent back to the resource task at the end. If it is
+/// being recorded, the complete response is stored in the network archive under the given key
+/// before the consumer hears that the load is done.
+fn new_tracking_task(start_chan: Chan<LoadResponse>,
+                     cancellation: CancellationHandle,
+                     host: ~str,
+                     mut record: Option<LoadRecord>,
+                     recording: Option<(NetworkArchive, ~str)>,
+                     resource_task: ResourceTask) -> Chan<LoadResponse> {
+    let (tracker_port, tracker_chan) = Chan::new();
+    spawn_named("load_tracker", proc() {
+        match tracker_port.recv_opt() {
+            Some(response) => {
+                let LoadResponse { metadata: metadata, progress_port: loader_port, .. } = response;
+                match record {
+                    Some(ref mut record) => record.record_response(&metadata),
+                    None => (),
+                }
+                let mut archived = recording.map(|(archive, key)| {
+                    (archive, ArchivedResponse::new(key, &metadata))
+                });
+                let (progress_port, progress_chan) = Chan::new();
+                let forwarded = start_chan.try_send(LoadResponse {
+                    metadata:      metadata,
+                    progress_port: progress_port,
+                    cancellation:  cancellation.clone(),
+                });
+                if !forwarded {
+                    cancellation.cancel();
+                }
+                loop {
+                    match loader_port.recv_opt() {
+                        Some(Payload(data)) => {
+                            match record {
+                                Some(ref mut record) => record.record_payload(data.len()),
+                                None => (),
+                            }
+                            match archived {
+                                Some((_, ref mut archived)) => archived.body.push_all(data),
+                                None => (),
+                            }
+                            if !cancellation.is_cancelled() &&
+                                    !progress_chan.try_send(Payload(data)) {
+                                cancellation.cancel();
+                            }
+                        }
+                        Some(Done(result)) => {
+                            match record {
+                                Some(ref mut record) => record.record_done(result.is_ok()),
+                                None => (),
+                            }
+                            if result.is_ok() {
+                                match archived.take() {
+                                    Some((archive, archived)) => archive.store(&archived),
+                                    None => (),
+                                }
+                            }
+                            progress_chan.try_send(Done(result));
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            None => (),
+        }
+        match record {
+            Some(record) => {
+                resource_task.try_send(LogLoad(record));
+            }
+            None => (),
+        }
+        resource_task.try_send(LoadFinished(host));
+    });
+    tracker_chan
 }
 
 pub struct ResourceManager {
     from_client: Port<ControlMsg>,
+    /// A handle to this resource task, passed on to loaders.
+    resource_task: ResourceTask,
     /// Per-scheme resource loaders
     loaders: ~[(~str, LoaderTaskFactory)],
+    /// Cookies shared by every pipeline that uses this resource task.
+    cookie_storage: CookieStorage,
+    /// Responses that can be reused instead of going to the network again.
+    http_cache: HttpCache,
+    /// Receives cache hit, miss and revalidation timings.
+    profiler_chan: ProfilerChan,
+    /// Loads waiting for their host to have a free slot, in the order they arrived, with the
+    /// time they arrived.
+    pending_loads: ~[(LoadData, Chan<LoadResponse>, u64)],
+    /// The number of loads in progress for each host.
+    loads_per_host: HashMap<~str, uint>,
+    /// Idle HTTP connections that loaders can reuse.
+    connection_pool: ConnectionPool,
+    /// Every load made so far, if a HAR file was asked for.
+    request_log: Option<RequestLog>,
+    /// Where responses are recorded to or replayed from, if anywhere.
+    network_archive: Option<NetworkArchive>,
 }
 
 
 pub fn ResourceManager(from_client: Port<ControlMsg>, 
-                       loaders: ~[(~str, LoaderTaskFactory)]) -> ResourceManager {
+                       resource_task: ResourceTask,
+                       loaders: ~[(~str, LoaderTaskFactory)],
+                       profiler_chan: ProfilerChan,
+                       http_cache_dir: Option<Path>,
+                       har_path: Option<Path>,
+                       network_archive: Option<NetworkArchive>) -> ResourceManager {
     ResourceManager {
         from_client : from_client,
+        resource_task : resource_task,
         loaders : loaders,
+        cookie_storage : CookieStorage::new(),
+        http_cache : HttpCache::new(http_cache_dir),
+        profiler_chan : profiler_chan,
+        pending_loads : ~[],
+        loads_per_host : HashMap::new(),
+        connection_pool : ConnectionPool::new(),
+        request_log : har_path.map(|path| RequestLog::new(path)),
+        network_archive : network_archive,
     }
 }
 
 
 impl ResourceManager {
-    fn start(&self) {
+    fn start(&mut self) {
         loop {
             match self.from_client.recv() {
-              Load(url, start_chan) => {
-                self.load(url.clone(), start_chan)
+              Load(load_data, start_chan) => {
+                self.load(load_data, start_chan)
+              }
+              SetCookiesForUrl(url, header, source) => {
+                self.cookie_storage.set_cookies_for_url(&url, header, source)
+              }
+              GetCookiesForUrl(url, consumer, source) => {
+                consumer.send(self.cookie_storage.cookies_for_url(&url, source))
+              }
+              StoreCachedResponse(entry) => {
+                self.http_cache.store(entry)
+              }
+              RememberCachedResponse(entry) => {
+                self.http_cache.insert(entry)
+              }
+              RegisterLoader(scheme, factory) => {
+                self.register_loader(scheme, factory)
+              }
+              LoadFinished(host) => {
+                self.finish_load(host)
+              }
+              CheckoutConnection(address, consumer) => {
+                consumer.send(self.connection_pool.checkout(&address, precise_time_ns()))
+              }
+              ReturnConnection(address, stream) => {
+                self.connection_pool.checkin(address, stream, precise_time_ns())
+              }
+              LogLoad(record) => {
+                match self.request_log {
+                    Some(ref mut log) => log.push(record),
+                    None => (),
+                }
               }
               Exit => {
+                match self.request_log {
+                    Some(ref log) => log.write_har(),
+                    None => (),
+                }
                 break
               }
             }
         }
     }
 
-    fn load(&self, url: Url, start_chan: Chan<LoadResponse>) {
-        match self.get_loader_factory(&url) {
-            Some(loader_factory) => {
-                debug!("resource_task: loading url: {:s}", url.to_str());
-                loader_factory(url, start_chan);
+    fn load(&mut self, load_data: LoadData, start_chan: Chan<LoadResponse>) {
+        let requested_at = precise_time_ns();
+        if !self.has_free_slot(&load_data) {
+            debug!("resource_task: queueing load of {:s}", load_data.url.to_str());
+            self.pending_loads.push((load_data, start_chan, requested_at));
+            return;
+        }
+        self.start_load(load_data, start_chan, requested_at);
+    }
+
+    /// Whether the host of `load_data` can take another load. Images may not use the last
+    /// slot, so that a page full of images can't hold up its own style sheets and scripts.
+    fn has_free_slot(&self, load_data: &LoadData) -> bool {
+        let host = &load_data.url.host;
+        if host.is_empty() {
+            return true;
+        }
+        let limit = match load_data.priority {
+            ImagePriority => MAX_LOADS_PER_HOST - 1,
+            SubresourcePriority | DocumentPriority => MAX_LOADS_PER_HOST,
+        };
+        self.loads_per_host.find(host).map_default(0, |&count| count) < limit
+    }
+
+    fn finish_load(&mut self, host: ~str) {
+        let remaining = match self.loads_per_host.find_mut(&host) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            self.loads_per_host.remove(&host);
+        }
+
+        // Start the most urgent queued loads that now fit, oldest first within a priority.
+        loop {
+            let mut next: Option<uint> = None;
+            for (i, &(ref load_data, _, _)) in self.pending_loads.iter().enumerate() {
+                let better = next.map_default(true, |j| {
+                    let (ref best, _, _) = self.pending_loads[j];
+                    load_data.priority > best.priority
+                });
+                if better && self.has_free_slot(load_data) {
+                    next = Some(i);
+                }
             }
+            match next {
+                Some(i) => {
+                    let (load_data, start_chan, requested_at) = self.pending_loads.remove(i);
+                    self.start_load(load_data, start_chan, requested_at);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn start_load(&mut self, load_data: LoadData, start_chan: Chan<LoadResponse>,
+                  requested_at: u64) {
+        if load_data.cancellation.is_cancelled() {
+            debug!("resource_task: load of {:s} was cancelled", load_data.url.to_str());
+            start_sending(start_chan, Metadata::default(load_data.url))
+                .send(Done(Err(Cancelled)));
+            return;
+        }
+
+        // The cache is bypassed while recording, so that the archive gets complete responses.
+        let archive_mode = if NetworkArchive::handles(&load_data) {
+            self.network_archive.as_ref().map(|archive| archive.mode)
+        } else {
+            None
+        };
+        let cacheable = archive_mode.is_none() && HttpCache::is_cacheable(&load_data);
+        let lookup = if cacheable { self.http_cache.lookup(&load_data) } else { Miss };
+        let record = self.request_log.as_ref().map(|_| {
+            let from_cache = match lookup { Fresh(..) => true, _ => false };
+            LoadRecord::new(&load_data, requested_at, from_cache)
+        });
+        let recording = match archive_mode {
+            Some(Record) => {
+                Some((self.network_archive.get_ref().clone(), archive_key(&load_data)))
+            }
+            _ => None,
+        };
+
+        let host = load_data.url.host.clone();
+        if !host.is_empty() {
+            self.loads_per_host.insert_or_update_with(host.clone(), 1, |_, count| *count += 1);
+        }
+        let start_chan = new_tracking_task(start_chan, load_data.cancellation.clone(), host,
+                                           record, recording, self.resource_task.clone());
+        let start_chan = sniffer::new_sniffer_task(start_chan);
+
+        if archive_mode == Some(Replay) {
+            match self.network_archive.get_ref().lookup(&load_data) {
+                Some(entry) => entry.send(start_chan),
+                None => {
+                    let key = archive_key(&load_data);
+                    error!("resource_task: replaying, but {:s} was never recorded", key);
+                    let error = NetworkError(format!("{:s} is not in the network archive", key));
+                    start_sending(start_chan, Metadata::default(load_data.url))
+                        .send(Done(Err(error)));
+                }
+            }
+            return;
+        }
+        let loader = match self.get_loader_factory(&load_data.url) {
+            Some(loader_factory) => loader_factory,
             None => {
-                debug!("resource_task: no loader for scheme {:s}", url.scheme);
-                start_sending(start_chan, Metadata::default(url)).send(Done(Err(())));
+                debug!("resource_task: no loader for scheme {:s}", load_data.url.scheme);
+                let error = UnsupportedScheme(load_data.url.scheme.clone());
+                start_sending(start_chan, Metadata::default(load_data.url))
+                    .send(Done(Err(error)));
+                return;
             }
+        };
+        debug!("resource_task: loading url: {:s}", load_data.url.to_str());
+
+        if !cacheable {
+            loader(load_data, start_chan);
+            return;
         }
+
+        let stale = match lookup {
+            Fresh(entry) => {
+                debug!("resource_task: cache hit for {:s}", load_data.url.to_str());
+                let start_time = precise_time_ns();
+                entry.send(start_chan);
+                let ms = (precise_time_ns() - start_time) as f64 / 1000000f64;
+                self.profiler_chan.send(TimeMsg(NetworkCacheHitCategory, ms));
+                return;
+            }
+            Stale(entry) => Some(entry),
+            Miss => None,
+        };
+
+        let disk = self.http_cache.disk();
+        let resource_task = self.resource_task.clone();
+        let profiler_chan = self.profiler_chan.clone();
+        spawn_named("http_cache", proc() {
+            http_cache::fill(load_data, stale, disk, loader, start_chan, resource_task,
+                             profiler_chan)
+        });
+    }
+
+    fn register_loader(&mut self, scheme: ~str, factory: LoaderTaskFactory) {
+        debug!("resource_task: registering loader for scheme {:s}", scheme);
+        self.loaders.retain(|&(ref existing, _)| *existing != scheme);
+        self.loaders.push((scheme, factory));
     }
 
     fn get_loader_factory(&self, url: &Url) -> Option<LoaderTask> {
+        let url_scheme = url.scheme.to_ascii_lower();
         for scheme_loader in self.loaders.iter() {
             match *scheme_loader {
                 (ref scheme, ref loader_factory) => {
-	            if (*scheme) == url.scheme {
-                        return Some((*loader_factory)());
+	            if (*scheme) == url_scheme {
+                        return Some(loader_factory.create(self.resource_task.clone()));
                     }
 	        }
             }
@@ -201,18 +711,19 @@ impl ResourceManager {
 
 #[test]
 fn test_exit() {
-    let resource_task = ResourceTask();
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
     resource_task.send(Exit);
 }
 
 #[test]
 fn test_bad_scheme() {
-    let resource_task = ResourceTask();
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
     let (start, start_chan) = Chan::new();
-    resource_task.send(Load(FromStr::from_str("bogus://whatever").unwrap(), start_chan));
+    let url = FromStr::from_str("bogus://whatever").unwrap();
+    resource_task.send(Load(LoadData::new(url), start_chan));
     let response = start.recv();
     match response.progress_port.recv() {
-      Done(result) => { assert!(result.is_err()) }
+      Done(result) => { assert!(result == Err(UnsupportedScheme(~"bogus"))) }
       _ => fail!("bleh")
     }
     resource_task.send(Exit);
@@ -222,9 +733,9 @@ fn test_bad_scheme() {
 static snicklefritz_payload: [u8, ..3] = [1, 2, 3];
 
 #[cfg(test)]
-fn snicklefritz_loader_factory() -> LoaderTask {
-    let f: LoaderTask = proc(url: Url, start_chan: Chan<LoadResponse>) {
-        let progress_chan = start_sending(start_chan, Metadata::default(url));
+fn snicklefritz_loader_factory(_resource_task: ResourceTask) -> LoaderTask {
+    let f: LoaderTask = proc(load_data: LoadData, start_chan: Chan<LoadResponse>) {
+        let progress_chan = start_sending(start_chan, Metadata::default(load_data.url));
         progress_chan.send(Payload(snicklefritz_payload.into_owned()));
         progress_chan.send(Done(Ok(())));
     };
@@ -233,15 +744,213 @@ fn snicklefritz_loader_factory() -> LoaderTask {
 
 #[test]
 fn should_delegate_to_scheme_loader() {
-    let loader_factories = ~[(~"snicklefritz", snicklefritz_loader_factory)];
-    let resource_task = create_resource_task_with_loaders(loader_factories);
+    let loader_factories = ~[(~"snicklefritz", loader_fn(snicklefritz_loader_factory))];
+    let resource_task = create_resource_task_with_loaders(loader_factories,
+                                                          Profiler::create(None), None, None,
+                                                          None);
     let (start, start_chan) = Chan::new();
-    resource_task.send(Load(FromStr::from_str("snicklefritz://heya").unwrap(), start_chan));
+    let url = FromStr::from_str("snicklefritz://heya").unwrap();
+    resource_task.send(Load(LoadData::new(url), start_chan));
 
     let response = start.recv();
     let progress = response.progress_port;
 
+    // The loader didn't supply a type, so it was sniffed from the payload.
+    assert_eq!(response.metadata.content_type, Some((~"application", ~"octet-stream")));
     assert!(progress.recv() == Payload(snicklefritz_payload.into_owned()));
     assert!(progress.recv() == Done(Ok(())));
     resource_task.send(Exit);
 }
+
+/// Holds each load open until the test lets it finish through `HangingLoads`. The payload is
+/// large enough to get past the sniffer, so the consumer receives the response straight away.
+#[cfg(test)]
+struct HangingLoaderFactory {
+    /// Takes the URL path of each load and the channel that releases it.
+    releases: SharedChan<(~str, Chan<()>)>,
+}
+
+#[cfg(test)]
+impl LoaderFactory for HangingLoaderFactory {
+    fn create(&self, _resource_task: ResourceTask) -> LoaderTask {
+        let releases = self.releases.clone();
+        proc(load_data: LoadData, start_chan: Chan<LoadResponse>) {
+            spawn_named("hanging_loader", proc() {
+                let (release_port, release_chan) = Chan::new();
+                releases.send((load_data.url.path.clone(), release_chan));
+                let progress_chan = start_sending(start_chan, Metadata::default(load_data.url));
+                progress_chan.send(Payload(vec::from_elem(2048, 0u8)));
+                release_port.recv();
+                progress_chan.send(Done(Err(Cancelled)));
+            })
+        }
+    }
+}
+
+/// The loads a `HangingLoaderFactory` is holding open.
+#[cfg(test)]
+struct HangingLoads {
+    port: Port<(~str, Chan<()>)>,
+    releases: HashMap<~str, Chan<()>>,
+}
+
+#[cfg(test)]
+impl HangingLoads {
+    fn new() -> (HangingLoads, LoaderTaskFactory) {
+        let (port, chan) = SharedChan::new();
+        let loads = HangingLoads {
+            port: port,
+            releases: HashMap::new(),
+        };
+        (loads, ~HangingLoaderFactory { releases: chan } as LoaderTaskFactory)
+    }
+
+    /// Cancels a load and lets its loader finish.
+    fn cancel(&mut self, response: &LoadResponse) {
+        response.cancellation.cancel();
+        let path = response.metadata.final_url.path.clone();
+        while !self.releases.contains_key(&path) {
+            let (path, release_chan) = self.port.recv();
+            self.releases.insert(path, release_chan);
+        }
+        self.releases.pop(&path).unwrap().send(());
+    }
+}
+
+#[cfg(test)]
+fn start_hanging_load(resource_task: &ResourceTask, path: &str, priority: LoadPriority)
+                      -> Port<LoadResponse> {
+    let (start, start_chan) = Chan::new();
+    let url = format!("hang://example.com/{:s}", path);
+    let mut load_data = LoadData::new(FromStr::from_str(url).unwrap());
+    load_data.priority = priority;
+    resource_task.send(Load(load_data, start_chan));
+    start
+}
+
+#[test]
+fn should_cancel_load() {
+    let (mut loads, factory) = HangingLoads::new();
+    let resource_task = create_resource_task_with_loaders(~[(~"hang", factory)],
+                                                          Profiler::create(None), None, None,
+                                                          None);
+    let response = start_hanging_load(&resource_task, "a", SubresourcePriority).recv();
+    assert!(response.progress_port.recv() == Payload(vec::from_elem(2048, 0u8)));
+    loads.cancel(&response);
+    assert!(response.progress_port.recv() == Done(Err(Cancelled)));
+    resource_task.send(Exit);
+}
+
+#[test]
+fn should_limit_loads_per_host() {
+    let (mut loads, factory) = HangingLoads::new();
+    let resource_task = create_resource_task_with_loaders(~[(~"hang", factory)],
+                                                          Profiler::create(None), None, None,
+                                                          None);
+    let running = vec::from_fn(MAX_LOADS_PER_HOST, |i| {
+        start_hanging_load(&resource_task, i.to_str().as_slice(), SubresourcePriority).recv()
+    });
+
+    // The host is full, so these wait, and the document jumps ahead of the image.
+    let image = start_hanging_load(&resource_task, "image", ImagePriority);
+    let document = start_hanging_load(&resource_task, "document", DocumentPriority);
+    assert!(image.try_recv().is_none());
+    assert!(document.try_recv().is_none());
+
+    loads.cancel(&running[0]);
+    let document = document.recv();
+    assert!(image.try_recv().is_none());
+
+    // Images can't take the last slot, so one more load has to finish before it starts.
+    loads.cancel(&running[1]);
+    assert!(image.try_recv().is_none());
+    loads.cancel(&document);
+    image.recv();
+    resource_task.send(Exit);
+}
+
+#[test]
+fn should_replay_recorded_loads() {
+    use extra::tempfile::TempDir;
+
+    let dir = TempDir::new("resource_task").unwrap();
+    let archive = NetworkArchive::new(Record, dir.path().clone());
+    let loader_factories = ~[(~"snicklefritz", loader_fn(snicklefritz_loader_factory))];
+    let resource_task = create_resource_task_with_loaders(loader_factories,
+                                                          Profiler::create(None), None, None,
+                                                          Some(archive));
+    let url: Url = FromStr::from_str("snicklefritz://heya").unwrap();
+    // The response is archived before the load is done.
+    assert!(load_whole_resource(&resource_task, url.clone()).is_ok());
+    resource_task.send(Exit);
+
+    // Replay without any loaders at all.
+    let archive = NetworkArchive::new(Replay, dir.path().clone());
+    let resource_task = create_resource_task_with_loaders(~[], Profiler::create(None), None,
+                                                          None, Some(archive));
+    match load_whole_resource(&resource_task, url) {
+        Ok((_, body)) => assert_eq!(body, snicklefritz_payload.into_owned()),
+        Err(..) => fail!("recorded load wasn't replayed"),
+    }
+    let unrecorded = FromStr::from_str("snicklefritz://other").unwrap();
+    assert!(load_whole_resource(&resource_task, unrecorded).is_err());
+    resource_task.send(Exit);
+}
+
+#[test]
+fn should_delegate_to_registered_loader() {
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
+    register_loader(&resource_task, "Snicklefritz", loader_fn(snicklefritz_loader_factory));
+    let (start, start_chan) = Chan::new();
+    let url = FromStr::from_str("snicklefritz://heya").unwrap();
+    resource_task.send(Load(LoadData::new(url), start_chan));
+
+    let progress = start.recv().progress_port;
+    assert!(progress.recv() == Payload(snicklefritz_payload.into_owned()));
+    assert!(progress.recv() == Done(Ok(())));
+    resource_task.send(Exit);
+}
+
+/// Serves the same payload for every URL, and counts the loads it has made.
+#[cfg(test)]
+struct PayloadLoaderFactory {
+    payload: ~[u8],
+    loads: UnsafeArc<AtomicUint>,
+}
+
+#[cfg(test)]
+impl LoaderFactory for PayloadLoaderFactory {
+    fn create(&self, _resource_task: ResourceTask) -> LoaderTask {
+        unsafe {
+            (*self.loads.get()).fetch_add(1, SeqCst);
+        }
+        let payload = self.payload.clone();
+        proc(load_data: LoadData, start_chan: Chan<LoadResponse>) {
+            let progress_chan = start_sending(start_chan, Metadata::default(load_data.url));
+            progress_chan.send(Payload(payload));
+            progress_chan.send(Done(Ok(())));
+        }
+    }
+}
+
+#[test]
+fn should_keep_registered_loader_state() {
+    let loads = UnsafeArc::new(AtomicUint::new(0));
+    let factory = ~PayloadLoaderFactory {
+        payload: ~[4, 5, 6],
+        loads: loads.clone(),
+    };
+    let resource_task = ResourceTask(Profiler::create(None), None, None, None);
+    register_loader(&resource_task, "payload", factory as LoaderTaskFactory);
+    for _ in range(0, 2) {
+        let url = FromStr::from_str("payload://heya").unwrap();
+        match load_whole_resource(&resource_task, url) {
+            Ok((_, body)) => assert_eq!(body, ~[4, 5, 6]),
+            Err(..) => fail!("load from the registered loader failed"),
+        }
+    }
+    unsafe {
+        assert_eq!((*loads.get()).load(SeqCst), 2);
+    }
+    resource_task.send(Exit);
+}
@@ -0,0 +1,432 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! MIME type sniffing, following http://mimesniff.spec.whatwg.org/.
+//!
+//! Every load is routed through a sniffer task. When sniffing could change the supplied content
+//! type, it holds back the metadata until it has seen enough of the payload to decide on one;
+//! other responses are passed on as they arrive.
+
+use resource_task::{LoadResponse, Metadata, Payload, Done, start_sending};
+
+use std::ascii::StrAsciiExt;
+use http::headers::HeaderEnum;
+use servo_util::task::spawn_named;
+
+/// The number of bytes the sniffing algorithms look at.
+static RESOURCE_HEADER_SIZE: uint = 1445;
+
+pub type MimeType = (~str, ~str);
+
+/// Returns a channel on which a loader can send a response that will be forwarded to
+/// `start_chan` once its content type has been sniffed.
+pub fn new_sniffer_task(start_chan: Chan<LoadResponse>) -> Chan<LoadResponse> {
+    let (sniffer_port, sniffer_chan) = Chan::new();
+    spawn_named("sniffer", proc() {
+        let response: LoadResponse = sniffer_port.recv();
+        let LoadResponse { metadata: mut metadata, progress_port: progress_port, .. } = response;
+
+        let no_sniff = metadata.headers.as_ref().map_default(false, |headers| {
+            headers.iter().any(|header| {
+                header.header_name().eq_ignore_ascii_case("X-Content-Type-Options") &&
+                    header.header_value().trim().eq_ignore_ascii_case("nosniff")
+            })
+        });
+
+        let apache_bug = metadata.headers.as_ref().map_default(false, |headers| {
+            headers.iter().any(|header| {
+                header.header_name().eq_ignore_ascii_case("Content-Type") &&
+                    is_apache_bug_content_type(header.header_value())
+            })
+        });
+
+        // Buffer the resource header, if it is needed.
+        let header_size = if needs_sniffing(&metadata.content_type, no_sniff, apache_bug) {
+            RESOURCE_HEADER_SIZE
+        } else {
+            0
+        };
+        let mut header = ~[];
+        let mut done = None;
+        while header.len() < header_size {
+            match progress_port.recv() {
+                Payload(data) => header.push_all_move(data),
+                Done(result) => {
+                    done = Some(result);
+                    break;
+                }
+            }
+        }
+        metadata.content_type =
+            Some(sniff(&metadata.content_type, header.as_slice(), no_sniff, apache_bug));
+
+        let progress_chan = start_sending(start_chan, metadata);
+        if !header.is_empty() {
+            progress_chan.send(Payload(header));
+        }
+        match done {
+            Some(result) => progress_chan.send(Done(result)),
+            None => loop {
+                match progress_port.recv() {
+                    Payload(data) => progress_chan.send(Payload(data)),
+                    Done(result) => {
+                        progress_chan.send(Done(result));
+                        break;
+                    }
+                }
+            },
+        }
+    });
+    sniffer_chan
+}
+
+/// Whether a `Content-Type` header has one of the values that old versions of Apache sent for
+/// files of any type, so that a `text/plain` type given with it may well be wrong.
+fn is_apache_bug_content_type(value: &str) -> bool {
+    match value {
+        "text/plain" | "text/plain; charset=ISO-8859-1" | "text/plain; charset=iso-8859-1" |
+        "text/plain; charset=UTF-8" => true,
+        _ => false,
+    }
+}
+
+/// Whether sniffing the resource header may change the supplied type: when it is missing or
+/// ambiguous, or when it is one sniffing refines. Responses with other types are not held back
+/// for sniffing.
+fn needs_sniffing(supplied: &Option<MimeType>, no_sniff: bool, apache_bug: bool) -> bool {
+    match *supplied {
+        Some((ref type_, ref subtype)) => {
+            let (type_, subtype) = (type_.to_ascii_lower(), subtype.to_ascii_lower());
+            match (type_.as_slice(), subtype.as_slice()) {
+                ("unknown", "unknown") | ("application", "unknown") | ("*", "*") => true,
+                _ if no_sniff => false,
+                ("text", "plain") => apache_bug,
+                ("application", "octet-stream") | ("text", "html") | ("image", _) => true,
+                _ => false,
+            }
+        }
+        None => true,
+    }
+}
+
+/// Determines the computed MIME type of a resource from its supplied type and the first bytes
+/// of its body. `apache_bug` says whether the `Content-Type` header had one of the values that
+/// `is_apache_bug_content_type` accepts; a `text/plain` type is only checked for binary data
+/// then.
+pub fn sniff(supplied: &Option<MimeType>, data: &[u8], no_sniff: bool, apache_bug: bool)
+             -> MimeType {
+    let supplied = match *supplied {
+        Some((ref type_, ref subtype)) => {
+            let (type_, subtype) = (type_.to_ascii_lower(), subtype.to_ascii_lower());
+            match (type_.as_slice(), subtype.as_slice()) {
+                ("unknown", "unknown") | ("application", "unknown") | ("*", "*") => None,
+                _ => Some((type_, subtype)),
+            }
+        }
+        None => None,
+    };
+    let data = data.slice_to(::std::cmp::min(data.len(), RESOURCE_HEADER_SIZE));
+
+    match supplied {
+        None => sniff_unknown(data),
+        Some(supplied) => {
+            if no_sniff {
+                return supplied;
+            }
+            let sniffed = {
+                let (ref type_, ref subtype) = supplied;
+                match (type_.as_slice(), subtype.as_slice()) {
+                    ("text", "plain") if apache_bug => Some(sniff_text_or_binary(data)),
+                    // Only binary types, so that a download can't turn into a document.
+                    ("application", "octet-stream") => {
+                        sniff_image(data).or_else(|| sniff_media(data))
+                                         .or_else(|| sniff_archive(data))
+                    }
+                    ("text", "html") => sniff_feed(data),
+                    ("image", _) => sniff_image(data),
+                    _ => None,
+                }
+            };
+            sniffed.unwrap_or(supplied)
+        }
+    }
+}
+
+/// Guesses a MIME type from the extension of a file name, for resources that have no
+/// `Content-Type` of their own.
+pub fn guess_from_extension(path: &str) -> Option<MimeType> {
+    let file_name = match path.rfind('/') {
+        Some(index) => path.slice_from(index + 1),
+        None => path,
+    };
+    let extension = match file_name.rfind('.') {
+        Some(index) => file_name.slice_from(index + 1).to_ascii_lower(),
+        None => return None,
+    };
+    let (type_, subtype) = match extension.as_slice() {
+        "html" | "htm" | "xht" => ("text", "html"),
+        "xhtml" => ("application", "xhtml+xml"),
+        "xml" => ("text", "xml"),
+        "css" => ("text", "css"),
+        "js" => ("application", "javascript"),
+        "json" => ("application", "json"),
+        "txt" => ("text", "plain"),
+        "png" => ("image", "png"),
+        "gif" => ("image", "gif"),
+        "jpg" | "jpeg" => ("image", "jpeg"),
+        "bmp" => ("image", "bmp"),
+        "ico" => ("image", "x-icon"),
+        "webp" => ("image", "webp"),
+        "svg" => ("image", "svg+xml"),
+        "ttf" => ("application", "x-font-ttf"),
+        "otf" => ("application", "x-font-otf"),
+        "woff" => ("application", "font-woff"),
+        "pdf" => ("application", "pdf"),
+        _ => return None,
+    };
+    Some((type_.to_owned(), subtype.to_owned()))
+}
+
+fn mime(type_: &str, subtype: &str) -> MimeType {
+    (type_.to_owned(), subtype.to_owned())
+}
+
+/// Whether `data` starts with `pattern`, comparing only the bits set in `mask`.
+fn matches_masked(data: &[u8], pattern: &[u8], mask: &[u8]) -> bool {
+    data.len() >= pattern.len() &&
+        range(0, pattern.len()).all(|i| (data[i] & mask[i]) == pattern[i])
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    match byte {
+        0x09 | 0x0A | 0x0C | 0x0D | 0x20 => true,
+        _ => false,
+    }
+}
+
+fn is_binary(byte: u8) -> bool {
+    match byte {
+        0x00..0x08 | 0x0B | 0x0E..0x1A | 0x1C..0x1F => true,
+        _ => false,
+    }
+}
+
+fn to_lower_byte(byte: u8) -> u8 {
+    if byte >= 'A' as u8 && byte <= 'Z' as u8 { byte + 32 } else { byte }
+}
+
+/// Matches `tag` case-insensitively after leading whitespace, followed by a space or `>`.
+fn matches_html_tag(data: &[u8], tag: &str) -> bool {
+    let start = match data.iter().position(|&b| !is_whitespace(b)) {
+        Some(start) => start,
+        None => return false,
+    };
+    let data = data.slice_from(start);
+    let tag = tag.as_bytes();
+    data.len() > tag.len() &&
+        range(0, tag.len()).all(|i| to_lower_byte(data[i]) == tag[i]) &&
+        (data[tag.len()] == 0x20 || data[tag.len()] == 0x3E)
+}
+
+fn sniff_unknown(data: &[u8]) -> MimeType {
+    let html_tags = ["<!doctype html", "<html", "<head", "<script", "<iframe", "<h1", "<div",
+                     "<font", "<table", "<a", "<style", "<title", "<b", "<body", "<br", "<p",
+                     "<!--"];
+    if html_tags.iter().any(|tag| matches_html_tag(data, *tag)) {
+        return mime("text", "html");
+    }
+
+    let start = data.iter().position(|&b| !is_whitespace(b)).unwrap_or(data.len());
+    if data.slice_from(start).starts_with(bytes!("<?xml")) {
+        return mime("text", "xml");
+    }
+    if data.starts_with(bytes!("%PDF-")) {
+        return mime("application", "pdf");
+    }
+    if data.starts_with(bytes!("%!PS-Adobe-")) {
+        return mime("application", "postscript");
+    }
+    match sniff_image(data).or_else(|| sniff_media(data)).or_else(|| sniff_archive(data)) {
+        Some(mime_type) => mime_type,
+        None => sniff_text_or_binary(data),
+    }
+}
+
+fn sniff_text_or_binary(data: &[u8]) -> MimeType {
+    if data.starts_with(&[0xFE, 0xFF]) || data.starts_with(&[0xFF, 0xFE]) ||
+            data.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return mime("text", "plain");
+    }
+    if data.iter().any(|&b| is_binary(b)) {
+        mime("application", "octet-stream")
+    } else {
+        mime("text", "plain")
+    }
+}
+
+/// Recognizes the image formats we decode by their signatures.
+pub fn sniff_image(data: &[u8]) -> Option<MimeType> {
+    if data.starts_with(&[0x00, 0x00, 0x01, 0x00]) || data.starts_with(&[0x00, 0x00, 0x02, 0x00]) {
+        Some(mime("image", "x-icon"))
+    } else if data.starts_with(bytes!("BM")) {
+        Some(mime("image", "bmp"))
+    } else if data.starts_with(bytes!("GIF87a")) || data.starts_with(bytes!("GIF89a")) {
+        Some(mime("image", "gif"))
+    } else if matches_masked(data, bytes!("RIFF\x00\x00\x00\x00WEBPVP"),
+                             &[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00,
+                               0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]) {
+        Some(mime("image", "webp"))
+    } else if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some(mime("image", "png"))
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(mime("image", "jpeg"))
+    } else {
+        None
+    }
+}
+
+fn sniff_media(data: &[u8]) -> Option<MimeType> {
+    if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some(mime("video", "webm"))
+    } else if data.starts_with(bytes!("OggS\x00")) {
+        Some(mime("application", "ogg"))
+    } else if matches_masked(data, bytes!("RIFF\x00\x00\x00\x00WAVE"),
+                             &[0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00,
+                               0xFF, 0xFF, 0xFF, 0xFF]) {
+        Some(mime("audio", "wave"))
+    } else if data.starts_with(bytes!("ID3")) {
+        Some(mime("audio", "mpeg"))
+    } else {
+        None
+    }
+}
+
+fn sniff_archive(data: &[u8]) -> Option<MimeType> {
+    if data.starts_with(&[0x1F, 0x8B, 0x08]) {
+        Some(mime("application", "x-gzip"))
+    } else if data.starts_with(bytes!("PK\x03\x04")) {
+        Some(mime("application", "zip"))
+    } else if data.starts_with(bytes!("Rar \x1A\x07\x00")) {
+        Some(mime("application", "x-rar-compressed"))
+    } else {
+        None
+    }
+}
+
+/// Distinguishes RSS and Atom feeds served as `text/html`.
+fn sniff_feed(data: &[u8]) -> Option<MimeType> {
+    let mut data = if data.starts_with(&[0xEF, 0xBB, 0xBF]) { data.slice_from(3) } else { data };
+    loop {
+        let start = match data.iter().position(|&b| !is_whitespace(b)) {
+            Some(start) => start,
+            None => return None,
+        };
+        data = data.slice_from(start);
+        if !data.starts_with(bytes!("<")) {
+            return None;
+        }
+
+        // Skip comments, processing instructions and declarations.
+        let end_marker: &[u8] = if data.starts_with(bytes!("<!--")) {
+            bytes!("-->")
+        } else if data.starts_with(bytes!("<!")) || data.starts_with(bytes!("<?")) {
+            bytes!(">")
+        } else if data.starts_with(bytes!("<rss")) {
+            return Some(mime("application", "rss+xml"));
+        } else if data.starts_with(bytes!("<feed")) {
+            return Some(mime("application", "atom+xml"));
+        } else if data.starts_with(bytes!("<rdf:RDF")) {
+            if contains(data, bytes!("http://purl.org/rss/1.0/")) &&
+                    contains(data, bytes!("http://www.w3.org/1999/02/22-rdf-syntax-ns#")) {
+                return Some(mime("application", "rss+xml"));
+            }
+            return None;
+        } else {
+            return None;
+        };
+        match find(data, end_marker) {
+            Some(index) => data = data.slice_from(index + end_marker.len()),
+            None => return None,
+        }
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<uint> {
+    range(0, data.len()).find(|&i| data.slice_from(i).starts_with(needle))
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    find(data, needle).is_some()
+}
+
+#[cfg(test)]
+fn sniff_str(supplied: Option<(&str, &str)>, data: &[u8]) -> MimeType {
+    sniff(&supplied.map(|(t, s)| mime(t, s)), data, false, true)
+}
+
+#[test]
+fn test_sniff_unknown() {
+    assert_eq!(sniff_str(None, bytes!("  \n<!DOCTYPE html>\n<html>")), mime("text", "html"));
+    assert_eq!(sniff_str(None, bytes!("<P>paragraph")), mime("text", "html"));
+    assert_eq!(sniff_str(None, bytes!("<pre>not a tag we sniff")), mime("text", "plain"));
+    assert_eq!(sniff_str(None, bytes!("<?xml version=\"1.0\"?>")), mime("text", "xml"));
+    assert_eq!(sniff_str(None, bytes!("GIF89a....")), mime("image", "gif"));
+    assert_eq!(sniff_str(None, &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00]),
+               mime("image", "png"));
+    assert_eq!(sniff_str(None, bytes!("RIFF\x10\x00\x00\x00WEBPVP8 ")), mime("image", "webp"));
+    assert_eq!(sniff_str(None, bytes!("just some text")), mime("text", "plain"));
+    assert_eq!(sniff_str(None, &[0x01, 0x02, 0x03]), mime("application", "octet-stream"));
+    assert_eq!(sniff_str(Some(("application", "unknown")), bytes!("<html>")),
+               mime("text", "html"));
+}
+
+#[test]
+fn test_sniff_supplied() {
+    assert_eq!(sniff_str(Some(("text", "plain")), &[0x00, 0x01]),
+               mime("application", "octet-stream"));
+    assert_eq!(sniff_str(Some(("text", "plain")), bytes!("<html>")), mime("text", "plain"));
+    assert_eq!(sniff_str(Some(("image", "png")), bytes!("GIF87a")), mime("image", "gif"));
+    assert_eq!(sniff_str(Some(("text", "css")), bytes!("<html>")), mime("text", "css"));
+    assert_eq!(sniff(&Some(mime("image", "png")), bytes!("GIF87a"), true, false),
+               mime("image", "png"));
+    assert_eq!(sniff(&Some(mime("text", "plain")), &[0x00, 0x01], false, false),
+               mime("text", "plain"));
+}
+
+#[test]
+fn test_needs_sniffing() {
+    assert!(needs_sniffing(&None, false, false));
+    assert!(needs_sniffing(&Some(mime("Application", "Unknown")), true, false));
+    assert!(needs_sniffing(&Some(mime("text", "plain")), false, true));
+    assert!(needs_sniffing(&Some(mime("application", "octet-stream")), false, false));
+    assert!(needs_sniffing(&Some(mime("image", "png")), false, false));
+    assert!(needs_sniffing(&Some(mime("text", "html")), false, false));
+    assert!(!needs_sniffing(&Some(mime("text", "plain")), false, false));
+    assert!(!needs_sniffing(&Some(mime("text", "plain")), true, true));
+    assert!(!needs_sniffing(&Some(mime("image", "png")), true, false));
+    assert!(!needs_sniffing(&Some(mime("text", "css")), false, false));
+    assert!(is_apache_bug_content_type("text/plain; charset=UTF-8"));
+    assert!(!is_apache_bug_content_type("text/plain; charset=utf-8"));
+
+    assert_eq!(sniff_str(Some(("application", "octet-stream")), bytes!("GIF89a....")),
+               mime("image", "gif"));
+    assert_eq!(sniff_str(Some(("application", "octet-stream")), bytes!("<html>")),
+               mime("application", "octet-stream"));
+}
+
+#[test]
+fn test_sniff_feed() {
+    assert_eq!(sniff_str(Some(("text", "html")), bytes!("<?xml version=\"1.0\"?>\n<rss>")),
+               mime("application", "rss+xml"));
+    assert_eq!(sniff_str(Some(("text", "html")), bytes!("<!-- hi --><feed xmlns=\"\">")),
+               mime("application", "atom+xml"));
+    assert_eq!(sniff_str(Some(("text", "html")), bytes!("<html><rss>")), mime("text", "html"));
+}
+
+#[test]
+fn test_guess_from_extension() {
+    assert_eq!(guess_from_extension("/tmp/index.HTML"), Some(mime("text", "html")));
+    assert_eq!(guess_from_extension("/tmp/style.css"), Some(mime("text", "css")));
+    assert_eq!(guess_from_extension("/tmp.d/README"), None);
+}
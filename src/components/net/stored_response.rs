@@ -0,0 +1,117 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! The file format in which the disk cache and the network archive keep responses: one field
+//! per line, with tab-separated values, then a blank line and the body.
+
+use std::from_str::from_str;
+use std::str;
+
+/// What a response file says about a response, apart from its body.
+#[deriving(Clone)]
+pub struct StoredResponse {
+    status: u16,
+    content_type: Option<(~str, ~str)>,
+    charset: Option<~str>,
+    /// The response headers, as (name, value) pairs.
+    headers: ~[(~str, ~str)],
+    /// The fields that only the disk cache or the archive use, as lists of values, name first.
+    /// A field has at most three values, and only the last may contain tabs.
+    fields: ~[~[~str]],
+}
+
+impl StoredResponse {
+    pub fn new(status: u16,
+               content_type: Option<(~str, ~str)>,
+               charset: Option<~str>,
+               headers: ~[(~str, ~str)]) -> StoredResponse {
+        StoredResponse {
+            status: status,
+            content_type: content_type,
+            charset: charset,
+            headers: headers,
+            fields: ~[],
+        }
+    }
+
+    pub fn push_field(&mut self, values: ~[~str]) {
+        self.fields.push(values);
+    }
+
+    /// Serializes everything that goes before the body, including the blank line.
+    pub fn head(&self) -> ~[u8] {
+        let mut lines: ~[~str] = self.fields.iter().map(|values| values.connect("\t")).collect();
+        lines.push(format!("status\t{:u}", self.status));
+        match self.content_type {
+            Some((ref type_, ref subtype)) => {
+                lines.push(format!("content-type\t{:s}\t{:s}", *type_, *subtype));
+            }
+            None => (),
+        }
+        match self.charset {
+            Some(ref charset) => lines.push(format!("charset\t{:s}", *charset)),
+            None => (),
+        }
+        for &(ref name, ref value) in self.headers.iter() {
+            lines.push(format!("header\t{:s}\t{:s}", *name, *value));
+        }
+        let mut head = lines.connect("\n").into_bytes();
+        head.push_all(bytes!("\n\n"));
+        head
+    }
+
+    /// Splits the contents of a response file into what `head` wrote and the body.
+    pub fn parse<'a>(data: &'a [u8]) -> Option<(StoredResponse, &'a [u8])> {
+        let separator = bytes!("\n\n");
+        let split = range(0, data.len()).find(|&i| data.slice_from(i).starts_with(separator));
+        let split = match split {
+            Some(split) => split,
+            None => return None,
+        };
+        let head = match str::from_utf8_opt(data.slice_to(split)) {
+            Some(head) => head,
+            None => return None,
+        };
+
+        let mut response = StoredResponse::new(200, None, None, ~[]);
+        for line in head.lines() {
+            let parts: ~[&str] = line.splitn('\t', 2).collect();
+            match (parts[0], parts.len()) {
+                ("status", 2) => response.status = from_str(parts[1]).unwrap_or(200),
+                ("content-type", 3) => {
+                    response.content_type = Some((parts[1].to_owned(), parts[2].to_owned()));
+                }
+                ("charset", 2) => response.charset = Some(parts[1].to_owned()),
+                ("header", 3) => {
+                    response.headers.push((parts[1].to_owned(), parts[2].to_owned()));
+                }
+                _ => response.fields.push(parts.iter().map(|part| part.to_owned()).collect()),
+            }
+        }
+        Some((response, data.slice_from(split + 2)))
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let mut response = StoredResponse::new(404, Some((~"text", ~"html")), Some(~"utf-8"),
+                                           ~[(~"X-Tabs", ~"a\tb")]);
+    response.push_field(~[~"url", ~"http://example.com/"]);
+    response.push_field(~[~"vary", ~"accept-language", ~"en\tfr"]);
+    let mut data = response.head();
+    data.push_all(bytes!("body\n\nwith a blank line"));
+
+    let (parsed, body) = StoredResponse::parse(data).unwrap();
+    assert_eq!(parsed.status, 404);
+    assert_eq!(parsed.content_type, Some((~"text", ~"html")));
+    assert_eq!(parsed.charset, Some(~"utf-8"));
+    assert_eq!(parsed.headers, ~[(~"X-Tabs", ~"a\tb")]);
+    assert_eq!(parsed.fields, response.fields);
+    assert_eq!(body, bytes!("body\n\nwith a blank line"));
+}
+
+#[test]
+fn test_no_body_separator() {
+    assert!(StoredResponse::parse(bytes!("status\t200")).is_none());
+}
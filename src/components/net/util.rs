@@ -3,7 +3,10 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::comm::{Chan, Port};
+use extra::time;
+use http::status;
 use servo_util::task::spawn_named;
+use std::num::FromPrimitive;
 
 pub fn spawn_listener<A: Send, S: IntoSendStr>(name: S, f: proc(Port<A>)) -> SharedChan<A> {
     let (setup_port, setup_chan) = Chan::new();
@@ -14,3 +17,39 @@ pub fn spawn_listener<A: Send, S: IntoSendStr>(name: S, f: proc(Port<A>)) -> Sha
     }
     setup_port.recv()
 }
+
+/// Parses an HTTP date, in any of the formats seen in `Expires` headers and cookie attributes
+/// in the wild, into seconds since the epoch.
+pub fn parse_http_date(value: &str) -> Option<i64> {
+    let formats = ["%a, %d %b %Y %H:%M:%S %Z",
+                   "%a, %d-%b-%Y %H:%M:%S %Z",
+                   "%A, %d-%b-%y %H:%M:%S %Z"];
+    for format in formats.iter() {
+        match time::strptime(value, *format) {
+            Ok(tm) => return Some(tm.to_timespec().sec),
+            Err(_) => (),
+        }
+    }
+    None
+}
+
+/// The status with the given code, or `200 OK` for codes rust-http doesn't know.
+pub fn to_status(code: u16) -> status::Status {
+    FromPrimitive::from_u16(code).unwrap_or(status::Ok)
+}
+
+/// Escapes text for inclusion in an HTML page.
+pub fn escape_html(text: &str) -> ~str {
+    let mut escaped = ~"";
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push_char(c),
+        }
+    }
+    escaped
+}
@@ -18,6 +18,7 @@ use std::unstable::raw::Box;
 pub enum Event_ {
     ResizeEvent(uint, uint), 
     ReflowEvent,
+    AnimationTickEvent,
     ClickEvent(uint, Point2D<f32>),
     MouseDownEvent(uint, Point2D<f32>),
     MouseUpEvent(uint, Point2D<f32>),
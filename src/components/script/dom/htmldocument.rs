@@ -4,9 +4,12 @@
 
 use dom::bindings::codegen::HTMLDocumentBinding;
 use dom::bindings::utils::{Reflectable, Reflector, Traceable};
+use dom::bindings::utils::{DOMString, ErrorResult, Fallible};
 use dom::document::{AbstractDocument, Document, HTML};
 use dom::htmlcollection::HTMLCollection;
 use dom::window::Window;
+use servo_net::cookie::NonHTTP;
+use servo_net::resource_task::{GetCookiesForUrl, SetCookiesForUrl};
 use servo_util::namespace::Null;
 
 use extra::url::Url;
@@ -66,6 +69,20 @@ impl HTMLDocument {
         // FIXME: This should be return OBJECT elements containing applets.
         self.parent.createHTMLCollection(|elem| eq_slice(elem.tag_name, "applet"))
     }
+
+    // http://www.whatwg.org/specs/web-apps/current-work/#dom-document-cookie
+    pub fn GetCookie(&self) -> Fallible<DOMString> {
+        let (port, chan) = Chan::new();
+        let resource_task = &self.parent.window.page.resource_task;
+        resource_task.send(GetCookiesForUrl(self.parent.url.clone(), chan, NonHTTP));
+        Ok(port.recv().unwrap_or(~""))
+    }
+
+    pub fn SetCookie(&self, cookie: DOMString) -> ErrorResult {
+        let resource_task = &self.parent.window.page.resource_task;
+        resource_task.send(SetCookiesForUrl(self.parent.url.clone(), cookie, NonHTTP));
+        Ok(())
+    }
 }
 
 impl Reflectable for HTMLDocument {
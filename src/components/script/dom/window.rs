@@ -12,7 +12,8 @@ use dom::node::AbstractNode;
 use dom::location::Location;
 use dom::navigator::Navigator;
 
-use layout_interface::{ReflowForDisplay, DocumentDamageLevel};
+use layout_interface::{AnimationDocumentDamage, ReflowForDisplay, DocumentDamageLevel};
+use layout_interface::StepAnimationClockMsg;
 use script_task::{ExitWindowMsg, FireTimerMsg, Page, ScriptChan};
 use servo_msg::compositor_msg::ScriptListener;
 use servo_net::image_cache_task::ImageCacheTask;
@@ -221,6 +222,13 @@ impl Window {
         self.active_timers.remove(&TimerHandle { handle: handle, cancel_chan: None });
     }
 
+    /// A testing hook: stops the clock transitions and animations run with, moves it forward by
+    /// `seconds`, and moves them along with it.
+    pub fn StepAnimationClock(&self, seconds: f64) {
+        self.page.layout_chan.send(StepAnimationClockMsg(seconds));
+        self.damage_and_reflow(AnimationDocumentDamage);
+    }
+
     pub fn damage_and_reflow(&self, damage: DocumentDamageLevel) {
         // FIXME This should probably be ReflowForQuery, not Display. All queries currently
         // currently rely on the display list, which means we can't destroy it by
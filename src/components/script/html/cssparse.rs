@@ -8,7 +8,7 @@ use std::comm::Port;
 use encoding::EncodingRef;
 use encoding::all::UTF_8;
 use style::Stylesheet;
-use servo_net::resource_task::{Load, LoadResponse, ProgressMsg, Payload, Done, ResourceTask};
+use servo_net::resource_task::{ResourceTask, load_whole_resource};
 use servo_util::task::spawn_named;
 use extra::url::Url;
 
@@ -33,38 +33,46 @@ pub fn spawn_css_parser(provenance: StylesheetProvenance,
             InlineProvenance(ref the_url, _) => (*the_url).clone()
         };
 
-        let sheet = match provenance {
+        let mut sheet = match provenance {
             UrlProvenance(url) => {
                 debug!("cssparse: loading style sheet at {:s}", url.to_str());
-                let (input_port, input_chan) = Chan::new();
-                resource_task.send(Load(url, input_chan));
-                let LoadResponse { metadata: metadata, progress_port: progress_port }
-                    = input_port.recv();
-                let protocol_encoding_label = metadata.charset.as_ref().map(|s| s.as_slice());
-                let iter = ProgressMsgPortIterator { progress_port: progress_port };
-                Stylesheet::from_bytes_iter(
-                    iter, metadata.final_url,
-                    protocol_encoding_label, Some(environment_encoding))
+                match load_stylesheet(url.clone(), &resource_task, environment_encoding) {
+                    Some(sheet) => sheet,
+                    // A style sheet that failed to load has no rules.
+                    None => Stylesheet::from_str("", url, environment_encoding),
+                }
             }
             InlineProvenance(base_url, data) => {
                 Stylesheet::from_str(data, base_url, environment_encoding)
             }
         };
+        sheet.load_imports(|url| {
+            debug!("cssparse: loading imported style sheet at {:s}", url.to_str());
+            load_stylesheet(url.clone(), &resource_task, environment_encoding)
+        });
         result_chan.send(sheet);
     });
 
     return result_port;
 }
 
-struct ProgressMsgPortIterator {
-    progress_port: Port<ProgressMsg>
-}
-
-impl Iterator<~[u8]> for ProgressMsgPortIterator {
-    fn next(&mut self) -> Option<~[u8]> {
-        match self.progress_port.recv() {
-            Payload(data) => Some(data),
-            Done(..) => None
+/// Returns `None` if the load fails or the server doesn't answer with a success status.
+fn load_stylesheet(url: Url, resource_task: &ResourceTask, environment_encoding: EncodingRef)
+                   -> Option<Stylesheet> {
+    let (metadata, bytes) = match load_whole_resource(resource_task, url.clone()) {
+        Ok(response) => response,
+        Err(error) => {
+            debug!("cssparse: couldn't load {:s}: {:s}", url.to_str(), error.to_str());
+            return None
         }
+    };
+    let code = metadata.status.code();
+    if code / 100 != 2 {
+        debug!("cssparse: {:s} responded with status {:u}", url.to_str(), code);
+        return None
     }
+    let protocol_encoding_label = metadata.charset.as_ref().map(|s| s.as_slice());
+    Some(Stylesheet::from_bytes(
+        bytes, metadata.final_url,
+        protocol_encoding_label, Some(environment_encoding)))
 }
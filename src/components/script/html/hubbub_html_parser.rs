@@ -18,7 +18,8 @@ use hubbub::hubbub;
 use js::jsapi::JSContext;
 use servo_msg::constellation_msg::SubpageId;
 use servo_net::image_cache_task::ImageCacheTask;
-use servo_net::resource_task::{Load, Payload, Done, ResourceTask, load_whole_resource};
+use servo_net::resource_task::{Load, LoadData, Payload, Done, ResourceTask, load_whole_resource};
+use servo_net::resource_task::{DocumentPriority, LoadError};
 use servo_util::url::make_url;
 use servo_util::task::spawn_named;
 use servo_util::namespace::Null;
@@ -48,7 +49,9 @@ pub struct JSFile {
     url: Url
 }
 
-type JSResult = ~[JSFile];
+/// The document's scripts in document order, each with the `<script>` element it came from, or
+/// why it couldn't be loaded.
+type JSResult = ~[(AbstractNode, Result<JSFile, LoadError>)];
 
 enum CSSMessage {
     CSSTaskNewFile(StylesheetProvenance),
@@ -56,8 +59,8 @@ enum CSSMessage {
 }
 
 enum JSMessage {
-    JSTaskNewFile(Url),
-    JSTaskNewInlineScript(~str, Url),
+    JSTaskNewFile(AbstractNode, Url),
+    JSTaskNewInlineScript(AbstractNode, ~str, Url),
     JSTaskExit
 }
 
@@ -131,21 +134,22 @@ fn js_script_listener(to_parent: SharedChan<HtmlDiscoveryMessage>,
 
     loop {
         match from_parent.recv_opt() {
-            Some(JSTaskNewFile(url)) => {
+            Some(JSTaskNewFile(element, url)) => {
                 match load_whole_resource(&resource_task, url.clone()) {
-                    Err(_) => {
-                        error!("error loading script {:s}", url.to_str());
+                    Err(error) => {
+                        error!("error loading script {:s}: {:s}", url.to_str(), error.to_str());
+                        result_vec.push((element, Err(error)));
                     }
                     Ok((metadata, bytes)) => {
-                        result_vec.push(JSFile {
+                        result_vec.push((element, Ok(JSFile {
                             data: str::from_utf8(bytes).to_owned(),
                             url: metadata.final_url,
-                        });
+                        })));
                     }
                 }
             }
-            Some(JSTaskNewInlineScript(data, url)) => {
-                result_vec.push(JSFile { data: data, url: url });
+            Some(JSTaskNewInlineScript(element, data, url)) => {
+                result_vec.push((element, Ok(JSFile { data: data, url: url })));
             }
             Some(JSTaskExit) | None => {
                 break;
@@ -248,7 +252,7 @@ pub fn parse_html(cx: *JSContext,
                   resource_task: ResourceTask,
                   image_cache_task: ImageCacheTask,
                   next_subpage_id: SubpageId)
-                  -> HtmlParserResult {
+                  -> Result<HtmlParserResult, LoadError> {
     debug!("Hubbub: parsing {:?}", url);
     // Spawn a CSS parser to receive links to CSS style sheets.
     let resource_task2 = resource_task.clone();
@@ -270,7 +274,9 @@ pub fn parse_html(cx: *JSContext,
 
     // Wait for the LoadResponse so that the parser knows the final URL.
     let (input_port, input_chan) = Chan::new();
-    resource_task.send(Load(url.clone(), input_chan));
+    let mut load_data = LoadData::new(url.clone());
+    load_data.priority = DocumentPriority;
+    resource_task.send(Load(load_data, input_chan));
     let load_response = input_port.recv();
 
     debug!("Fetched page; metadata is {:?}", load_response.metadata);
@@ -458,7 +464,7 @@ pub fn parse_html(cx: *JSContext,
                         Some(src) => {
                             debug!("found script: {:s}", src.Value());
                             let new_url = make_url(src.Value(), Some(url3.clone()));
-                            js_chan2.send(JSTaskNewFile(new_url));
+                            js_chan2.send(JSTaskNewFile(scriptnode, new_url));
                         }
                         None => {
                             let mut data = ~[];
@@ -471,7 +477,8 @@ pub fn parse_html(cx: *JSContext,
                             }
 
                             debug!("script data = {:?}", data);
-                            js_chan2.send(JSTaskNewInlineScript(data.concat(), url3.clone()));
+                            js_chan2.send(JSTaskNewInlineScript(scriptnode, data.concat(),
+                                                                 url3.clone()));
                         }
                     }
                 });
@@ -502,16 +509,15 @@ pub fn parse_html(cx: *JSContext,
     debug!("set tree handler");
 
     debug!("loaded page");
+    let result;
     loop {
         match load_response.progress_port.recv() {
             Payload(data) => {
                 debug!("received data");
                 parser.parse_chunk(data);
             }
-            Done(Err(..)) => {
-                fail!("Failed to load page URL {:s}", url.to_str());
-            }
-            Done(..) => {
+            Done(done) => {
+                result = done;
                 break;
             }
         }
@@ -520,8 +526,10 @@ pub fn parse_html(cx: *JSContext,
     css_chan.send(CSSTaskExit);
     js_chan.send(JSTaskExit);
 
-    HtmlParserResult {
-        discovery_port: discovery_port,
-    }
+    result.map(|()| {
+        HtmlParserResult {
+            discovery_port: discovery_port,
+        }
+    })
 }
 
@@ -34,6 +34,10 @@ pub enum Msg {
     /// FIXME(pcwalton): As noted below, this isn't very type safe.
     QueryMsg(LayoutQuery),
 
+    /// Moves the animation clock forward by this many seconds, stopping it first if it runs with
+    /// the time of day. Transitions and animations move along at the next reflow.
+    StepAnimationClockMsg(f64),
+
     /// Destroys layout data associated with a DOM node.
     ///
     /// TODO(pcwalton): Maybe think about batching to avoid message traffic.
@@ -72,6 +76,9 @@ pub struct HitTestResponse(UntrustedNodeAddress);
 pub enum DocumentDamageLevel {
     /// Reflow, but do not perform CSS selector matching.
     ReflowDocumentDamage,
+    /// Restyle the nodes whose transitions and animations are running, without selector
+    /// matching, and reflow.
+    AnimationDocumentDamage,
     /// Perform CSS selector matching and reflow.
     MatchSelectorsDocumentDamage,
     /// Content changed; set full style damage and do the above.
@@ -151,4 +158,6 @@ fn test_add_damage() {
     assert_add(ContentChangedDocumentDamage, ReflowDocumentDamage, ContentChangedDocumentDamage);
     assert_add(MatchSelectorsDocumentDamage, ContentChangedDocumentDamage, ContentChangedDocumentDamage);
     assert_add(ContentChangedDocumentDamage, MatchSelectorsDocumentDamage, ContentChangedDocumentDamage);
+    assert_add(ReflowDocumentDamage, AnimationDocumentDamage, AnimationDocumentDamage);
+    assert_add(AnimationDocumentDamage, MatchSelectorsDocumentDamage, MatchSelectorsDocumentDamage);
 }
@@ -9,7 +9,8 @@ use dom::bindings::codegen::RegisterBindings;
 use dom::bindings::utils::{Reflectable, GlobalStaticData};
 use dom::document::AbstractDocument;
 use dom::element::Element;
-use dom::event::{Event_, ResizeEvent, ReflowEvent, ClickEvent, MouseDownEvent, MouseUpEvent};
+use dom::event::{Event_, ResizeEvent, ReflowEvent, AnimationTickEvent, ClickEvent};
+use dom::event::{MouseDownEvent, MouseUpEvent};
 use dom::event::Event;
 use dom::eventtarget::AbstractEventTarget;
 use dom::htmldocument::HTMLDocument;
@@ -18,7 +19,7 @@ use dom::window::{TimerData, TimerHandle, Window};
 use html::hubbub_html_parser::HtmlParserResult;
 use html::hubbub_html_parser::{HtmlDiscoveredStyle, HtmlDiscoveredIFrame, HtmlDiscoveredScript};
 use html::hubbub_html_parser;
-use layout_interface::{AddStylesheetMsg, DocumentDamage};
+use layout_interface::{AddStylesheetMsg, AnimationDocumentDamage, DocumentDamage};
 use layout_interface::{ContentBoxQuery, ContentBoxResponse};
 use layout_interface::{DocumentDamageLevel, HitTestQuery, HitTestResponse, LayoutQuery};
 use layout_interface::{LayoutChan, MatchSelectorsDocumentDamage, QueryMsg};
@@ -44,7 +45,7 @@ use servo_msg::constellation_msg;
 use servo_net::image_cache_task::ImageCacheTask;
 use servo_net::resource_task::ResourceTask;
 use servo_util::geometry::to_frac_px;
-use servo_util::url::make_url;
+use servo_util::url::{failure_url, make_url};
 use servo_util::task::spawn_named;
 use servo_util::namespace::Null;
 use std::comm::{Port, SharedChan};
@@ -108,6 +109,9 @@ pub struct Page {
     /// A handle for communicating messages to the layout task.
     layout_chan: LayoutChan,
 
+    /// A handle to the resource task, for loads and cookies made on behalf of this page.
+    resource_task: ResourceTask,
+
     /// The port that we will use to join layout. If this is `None`, then layout is not running.
     layout_join_port: Option<Port<()>>,
 
@@ -144,12 +148,14 @@ pub struct PageTreeIterator<'a> {
 }
 
 impl PageTree {
-    fn new(id: PipelineId, layout_chan: LayoutChan, window_size: Size2D<uint>) -> PageTree {
+    fn new(id: PipelineId, layout_chan: LayoutChan, resource_task: ResourceTask,
+           window_size: Size2D<uint>) -> PageTree {
         PageTree {
             page: @mut Page {
                 id: id,
                 frame: None,
                 layout_chan: layout_chan,
+                resource_task: resource_task,
                 layout_join_port: None,
                 damage: None,
                 window_size: window_size,
@@ -429,7 +435,7 @@ impl ScriptTask {
         let js_runtime = js::rust::rt();
 
         let script_task = @mut ScriptTask {
-            page_tree: PageTree::new(id, layout_chan, window_size),
+            page_tree: PageTree::new(id, layout_chan, resource_task.clone(), window_size),
 
             image_cache_task: img_cache_task,
             resource_task: resource_task,
@@ -550,7 +556,9 @@ impl ScriptTask {
         let parent_page_tree = self.page_tree.find(old_id).expect("ScriptTask: received a layout
             whose parent has a PipelineId which does not correspond to a pipeline in the script
             task's page tree. This is a bug.");
-        let new_page_tree = PageTree::new(new_id, layout_chan, parent_page_tree.page.window_size);
+        let new_page_tree = PageTree::new(new_id, layout_chan,
+                                          parent_page_tree.page.resource_task.clone(),
+                                          parent_page_tree.page.window_size);
         parent_page_tree.inner.push(new_page_tree);
     }
 
@@ -697,6 +705,17 @@ impl ScriptTask {
                                                                  self.resource_task.clone(),
                                                                  self.image_cache_task.clone(),
                                                                  page.next_subpage_id.clone());
+        let html_parsing_result = match html_parsing_result {
+            Ok(html_parsing_result) => html_parsing_result,
+            Err(error) => {
+                error!("ScriptTask: failed to load {:s}: {:s}", url.to_str(), error.to_str());
+                // Show why instead, unless it was the failure page itself that failed.
+                if "about" != url.scheme || "failure" != url.path {
+                    self.load(pipeline_id, failure_url(url.to_str(), error.to_str()));
+                }
+                return;
+            }
+        };
 
         let HtmlParserResult {
             discovery_port
@@ -757,12 +776,23 @@ impl ScriptTask {
         let cx = page.js_info.get_ref().js_context;
         compartment.define_functions(debug_fns);
 
-        // Evaluate every script in the document.
-        for file in js_scripts.iter() {
-            let _ = cx.evaluate_script(compartment.global_obj,
-                                       file.data.clone(),
-                                       file.url.to_str(),
-                                       1);
+        // Evaluate every script in the document, and fire an error event at the `<script>`
+        // elements whose source couldn't be loaded.
+        for &(element, ref script) in js_scripts.iter() {
+            match *script {
+                Ok(ref file) => {
+                    let _ = cx.evaluate_script(compartment.global_obj,
+                                               file.data.clone(),
+                                               file.url.to_str(),
+                                               1);
+                }
+                Err(..) => {
+                    let event = Event::new(window);
+                    event.mut_event().InitEvent(~"error", false, false);
+                    let target = AbstractEventTarget::from_node(element);
+                    let _ = target.eventtarget().dispatch_event_with_target(target, None, event);
+                }
+            }
         }
 
         // We have no concept of a document loader right now, so just dispatch the
@@ -843,6 +873,15 @@ impl ScriptTask {
                 }
             }
 
+            AnimationTickEvent => {
+                debug!("script got animation tick event");
+
+                if page.frame.is_some() {
+                    page.damage(AnimationDocumentDamage);
+                    page.reflow(ReflowForDisplay, self.chan.clone(), self.compositor)
+                }
+            }
+
             ClickEvent(_button, point) => {
                 debug!("ClickEvent: clicked at {:?}", point);
 
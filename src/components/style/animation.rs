@@ -0,0 +1,848 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! CSS transitions and animations: how computed values are interpolated, which transitions run
+//! when a restyle changes them, and where animations are on their timeline. The caller keeps the
+//! running transitions and animations of each element, and the clock they are run with.
+
+use std::ascii::StrAsciiExt;
+use cssparser::{RGBA, Color, CurrentColor};
+use cssparser::ast::*;
+use servo_util::geometry::Au;
+use parsing_utils::{one_component_value, get_ident_lower, parse_comma_separated};
+use properties::{AnimatedProperty, ComputedValues};
+use properties::common_types::CSSFloat;
+use properties::common_types::computed::{LengthOrPercentage, LP_Length, LP_Percentage};
+use properties::common_types::computed::{LengthOrPercentageOrAuto, LPA_Length, LPA_Percentage};
+use properties::common_types::computed::{LengthOrPercentageOrNone, LPN_Length, LPN_Percentage};
+use properties::computed_values::transition_property::{All, Property};
+use properties::computed_values::{animation_direction, animation_fill_mode};
+use properties::computed_values::{animation_iteration_count, animation_play_state};
+use properties::PropertyDeclaration;
+use keyframes::KeyframesRule;
+use extra::arc::Arc;
+use extra::time::precise_time_s;
+use std::util;
+
+
+/// A value between two others, `progress` of the way from `self` to `other`. `None` if there is
+/// no such value, for example between `auto` and a length.
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, progress: f64) -> Option<Self>;
+}
+
+impl Interpolate for CSSFloat {
+    #[inline]
+    fn interpolate(&self, other: &CSSFloat, progress: f64) -> Option<CSSFloat> {
+        Some(*self + (*other - *self) * progress)
+    }
+}
+
+impl Interpolate for Au {
+    #[inline]
+    fn interpolate(&self, other: &Au, progress: f64) -> Option<Au> {
+        Some(Au((**self as f64 + (**other - **self) as f64 * progress).round() as i32))
+    }
+}
+
+impl Interpolate for RGBA {
+    /// Colors are interpolated with premultiplied alpha, so that fading from transparent doesn't
+    /// go through the color the transparent one happens to have.
+    fn interpolate(&self, other: &RGBA, progress: f64) -> Option<RGBA> {
+        let from_alpha = self.alpha as f64;
+        let to_alpha = other.alpha as f64;
+        let alpha = from_alpha + (to_alpha - from_alpha) * progress;
+        if alpha <= 0. {
+            return Some(RGBA { red: 0., green: 0., blue: 0., alpha: 0. })
+        }
+        let channel = |from: f64, to: f64| {
+            let premultiplied = from * from_alpha + (to * to_alpha - from * from_alpha) * progress;
+            clamp(premultiplied / alpha)
+        };
+        Some(RGBA {
+            red: channel(self.red as f64, other.red as f64) as f32,
+            green: channel(self.green as f64, other.green as f64) as f32,
+            blue: channel(self.blue as f64, other.blue as f64) as f32,
+            alpha: clamp(alpha) as f32,
+        })
+    }
+}
+
+#[inline]
+fn clamp(value: f64) -> f64 {
+    if value < 0. { 0. } else if value > 1. { 1. } else { value }
+}
+
+impl Interpolate for Color {
+    fn interpolate(&self, other: &Color, progress: f64) -> Option<Color> {
+        match (self, other) {
+            (&RGBA(ref from), &RGBA(ref to)) => from.interpolate(to, progress).map(RGBA),
+            (&CurrentColor, &CurrentColor) => Some(CurrentColor),
+            // FIXME: currentColor is only resolved at paint time, so there is nothing to
+            // interpolate with here.
+            _ => None,
+        }
+    }
+}
+
+impl Interpolate for LengthOrPercentage {
+    fn interpolate(&self, other: &LengthOrPercentage, progress: f64)
+                   -> Option<LengthOrPercentage> {
+        match (self, other) {
+            (&LP_Length(ref from), &LP_Length(ref to)) => {
+                from.interpolate(to, progress).map(LP_Length)
+            }
+            (&LP_Percentage(ref from), &LP_Percentage(ref to)) => {
+                from.interpolate(to, progress).map(LP_Percentage)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Interpolate for LengthOrPercentageOrAuto {
+    fn interpolate(&self, other: &LengthOrPercentageOrAuto, progress: f64)
+                   -> Option<LengthOrPercentageOrAuto> {
+        match (self, other) {
+            (&LPA_Length(ref from), &LPA_Length(ref to)) => {
+                from.interpolate(to, progress).map(LPA_Length)
+            }
+            (&LPA_Percentage(ref from), &LPA_Percentage(ref to)) => {
+                from.interpolate(to, progress).map(LPA_Percentage)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Interpolate for LengthOrPercentageOrNone {
+    fn interpolate(&self, other: &LengthOrPercentageOrNone, progress: f64)
+                   -> Option<LengthOrPercentageOrNone> {
+        match (self, other) {
+            (&LPN_Length(ref from), &LPN_Length(ref to)) => {
+                from.interpolate(to, progress).map(LPN_Length)
+            }
+            (&LPN_Percentage(ref from), &LPN_Percentage(ref to)) => {
+                from.interpolate(to, progress).map(LPN_Percentage)
+            }
+            _ => None,
+        }
+    }
+}
+
+
+#[deriving(Eq, Clone)]
+pub enum TimingFunction {
+    CubicBezier(CSSFloat, CSSFloat, CSSFloat, CSSFloat),
+    /// The number of steps, and whether the value changes at the end of each step rather than at
+    /// its start.
+    Steps(uint, bool),
+}
+
+pub static EASE: TimingFunction = CubicBezier(0.25, 0.1, 0.25, 1.);
+
+impl TimingFunction {
+    /// ease | linear | ease-in | ease-out | ease-in-out | step-start | step-end
+    /// | steps(<integer>[, [ start | end ] ]?)
+    /// | cubic-bezier(<number>, <number>, <number>, <number>)
+    pub fn parse(input: &ComponentValue) -> Option<TimingFunction> {
+        match input {
+            &Ident(ref value) => {
+                // FIXME: Workaround for https://github.com/mozilla/rust/issues/10683
+                let value_lower = value.to_ascii_lower();
+                match value_lower.as_slice() {
+                    "ease" => Some(EASE),
+                    "linear" => Some(CubicBezier(0., 0., 1., 1.)),
+                    "ease-in" => Some(CubicBezier(0.42, 0., 1., 1.)),
+                    "ease-out" => Some(CubicBezier(0., 0., 0.58, 1.)),
+                    "ease-in-out" => Some(CubicBezier(0.42, 0., 0.58, 1.)),
+                    "step-start" => Some(Steps(1, false)),
+                    "step-end" => Some(Steps(1, true)),
+                    _ => None,
+                }
+            }
+            &Function(ref name, ref arguments) => {
+                let name_lower = name.to_ascii_lower();
+                match name_lower.as_slice() {
+                    "cubic-bezier" => {
+                        let numbers = parse_comma_separated(arguments.as_slice(), |v| match v {
+                            &Number(ref value) => Some(value.value),
+                            _ => None,
+                        });
+                        match numbers {
+                            Some(ref numbers) if numbers.len() == 4 &&
+                                                 numbers[0] >= 0. && numbers[0] <= 1. &&
+                                                 numbers[2] >= 0. && numbers[2] <= 1. => {
+                                Some(CubicBezier(numbers[0], numbers[1], numbers[2], numbers[3]))
+                            }
+                            _ => None,
+                        }
+                    }
+                    "steps" => {
+                        let mut iter = arguments.as_slice()
+                                                .split(|component_value| *component_value == Comma);
+                        let steps = match iter.next().and_then(one_component_value) {
+                            Some(&Number(ref value)) => match value.int_value {
+                                Some(steps) if steps > 0 => steps as uint,
+                                _ => return None,
+                            },
+                            _ => return None,
+                        };
+                        let at_end = match iter.next() {
+                            None => true,
+                            Some(position) => {
+                                match one_component_value(position).and_then(get_ident_lower) {
+                                    Some(ref keyword) if keyword.as_slice() == "end" => true,
+                                    Some(ref keyword) if keyword.as_slice() == "start" => false,
+                                    _ => return None,
+                                }
+                            }
+                        };
+                        if iter.next().is_some() {
+                            return None
+                        }
+                        Some(Steps(steps, at_end))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The output progress of the function for an input `progress` between 0 and 1.
+    pub fn solve(&self, progress: f64) -> f64 {
+        match *self {
+            CubicBezier(x1, y1, x2, y2) => {
+                if x1 == y1 && x2 == y2 {
+                    // Linear.
+                    return progress
+                }
+                solve_cubic_bezier(x1, y1, x2, y2, progress)
+            }
+            Steps(steps, at_end) => {
+                let steps = steps as f64;
+                if at_end {
+                    (progress * steps).floor() / steps
+                } else {
+                    (progress * steps).ceil() / steps
+                }
+            }
+        }
+    }
+}
+
+/// The y coordinate of the point of the curve from (0, 0) to (1, 1) with control points (x1, y1)
+/// and (x2, y2) that has `x` as its x coordinate.
+fn solve_cubic_bezier(x1: f64, y1: f64, x2: f64, y2: f64, x: f64) -> f64 {
+    fn bezier(p1: f64, p2: f64, t: f64) -> f64 {
+        let u = 1. - t;
+        3. * u * u * t * p1 + 3. * u * t * t * p2 + t * t * t
+    }
+    if x <= 0. {
+        return 0.
+    }
+    if x >= 1. {
+        return 1.
+    }
+    // x is monotonic in t as x1 and x2 are between 0 and 1, so bisect.
+    let mut low = 0.;
+    let mut high = 1.;
+    let mut t = x;
+    for _ in range(0, 40) {
+        let current_x = bezier(x1, x2, t);
+        if (current_x - x).abs() < 1e-7 {
+            break
+        }
+        if current_x < x {
+            low = t
+        } else {
+            high = t
+        }
+        t = (low + high) / 2.;
+    }
+    bezier(y1, y2, t)
+}
+
+
+/// <time>, in seconds.
+pub fn parse_time(input: &ComponentValue) -> Option<CSSFloat> {
+    match input {
+        &Dimension(ref value, ref unit) => {
+            let unit_lower = unit.to_ascii_lower();
+            match unit_lower.as_slice() {
+                "s" => Some(value.value),
+                "ms" => Some(value.value / 1000.),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+
+/// A change of a property of an element that is being transitioned.
+#[deriving(Clone)]
+pub struct PropertyTransition {
+    property: AnimatedProperty,
+    /// When the delay is over, in seconds.
+    start_time: f64,
+    /// In seconds.
+    duration: f64,
+    timing_function: TimingFunction,
+}
+
+impl PropertyTransition {
+    pub fn is_finished(&self, now: f64) -> bool {
+        now >= self.start_time + self.duration
+    }
+
+    fn progress(&self, now: f64) -> f64 {
+        if now < self.start_time {
+            0.
+        } else if self.is_finished(now) {
+            1.
+        } else {
+            self.timing_function.solve((now - self.start_time) / self.duration)
+        }
+    }
+}
+
+/// Updates the transitions of an element being restyled from `old_style` to `new_style` at time
+/// `now`, then applies them to `new_style`. Finished transitions are removed from `running`.
+///
+/// `old_style` is the style the element had before, with the transitions that were running then
+/// applied, so that a transition that is interrupted starts from where it was.
+pub fn update_transitions(running: &mut ~[PropertyTransition], old_style: &ComputedValues,
+                          new_style: &mut ComputedValues, now: f64) {
+    {
+        let new_style: &ComputedValues = &*new_style;
+
+        for property in AnimatedProperty::differences(old_style, new_style).move_iter() {
+            let name = property.name();
+            // Restyling during a transition doesn't start it again unless its end value changed.
+            if running.iter().any(|transition| {
+                transition.property.name() == name && transition.property.ends_at(new_style)
+            }) {
+                continue
+            }
+            running.retain(|transition| transition.property.name() != name);
+            match transition_for(new_style, name) {
+                Some((duration, delay, timing_function)) => {
+                    running.push(PropertyTransition {
+                        property: property,
+                        start_time: now + delay,
+                        duration: duration,
+                        timing_function: timing_function,
+                    })
+                }
+                None => {}
+            }
+        }
+
+        // Transitions to values the element no longer has are cancelled.
+        running.retain(|transition| transition.property.ends_at(new_style));
+    }
+
+    for transition in running.iter() {
+        transition.property.update(new_style, transition.progress(now));
+    }
+    running.retain(|transition| !transition.is_finished(now));
+}
+
+/// The duration, delay and timing function of the transitions of the property `name` in `style`,
+/// if it has any. The last matching entry of `transition-property` is used, with the values at
+/// the same index in the other lists, which are repeated as needed.
+fn transition_for(style: &ComputedValues, name: &str) -> Option<(f64, f64, TimingFunction)> {
+    let transition = &style.Transition;
+    let mut index = None;
+    for (i, property) in transition.transition_property.iter().enumerate() {
+        match *property {
+            All => index = Some(i),
+            Property(ref property_name) if property_name.as_slice() == name => index = Some(i),
+            Property(_) => {}
+        }
+    }
+    index.and_then(|i| {
+        let duration = value_at(transition.transition_duration, i);
+        let delay = value_at(transition.transition_delay, i);
+        if duration + delay <= 0. {
+            // Nothing to see: the value changes right away.
+            None
+        } else {
+            Some((duration, delay, value_at(transition.transition_timing_function, i)))
+        }
+    })
+}
+
+/// The value for the `index`th item of a list property, the values being repeated as needed.
+fn value_at<T: Clone>(list: &[T], index: uint) -> T {
+    list[index % list.len()].clone()
+}
+
+
+/// The clock transitions and animations are run with, in seconds. A manual clock only moves when
+/// it is stepped, so that tests can look at any point of an animation.
+#[deriving(Clone)]
+pub enum AnimationClock {
+    SystemClock,
+    ManualClock(f64),
+}
+
+impl AnimationClock {
+    pub fn now(&self) -> f64 {
+        match *self {
+            SystemClock => precise_time_s(),
+            ManualClock(time) => time,
+        }
+    }
+
+    pub fn is_manual(&self) -> bool {
+        match *self {
+            SystemClock => false,
+            ManualClock(_) => true,
+        }
+    }
+
+    /// Moves the clock `seconds` forward. The system clock is stopped at the time of day first,
+    /// and becomes a manual clock.
+    pub fn step(&mut self, seconds: f64) {
+        *self = ManualClock(self.now() + seconds)
+    }
+}
+
+
+/// An animation of an element, which runs for as long as its name is in `animation-name`.
+#[deriving(Clone)]
+pub struct RunningAnimation {
+    name: ~str,
+    keyframes: Arc<KeyframesRule>,
+    /// When the animation started, not counting the time it was paused for.
+    start_time: f64,
+    /// How long the animation had been running for when it was paused, if it is.
+    paused_at: Option<f64>,
+    /// The style of the element when the animation started, cascaded without any keyframe.
+    base_style: ComputedValues,
+    /// The style of the element with each of `keyframes` added, cascaded when the animation
+    /// started.
+    keyframe_styles: ~[ComputedValues],
+}
+
+/// Starts and stops the animations of an element according to the `animation-name` of its new
+/// style, then applies them to that style at time `now`. Returns whether any of them still
+/// changes with time.
+///
+/// `keyframes_for` finds the `@keyframes` rule with a given name, and `cascade_keyframe` cascades
+/// the element again with the declarations of a keyframe added. Keyframes are only cascaded when
+/// their animation starts.
+pub fn update_animations(running: &mut ~[RunningAnimation], style: &mut ComputedValues, now: f64,
+                         keyframes_for: |&str| -> Option<Arc<KeyframesRule>>,
+                         cascade_keyframe: |&Arc<~[PropertyDeclaration]>| -> ComputedValues)
+                         -> bool {
+    let animation = style.Animation.clone();
+    let names = animation.animation_name.as_slice();
+
+    // Animations keep going as long as their name is still there, wherever it is in the list.
+    // `indices` holds where in the list each running animation is, which picks its values of the
+    // other `animation-*` properties.
+    let mut previous = util::replace(running, ~[]);
+    let mut indices = ~[];
+    for (index, name) in names.iter().enumerate() {
+        match previous.iter().position(|animation| animation.name == *name) {
+            Some(previous_index) => {
+                running.push(previous.remove(previous_index));
+                indices.push(index)
+            }
+            None => match keyframes_for(name.as_slice()) {
+                Some(keyframes) => {
+                    let mut keyframe_styles = ~[];
+                    for keyframe in keyframes.get().keyframes.iter() {
+                        keyframe_styles.push(cascade_keyframe(&keyframe.declarations))
+                    }
+                    running.push(RunningAnimation {
+                        name: name.clone(),
+                        keyframes: keyframes,
+                        start_time: now,
+                        paused_at: None,
+                        base_style: cascade_keyframe(&Arc::new(~[])),
+                        keyframe_styles: keyframe_styles,
+                    });
+                    indices.push(index)
+                }
+                None => {}
+            },
+        }
+    }
+
+    let mut changing = false;
+    for (running_animation, &index) in running.mut_iter().zip(indices.iter()) {
+        let duration = value_at(animation.animation_duration, index);
+        let delay = value_at(animation.animation_delay, index);
+        let fill_mode = value_at(animation.animation_fill_mode, index);
+        let paused =
+            value_at(animation.animation_play_state, index) == animation_play_state::paused;
+
+        // Pausing stops the time the animation has been running for.
+        let elapsed = match (paused, running_animation.paused_at) {
+            (true, Some(elapsed)) => elapsed,
+            (true, None) => {
+                let elapsed = now - running_animation.start_time;
+                running_animation.paused_at = Some(elapsed);
+                elapsed
+            }
+            (false, Some(elapsed)) => {
+                running_animation.start_time = now - elapsed;
+                running_animation.paused_at = None;
+                elapsed
+            }
+            (false, None) => now - running_animation.start_time,
+        };
+
+        let iterations = match value_at(animation.animation_iteration_count, index) {
+            animation_iteration_count::Infinite if duration > 0. => None,
+            animation_iteration_count::Infinite => Some(0.),
+            animation_iteration_count::Count(count) => Some(count),
+        };
+        let time = elapsed - delay;
+        let (iteration, iteration_progress) = if time < 0. {
+            changing = changing || !paused;
+            match fill_mode {
+                animation_fill_mode::backwards | animation_fill_mode::both => (0., 0.),
+                _ => continue,
+            }
+        } else if iterations.map_or(false, |iterations| time >= duration * iterations) {
+            match fill_mode {
+                animation_fill_mode::forwards | animation_fill_mode::both => {
+                    // Where the last iteration ended.
+                    let iterations = iterations.unwrap();
+                    let last = iterations.ceil() - 1.;
+                    if iterations == 0. {
+                        (0., 0.)
+                    } else {
+                        (last, iterations - last)
+                    }
+                }
+                _ => continue,
+            }
+        } else {
+            changing = changing || !paused;
+            let iteration = (time / duration).floor();
+            (iteration, time / duration - iteration)
+        };
+
+        let reversed = match value_at(animation.animation_direction, index) {
+            animation_direction::normal => false,
+            animation_direction::reverse => true,
+            animation_direction::alternate => iteration % 2. == 1.,
+            animation_direction::alternate_reverse => iteration % 2. == 0.,
+        };
+        let progress = if reversed { 1. - iteration_progress } else { iteration_progress };
+
+        // The keyframes on either side of the progress. There always are ones at 0 and 1.
+        let keyframes = running_animation.keyframes.get().keyframes.as_slice();
+        let next = match keyframes.iter().position(|keyframe| keyframe.offset > progress) {
+            Some(next) => next,
+            None => keyframes.len() - 1,
+        };
+        let (from, to) = (&keyframes[next - 1], &keyframes[next]);
+        let timing_function = value_at(animation.animation_timing_function, index);
+        let keyframe_progress = timing_function.solve(
+            (progress - from.offset) / (to.offset - from.offset));
+
+        let from_style = &running_animation.keyframe_styles[next - 1];
+        let to_style = &running_animation.keyframe_styles[next];
+        for property in AnimatedProperty::differences(&running_animation.base_style, from_style)
+                                         .move_iter() {
+            property.update(style, 1.)
+        }
+        for property in AnimatedProperty::differences(from_style, to_style).move_iter() {
+            property.update(style, keyframe_progress)
+        }
+    }
+    changing
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cssparser::tokenize;
+    use cssparser::ast::ComponentValue;
+    use encoding::EncodingRef;
+    use encoding::all::UTF_8;
+    use extra::arc::Arc;
+    use servo_util::geometry::Au;
+    use servo_util::url::make_url;
+    use keyframes::KeyframesRule;
+    use properties::{cascade, parse_style_attribute, ComputedValues};
+    use properties::common_types::computed::{LengthOrPercentageOrAuto, LPA_Length, LPA_Auto};
+    use stylesheets::{Stylesheet, CSSKeyframesRule};
+    use properties::computed_values::transition_property::Property;
+    use super::*;
+
+    fn parse_timing_function(input: &str) -> Option<TimingFunction> {
+        let values: ~[ComponentValue] = tokenize(input).map(|(v, _)| v).collect();
+        assert_eq!(values.len(), 1);
+        TimingFunction::parse(&values[0])
+    }
+
+    #[test]
+    fn test_timing_functions() {
+        let linear = parse_timing_function("linear").unwrap();
+        assert_eq!(linear.solve(0.25), 0.25);
+        let ease = parse_timing_function("ease").unwrap();
+        assert_eq!(ease.solve(0.), 0.);
+        assert_eq!(ease.solve(1.), 1.);
+        assert!(ease.solve(0.5) > 0.5);
+        let ease_in = parse_timing_function("cubic-bezier(0.42, 0, 1, 1)").unwrap();
+        assert!(ease_in.solve(0.5) < 0.5);
+        let steps = parse_timing_function("steps(4)").unwrap();
+        assert_eq!(steps.solve(0.3), 0.25);
+        let steps = parse_timing_function("steps(4, start)").unwrap();
+        assert_eq!(steps.solve(0.3), 0.5);
+        assert!(parse_timing_function("cubic-bezier(2, 0, 1, 1)").is_none());
+        assert!(parse_timing_function("steps(0)").is_none());
+        assert!(parse_timing_function("steps(2, middle)").is_none());
+    }
+
+    #[test]
+    fn test_transition_shorthand() {
+        let block = parse_style_attribute("transition: width 1s linear .5s, 200ms color");
+        let style = cascade(&[block.normal.clone()], None);
+        assert_eq!(style.Transition.transition_property,
+                   ~[Property(~"width"), Property(~"color")]);
+        assert_eq!(style.Transition.transition_duration, ~[1., 0.2]);
+        assert_eq!(style.Transition.transition_delay, ~[0.5, 0.]);
+        assert_eq!(style.Transition.transition_timing_function,
+                   ~[parse_timing_function("linear").unwrap(), EASE]);
+
+        let block = parse_style_attribute("transition: none, width 1s");
+        assert!(block.normal.get().is_empty());
+        let block = parse_style_attribute("transition: width -1s");
+        assert!(block.normal.get().is_empty());
+    }
+
+    fn styles_with_width_change() -> (ComputedValues, ComputedValues) {
+        let mut old = cascade(&[], None);
+        old.Box.width = LPA_Length(Au::from_px(0));
+        let mut new = old.clone();
+        new.Box.width = LPA_Length(Au::from_px(100));
+        new.Transition.transition_property = ~[Property(~"width")];
+        new.Transition.transition_duration = ~[1.];
+        new.Transition.transition_timing_function = ~[parse_timing_function("linear").unwrap()];
+        (old, new)
+    }
+
+    #[test]
+    fn test_transition_runs_to_completion() {
+        let (old, new) = styles_with_width_change();
+        let mut running = ~[];
+        let mut style = new.clone();
+        update_transitions(&mut running, &old, &mut style, 10.);
+        assert_eq!(running.len(), 1);
+        assert!(style.Box.width == LPA_Length(Au::from_px(0)));
+
+        // Restyling with the same end value keeps the transition going.
+        let previous = style;
+        let mut style = new.clone();
+        update_transitions(&mut running, &previous, &mut style, 10.5);
+        assert_eq!(running.len(), 1);
+        assert!(style.Box.width == LPA_Length(Au::from_px(50)));
+
+        let previous = style;
+        let mut style = new.clone();
+        update_transitions(&mut running, &previous, &mut style, 11.);
+        assert!(running.is_empty());
+        assert!(style == new);
+    }
+
+    #[test]
+    fn test_transitions_that_do_not_start() {
+        let (old, mut new) = styles_with_width_change();
+        let mut running = ~[];
+
+        // Other properties are not transitioned.
+        new.Transition.transition_property = ~[Property(~"height")];
+        let mut style = new.clone();
+        update_transitions(&mut running, &old, &mut style, 0.);
+        assert!(running.is_empty());
+        assert!(style == new);
+
+        // Nor are values that can't be interpolated.
+        new.Transition.transition_property = ~[Property(~"width")];
+        new.Box.width = LPA_Auto;
+        let mut style = new.clone();
+        update_transitions(&mut running, &old, &mut style, 0.);
+        assert!(running.is_empty());
+
+        // Nor anything when the duration is zero.
+        new.Box.width = LPA_Length(Au::from_px(100));
+        new.Transition.transition_duration = ~[0.];
+        let mut style = new.clone();
+        update_transitions(&mut running, &old, &mut style, 0.);
+        assert!(running.is_empty());
+        assert!(style == new);
+    }
+
+    #[test]
+    fn test_transition_delay_and_interruption() {
+        let (old, mut new) = styles_with_width_change();
+        new.Transition.transition_delay = ~[1.];
+        let mut running = ~[];
+        let mut style = new.clone();
+        update_transitions(&mut running, &old, &mut style, 0.);
+        let previous = style;
+        let mut style = new.clone();
+        update_transitions(&mut running, &previous, &mut style, 0.9);
+        assert!(style.Box.width == LPA_Length(Au::from_px(0)));
+        let previous = style;
+        let mut style = new.clone();
+        update_transitions(&mut running, &previous, &mut style, 1.5);
+        assert!(style.Box.width == LPA_Length(Au::from_px(50)));
+
+        // Going back starts a new transition from where the first one was.
+        let mut back = new.clone();
+        back.Box.width = LPA_Length(Au::from_px(0));
+        let previous = style;
+        let mut style = back.clone();
+        update_transitions(&mut running, &previous, &mut style, 1.5);
+        assert_eq!(running.len(), 1);
+        assert!(style.Box.width == LPA_Length(Au::from_px(50)));
+        let previous = style;
+        let mut style = back.clone();
+        update_transitions(&mut running, &previous, &mut style, 3.);
+        assert!(style.Box.width == LPA_Length(Au::from_px(25)));
+    }
+
+    fn parse_keyframes(css: &str) -> Arc<KeyframesRule> {
+        let base_url = make_url("http://example.com/style/main.css", None);
+        let stylesheet = Stylesheet::from_str(css, base_url, UTF_8 as EncodingRef);
+        let mut rules = stylesheet.rules;
+        match rules.pop() {
+            CSSKeyframesRule(rule) => Arc::new(rule),
+            _ => fail!("expected a @keyframes rule"),
+        }
+    }
+
+    /// Runs the animations of an element styled with `declarations` at the time of `clock`, and
+    /// returns its width and whether the animations still change with time.
+    fn animate(running: &mut ~[RunningAnimation], keyframes: &Arc<KeyframesRule>,
+               declarations: &str, clock: &AnimationClock) -> (LengthOrPercentageOrAuto, bool) {
+        let declarations = parse_style_attribute(declarations).normal;
+        let mut style = cascade(&[declarations.clone()], None);
+        let changing = update_animations(running, &mut style, clock.now(), |name| {
+            if name == "grow" { Some(keyframes.clone()) } else { None }
+        }, |keyframe| {
+            cascade(&[declarations.clone(), keyframe.clone()], None)
+        });
+        (style.Box.width, changing)
+    }
+
+    fn px(px: int) -> LengthOrPercentageOrAuto {
+        LPA_Length(Au::from_px(px))
+    }
+
+    static GROW: &'static str =
+        "@keyframes grow { from { width: 0px } 50% { width: 100px } to { width: 200px } }";
+
+    #[test]
+    fn test_animation_timeline() {
+        let keyframes = parse_keyframes(GROW);
+        let style = "width: 10px; animation-name: grow; animation-duration: 2s; \
+                     animation-timing-function: linear";
+        let mut running = ~[];
+        let mut clock = ManualClock(5.);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(0), true));
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(50), true));
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(100), true));
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(150), true));
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(10), false));
+
+        // A finished animation doesn't start again while its name stays.
+        clock.step(1.);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(10), false));
+        assert_eq!(running.len(), 1);
+        animate(&mut running, &keyframes, "width: 10px", &clock);
+        assert!(running.is_empty());
+
+        // Nor does anything happen for names without @keyframes.
+        animate(&mut running, &keyframes, "animation-name: shrink; animation-duration: 1s", &clock);
+        assert!(running.is_empty());
+    }
+
+    #[test]
+    fn test_animation_iterations_direction_and_fill() {
+        let keyframes = parse_keyframes(GROW);
+        let style = "animation-name: grow; animation-duration: 2s; \
+                     animation-timing-function: linear; animation-delay: 1s; \
+                     animation-iteration-count: 2; animation-direction: alternate; \
+                     animation-fill-mode: both";
+        let mut running = ~[];
+        let mut clock = ManualClock(0.);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(0), true));
+        clock.step(1.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(50), true));
+        clock.step(2.);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(150), true));
+        clock.step(6.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(0), false));
+    }
+
+    #[test]
+    fn test_animations_with_the_same_name() {
+        let keyframes = parse_keyframes(GROW);
+        let style = "animation-name: grow, grow; animation-duration: 4s, 2s; \
+                     animation-timing-function: linear";
+        let mut running = ~[];
+        let mut clock = ManualClock(0.);
+        animate(&mut running, &keyframes, style, &clock);
+        assert_eq!(running.len(), 2);
+        // Each runs with its own duration, and the last one wins while it runs.
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(50), true));
+        clock.step(2.);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(125), true));
+    }
+
+    #[test]
+    fn test_paused_animation() {
+        let keyframes = parse_keyframes(GROW);
+        let style = "animation-name: grow; animation-duration: 2s; \
+                     animation-timing-function: linear";
+        let paused = "animation-name: grow; animation-duration: 2s; \
+                      animation-timing-function: linear; animation-play-state: paused";
+        let mut running = ~[];
+        let mut clock = ManualClock(0.);
+        animate(&mut running, &keyframes, style, &clock);
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, paused, &clock), (px(50), false));
+        clock.step(10.);
+        assert_eq!(animate(&mut running, &keyframes, paused, &clock), (px(50), false));
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(50), true));
+        clock.step(0.5);
+        assert_eq!(animate(&mut running, &keyframes, style, &clock), (px(100), true));
+    }
+
+    #[test]
+    fn test_keyframes_are_cascaded_when_the_animation_starts() {
+        let keyframes = parse_keyframes(GROW);
+        let declarations = parse_style_attribute("animation-name: grow; animation-duration: 2s")
+                               .normal;
+        let mut running = ~[];
+        let mut cascades = 0;
+        for &now in [0., 0.5, 1.].iter() {
+            let mut style = cascade(&[declarations.clone()], None);
+            update_animations(&mut running, &mut style, now, |_| Some(keyframes.clone()),
+                              |keyframe| {
+                cascades += 1;
+                cascade(&[declarations.clone(), keyframe.clone()], None)
+            });
+        }
+        // Once without any keyframe, and once for each of the three keyframes.
+        assert_eq!(cascades, 4);
+    }
+}
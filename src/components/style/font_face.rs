@@ -0,0 +1,271 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::ascii::StrAsciiExt;
+use cssparser::parse_declaration_list;
+use cssparser::ast::*;
+use extra::url::Url;
+use servo_util::url::make_url;
+use errors::{ErrorLoggerIterator, log_css_error};
+use parsing_utils::one_component_value;
+use properties::longhands;
+use properties::computed_values::{font_style, font_weight};
+use properties::computed_values::font_family::FamilyName;
+use stylesheets::{CSSRule, CSSFontFaceRule};
+
+
+pub struct FontFaceRule {
+    family: ~str,
+    /// In the order they should be tried.
+    sources: ~[Source],
+    weight: font_weight::T,
+    style: font_style::T,
+    /// Inclusive ranges of code points.
+    unicode_range: ~[(u32, u32)],
+}
+
+pub enum Source {
+    /// Already resolved against the base URL of the style sheet, with the format hints that
+    /// followed it, lowercased.
+    UrlSource(Url, ~[~str]),
+    /// The name of a font installed on the system.
+    LocalSource(~str),
+}
+
+
+pub fn parse_font_face_rule(rule: AtRule, parent_rules: &mut ~[CSSRule], base_url: &Url) {
+    let location = rule.location;
+    let block = match rule.block {
+        Some(block) => block,
+        None => {
+            log_css_error(location, "Invalid @font-face rule");
+            return
+        }
+    };
+    let mut family = None;
+    let mut sources = None;
+    let mut weight = font_weight::Weight400;
+    let mut style = font_style::normal;
+    let mut unicode_range = ~[(0, 0x10FFFF)];
+
+    for item in ErrorLoggerIterator(parse_declaration_list(block.move_iter())) {
+        match item {
+            Decl_AtRule(rule) => log_css_error(
+                rule.location, format!("Unsupported at-rule in @font-face: @{:s}", rule.name)),
+            Declaration(Declaration{ location: l, name: n, value: v, important: _ }) => {
+                // FIXME: Workaround for https://github.com/mozilla/rust/issues/10683
+                let name_lower = n.to_ascii_lower();
+                let valid = match name_lower.as_slice() {
+                    "font-family" => match parse_family_name(v) {
+                        Some(value) => { family = Some(value); true },
+                        None => false,
+                    },
+                    "src" => match parse_sources(v, base_url) {
+                        Some(value) => { sources = Some(value); true },
+                        None => false,
+                    },
+                    "font-weight" => match parse_weight(v) {
+                        Some(value) => { weight = value; true },
+                        None => false,
+                    },
+                    "font-style" => match one_component_value(v)
+                                          .and_then(longhands::font_style::from_component_value) {
+                        Some(value) => { style = value; true },
+                        None => false,
+                    },
+                    "unicode-range" => match parse_unicode_range(v) {
+                        Some(value) => { unicode_range = value; true },
+                        None => false,
+                    },
+                    _ => {
+                        log_css_error(l, format!("Unsupported @font-face descriptor: {:s}", n));
+                        true
+                    }
+                };
+                if !valid {
+                    log_css_error(l, format!("Invalid @font-face descriptor: {:s}", n))
+                }
+            }
+        }
+    }
+
+    match (family, sources) {
+        (Some(family), Some(sources)) => parent_rules.push(CSSFontFaceRule(FontFaceRule {
+            family: family,
+            sources: sources,
+            weight: weight,
+            style: style,
+            unicode_range: unicode_range,
+        })),
+        _ => log_css_error(location, "@font-face rule without font-family or src"),
+    }
+}
+
+
+/// <url> [ format(<string>#) ]? | local(<family-name>), comma separated.
+fn parse_sources(input: &[ComponentValue], base_url: &Url) -> Option<~[Source]> {
+    let mut sources = ~[];
+    for source in input.split(|component_value| *component_value == Comma) {
+        let mut iter = source.skip_whitespace();
+        let source = match iter.next() {
+            Some(&URL(ref url)) => {
+                let formats = match iter.next() {
+                    Some(&Function(ref name, ref arguments))
+                            if name.eq_ignore_ascii_case("format") => {
+                        match parse_format_hints(arguments.as_slice()) {
+                            Some(formats) => formats,
+                            None => return None,
+                        }
+                    }
+                    None => ~[],
+                    _ => return None,
+                };
+                UrlSource(make_url(url.as_slice(), Some(base_url.clone())), formats)
+            }
+            Some(&Function(ref name, ref arguments)) if name.eq_ignore_ascii_case("local") => {
+                match parse_family_name(arguments.as_slice()) {
+                    Some(name) => LocalSource(name),
+                    None => return None,
+                }
+            }
+            _ => return None,
+        };
+        if iter.next().is_some() {
+            return None
+        }
+        sources.push(source);
+    }
+    Some(sources)
+}
+
+
+/// <string>#, inside format()
+fn parse_format_hints(input: &[ComponentValue]) -> Option<~[~str]> {
+    let mut formats = ~[];
+    for format in input.split(|component_value| *component_value == Comma) {
+        match one_component_value(format) {
+            Some(&String(ref format)) => formats.push(format.to_ascii_lower()),
+            _ => return None,
+        }
+    }
+    Some(formats)
+}
+
+
+/// A single <family-name>.
+fn parse_family_name(input: &[ComponentValue]) -> Option<~str> {
+    match longhands::font_family::parse(input) {
+        Some(mut families) => {
+            if families.len() != 1 {
+                return None
+            }
+            match families.pop() {
+                FamilyName(name) => Some(name),
+            }
+        }
+        None => None,
+    }
+}
+
+
+/// normal | bold | 100 | 200 | 300 | 400 | 500 | 600 | 700 | 800 | 900
+fn parse_weight(input: &[ComponentValue]) -> Option<font_weight::T> {
+    match one_component_value(input).and_then(longhands::font_weight::from_component_value) {
+        Some(longhands::font_weight::SpecifiedWeight100) => Some(font_weight::Weight100),
+        Some(longhands::font_weight::SpecifiedWeight200) => Some(font_weight::Weight200),
+        Some(longhands::font_weight::SpecifiedWeight300) => Some(font_weight::Weight300),
+        Some(longhands::font_weight::SpecifiedWeight400) => Some(font_weight::Weight400),
+        Some(longhands::font_weight::SpecifiedWeight500) => Some(font_weight::Weight500),
+        Some(longhands::font_weight::SpecifiedWeight600) => Some(font_weight::Weight600),
+        Some(longhands::font_weight::SpecifiedWeight700) => Some(font_weight::Weight700),
+        Some(longhands::font_weight::SpecifiedWeight800) => Some(font_weight::Weight800),
+        Some(longhands::font_weight::SpecifiedWeight900) => Some(font_weight::Weight900),
+        // bolder and lighter are relative to an element, which a font face doesn't have.
+        Some(longhands::font_weight::Bolder) | Some(longhands::font_weight::Lighther) |
+        None => None,
+    }
+}
+
+
+/// <urange>#
+fn parse_unicode_range(input: &[ComponentValue]) -> Option<~[(u32, u32)]> {
+    let mut ranges = ~[];
+    for range in input.split(|component_value| *component_value == Comma) {
+        match one_component_value(range) {
+            Some(&UnicodeRange(start, end)) if start <= end => ranges.push((start, end)),
+            _ => return None,
+        }
+    }
+    Some(ranges)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use encoding::EncodingRef;
+    use encoding::all::UTF_8;
+    use servo_util::url::make_url;
+    use properties::computed_values::{font_style, font_weight};
+    use stylesheets::{Stylesheet, CSSFontFaceRule};
+    use super::*;
+
+    fn parse(descriptors: &str) -> Option<FontFaceRule> {
+        let css = format!("@font-face \\{ {:s} \\}", descriptors);
+        let base_url = make_url("http://example.com/style/main.css", None);
+        let stylesheet = Stylesheet::from_str(css, base_url, UTF_8 as EncodingRef);
+        let mut rules = stylesheet.rules;
+        match rules.pop_opt() {
+            Some(CSSFontFaceRule(rule)) => Some(rule),
+            Some(_) => fail!("unexpected rule"),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn test_font_face_descriptors() {
+        let rule = parse("font-family: 'Open Sans'; \
+                          src: local(Open Sans), url(../fonts/open-sans.ttf) format('truetype'); \
+                          font-weight: bold; font-style: italic; \
+                          unicode-range: U+0-7F, U+0100-017F").unwrap();
+        assert_eq!(rule.family, ~"Open Sans");
+        assert_eq!(rule.sources.len(), 2);
+        match rule.sources[0] {
+            LocalSource(ref name) => assert_eq!(name, &~"Open Sans"),
+            _ => fail!("expected a local source"),
+        }
+        match rule.sources[1] {
+            UrlSource(ref url, ref formats) => {
+                assert_eq!(url.to_str(), ~"http://example.com/fonts/open-sans.ttf");
+                assert_eq!(formats, &~[~"truetype"]);
+            }
+            _ => fail!("expected a url source"),
+        }
+        assert!(rule.weight == font_weight::Weight700);
+        assert!(rule.style == font_style::italic);
+        assert_eq!(rule.unicode_range, ~[(0, 0x7F), (0x100, 0x17F)]);
+    }
+
+    #[test]
+    fn test_font_face_requires_family_and_src() {
+        assert!(parse("font-family: Foo").is_none());
+        assert!(parse("src: url(foo.ttf)").is_none());
+        assert!(parse("font-family: Foo; src: url(foo.ttf) bar").is_none());
+        let rule = parse("font-family: Foo; src: url(foo.ttf); font-weight: bolder").unwrap();
+        assert!(rule.weight == font_weight::Weight400);
+    }
+
+    #[test]
+    fn test_font_face_format_hints() {
+        let rule = parse("font-family: Foo; \
+                          src: url(foo.woff) format('WOFF', \"woff2\"), url(foo.ttf)").unwrap();
+        let formats: ~[~[~str]] = rule.sources.iter().map(|source| {
+            match *source {
+                UrlSource(_, ref formats) => formats.clone(),
+                LocalSource(..) => fail!("expected a url source"),
+            }
+        }).collect();
+        assert_eq!(formats, ~[~[~"woff", ~"woff2"], ~[]]);
+        assert!(parse("font-family: Foo; src: url(foo.ttf) format(truetype)").is_none());
+    }
+}
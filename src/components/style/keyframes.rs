@@ -0,0 +1,169 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::ascii::StrAsciiExt;
+use extra::arc::Arc;
+use cssparser::parse_rule_list;
+use cssparser::ast::*;
+use errors::{ErrorLoggerIterator, log_css_error};
+use parsing_utils::{one_component_value, parse_comma_separated};
+use properties::{PropertyDeclaration, parse_property_declaration_list};
+use stylesheets::{CSSRule, CSSKeyframesRule};
+
+
+#[deriving(Clone)]
+pub struct KeyframesRule {
+    name: ~str,
+    /// Sorted by offset, with one keyframe per offset. There always are keyframes at 0 and 1:
+    /// when the rule doesn't have them, they are added without declarations, so that the element
+    /// keeps its own style there.
+    keyframes: ~[Keyframe],
+}
+
+#[deriving(Clone)]
+pub struct Keyframe {
+    /// Between 0 and 1.
+    offset: f64,
+    /// `!important` declarations are ignored in keyframes.
+    declarations: Arc<~[PropertyDeclaration]>,
+}
+
+
+pub fn parse_keyframes_rule(rule: AtRule, parent_rules: &mut ~[CSSRule]) {
+    let location = rule.location;
+    let name = match one_component_value(rule.prelude) {
+        Some(&Ident(ref name)) => {
+            let name_lower = name.to_ascii_lower();
+            match name_lower.as_slice() {
+                "none" | "initial" | "inherit" | "unset" => None,
+                _ => Some(name.clone()),
+            }
+        }
+        Some(&String(ref name)) => Some(name.clone()),
+        _ => None,
+    };
+    let (name, block) = match (name, rule.block) {
+        (Some(name), Some(block)) => (name, block),
+        _ => {
+            log_css_error(location, "Invalid @keyframes rule");
+            return
+        }
+    };
+
+    // (offset, declarations) pairs, sorted by offset.
+    let mut keyframes: ~[(f64, ~[PropertyDeclaration])] = ~[];
+    for rule in ErrorLoggerIterator(parse_rule_list(block.move_iter())) {
+        match rule {
+            QualifiedRule(rule) => {
+                let QualifiedRule{location: location, prelude: prelude, block: block} = rule;
+                let offsets = match parse_keyframe_selector(prelude) {
+                    Some(offsets) => offsets,
+                    None => {
+                        log_css_error(location, "Invalid keyframe selector");
+                        continue
+                    }
+                };
+                let declarations = parse_property_declaration_list(block.move_iter()).normal;
+                for &offset in offsets.iter() {
+                    add_keyframe(&mut keyframes, offset, declarations.get().as_slice());
+                }
+            }
+            AtRule(rule) => log_css_error(
+                rule.location, format!("Unsupported at-rule in @keyframes: @{:s}", rule.name)),
+        }
+    }
+    add_keyframe(&mut keyframes, 0., &[]);
+    add_keyframe(&mut keyframes, 1., &[]);
+
+    parent_rules.push(CSSKeyframesRule(KeyframesRule {
+        name: name,
+        keyframes: keyframes.move_iter().map(|(offset, declarations)| {
+            Keyframe {
+                offset: offset,
+                declarations: Arc::new(declarations),
+            }
+        }).collect(),
+    }))
+}
+
+
+/// Keyframes with the same offset are merged, the declarations of later ones coming last so that
+/// they win in the cascade.
+fn add_keyframe(keyframes: &mut ~[(f64, ~[PropertyDeclaration])], offset: f64,
+                declarations: &[PropertyDeclaration]) {
+    let index = match keyframes.iter().position(|&(other_offset, _)| other_offset >= offset) {
+        Some(index) => index,
+        None => return keyframes.push((offset, declarations.to_owned())),
+    };
+    match keyframes[index] {
+        (other_offset, ref mut other_declarations) if other_offset == offset => {
+            return other_declarations.push_all(declarations)
+        }
+        _ => {}
+    }
+    keyframes.insert(index, (offset, declarations.to_owned()))
+}
+
+
+/// [ from | to | <percentage> ]#
+fn parse_keyframe_selector(input: &[ComponentValue]) -> Option<~[f64]> {
+    parse_comma_separated(input, |component_value| {
+        match component_value {
+            &Ident(ref value) if value.eq_ignore_ascii_case("from") => Some(0.),
+            &Ident(ref value) if value.eq_ignore_ascii_case("to") => Some(1.),
+            &Percentage(ref value) if value.value >= 0. && value.value <= 100. => {
+                Some(value.value / 100.)
+            }
+            _ => None,
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use encoding::EncodingRef;
+    use encoding::all::UTF_8;
+    use servo_util::url::make_url;
+    use stylesheets::{Stylesheet, CSSKeyframesRule};
+    use super::*;
+
+    fn parse(css: &str) -> Option<KeyframesRule> {
+        let base_url = make_url("http://example.com/style/main.css", None);
+        let stylesheet = Stylesheet::from_str(css, base_url, UTF_8 as EncodingRef);
+        let mut rules = stylesheet.rules;
+        match rules.pop_opt() {
+            Some(CSSKeyframesRule(rule)) => Some(rule),
+            Some(_) => fail!("unexpected rule"),
+            None => None,
+        }
+    }
+
+    fn offsets_and_lengths(rule: &KeyframesRule) -> ~[(f64, uint)] {
+        rule.keyframes.iter().map(|keyframe| {
+            (keyframe.offset, keyframe.declarations.get().len())
+        }).collect()
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let rule = parse("@keyframes slide { \
+                              to { width: 10px } \
+                              50%, 25% { width: 5px; height: 5px !important } \
+                              bogus { width: 1px } \
+                              50% { height: 1px } \
+                          }").unwrap();
+        assert_eq!(rule.name, ~"slide");
+        assert_eq!(offsets_and_lengths(&rule),
+                   ~[(0., 0), (0.25, 1), (0.5, 2), (1., 1)]);
+    }
+
+    #[test]
+    fn test_invalid_keyframes_names() {
+        assert!(parse("@keyframes { to { width: 10px } }").is_none());
+        assert!(parse("@keyframes none { to { width: 10px } }").is_none());
+        assert!(parse("@keyframes a b { to { width: 10px } }").is_none());
+        assert_eq!(parse("@keyframes 'a b' { }").unwrap().name, ~"a b");
+    }
+}
@@ -3,10 +3,14 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use std::ascii::StrAsciiExt;
+use std::num::Zero;
 use cssparser::parse_rule_list;
 use cssparser::ast::*;
+use extra::url::Url;
 
 use errors::{ErrorLoggerIterator, log_css_error};
+use properties::common_types::{Au, CSSFloat};
+use properties::common_types::specified::{Length, Au_, Em, Ex};
 use stylesheets::{CSSRule, CSSMediaRule, parse_style_rule, parse_nested_at_rule};
 use namespaces::NamespaceMap;
 
@@ -23,32 +27,74 @@ pub struct MediaQueryList {
     media_queries: ~[MediaQuery]
 }
 
-// For now, this is a "Level 2 MQ", ie. a media type.
+/// A media type, optionally negated or hidden from older browsers with `only`, and the
+/// feature expressions that must all hold.
 struct MediaQuery {
+    qualifier: Option<Qualifier>,
     media_type: MediaQueryType,
-    // TODO: Level 3 MQ expressions
+    expressions: ~[Expression],
 }
 
+#[deriving(Eq)]
+enum Qualifier {
+    Only,
+    Not,
+}
 
 enum MediaQueryType {
     All,  // Always true
     MediaType(MediaType),
+    UnknownMediaType,  // Never true, but "not" makes it true
 }
 
-#[deriving(Eq)]
+#[deriving(Eq, Clone)]
 pub enum MediaType {
     Screen,
     Print,
 }
 
+/// A media feature expression. Features without a value, like `(width)`, are `None` and hold
+/// when the feature is not zero.
+enum Expression {
+    Width(Option<Range<Au>>),
+    Height(Option<Range<Au>>),
+    DeviceWidth(Option<Range<Au>>),
+    DeviceHeight(Option<Range<Au>>),
+    Orientation(Option<Orientation>),
+    AspectRatio(Option<Range<CSSFloat>>),
+    DeviceAspectRatio(Option<Range<CSSFloat>>),
+    /// In dots per CSS pixel.
+    Resolution(Option<Range<CSSFloat>>),
+}
+
+/// The value of a feature, with the `min-` or `max-` prefix it was given.
+enum Range<T> {
+    Min(T),
+    Max(T),
+    Exactly(T),
+}
+
+#[deriving(Eq)]
+enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+#[deriving(Clone)]
 pub struct Device {
     media_type: MediaType,
-    // TODO: Level 3 MQ data: viewport size, etc.
+    viewport_width: Au,
+    viewport_height: Au,
+    /// Device pixels per CSS pixel.
+    device_pixel_ratio: CSSFloat,
 }
 
+/// Lengths in media queries that are relative to the font size use its initial value.
+static INITIAL_FONT_SIZE_PX: CSSFloat = 16.;
+
 
 pub fn parse_media_rule(rule: AtRule, parent_rules: &mut ~[CSSRule],
-                        namespaces: &NamespaceMap) {
+                        namespaces: &NamespaceMap, base_url: &Url) {
     let media_queries = parse_media_query_list(rule.prelude);
     let block = match rule.block {
         Some(block) => block,
@@ -62,7 +108,7 @@ pub fn parse_media_rule(rule: AtRule, parent_rules: &mut ~[CSSRule],
         match rule {
             QualifiedRule(rule) => parse_style_rule(rule, &mut rules, namespaces),
             AtRule(rule) => parse_nested_at_rule(
-                rule.name.to_ascii_lower(), rule, &mut rules, namespaces),
+                rule.name.to_ascii_lower(), rule, &mut rules, namespaces, base_url),
         }
     }
     parent_rules.push(CSSMediaRule(MediaRule {
@@ -73,60 +119,348 @@ pub fn parse_media_rule(rule: AtRule, parent_rules: &mut ~[CSSRule],
 
 
 pub fn parse_media_query_list(input: &[ComponentValue]) -> MediaQueryList {
-    let iter = &mut input.skip_whitespace();
-    let mut next = iter.next();
-    if next.is_none() {
-        return MediaQueryList{ media_queries: ~[MediaQuery{media_type: All}] }
+    if input.skip_whitespace().next().is_none() {
+        return MediaQueryList{ media_queries: ~[MediaQuery{
+            qualifier: None,
+            media_type: All,
+            expressions: ~[],
+        }] }
     }
-    let mut queries = ~[];
+    // Queries that don't parse are "not all", which are left out.
+    let queries = input.split(|component_value| {
+        match *component_value {
+            Comma => true,
+            _ => false,
+        }
+    }).filter_map(parse_media_query).collect();
+    MediaQueryList{ media_queries: queries }
+}
+
+
+fn parse_media_query(input: &[ComponentValue]) -> Option<MediaQuery> {
+    let iter = &mut input.skip_whitespace();
+    let first = iter.next();
+    let qualifier = match first {
+        Some(&Ident(ref value)) if value.eq_ignore_ascii_case("only") => Some(Only),
+        Some(&Ident(ref value)) if value.eq_ignore_ascii_case("not") => Some(Not),
+        _ => None,
+    };
+    let type_or_expression = if qualifier.is_some() { iter.next() } else { first };
+
+    let (media_type, mut next_expression) = match type_or_expression {
+        Some(&Ident(ref value)) => {
+            // FIXME: Workaround for https://github.com/mozilla/rust/issues/10683
+            let value_lower = value.to_ascii_lower();
+            let media_type = match value_lower.as_slice() {
+                "screen" => MediaType(Screen),
+                "print" => MediaType(Print),
+                "all" => All,
+                // These can't be media types.
+                "and" | "not" | "only" | "or" => return None,
+                _ => UnknownMediaType,
+            };
+            // The media type is either alone or followed by "and" and an expression.
+            let next = match iter.next() {
+                None => None,
+                Some(&Ident(ref value)) if value.eq_ignore_ascii_case("and") => {
+                    match iter.next() {
+                        Some(expression) => Some(expression),
+                        None => return None,
+                    }
+                }
+                Some(_) => return None,
+            };
+            (media_type, next)
+        }
+        // A query of only expressions is for all media, and can't have a qualifier.
+        Some(&ParenthesisBlock(..)) if qualifier.is_none() => (All, type_or_expression),
+        _ => return None,
+    };
+
+    let mut expressions = ~[];
     loop {
-        let mq = match next {
-            Some(&Ident(ref value)) => {
-                // FIXME: Workaround for https://github.com/mozilla/rust/issues/10683
-                let value_lower = value.to_ascii_lower(); 
-                match value_lower.as_slice() {
-                    "screen" => Some(MediaQuery{ media_type: MediaType(Screen) }),
-                    "print" => Some(MediaQuery{ media_type: MediaType(Print) }),
-                    "all" => Some(MediaQuery{ media_type: All }),
-                    _ => None
+        match next_expression {
+            None => break,
+            Some(&ParenthesisBlock(ref block)) => {
+                match parse_expression(block.as_slice()) {
+                    Some(expression) => expressions.push(expression),
+                    None => return None,
                 }
-            },
-            _ => None
-        };
-        match iter.next() {
-            None => {
-                for mq in mq.move_iter() {
-                    queries.push(mq);
+            }
+            Some(_) => return None,
+        }
+        next_expression = match iter.next() {
+            None => None,
+            Some(&Ident(ref value)) if value.eq_ignore_ascii_case("and") => {
+                match iter.next() {
+                    Some(expression) => Some(expression),
+                    None => return None,
                 }
-                return MediaQueryList{ media_queries: queries }
-            },
-            Some(&Comma) => {
-                for mq in mq.move_iter() {
-                    queries.push(mq);
+            }
+            Some(_) => return None,
+        };
+    }
+    Some(MediaQuery{ qualifier: qualifier, media_type: media_type, expressions: expressions })
+}
+
+
+/// Parses the inside of a parenthesized `feature: value` or `feature` expression.
+fn parse_expression(input: &[ComponentValue]) -> Option<Expression> {
+    let iter = &mut input.skip_whitespace();
+    let name = match iter.next() {
+        Some(&Ident(ref value)) => value.to_ascii_lower(),
+        _ => return None,
+    };
+    let value: Option<~[&ComponentValue]> = match iter.next() {
+        None => None,
+        Some(&Colon) => Some(iter.collect()),
+        Some(_) => return None,
+    };
+    let (prefix, feature) = if name.starts_with("min-") || name.starts_with("max-") {
+        (name.slice_to(4), name.slice_from(4))
+    } else {
+        ("", name.as_slice())
+    };
+
+    let value = match value {
+        Some(value) => value,
+        // Features without a value can't have a prefix.
+        None if prefix.is_empty() => {
+            return match feature {
+                "width" => Some(Width(None)),
+                "height" => Some(Height(None)),
+                "device-width" => Some(DeviceWidth(None)),
+                "device-height" => Some(DeviceHeight(None)),
+                "orientation" => Some(Orientation(None)),
+                "aspect-ratio" => Some(AspectRatio(None)),
+                "device-aspect-ratio" => Some(DeviceAspectRatio(None)),
+                "resolution" => Some(Resolution(None)),
+                _ => None,
+            }
+        }
+        None => return None,
+    };
+    match feature {
+        "width" => parse_length(value).map(|length| Width(Some(with_prefix(prefix, length)))),
+        "height" => parse_length(value).map(|length| Height(Some(with_prefix(prefix, length)))),
+        "device-width" => {
+            parse_length(value).map(|length| DeviceWidth(Some(with_prefix(prefix, length))))
+        }
+        "device-height" => {
+            parse_length(value).map(|length| DeviceHeight(Some(with_prefix(prefix, length))))
+        }
+        "orientation" if prefix.is_empty() => {
+            match value.as_slice() {
+                [&Ident(ref value)] if value.eq_ignore_ascii_case("portrait") => {
+                    Some(Orientation(Some(Portrait)))
                 }
-            },
-            // Ingnore this comma-separated part
-            _ => loop {
-                match iter.next() {
-                    Some(&Comma) => break,
-                    None => return MediaQueryList{ media_queries: queries },
-                    _ => (),
+                [&Ident(ref value)] if value.eq_ignore_ascii_case("landscape") => {
+                    Some(Orientation(Some(Landscape)))
                 }
-            },
+                _ => None,
+            }
+        }
+        "aspect-ratio" => {
+            parse_ratio(value).map(|ratio| AspectRatio(Some(with_prefix(prefix, ratio))))
+        }
+        "device-aspect-ratio" => {
+            parse_ratio(value).map(|ratio| DeviceAspectRatio(Some(with_prefix(prefix, ratio))))
+        }
+        "resolution" => {
+            parse_resolution(value).map(|dppx| Resolution(Some(with_prefix(prefix, dppx))))
+        }
+        _ => None,
+    }
+}
+
+
+fn with_prefix<T>(prefix: &str, value: T) -> Range<T> {
+    match prefix {
+        "min-" => Min(value),
+        "max-" => Max(value),
+        _ => Exactly(value),
+    }
+}
+
+
+fn parse_length(value: &[&ComponentValue]) -> Option<Au> {
+    match value {
+        [value] => {
+            match Length::parse_non_negative(*value) {
+                Some(Au_(length)) => Some(length),
+                Some(Em(em)) => Some(Au::from_frac_px(em * INITIAL_FONT_SIZE_PX)),
+                // Like properties, an ex is half an em.
+                Some(Ex(ex)) => Some(Au::from_frac_px(ex * INITIAL_FONT_SIZE_PX / 2.)),
+                None => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+
+/// Parses a ratio of two positive integers, like `16/9`, as a number.
+fn parse_ratio(value: &[&ComponentValue]) -> Option<CSSFloat> {
+    match value {
+        [&Number(ref numerator), &Delim('/'), &Number(ref denominator)]
+                if numerator.int_value.is_some() && denominator.int_value.is_some() &&
+                   numerator.value > 0. && denominator.value > 0. => {
+            Some(numerator.value / denominator.value)
         }
-        next = iter.next();
+        _ => None,
+    }
+}
+
+
+/// Parses a resolution into dots per CSS pixel.
+fn parse_resolution(value: &[&ComponentValue]) -> Option<CSSFloat> {
+    match value {
+        [&Dimension(ref value, ref unit)] if value.value > 0. => {
+            // FIXME: Workaround for https://github.com/mozilla/rust/issues/10683
+            let unit_lower = unit.to_ascii_lower();
+            match unit_lower.as_slice() {
+                "dppx" => Some(value.value),
+                "dpi" => Some(value.value / 96.),
+                "dpcm" => Some(value.value * 2.54 / 96.),
+                _ => None,
+            }
+        }
+        _ => None,
     }
 }
 
 
 impl MediaQueryList {
     pub fn evaluate(&self, device: &Device) -> bool {
-        self.media_queries.iter().any(|mq| {
-            match mq.media_type {
-                MediaType(media_type) => media_type == device.media_type,
-                All => true,
+        self.media_queries.iter().any(|mq| mq.evaluate(device))
+    }
+}
+
+
+impl MediaQuery {
+    fn evaluate(&self, device: &Device) -> bool {
+        let media_type_matches = match self.media_type {
+            MediaType(media_type) => media_type == device.media_type,
+            All => true,
+            UnknownMediaType => false,
+        };
+        let matches = media_type_matches &&
+            self.expressions.iter().all(|expression| expression.evaluate(device));
+        match self.qualifier {
+            Some(Not) => !matches,
+            Some(Only) | None => matches,
+        }
+    }
+}
+
+
+impl Expression {
+    fn evaluate(&self, device: &Device) -> bool {
+        let (width, height) = (device.viewport_width, device.viewport_height);
+        let aspect_ratio = if height == Au(0) {
+            0.
+        } else {
+            width.to_f64().unwrap() / height.to_f64().unwrap()
+        };
+        // FIXME: Layout only knows the size of the window, which we use for the device too.
+        match *self {
+            Width(ref range) | DeviceWidth(ref range) => evaluate_range(range, width),
+            Height(ref range) | DeviceHeight(ref range) => evaluate_range(range, height),
+            Orientation(None) => true,
+            // A square viewport is portrait.
+            Orientation(Some(orientation)) => {
+                let actual = if height >= width { Portrait } else { Landscape };
+                orientation == actual
+            }
+            AspectRatio(ref range) | DeviceAspectRatio(ref range) => {
+                evaluate_range(range, aspect_ratio)
             }
-            // TODO: match Level 3 expressions
-        })
+            Resolution(ref range) => evaluate_range(range, device.device_pixel_ratio),
+        }
+    }
+}
+
+
+fn evaluate_range<T: Eq + Ord + Zero>(range: &Option<Range<T>>, value: T) -> bool {
+    match *range {
+        None => !value.is_zero(),
+        Some(Min(ref min)) => value >= *min,
+        Some(Max(ref max)) => value <= *max,
+        Some(Exactly(ref exactly)) => value == *exactly,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cssparser::tokenize;
+    use properties::common_types::Au;
+    use super::*;
+
+    fn device(width: int, height: int) -> Device {
+        Device {
+            media_type: Screen,
+            viewport_width: Au::from_px(width),
+            viewport_height: Au::from_px(height),
+            device_pixel_ratio: 1.,
+        }
+    }
+
+    fn matches(media_query_list: &str, device: &Device) -> bool {
+        let input = tokenize(media_query_list).map(|(value, _)| value).to_owned_vec();
+        parse_media_query_list(input).evaluate(device)
+    }
+
+    #[test]
+    fn test_media_types() {
+        let screen = device(800, 600);
+        assert!(matches("", &screen));
+        assert!(matches("all", &screen));
+        assert!(matches("print, screen", &screen));
+        assert!(!matches("print", &screen));
+        assert!(matches("not print", &screen));
+        assert!(!matches("not screen", &screen));
+        assert!(matches("only screen", &screen));
+        assert!(!matches("tv", &screen));
+        assert!(matches("not tv", &screen));
+        // Queries that don't parse never match, but don't spoil the rest of the list.
+        assert!(!matches("screen print", &screen));
+        assert!(matches("screen print, screen", &screen));
+        assert!(!matches("only (width)", &screen));
+    }
+
+    #[test]
+    fn test_width_and_height() {
+        let small = device(500, 800);
+        let large = device(1000, 800);
+        assert!(matches("(max-width: 600px)", &small));
+        assert!(!matches("(max-width: 600px)", &large));
+        assert!(matches("screen and (min-width: 600px) and (max-height: 50em)", &large));
+        assert!(!matches("screen and (min-width: 600px) and (max-height: 40em)", &large));
+        assert!(matches("(width: 500px)", &small));
+        assert!(matches("(height)", &small));
+        assert!(!matches("(width)", &device(0, 800)));
+        assert!(matches("not screen and (max-width: 600px)", &large));
+        // Negative lengths and prefixes without a value are invalid.
+        assert!(!matches("(max-width: -1px)", &small));
+        assert!(!matches("(min-width)", &small));
+    }
+
+    #[test]
+    fn test_orientation_aspect_ratio_and_resolution() {
+        let portrait = device(600, 800);
+        let landscape = device(1600, 900);
+        assert!(matches("(orientation: portrait)", &portrait));
+        assert!(!matches("(orientation: portrait)", &landscape));
+        assert!(matches("(orientation: landscape)", &landscape));
+        assert!(matches("(aspect-ratio: 16/9)", &landscape));
+        assert!(matches("(min-aspect-ratio: 4 / 3)", &landscape));
+        assert!(!matches("(min-aspect-ratio: 4/3)", &portrait));
+        assert!(matches("(max-resolution: 96dpi)", &portrait));
+        assert!(!matches("(min-resolution: 2dppx)", &portrait));
+        let mut retina = device(600, 800);
+        retina.device_pixel_ratio = 2.;
+        assert!(matches("(min-resolution: 2dppx)", &retina));
+        assert!(matches("(min-resolution: 192dpi)", &retina));
     }
 }
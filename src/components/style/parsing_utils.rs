@@ -4,7 +4,7 @@
 
 
 use std::ascii::StrAsciiExt;
-use cssparser::ast::{ComponentValue, Ident, SkipWhitespaceIterable};
+use cssparser::ast::{ComponentValue, Ident, Comma, SkipWhitespaceIterable};
 
 
 pub fn one_component_value<'a>(input: &'a [ComponentValue]) -> Option<&'a ComponentValue> {
@@ -19,3 +19,18 @@ pub fn get_ident_lower(component_value: &ComponentValue) -> Option<~str> {
         _ => None,
     }
 }
+
+
+/// Parses a comma separated list with `parse_one`, which is given the only component value of
+/// each item. Empty lists and items are invalid.
+pub fn parse_comma_separated<T>(input: &[ComponentValue],
+                                parse_one: |&ComponentValue| -> Option<T>) -> Option<~[T]> {
+    let mut result = ~[];
+    for item in input.split(|component_value| *component_value == Comma) {
+        match one_component_value(item).and_then(|component_value| parse_one(component_value)) {
+            Some(value) => result.push(value),
+            None => return None,
+        }
+    }
+    Some(result)
+}
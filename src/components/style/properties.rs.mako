@@ -12,6 +12,8 @@ pub use cssparser::ast::*;
 use errors::{ErrorLoggerIterator, log_css_error};
 pub use parsing_utils::*;
 pub use self::common_types::*;
+pub use animation::{TimingFunction, EASE, parse_time};
+use animation::Interpolate;
 
 pub mod common_types;
 
@@ -392,6 +394,27 @@ pub mod longhands {
     // TODO: collapse. Well, do tables first.
     ${single_keyword("visibility", "visible hidden", inherited=True)}
 
+    // CSS Color Module Level 3
+
+    // TODO: The renderer doesn't draw with it yet.
+    <%self:single_component_value name="opacity">
+        pub use to_computed_value = super::computed_as_specified;
+        pub type SpecifiedValue = CSSFloat;
+        pub mod computed_value {
+            pub type T = super::super::CSSFloat;
+        }
+        #[inline] pub fn get_initial_value() -> computed_value::T { 1. }
+        /// <number>, clamped to [0, 1]
+        pub fn from_component_value(input: &ComponentValue) -> Option<SpecifiedValue> {
+            match input {
+                &Number(ref value) if value.value < 0. => Some(0.),
+                &Number(ref value) if value.value > 1. => Some(1.),
+                &Number(ref value) => Some(value.value),
+                _ => None,
+            }
+        }
+    </%self:single_component_value>
+
     // CSS 2.1, Section 12 - Generated content, automatic numbering, and lists
 
     <%self:longhand name="content" inherited="False">
@@ -714,6 +737,185 @@ pub mod longhands {
     // CSS 2.1, Section 17 - Tables
 
     // CSS 2.1, Section 18 - User interface
+
+    // CSS Transitions
+
+    ${new_style_struct("Transition")}
+
+    <%self:longhand name="transition-property">
+        pub use to_computed_value = super::computed_as_specified;
+        pub mod computed_value {
+            #[deriving(Eq, Clone)]
+            pub enum SingleComputedValue {
+                All,
+                /// The name of a property, in lower case. It might not be one that can be
+                /// transitioned, or not one at all.
+                Property(~str),
+            }
+            /// Empty for `none`.
+            pub type T = ~[SingleComputedValue];
+        }
+        pub type SpecifiedValue = computed_value::T;
+        #[inline] pub fn get_initial_value() -> computed_value::T { ~[All] }
+        /// all | <IDENT>
+        pub fn parse_one(input: &ComponentValue) -> Option<SingleComputedValue> {
+            get_ident_lower(input).and_then(|keyword| {
+                match keyword.as_slice() {
+                    "all" => Some(All),
+                    "none" | "initial" | "inherit" | "unset" => None,
+                    _ => Some(Property(keyword.clone())),
+                }
+            })
+        }
+        /// none | [ all | <IDENT> ]#
+        pub fn parse(input: &[ComponentValue]) -> Option<SpecifiedValue> {
+            match one_component_value(input).and_then(get_ident_lower) {
+                Some(ref keyword) if keyword.as_slice() == "none" => Some(~[]),
+                _ => parse_comma_separated(input, parse_one),
+            }
+        }
+    </%self:longhand>
+
+    <%def name="time_list(name, non_negative)">
+        <%self:longhand name="${name}">
+            pub use to_computed_value = super::computed_as_specified;
+            pub mod computed_value {
+                /// In seconds.
+                pub type T = ~[super::super::CSSFloat];
+            }
+            pub type SpecifiedValue = computed_value::T;
+            #[inline] pub fn get_initial_value() -> computed_value::T { ~[0.] }
+            /// <time>#
+            pub fn parse(input: &[ComponentValue]) -> Option<SpecifiedValue> {
+                % if non_negative:
+                    parse_comma_separated(input, |v| parse_time(v).filtered(|time| *time >= 0.))
+                % else:
+                    parse_comma_separated(input, parse_time)
+                % endif
+            }
+        </%self:longhand>
+    </%def>
+
+    ${time_list("transition-duration", non_negative=True)}
+
+    <%def name="timing_function_list(name)">
+        <%self:longhand name="${name}">
+            pub use to_computed_value = super::computed_as_specified;
+            pub mod computed_value {
+                pub type T = ~[super::super::TimingFunction];
+            }
+            pub type SpecifiedValue = computed_value::T;
+            #[inline] pub fn get_initial_value() -> computed_value::T { ~[EASE] }
+            /// <timing-function>#
+            pub fn parse(input: &[ComponentValue]) -> Option<SpecifiedValue> {
+                parse_comma_separated(input, TimingFunction::parse)
+            }
+        </%self:longhand>
+    </%def>
+
+    ${timing_function_list("transition-timing-function")}
+
+    ${time_list("transition-delay", non_negative=False)}
+
+    // CSS Animations
+
+    ${new_style_struct("Animation")}
+
+    <%def name="keyword_list(name, values)">
+        <%self:longhand name="${name}">
+            pub use to_computed_value = super::computed_as_specified;
+            pub mod computed_value {
+                #[deriving(Eq, Clone)]
+                pub enum SingleComputedValue {
+                    % for value in values.split():
+                        ${to_rust_ident(value)},
+                    % endfor
+                }
+                pub type T = ~[SingleComputedValue];
+            }
+            pub type SpecifiedValue = computed_value::T;
+            #[inline] pub fn get_initial_value() -> computed_value::T {
+                ~[${to_rust_ident(values.split()[0])}]
+            }
+            /// [ ${" | ".join(values.split())} ]#
+            pub fn parse(input: &[ComponentValue]) -> Option<SpecifiedValue> {
+                parse_comma_separated(input, |v| get_ident_lower(v).and_then(|keyword| {
+                    match keyword.as_slice() {
+                        % for value in values.split():
+                            "${value}" => Some(${to_rust_ident(value)}),
+                        % endfor
+                        _ => None,
+                    }
+                }))
+            }
+        </%self:longhand>
+    </%def>
+
+    <%self:longhand name="animation-name">
+        pub use to_computed_value = super::computed_as_specified;
+        pub mod computed_value {
+            /// Empty for `none`.
+            pub type T = ~[~str];
+        }
+        pub type SpecifiedValue = computed_value::T;
+        #[inline] pub fn get_initial_value() -> computed_value::T { ~[] }
+        /// none | [ <IDENT> | <string> ]#
+        ///
+        /// FIXME: `none` is only accepted on its own, not as one of the names of the list.
+        pub fn parse(input: &[ComponentValue]) -> Option<SpecifiedValue> {
+            match one_component_value(input).and_then(get_ident_lower) {
+                Some(ref keyword) if keyword.as_slice() == "none" => return Some(~[]),
+                _ => (),
+            }
+            parse_comma_separated(input, |v| {
+                match v {
+                    &Ident(ref name) => {
+                        let name_lower = name.to_ascii_lower();
+                        match name_lower.as_slice() {
+                            "none" | "initial" | "inherit" | "unset" => None,
+                            _ => Some(name.clone()),
+                        }
+                    }
+                    &String(ref name) => Some(name.clone()),
+                    _ => None,
+                }
+            })
+        }
+    </%self:longhand>
+
+    ${time_list("animation-duration", non_negative=True)}
+
+    ${timing_function_list("animation-timing-function")}
+
+    <%self:longhand name="animation-iteration-count">
+        pub use to_computed_value = super::computed_as_specified;
+        pub mod computed_value {
+            use super::super::CSSFloat;
+            #[deriving(Eq, Clone)]
+            pub enum SingleComputedValue {
+                Infinite,
+                Count(CSSFloat),
+            }
+            pub type T = ~[SingleComputedValue];
+        }
+        pub type SpecifiedValue = computed_value::T;
+        #[inline] pub fn get_initial_value() -> computed_value::T { ~[Count(1.)] }
+        /// [ infinite | <number> ]#
+        pub fn parse(input: &[ComponentValue]) -> Option<SpecifiedValue> {
+            parse_comma_separated(input, |v| {
+                match v {
+                    &Number(ref value) if value.value >= 0. => Some(Count(value.value)),
+                    &Ident(ref value) if value.eq_ignore_ascii_case("infinite") => Some(Infinite),
+                    _ => None,
+                }
+            })
+        }
+    </%self:longhand>
+
+    ${keyword_list("animation-direction", "normal reverse alternate alternate-reverse")}
+    ${keyword_list("animation-play-state", "running paused")}
+    ${time_list("animation-delay", non_negative=False)}
+    ${keyword_list("animation-fill-mode", "none forwards backwards both")}
 }
 
 
@@ -921,6 +1123,74 @@ pub mod shorthands {
         })
     </%self:shorthand>
 
+    <%self:shorthand name="transition" sub_properties="transition-property
+            transition-duration transition-timing-function transition-delay">
+        let mut properties = ~[];
+        let mut durations = ~[];
+        let mut timing_functions = ~[];
+        let mut delays = ~[];
+        let mut none = false;
+        for item in input.split(|component_value| *component_value == Comma) {
+            let mut property = None;
+            let mut duration = None;
+            let mut timing_function = None;
+            let mut delay = None;
+            let mut empty = true;
+            for component_value in item.skip_whitespace() {
+                empty = false;
+                // The first time is the duration, the second one the delay.
+                match parse_time(component_value) {
+                    Some(time) if duration.is_none() && time >= 0. => {
+                        duration = Some(time);
+                        continue
+                    }
+                    Some(time) if duration.is_some() && delay.is_none() => {
+                        delay = Some(time);
+                        continue
+                    }
+                    Some(_) => return None,
+                    None => (),
+                }
+                if timing_function.is_none() {
+                    match TimingFunction::parse(component_value) {
+                        Some(f) => { timing_function = Some(f); continue },
+                        None => (),
+                    }
+                }
+                if property.is_some() || none {
+                    return None
+                }
+                match get_ident_lower(component_value) {
+                    Some(ref keyword) if keyword.as_slice() == "none" => none = true,
+                    _ => match transition_property::parse_one(component_value) {
+                        Some(p) => property = Some(p),
+                        None => return None,
+                    }
+                }
+            }
+            if empty {
+                return None
+            }
+            properties.push(property.unwrap_or(transition_property::All));
+            durations.push(duration.unwrap_or(0.));
+            timing_functions.push(timing_function.unwrap_or(EASE));
+            delays.push(delay.unwrap_or(0.));
+        }
+        // none is only valid on its own.
+        if none {
+            if properties.len() > 1 {
+                return None
+            }
+            properties = ~[];
+        }
+        Some(Longhands {
+            transition_property: Some(properties),
+            transition_duration: Some(durations),
+            transition_timing_function: Some(timing_functions),
+            transition_delay: Some(delays),
+        })
+    </%self:shorthand>
+
 }
 
 
@@ -1090,6 +1360,83 @@ impl ComputedValues {
     }
 }
 
+
+<%
+    ANIMATABLE_LONGHANDS = [LONGHANDS_BY_NAME[name] for name in """
+        margin-top margin-right margin-bottom margin-left
+        padding-top padding-right padding-bottom padding-left
+        border-top-color border-right-color border-bottom-color border-left-color
+        border-top-width border-right-width border-bottom-width border-left-width
+        top right bottom left width height min-width max-width opacity
+        background-color color font-size
+    """.split()]
+    STYLE_STRUCT_OF = dict((longhand.name, style_struct)
+                           for style_struct, longhands in LONGHANDS_PER_STYLE_STRUCT
+                           for longhand in longhands)
+%>
+
+/// A change of a property that can be transitioned, from one computed value to another.
+#[deriving(Clone)]
+pub enum AnimatedProperty {
+    % for property in ANIMATABLE_LONGHANDS:
+        ${property.ident}_animation(longhands::${property.ident}::computed_value::T,
+                                    longhands::${property.ident}::computed_value::T),
+    % endfor
+}
+
+impl AnimatedProperty {
+    /// The properties that can be transitioned from their value in `old` to the one in `new`.
+    pub fn differences(old: &ComputedValues, new: &ComputedValues) -> ~[AnimatedProperty] {
+        let mut result = ~[];
+        % for property in ANIMATABLE_LONGHANDS:
+            {
+                let from = &old.${STYLE_STRUCT_OF[property.name]}.${property.ident};
+                let to = &new.${STYLE_STRUCT_OF[property.name]}.${property.ident};
+                if from != to && from.interpolate(to, 0.5).is_some() {
+                    result.push(${property.ident}_animation(from.clone(), to.clone()))
+                }
+            }
+        % endfor
+        result
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            % for property in ANIMATABLE_LONGHANDS:
+                ${property.ident}_animation(..) => "${property.name}",
+            % endfor
+        }
+    }
+
+    /// Whether `style` has the value this change goes to.
+    pub fn ends_at(&self, style: &ComputedValues) -> bool {
+        match *self {
+            % for property in ANIMATABLE_LONGHANDS:
+                ${property.ident}_animation(_, ref to) => {
+                    *to == style.${STYLE_STRUCT_OF[property.name]}.${property.ident}
+                }
+            % endfor
+        }
+    }
+
+    /// Sets the property in `style` to its value `progress` of the way through the change,
+    /// 0 being the start and 1 the end.
+    pub fn update(&self, style: &mut ComputedValues, progress: f64) {
+        match *self {
+            % for property in ANIMATABLE_LONGHANDS:
+                ${property.ident}_animation(ref from, ref to) => {
+                    match from.interpolate(to, progress) {
+                        Some(value) => {
+                            style.${STYLE_STRUCT_OF[property.name]}.${property.ident} = value
+                        }
+                        None => (),
+                    }
+                }
+            % endfor
+        }
+    }
+}
+
 #[inline]
 fn get_initial_values() -> ComputedValues {
     ComputedValues {
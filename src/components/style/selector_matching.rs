@@ -7,7 +7,9 @@ use std::ascii::StrAsciiExt;
 use std::hashmap::HashMap;
 use std::str;
 use std::to_bytes;
+use std::util::replace;
 
+use servo_util::geometry::Au;
 use servo_util::namespace;
 use servo_util::smallvec::{SmallVec, SmallVec16};
 use servo_util::sort;
@@ -16,7 +18,10 @@ use media_queries::{Device, Screen};
 use node::{TElement, TNode};
 use properties::{PropertyDeclaration, PropertyDeclarationBlock};
 use selectors::*;
-use stylesheets::{Stylesheet, iter_style_rules};
+use font_face::FontFaceRule;
+use keyframes::KeyframesRule;
+use stylesheets::{Stylesheet, iter_style_rules, iter_font_face_rules, iter_keyframes_rules};
+use stylesheets::media_rules_differ;
 
 pub enum StylesheetOrigin {
     UserAgentOrigin,
@@ -294,6 +299,12 @@ pub struct Stylist {
     priv before_map: PerPseudoElementSelectorMap,
     priv after_map: PerPseudoElementSelectorMap,
     priv rules_source_order: uint,
+    /// The stylesheets, kept so that their rules can be added again when a new device changes
+    /// which `@media` rules apply.
+    priv stylesheets: ~[(Stylesheet, StylesheetOrigin)],
+    priv device: Device,
+    /// The `@keyframes` rules that apply, by name. The last one with a given name wins.
+    priv keyframes: HashMap<~str, Arc<KeyframesRule>>,
 }
 
 impl Stylist {
@@ -304,10 +315,66 @@ impl Stylist {
             before_map: PerPseudoElementSelectorMap::new(),
             after_map: PerPseudoElementSelectorMap::new(),
             rules_source_order: 0u,
+            stylesheets: ~[],
+            // The viewport size isn't known until the first reflow sets the device.
+            device: Device {
+                media_type: Screen,  // TODO, use Print when printing
+                viewport_width: Au(0),
+                viewport_height: Au(0),
+                device_pixel_ratio: 1.,
+            },
+            keyframes: HashMap::new(),
         }
     }
 
     pub fn add_stylesheet(&mut self, stylesheet: Stylesheet, origin: StylesheetOrigin) {
+        self.add_rules(&stylesheet, origin);
+        self.stylesheets.push((stylesheet, origin));
+    }
+
+    /// Sets the device that `@media` rules are evaluated against, as when the window is resized.
+    /// Returns whether that changed which rules apply, in which case all elements need to be
+    /// matched again.
+    pub fn set_device(&mut self, device: Device) -> bool {
+        let changed = self.stylesheets.iter().any(|&(ref stylesheet, _)| {
+            media_rules_differ(stylesheet.rules.as_slice(), &self.device, &device)
+        });
+        self.device = device;
+        if !changed {
+            return false
+        }
+
+        self.element_map = PerPseudoElementSelectorMap::new();
+        self.before_map = PerPseudoElementSelectorMap::new();
+        self.after_map = PerPseudoElementSelectorMap::new();
+        self.rules_source_order = 0;
+        self.keyframes = HashMap::new();
+        let stylesheets = replace(&mut self.stylesheets, ~[]);
+        for &(ref stylesheet, origin) in stylesheets.iter() {
+            self.add_rules(stylesheet, origin);
+        }
+        self.stylesheets = stylesheets;
+        true
+    }
+
+    /// The `@keyframes` rule named `name`, if there is one.
+    pub fn get_keyframes(&self, name: &str) -> Option<Arc<KeyframesRule>> {
+        self.keyframes.find_equiv(&name).map(|keyframes| keyframes.clone())
+    }
+
+    /// Calls `callback` with each `@font-face` rule that applies on the current device.
+    pub fn iter_font_face_rules(&self, callback: |&FontFaceRule|) {
+        for &(ref stylesheet, _) in self.stylesheets.iter() {
+            iter_font_face_rules(stylesheet.rules.as_slice(), &self.device, |rule| callback(rule))
+        }
+    }
+
+    fn add_rules(&mut self, stylesheet: &Stylesheet, origin: StylesheetOrigin) {
+        let device = self.device.clone();
+        iter_keyframes_rules(stylesheet.rules.as_slice(), &device, |keyframes| {
+            self.keyframes.insert(keyframes.name.clone(), Arc::new(keyframes.clone()));
+        });
+
         let (mut element_map, mut before_map, mut after_map) = match origin {
             UserAgentOrigin => (
                 &mut self.element_map.user_agent,
@@ -348,8 +415,7 @@ impl Stylist {
             };
         );
 
-        let device = &Device { media_type: Screen };  // TODO, use Print when printing
-        iter_style_rules(stylesheet.rules.as_slice(), device, |style_rule| {
+        iter_style_rules(stylesheet.rules.as_slice(), &device, |style_rule| {
             append!(normal);
             append!(important);
             self.rules_source_order += 1;
@@ -358,6 +424,9 @@ impl Stylist {
 
     /// Returns the applicable CSS declarations for the given element. This corresponds to
     /// `ElementRuleCollector` in WebKit.
+    ///
+    /// Returns the length `applicable_declarations` has once the normal declarations are in, so
+    /// that animations can be put between them and the `!important` ones.
     pub fn get_applicable_declarations<E:TElement,
                                        N:TNode<E>,
                                        V:SmallVec<Arc<~[PropertyDeclaration]>>>(
@@ -365,7 +434,8 @@ impl Stylist {
                                        element: &N,
                                        style_attribute: Option<&PropertyDeclarationBlock>,
                                        pseudo_element: Option<PseudoElement>,
-                                       applicable_declarations: &mut V) {
+                                       applicable_declarations: &mut V)
+                                       -> uint {
         assert!(element.is_element());
         assert!(style_attribute.is_none() || pseudo_element.is_none(),
                 "Style attributes do not apply to pseudo-elements");
@@ -417,6 +487,7 @@ impl Stylist {
 
         // Step 2: Normal style attributes.
         style_attribute.map(|sa| applicable_declarations.push(sa.normal.clone()));
+        let normal_count = applicable_declarations.len();
 
         // Step 3: Author-supplied `!important` rules.
         while i < rule_map_indices[4] {
@@ -432,6 +503,8 @@ impl Stylist {
             applicable_declarations.push(declaration_iter.next().unwrap());
             i += 1
         }
+
+        normal_count
     }
 }
 
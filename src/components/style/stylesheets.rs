@@ -4,9 +4,12 @@
 
 use std::iter::Iterator;
 use std::ascii::StrAsciiExt;
+use std::hashmap::HashMap;
+use extra::arc::Arc;
 use extra::url::Url;
 
 use encoding::EncodingRef;
+use servo_util::url::make_url;
 
 use cssparser::{decode_stylesheet_bytes, tokenize, parse_stylesheet_rules, ToCss};
 use cssparser::ast::*;
@@ -14,8 +17,10 @@ use selectors;
 use properties;
 use errors::{ErrorLoggerIterator, log_css_error};
 use namespaces::{NamespaceMap, parse_namespace_rule};
-use media_queries::{MediaRule, parse_media_rule};
+use media_queries::{MediaRule, MediaQueryList, parse_media_rule, parse_media_query_list};
 use media_queries;
+use font_face::{FontFaceRule, parse_font_face_rule};
+use keyframes::{KeyframesRule, parse_keyframes_rule};
 
 
 pub struct Stylesheet {
@@ -31,6 +36,9 @@ pub struct Stylesheet {
 pub enum CSSRule {
     CSSStyleRule(StyleRule),
     CSSMediaRule(MediaRule),
+    CSSImportRule(ImportRule),
+    CSSFontFaceRule(FontFaceRule),
+    CSSKeyframesRule(KeyframesRule),
 }
 
 
@@ -40,6 +48,17 @@ pub struct StyleRule {
 }
 
 
+pub struct ImportRule {
+    location: SourceLocation,
+    /// Already resolved against the base URL of the importing style sheet.
+    url: Url,
+    media_queries: MediaQueryList,
+    /// The rules of the imported style sheet, shared with the other rules importing the same
+    /// URL. `None` until `Stylesheet::load_imports` has fetched it, or if it could not be used.
+    rules: Option<Arc<~[CSSRule]>>,
+}
+
+
 impl Stylesheet {
     pub fn from_bytes_iter<I: Iterator<~[u8]>>(
             mut input: I, base_url: Url, protocol_encoding_label: Option<&str>,
@@ -94,8 +113,7 @@ impl Stylesheet {
                                               "@import must be before any rule but @charset")
                             } else {
                                 next_state = STATE_IMPORTS;
-                                // TODO: support @import
-                                log_css_error(rule.location, "@import is not supported yet")
+                                parse_import_rule(rule, &mut rules, &base_url)
                             }
                         },
                         "namespace" => {
@@ -112,7 +130,8 @@ impl Stylesheet {
                         },
                         _ => {
                             next_state = STATE_BODY;
-                            parse_nested_at_rule(lower_name, rule, &mut rules, &namespaces)
+                            parse_nested_at_rule(lower_name, rule, &mut rules, &namespaces,
+                                                 &base_url)
                         },
                     }
                 },
@@ -121,6 +140,100 @@ impl Stylesheet {
         }
         Stylesheet{ rules: rules, namespaces: namespaces, encoding: encoding, base_url: base_url }
     }
+
+    /// Fetches the style sheets of the `@import` rules, and the ones they import in turn.
+    /// `fetch` returns `None` for sheets that could not be loaded. Each URL is fetched once,
+    /// however many rules import it. A sheet that imports itself or one of the sheets importing
+    /// it is left out, and so are imports nested more than `MAX_IMPORT_DEPTH` deep.
+    pub fn load_imports(&mut self, fetch: |&Url| -> Option<Stylesheet>) {
+        let mut ancestors = ~[self.base_url.clone()];
+        let mut loaded = HashMap::new();
+        load_imports(&mut self.rules, &mut ancestors, &mut loaded, 0, fetch)
+    }
+}
+
+
+/// How many style sheets deep `@import` rules are followed.
+pub static MAX_IMPORT_DEPTH: uint = 16;
+
+
+/// `loaded` holds the rules of each URL fetched so far, or `None` if it couldn't be used.
+fn load_imports(rules: &mut ~[CSSRule], ancestors: &mut ~[Url],
+                loaded: &mut HashMap<~str, Option<Arc<~[CSSRule]>>>, depth: uint,
+                fetch: |&Url| -> Option<Stylesheet>) {
+    // @import rules all come first.
+    for rule in rules.mut_iter() {
+        let rule = match *rule {
+            CSSImportRule(ref mut rule) => rule,
+            _ => break,
+        };
+        if ancestors.contains(&rule.url) {
+            log_css_error(rule.location, format!("@import cycle at {:s}", rule.url.to_str()));
+            continue
+        }
+        let key = rule.url.to_str();
+        match loaded.find(&key) {
+            Some(imported) => {
+                rule.rules = imported.clone();
+                continue
+            }
+            None => {}
+        }
+        if depth >= MAX_IMPORT_DEPTH {
+            log_css_error(rule.location, format!("@import nested too deeply at {:s}", key));
+            continue
+        }
+        let imported = match fetch(&rule.url) {
+            // Redirects can lead back to an ancestor too.
+            Some(ref stylesheet) if ancestors.contains(&stylesheet.base_url) => {
+                log_css_error(rule.location, format!("@import cycle at {:s}",
+                                                     stylesheet.base_url.to_str()));
+                None
+            }
+            Some(mut stylesheet) => {
+                let length = ancestors.len();
+                ancestors.push(rule.url.clone());
+                ancestors.push(stylesheet.base_url.clone());
+                load_imports(&mut stylesheet.rules, ancestors, loaded, depth + 1,
+                             |url| fetch(url));
+                ancestors.truncate(length);
+                Some(Arc::new(stylesheet.rules))
+            }
+            None => None,
+        };
+        loaded.insert(key, imported.clone());
+        rule.rules = imported;
+    }
+}
+
+
+pub fn parse_import_rule(rule: AtRule, parent_rules: &mut ~[CSSRule], base_url: &Url) {
+    let location = rule.location;
+    if rule.block.is_some() {
+        log_css_error(location, "Invalid @import rule");
+        return
+    }
+    let prelude = rule.prelude.as_slice();
+    let start = match prelude.iter().position(|component_value| *component_value != WhiteSpace) {
+        Some(start) => start,
+        None => {
+            log_css_error(location, "Invalid @import rule");
+            return
+        }
+    };
+    let url = match prelude[start] {
+        URL(ref value) | String(ref value) => make_url(value.as_slice(), Some(base_url.clone())),
+        _ => {
+            log_css_error(location, "Invalid @import rule");
+            return
+        }
+    };
+    parent_rules.push(CSSImportRule(ImportRule {
+        location: location,
+        url: url,
+        media_queries: parse_media_query_list(prelude.slice_from(start + 1)),
+        rules: None,
+    }))
 }
 
 
@@ -142,22 +255,235 @@ pub fn parse_style_rule(rule: QualifiedRule, parent_rules: &mut ~[CSSRule],
 
 // lower_name is passed explicitly to avoid computing it twice.
 pub fn parse_nested_at_rule(lower_name: &str, rule: AtRule,
-                            parent_rules: &mut ~[CSSRule], namespaces: &NamespaceMap) {
+                            parent_rules: &mut ~[CSSRule], namespaces: &NamespaceMap,
+                            base_url: &Url) {
     match lower_name {
-        "media" => parse_media_rule(rule, parent_rules, namespaces),
+        "media" => parse_media_rule(rule, parent_rules, namespaces, base_url),
+        "font-face" => parse_font_face_rule(rule, parent_rules, base_url),
+        "keyframes" => parse_keyframes_rule(rule, parent_rules),
         _ => log_css_error(rule.location, format!("Unsupported at-rule: @{:s}", lower_name))
     }
 }
 
 
+/// Collects the rules that apply on `device`, other than `@media` and `@import` rules, whose
+/// rules are collected instead, in reverse cascade order. A style sheet imported several times
+/// is only expanded where it is imported last, which is where its rules take effect, so shared
+/// imports don't multiply the work. `expanded` holds the imported sheets seen so far.
+fn collect_applying_rules<'a>(rules: &'a [CSSRule], device: &media_queries::Device,
+                              expanded: &mut ~[*~[CSSRule]], applying: &mut ~[&'a CSSRule]) {
+    for rule in rules.rev_iter() {
+        match *rule {
+            CSSMediaRule(ref rule) => if rule.media_queries.evaluate(device) {
+                collect_applying_rules(rule.rules.as_slice(), device, expanded, applying)
+            },
+            CSSImportRule(ref rule) => match rule.rules {
+                Some(ref rules) if rule.media_queries.evaluate(device) => {
+                    let rules = rules.get();
+                    let key = rules as *~[CSSRule];
+                    if !expanded.contains(&key) {
+                        expanded.push(key);
+                        collect_applying_rules(rules.as_slice(), device, expanded, applying)
+                    }
+                }
+                _ => {}
+            },
+            CSSStyleRule(..) | CSSFontFaceRule(..) | CSSKeyframesRule(..) => applying.push(rule),
+        }
+    }
+}
+
+
+/// Calls `callback` with the rules that apply on `device`, in cascade order, looking into
+/// `@media` rules and imported style sheets.
+fn iter_applying_rules(rules: &[CSSRule], device: &media_queries::Device,
+                       callback: |&CSSRule|) {
+    let mut applying = ~[];
+    collect_applying_rules(rules, device, &mut ~[], &mut applying);
+    for rule in applying.rev_iter() {
+        callback(*rule)
+    }
+}
+
+
 pub fn iter_style_rules<'a>(rules: &[CSSRule], device: &media_queries::Device,
                             callback: |&StyleRule|) {
-    for rule in rules.iter() {
+    iter_applying_rules(rules, device, |rule| {
         match *rule {
             CSSStyleRule(ref rule) => callback(rule),
-            CSSMediaRule(ref rule) => if rule.media_queries.evaluate(device) {
-                iter_style_rules(rule.rules.as_slice(), device, |s| callback(s))
+            _ => {}
+        }
+    })
+}
+
+
+pub fn iter_font_face_rules(rules: &[CSSRule], device: &media_queries::Device,
+                            callback: |&FontFaceRule|) {
+    iter_applying_rules(rules, device, |rule| {
+        match *rule {
+            CSSFontFaceRule(ref rule) => callback(rule),
+            _ => {}
+        }
+    })
+}
+
+
+pub fn iter_keyframes_rules(rules: &[CSSRule], device: &media_queries::Device,
+                            callback: |&KeyframesRule|) {
+    iter_applying_rules(rules, device, |rule| {
+        match *rule {
+            CSSKeyframesRule(ref rule) => callback(rule),
+            _ => {}
+        }
+    })
+}
+
+
+/// Whether any `@media` or `@import` rule applies on one device but not the other.
+pub fn media_rules_differ(rules: &[CSSRule], a: &media_queries::Device,
+                          b: &media_queries::Device) -> bool {
+    imported_media_rules_differ(rules, a, b, &mut ~[])
+}
+
+
+/// `checked` holds the imported style sheets looked into so far, which need not be looked into
+/// again wherever else they are imported.
+fn imported_media_rules_differ(rules: &[CSSRule], a: &media_queries::Device,
+                               b: &media_queries::Device, checked: &mut ~[*~[CSSRule]])
+                               -> bool {
+    rules.iter().any(|rule| {
+        match *rule {
+            CSSStyleRule(..) | CSSFontFaceRule(..) | CSSKeyframesRule(..) => false,
+            CSSMediaRule(ref rule) => {
+                rule.media_queries.evaluate(a) != rule.media_queries.evaluate(b) ||
+                    imported_media_rules_differ(rule.rules.as_slice(), a, b, checked)
+            }
+            CSSImportRule(ref rule) => {
+                rule.media_queries.evaluate(a) != rule.media_queries.evaluate(b) ||
+                    rule.rules.as_ref().map_or(false, |rules| {
+                        let key = rules.get() as *~[CSSRule];
+                        if checked.contains(&key) {
+                            return false
+                        }
+                        checked.push(key);
+                        imported_media_rules_differ(rules.get().as_slice(), a, b, checked)
+                    })
             }
         }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use encoding::EncodingRef;
+    use encoding::all::UTF_8;
+    use extra::url::Url;
+    use media_queries::{Device, Screen};
+    use properties::common_types::Au;
+    use selectors::LocalNameSelector;
+    use servo_util::url::make_url;
+    use super::{MAX_IMPORT_DEPTH, Stylesheet, iter_style_rules};
+
+    fn parse(css: &str, url: &str) -> Stylesheet {
+        Stylesheet::from_str(css, make_url(url, None), UTF_8 as EncodingRef)
+    }
+
+    /// The element names of the rules that apply on a screen, in cascade order.
+    fn local_names(stylesheet: &Stylesheet) -> ~[~str] {
+        let device = Device {
+            media_type: Screen,
+            viewport_width: Au::from_px(800),
+            viewport_height: Au::from_px(600),
+            device_pixel_ratio: 1.,
+        };
+        let mut names = ~[];
+        iter_style_rules(stylesheet.rules.as_slice(), &device, |rule| {
+            match rule.selectors[0].compound_selectors.get().simple_selectors[0] {
+                LocalNameSelector(ref name) => names.push(name.clone()),
+                _ => fail!("unexpected selector"),
+            }
+        });
+        names
+    }
+
+    #[test]
+    fn test_import_order_and_cycles() {
+        let mut stylesheet = parse("@import 'b.css'; @import url(c.css) print; \
+                                    @import 'd.css'; a {}", "http://example.com/a.css");
+        let mut fetched = ~[];
+        stylesheet.load_imports(|url: &Url| {
+            let url = url.to_str();
+            fetched.push(url.clone());
+            let css = match url.as_slice() {
+                "http://example.com/b.css" => "@import 'a.css'; @import 'e.css' screen; b {}",
+                "http://example.com/c.css" => "c {}",
+                "http://example.com/e.css" => "@import 'b.css'; e {}",
+                _ => return None,
+            };
+            Some(parse(css, url.as_slice()))
+        });
+        assert_eq!(fetched, ~[~"http://example.com/b.css", ~"http://example.com/e.css",
+                              ~"http://example.com/c.css", ~"http://example.com/d.css"]);
+        assert_eq!(local_names(&stylesheet), ~[~"e", ~"b", ~"a"]);
+    }
+
+    #[test]
+    fn test_import_fetches_each_url_once() {
+        let mut stylesheet = parse("@import 'b.css'; @import 'c.css'; @import 'x.css'; \
+                                    @import 'x.css'; a {}", "http://example.com/a.css");
+        let mut fetched = ~[];
+        stylesheet.load_imports(|url: &Url| {
+            let url = url.to_str();
+            fetched.push(url.clone());
+            let css = match url.as_slice() {
+                "http://example.com/b.css" => "@import 'd.css'; b {}",
+                "http://example.com/c.css" => "@import 'd.css'; c {}",
+                "http://example.com/d.css" => "d {}",
+                _ => return None,
+            };
+            Some(parse(css, url.as_slice()))
+        });
+        assert_eq!(fetched, ~[~"http://example.com/b.css", ~"http://example.com/d.css",
+                              ~"http://example.com/c.css", ~"http://example.com/x.css"]);
+        // `d.css` takes effect where it is imported last.
+        assert_eq!(local_names(&stylesheet), ~[~"b", ~"d", ~"c", ~"a"]);
+    }
+
+    #[test]
+    fn test_shared_imports_are_expanded_once() {
+        // Each sheet imports the next one twice, so expanding every import would take 2^16
+        // rules.
+        let mut stylesheet = parse("@import '1.css'; @import '1.css'; a {}",
+                                   "http://example.com/0.css");
+        let mut fetched = 0;
+        stylesheet.load_imports(|url: &Url| {
+            fetched += 1;
+            let css = format!("@import '{:u}.css'; @import '{:u}.css'; b \\{\\}",
+                              fetched + 1, fetched + 1);
+            Some(parse(css, url.to_str()))
+        });
+        assert_eq!(fetched, MAX_IMPORT_DEPTH);
+        assert_eq!(local_names(&stylesheet).len(), MAX_IMPORT_DEPTH + 1);
+    }
+
+    #[test]
+    fn test_import_depth_is_capped() {
+        let mut stylesheet = parse("@import '1.css'; a {}", "http://example.com/0.css");
+        let mut fetched = 0;
+        stylesheet.load_imports(|url: &Url| {
+            fetched += 1;
+            let css = format!("@import '{:u}.css'; b \\{\\}", fetched + 1);
+            Some(parse(css, url.to_str()))
+        });
+        assert_eq!(fetched, MAX_IMPORT_DEPTH);
+        assert_eq!(local_names(&stylesheet).len(), MAX_IMPORT_DEPTH + 1);
+    }
+
+    #[test]
+    fn test_import_after_rules_is_ignored() {
+        let mut stylesheet = parse("a {} @import 'b.css';", "http://example.com/a.css");
+        stylesheet.load_imports(|_| fail!("nothing should be imported"));
+        assert_eq!(local_names(&stylesheet), ~[~"a"]);
     }
 }
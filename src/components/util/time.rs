@@ -6,7 +6,7 @@
 
 use extra::time::precise_time_ns;
 use extra::treemap::TreeMap;
-use std::comm::{Port, SharedChan};
+use std::comm::{Chan, Port, SharedChan};
 use std::iter::AdditiveIterator;
 use task::{spawn_named};
 
@@ -41,6 +41,8 @@ pub enum ProfilerMsg {
     TimeMsg(ProfilerCategory, f64),
     /// Message used to force print the profiling metrics
     PrintMsg,
+    /// Requests the profiling metrics as a formatted table, or `None` if the profiler is off
+    ReportMsg(Chan<Option<~str>>),
     /// Tells the profiler to shut down.
     ExitMsg,
 }
@@ -62,6 +64,9 @@ pub enum ProfilerCategory {
     RenderingDrawingCategory,
     RenderingPrepBuffCategory,
     RenderingCategory,
+    NetworkCacheHitCategory,
+    NetworkCacheMissCategory,
+    NetworkCacheRevalidatedCategory,
     // FIXME(rust#8803): workaround for lack of CTFE function on enum types to return length
     NumBuckets,
 }
@@ -90,6 +95,9 @@ impl ProfilerCategory {
         buckets.insert(RenderingDrawingCategory, ~[]);
         buckets.insert(RenderingPrepBuffCategory, ~[]);
         buckets.insert(RenderingCategory, ~[]);
+        buckets.insert(NetworkCacheHitCategory, ~[]);
+        buckets.insert(NetworkCacheMissCategory, ~[]);
+        buckets.insert(NetworkCacheRevalidatedCategory, ~[]);
 
         buckets
     }
@@ -148,6 +156,7 @@ impl Profiler {
                     loop {
                         match port.recv_opt() {
                             None | Some(ExitMsg) => break,
+                            Some(ReportMsg(chan)) => chan.send(None),
                             _ => {}
                         }
                     }
@@ -188,6 +197,10 @@ impl Profiler {
                 Some(TimeMsg(..)) => self.print_buckets(),
                 _ => ()
             },
+            ReportMsg(chan) => {
+                chan.send(Some(self.format_buckets()));
+                return true;
+            }
             ExitMsg => return false,
         };
         self.last_msg = Some(msg);
@@ -195,9 +208,13 @@ impl Profiler {
     }
 
     fn print_buckets(&mut self) {
-        println(format!("{:39s} {:15s} {:15s} {:15s} {:15s} {:15s}",
-                         "_category_", "_mean (ms)_", "_median (ms)_",
-                         "_min (ms)_", "_max (ms)_", "_bucket size_"));
+        println(self.format_buckets());
+    }
+
+    fn format_buckets(&self) -> ~str {
+        let mut lines = ~[format!("{:39s} {:15s} {:15s} {:15s} {:15s} {:15s}",
+                                  "_category_", "_mean (ms)_", "_median (ms)_",
+                                  "_min (ms)_", "_max (ms)_", "_bucket size_")];
         for (category, data) in self.buckets.iter() {
             // FIXME(XXX): TreeMap currently lacks mut_iter()
             let mut data = data.clone();
@@ -215,11 +232,12 @@ impl Profiler {
                      data[data_len / 2],
                      data.iter().min().unwrap(),
                      data.iter().max().unwrap());
-                println(format!("{:-35s}: {:15.4f} {:15.4f} {:15.4f} {:15.4f} {:15u}",
-                             category.format(), mean, median, min, max, data_len));
+                lines.push(format!("{:-35s}: {:15.4f} {:15.4f} {:15.4f} {:15.4f} {:15u}",
+                                   category.format(), mean, median, min, max, data_len));
             }
         }
-        println("");
+        lines.push(~"");
+        lines.connect("\n")
     }
 }
 
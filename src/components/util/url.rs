@@ -16,7 +16,6 @@ Create a URL object from a string. Does various helpful browsery things like
   is based off the current url
 
 */
-// TODO: about:failure->
 pub fn make_url(str_url: &str, current_url: Option<Url>) -> Url {
     let str_url = str_url.trim_chars(& &[' ', '\t', '\n', '\r', '\x0C']).to_owned();
     let schm = url::get_scheme(str_url);
@@ -55,25 +54,33 @@ pub fn make_url(str_url: &str, current_url: Option<Url>) -> Url {
                 }
             }
         },
-        Ok((scheme, page)) => {
+        Ok((scheme, _)) => {
             match scheme {
-                ~"about" => {
-                    match page {
-                        ~"failure" => {
-                            let mut path = os::getcwd();
-                            path.push("../src/test/html/failure.html");
-                            // FIXME (#1094): not the right way to transform a path
-                            ~"file://" + path.display().to_str()
+                ~"data" => {
+                    // Drop tabs and newlines within data: URLs, e.g. within a base64
+                    // src="..." block, as the URL parser does. extra::url decodes each
+                    // escaped byte of the path into a char of its own, so the path is escaped
+                    // once more: it then holds the data as written, escapes included, and the
+                    // data: loader decodes it. Other characters are escaped as UTF-8 bytes.
+                    let mut encoded = ~"";
+                    let mut in_path = true;
+                    for c in str_url.chars().filter(|&c| c != '\t' && c != '\n' && c != '\r') {
+                        match c {
+                            '?' | '#' => {
+                                in_path = false;
+                                encoded.push_char(c);
+                            }
+                            '%' if in_path => encoded.push_str("%25"),
+                            _ if is_url_path_char(c) => encoded.push_char(c),
+                            _ => {
+                                let escape = if in_path { "%25" } else { "%" };
+                                for b in c.to_str().bytes() {
+                                    encoded.push_str(format!("{:s}{:02X}", escape, b));
+                                }
+                            }
                         }
-                        // TODO: handle the rest of the about: pages
-                        _ => str_url
                     }
-                },
-                ~"data" => {
-                    // Drop whitespace within data: URLs, e.g. newlines within a base64
-                    // src="..." block.  Whitespace intended as content should be
-                    // %-encoded or base64'd.
-                    str_url.chars().filter(|&c| !c.is_whitespace()).collect()
+                    encoded
                 },
                 _ => str_url
             }
@@ -84,6 +91,24 @@ pub fn make_url(str_url: &str, current_url: Option<Url>) -> Url {
     url::from_str(str_url).unwrap()
 }
 
+/// The `about:failure` page that explains why `failed_url` couldn't be shown.
+pub fn failure_url(failed_url: &str, reason: &str) -> Url {
+    make_url(format!("about:failure?url={:s}&reason={:s}",
+                     url::encode_component(failed_url), url::encode_component(reason)),
+             None)
+}
+
+/// Whether `c` can appear unescaped in a URL path, as far as `extra::url` is concerned. The
+/// query and fragment delimiters are included so that they keep their meaning.
+fn is_url_path_char(c: char) -> bool {
+    match c {
+        'a'..'z' | 'A'..'Z' | '0'..'9' => true,
+        '&' | '\'' | '(' | ')' | '.' | '@' | ':' | '%' | '/' | '+' | '!' | '*' | ',' | ';' |
+        '=' | '_' | '-' | '~' | '?' | '#' => true,
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod make_url_tests {
     use super::make_url;
@@ -185,6 +210,17 @@ mod make_url_tests {
         assert!(new_url.path == ~"/crumpet.html");
     }
 
+    #[test]
+    fn should_keep_data_url_escaped() {
+        let url = make_url(~"data:text/html,<p>a b</p>\n<p>c</p>", None);
+        assert!(url.scheme == ~"data");
+        assert!(url.path == ~"text/html,%3Cp%3Ea%20b%3C/p%3E%3Cp%3Ec%3C/p%3E");
+
+        let url = make_url(~"data:,caf\u00e9%E9?b=%3F\u00e9#top", None);
+        assert!(url.path == ~",caf%C3%A9%E9");
+        assert!(url.query == ~[(~"b", ~"?\u00c3\u00a9")]);
+        assert!(url.fragment == Some(~"top"));
+    }
 }
 
 pub type UrlMap<T> = HashMap<Url, T>;